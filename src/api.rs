@@ -0,0 +1,475 @@
+// An HTTP API for headless flashing stations, so a rack of boxes with sticks plugged in can
+// be driven from one console: `iso_maker serve --token <secret>`.
+//
+//     GET  /devices            The removable devices, as the GUI lists them
+//     GET  /jobs               Every job since the server started
+//     POST /jobs?source=<image>&device=<path>[&verify=<algorithm>|none][&checksum=<algorithm>:<digest>]
+//     GET  /jobs/<id>          One job
+//     GET  /jobs/<id>/events   Its progress as server-sent events, until it ends
+//     POST /jobs/<id>/cancel   Stops it
+//
+// Anyone who can start a job can erase a device, so every request needs the token as
+// `Authorization: Bearer <token>`. Jobs write the way `iso_maker write` does, with the
+// settings' verification and checkpoints, only to removable devices that don't hold the
+// running system, and go into the audit log.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use crate::audit;
+use crate::bmap::Bmap;
+use crate::config::Config;
+use crate::devices::{self, Device};
+use crate::hash::Algorithm;
+use crate::hooks;
+use crate::json;
+use crate::writer::{self, Phase};
+
+/// Where the server listens unless told otherwise, only this machine can reach it.
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8400";
+
+// How often an event stream looks for progress
+const EVENT_INTERVAL: Duration = Duration::from_millis(500);
+
+// A client that connects and says nothing doesn't keep a thread forever
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+	Running,
+	Done,
+	Cancelled,
+	Failed(String),
+}
+
+struct Job {
+	id: usize,
+	/// A ticket number or who asked for it, as the client labelled it
+	label: Option<String>,
+	source: String,
+	device: String,
+	phase: Phase,
+	done: u64,
+	total: u64,
+	state: State,
+	cancel_tx: Option<mpsc::Sender<()>>,
+}
+
+impl Job {
+	fn json(&self) -> String {
+		let (state, error) = match &self.state {
+			State::Running => ("running", None),
+			State::Done => ("done", None),
+			State::Cancelled => ("cancelled", None),
+			State::Failed(e) => ("failed", Some(e.as_str())),
+		};
+
+		json::object(&[
+			("id", self.id.to_string()),
+			("label", json::optional(self.label.as_deref())),
+			("source", json::string(&self.source)),
+			("device", json::string(&self.device)),
+			("phase", json::string(&format!("{:?}", self.phase))),
+			("done", self.done.to_string()),
+			("total", self.total.to_string()),
+			("state", json::string(state)),
+			("error", json::optional(error)),
+		])
+	}
+}
+
+/// The server's jobs and what it needs to start them.
+pub struct Server {
+	token: String,
+	config: Config,
+	jobs: Mutex<Vec<Job>>,
+}
+
+struct Request {
+	method: String,
+	path: String,
+	query: Vec<(String, String)>,
+	authorization: Option<String>,
+}
+
+impl Request {
+	fn param(&self, name: &str) -> Option<&str> {
+		self.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+	}
+}
+
+#[derive(Debug, PartialEq)]
+enum Response {
+	Json(u16, String),
+	/// Progress of the job with this id, as it happens
+	Events(usize),
+}
+
+fn error(status: u16, message: &str) -> Response {
+	Response::Json(status, json::object(&[("error", json::string(message))]))
+}
+
+/// Serves the API on `listen` until the process is stopped.
+pub fn serve(listen: &str, token: String) -> Result<(), String> {
+	if token.trim().is_empty() {
+		return Err("API error: the token can't be empty".into());
+	}
+
+	let listener = TcpListener::bind(listen).map_err(|e| format!("API error: {listen}: {e}"))?;
+	let server = Arc::new(Server { token, config: Config::load(), jobs: Mutex::default() });
+	eprintln!("Listening on http://{listen}");
+
+	for stream in listener.incoming() {
+		let Ok(stream) = stream else {
+			continue;
+		};
+
+		let server = server.clone();
+		std::thread::spawn(move || {
+			if let Err(e) = connection(&server, stream) {
+				eprintln!("{e}");
+			}
+		});
+	}
+
+	Ok(())
+}
+
+// One request per connection, then it's closed
+fn connection(server: &Arc<Server>, stream: TcpStream) -> Result<(), String> {
+	stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(|e| format!("API error: {e}"))?;
+	let mut writer = stream.try_clone().map_err(|e| format!("API error: {e}"))?;
+
+	let response = match read_request(&mut BufReader::new(stream)) {
+		Ok(request) => route(server, &request),
+		Err(e) => error(400, &e),
+	};
+
+	match response {
+		Response::Json(status, body) => respond(&mut writer, status, &body),
+		Response::Events(id) => events(server, id, &mut writer),
+	}
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request, String> {
+	let mut line = String::new();
+	reader.read_line(&mut line).map_err(|e| format!("API error: {e}"))?;
+
+	let mut parts = line.split_whitespace();
+	let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+		return Err("Not an HTTP request".into());
+	};
+
+	let (path, query) = target.split_once('?').unwrap_or((target, ""));
+	let query = query.split('&')
+		.filter(|pair| !pair.is_empty())
+		.map(|pair| {
+			let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+			(decode(key), decode(value))
+		})
+		.collect();
+
+	// Only the token matters of the headers, the body isn't used
+	let mut authorization = None;
+	loop {
+		let mut header = String::new();
+		if reader.read_line(&mut header).map_err(|e| format!("API error: {e}"))? == 0 || header.trim().is_empty() {
+			break;
+		}
+
+		if let Some((name, value)) = header.split_once(':') && name.trim().eq_ignore_ascii_case("authorization") {
+			authorization = Some(value.trim().to_string());
+		}
+	}
+
+	Ok(Request { method: method.to_string(), path: path.to_string(), query, authorization })
+}
+
+// Percent-decoding, with + for a space as forms send it
+fn decode(value: &str) -> String {
+	let bytes = value.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+
+	let mut i = 0;
+	while i < bytes.len() {
+		let escaped = (bytes[i] == b'%').then(|| value.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())).flatten();
+
+		match (bytes[i], escaped) {
+			(_, Some(byte)) => {
+				decoded.push(byte);
+				i += 2;
+			},
+			(b'+', _) => decoded.push(b' '),
+			(byte, _) => decoded.push(byte),
+		}
+		i += 1;
+	}
+
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn route(server: &Arc<Server>, request: &Request) -> Response {
+	if !authorized(&server.token, request.authorization.as_deref()) {
+		return error(401, "Missing or wrong token, send it as Authorization: Bearer <token>");
+	}
+
+	let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+
+	match (request.method.as_str(), segments.as_slice()) {
+		("GET", ["devices"]) => {
+			let devices: Vec<String> = devices::list().iter().map(device_json).collect();
+			Response::Json(200, json::array(&devices))
+		},
+		("GET", ["jobs"]) => {
+			let jobs = server.jobs.lock().unwrap();
+			Response::Json(200, json::array(&jobs.iter().map(Job::json).collect::<Vec<_>>()))
+		},
+		("POST", ["jobs"]) => match start(server, request) {
+			Ok(id) => job(server, id).map_or_else(|| error(500, "The job went missing"), |json| Response::Json(201, json)),
+			Err((status, e)) => error(status, &e),
+		},
+		("GET", ["jobs", id]) => match id.parse().ok().and_then(|id| job(server, id)) {
+			Some(json) => Response::Json(200, json),
+			None => error(404, "No such job"),
+		},
+		("GET", ["jobs", id, "events"]) => match id.parse().ok().filter(|&id| job(server, id).is_some()) {
+			Some(id) => Response::Events(id),
+			None => error(404, "No such job"),
+		},
+		("POST", ["jobs", id, "cancel"]) => {
+			let mut jobs = server.jobs.lock().unwrap();
+			match id.parse().ok().and_then(|id: usize| jobs.iter_mut().find(|job| job.id == id)) {
+				Some(job) => {
+					// Stops at the next chunk, the job says so once it has
+					if let Some(tx) = job.cancel_tx.take() {
+						let _ = tx.try_send(());
+					}
+					Response::Json(202, job.json())
+				},
+				None => error(404, "No such job"),
+			}
+		},
+		_ => error(404, "No such endpoint"),
+	}
+}
+
+// Compared all the way through, so the time taken doesn't give away how much of it was right
+fn authorized(token: &str, authorization: Option<&str>) -> bool {
+	let Some(given) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+		return false;
+	};
+
+	given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |differ, (a, b)| differ | (a ^ b)) == 0
+}
+
+fn job(server: &Server, id: usize) -> Option<String> {
+	server.jobs.lock().unwrap().iter().find(|job| job.id == id).map(Job::json)
+}
+
+fn device_json(device: &Device) -> String {
+	json::object(&[
+		("path", json::string(&device.path)),
+		("model", json::string(&device.model)),
+		("serial", json::optional(device.serial.as_deref())),
+		("size", device.size.to_string()),
+		("system", device.holds_system().to_string()),
+	])
+}
+
+// Checks the job over and starts it on a thread of its own, returning its id
+fn start(server: &Arc<Server>, request: &Request) -> Result<usize, (u16, String)> {
+	let (Some(source), Some(path)) = (request.param("source"), request.param("device")) else {
+		return Err((400, "A job needs a source and a device".into()));
+	};
+
+	let config = &server.config;
+	let verify = match request.param("verify") {
+		None => config.verify.then_some(config.verify_algorithm),
+		Some("none") => None,
+		Some(name) => Some(name.parse::<Algorithm>().map_err(|e| (400, e))?),
+	};
+
+	let expected = match request.param("checksum").map(|value| value.split_once(':')) {
+		None => None,
+		Some(Some((algorithm, digest))) => Some((algorithm.parse::<Algorithm>().map_err(|e| (400, e))?, digest.to_string())),
+		Some(None) => return Err((400, "The checksum needs an algorithm and digest, e.g. sha256:9f86d0...".into())),
+	};
+
+	// Only what the GUI would offer, never a fixed disk
+	let Some(device) = devices::list().into_iter().find(|device| device.path == path) else {
+		return Err((404, format!("{path} isn't a removable device")));
+	};
+	if device.holds_system() {
+		return Err((409, format!("{path} holds the running system and can't be written")));
+	}
+
+	let (cancel_tx, cancel_rx) = mpsc::channel(1);
+	let id = {
+		let mut jobs = server.jobs.lock().unwrap();
+
+		// Checked with the lock held, so two requests can't both get the device
+		if jobs.iter().any(|job| job.device == device.path && job.state == State::Running) {
+			return Err((409, format!("{path} is already being written")));
+		}
+
+		let id = jobs.len() + 1;
+		jobs.push(Job {
+			id,
+			label: request.param("label").map(str::trim).filter(|label| !label.is_empty()).map(String::from),
+			source: source.to_string(),
+			device: device.path.clone(),
+			phase: Phase::Writing,
+			done: 0,
+			total: 0,
+			state: State::Running,
+			cancel_tx: Some(cancel_tx),
+		});
+		id
+	};
+
+	let finish = move |server: &Server, result: Result<(), String>| {
+		if let Some(job) = server.jobs.lock().unwrap().iter_mut().find(|job| job.id == id) {
+			job.cancel_tx = None;
+			job.state = match result {
+				Ok(()) => State::Done,
+				Err(e) if e == "Cancelled" => State::Cancelled,
+				Err(e) => State::Failed(e),
+			};
+		}
+	};
+
+	// A job that can't be recorded doesn't go ahead
+	let audit = audit::Entry::new(config, audit::Operation::Write, &device.path, device.serial.clone(), source)
+		.labelled(request.param("label").unwrap_or_default());
+	if let Err(e) = audit.started() {
+		finish(server, Err(e.clone()));
+		return Err((if hooks::refused(&e) { 403 } else { 500 }, e));
+	}
+
+	let options = writer::Options {
+		verify,
+		expected,
+		bmap: Bmap::find(source).and_then(|path| Bmap::load(&path).ok()),
+		mmap: config.mmap,
+		checkpoint: config.checkpoint(),
+		image_hash: audit.image_hash.clone(),
+		readback: audit.readback.clone(),
+		skip_unchanged: config.skip_unchanged,
+		delta: config.delta,
+		..writer::Options::default()
+	};
+
+	let (server, source, dest) = (server.clone(), source.to_string(), device.path);
+	std::thread::spawn(move || {
+		let result = run(&server, id, source, dest, options, cancel_rx);
+		finish(&server, audit::finish(Some(&audit), result));
+	});
+
+	Ok(id)
+}
+
+// The write, with its progress kept on the job
+fn run(server: &Server, id: usize, source: String, dest: String, options: writer::Options, cancel_rx: mpsc::Receiver<()>) -> Result<(), String> {
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.build()
+		.map_err(|e| format!("Runtime error: {e}"))?;
+
+	runtime.block_on(async move {
+		let (progress_tx, mut progress_rx) = mpsc::channel(100);
+		let copy = writer::copy_with_progress(source, dest, options, cancel_rx, progress_tx);
+
+		let progress = async {
+			while let Some((phase, done, total)) = progress_rx.recv().await {
+				if let Some(job) = server.jobs.lock().unwrap().iter_mut().find(|job| job.id == id) {
+					(job.phase, job.done, job.total) = (phase, done, total);
+				}
+			}
+		};
+
+		tokio::join!(copy, progress).0
+	})
+}
+
+fn respond(writer: &mut impl Write, status: u16, body: &str) -> Result<(), String> {
+	let reason = match status {
+		200 => "OK",
+		201 => "Created",
+		202 => "Accepted",
+		400 => "Bad Request",
+		401 => "Unauthorized",
+		403 => "Forbidden",
+		404 => "Not Found",
+		409 => "Conflict",
+		_ => "Internal Server Error",
+	};
+
+	write!(writer, "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+		.map_err(|e| format!("API error: {e}"))
+}
+
+// Sends the job each time it changes, and once more when it ends
+fn events(server: &Server, id: usize, writer: &mut impl Write) -> Result<(), String> {
+	write!(writer, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")
+		.map_err(|e| format!("API error: {e}"))?;
+
+	let mut last = String::new();
+	loop {
+		let (json, running) = {
+			let jobs = server.jobs.lock().unwrap();
+			let Some(job) = jobs.iter().find(|job| job.id == id) else {
+				return Ok(());
+			};
+			(job.json(), job.state == State::Running)
+		};
+
+		if json != last {
+			let event = if running { "progress" } else { "end" };
+			// A client that went away is the end of it too
+			if write!(writer, "event: {event}\ndata: {json}\n\n").and_then(|()| writer.flush()).is_err() {
+				return Ok(());
+			}
+			last = json;
+		}
+
+		if !running {
+			return Ok(());
+		}
+
+		std::thread::sleep(EVENT_INTERVAL);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn request(raw: &str) -> Request {
+		read_request(&mut raw.as_bytes()).unwrap()
+	}
+
+	#[test]
+	fn requests_need_the_token_and_a_job_needs_a_device() {
+		let server = Arc::new(Server { token: "s3cret".into(), config: Config::default(), jobs: Mutex::default() });
+
+		let without = request("GET /jobs HTTP/1.1\r\nHost: bench\r\n\r\n");
+		assert!(matches!(route(&server, &without), Response::Json(401, _)));
+
+		let wrong = request("GET /jobs HTTP/1.1\r\nAuthorization: Bearer s3creT\r\n\r\n");
+		assert!(matches!(route(&server, &wrong), Response::Json(401, _)));
+
+		let jobs = request("GET /jobs HTTP/1.1\r\nauthorization: Bearer s3cret\r\n\r\n");
+		assert_eq!(route(&server, &jobs), Response::Json(200, "[]".into()));
+
+		let start = request("POST /jobs?source=https%3A%2F%2Fexample.com%2Fmy+image.iso HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n");
+		assert_eq!(start.param("source"), Some("https://example.com/my image.iso"));
+		assert!(matches!(route(&server, &start), Response::Json(400, _)));
+
+		let missing = request("GET /jobs/7/events HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n");
+		assert!(matches!(route(&server, &missing), Response::Json(404, _)));
+	}
+}
@@ -1,237 +1,4189 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use iced::widget::pick_list;
 use iced::font::Weight;
-use iced::widget::Column;
+use iced::keyboard::{self, key::Named, Key, Modifiers};
+use iced::window;
+use iced::widget::{Column, Row};
 use iced::{
-    widget::{button, column, progress_bar, row, text, text_input},
-    Element,Task, Font,
+    widget::{button, checkbox, column, container, horizontal_space, progress_bar, row, slider, text, text_input, tooltip},
+    padding, Alignment, Background, Border, Color, Element, Subscription, Task, Font,
 };
+use iced::futures::{stream, Stream};
 use rfd::FileDialog;
-use sysinfo::{Disk, Disks};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
+mod boot_tool;
+mod browse_tool;
+mod compare_tool;
+mod format_tool;
+mod hash_tool;
+mod iso_tool;
+mod kiosk;
+mod partition_tool;
+mod status;
+
+use self::boot_tool::BootTool;
+use self::browse_tool::BrowseTool;
+use self::compare_tool::CompareTool;
+use self::format_tool::FormatTool;
+use self::hash_tool::HashTool;
+use self::iso_tool::IsoTool;
+use self::kiosk::Kiosk;
+use self::partition_tool::PartitionTool;
+use crate::audit;
+use crate::bmap::Bmap;
+use crate::busy;
+use crate::config::{self, Config};
+use crate::customize::{Automation, Customizations};
+use crate::devices::{self, Device, DeviceEnumerator, Partition};
+use crate::elevate;
+use crate::eta::Estimate;
+use crate::filecopy;
+use crate::hash::{self, Algorithm};
+use crate::hybrid::{self, Layout};
+use crate::instance::{self, Request};
+use crate::mime;
+use crate::partition::{self, Filesystem};
+use crate::pi;
+use crate::profile::Profile;
+use crate::provider;
+use crate::qemu::{self, Firmware};
+use crate::recovery::{self, Interrupted, Leftover};
+use crate::secureboot::{self, SecureBoot};
+use crate::sound::{self, Sound};
+use crate::source;
+use crate::taskbar;
+use crate::template::{Target, Template};
+use crate::udev;
+use crate::uefi;
+use crate::units::{self, Units};
+use crate::wim::{self, WimImage};
+use crate::writer::{self, copy_with_progress, Phase};
+
+const PALETTE_INPUT: &str = "command-palette";
+
+// Label of the exFAT partition made in the space an image leaves free
+const DATA_LABEL: &str = "DATA";
+
+// Shown next to a device picked because it's the only one plugged in
+const AUTO_SELECTED: &str = "auto-selected";
+
+// Note on a device that was unplugged during its write and is back
+const RECONNECTED: &str = "plugged back in, the write can be resumed";
+
+// Shown next to the only device a template's target matches
+const TEMPLATE_MATCH: &str = "the one the template is for";
+
+// Note on the stick a duplicating job went on to
+const NEXT_COPY: &str = "swapped in, writing the next copy";
+
 #[derive(Debug, Clone)]
 pub enum Message {
 	SourceChanged,
+	/// An image handed over from outside, e.g. on the command line
+	OpenImage(String),
+	/// The images the providers have, by URL, see [`provider`], and why any couldn't be listed
+	ProviderImagesLoaded(Vec<String>, Vec<String>),
+	ProviderImagePicked(String),
+	Instance(Request),
+	Recover(PathBuf, Recovery),
+	ConfirmCleanUp,
+	BackFromCleanUp,
+	CleanedUp(PathBuf, Result<(), String>),
+	ModeChanged(Mode),
+	WimImagesLoaded(usize, Result<Vec<WimImage>, String>),
+	WimImageChanged(WimImage),
+	SourceInspected(usize, Option<Layout>, Option<SecureBoot>, Option<Result<Bmap, String>>),
+	SourceFingerprinted(usize, Option<String>),
+	ProfileSelected(String),
+	ProfileNameChanged(String),
+	SaveProfile,
+	RunProfile,
+	DeleteProfile,
+	/// Pick a job template file and set the job up from it
+	LoadTemplate,
+	HybridizeToggled(bool),
+	PickAnswerFile,
+	ClearAnswerFile,
+	LabelChanged(String),
+	DataPartitionToggled(bool),
+	ExpandToggled(bool),
+	/// Write each stick swapped in for the written one
+	DuplicateToggled(bool),
+	DataLabelChanged(String),
+	PiToggled(bool),
+	PiHostnameChanged(String),
+	PiSshToggled(bool),
+	PiUserChanged(String),
+	PiPasswordChanged(String),
+	PiSsidChanged(String),
+	PiWifiPasswordChanged(String),
+	PiCountryChanged(String),
+	PickFirstBoot,
+	ClearFirstBoot,
+	AddPayloadFiles,
+	AddPayloadFolder,
+	RemovePayload(usize),
+	PickAutomation,
+	AutomationKindChanged(Automation),
+	ClearAutomation,
 	DestChanged(String),
+	/// Write the image to the whole disk or one of its partitions
+	WriteToChanged(WriteTo),
+	PartitionConfirmed(bool),
+	DestInputChanged(String),
+	UseDest,
+	FixedDiskConfirmed(bool),
+	JobLabelChanged(String),
+	SourceInputChanged(String),
+	UseSource,
+	ChecksumChanged(String),
+	AlgorithmChanged(Algorithm),
+	VerifySource,
+	HashProgress(usize, u64),
+	SourceHashed(usize, Result<String, String>),
+	RefreshDevices,
 	StartCopy,
-	CopyProgress(u64),
-	CopyComplete(Result<(), String>),
+	StartVerify,
+	ConfirmCopy,
+	DryRun,
+	BackFromConfirm,
+	RetryAsRoot,
+	/// Try again once whatever kept the device busy was closed
+	RetryBusy,
+	/// Carry on with a write that was cut off by the device being unplugged
+	Resume,
+	CheckStalled,
+	/// Stop waiting on a write the device doesn't answer
+	AbortStalled,
+	/// A job's progress, for the run it was started as
+	CopyProgress(usize, u64, Phase, u64, u64),
+	CopyComplete(usize, u64, Result<(), String>),
+	DryRunComplete(usize, u64, Result<Vec<String>, String>),
+	PartitionsRead(usize, Result<Vec<partition::Entry>, String>),
+	UefiChecked(usize, Result<uefi::Boot, String>),
+	/// Boot the device or image at this path in QEMU
+	TestBoot(String, Firmware),
+	TestBootEnded(usize, Result<(), String>),
 	Cancel,
+	NewJob,
+	CloseJob(usize),
+	SelectJob(usize),
+	OpenTool(Tool),
+	HashTool(hash_tool::Message),
+	CompareTool(compare_tool::Message),
+	BootTool(boot_tool::Message),
+	PartitionTool(partition_tool::Message),
+	FormatTool(format_tool::Message),
+	Kiosk(kiosk::Message),
+	IsoTool(iso_tool::Message),
+	BrowseTool(browse_tool::Message),
+	ToggleSettings,
+	ThemeChanged(String),
+	AccentChanged(String),
+	BackgroundChanged(String),
+	DangerChanged(String),
+	ScaleChanged(f64),
+	UnitsChanged(Units),
+	ColorBlindToggled(bool),
+	SoundToggled(bool),
+	SkipUnchangedToggled(bool),
+	DeltaToggled(bool),
+	VerifyToggled(bool),
+	InstallUdevRule,
+	RemoveUdevRule,
+	UdevRuleChanged(Result<(), String>),
+	RegisterFileTypes,
+	UnregisterFileTypes,
+	FileTypesChanged(Result<(), String>),
+	VerifyAlgorithmChanged(Algorithm),
+	MmapToggled(bool),
+	CheckpointChanged(String),
+	StallSecsChanged(String),
+	Escape,
+	TogglePalette,
+	PaletteQueryChanged(String),
+	PaletteSubmit,
+	PaletteCommand(Command),
 }
 
-pub struct IsoMaker {
+/// Actions that can be run from the command palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+	PickSource,
+	RefreshDevices,
+	StartCopy,
+	VerifyOnly,
+	Cancel,
+	NewJob,
+	CloseJob,
+	Settings,
+	HashFile,
+	Compare,
+	BootParams,
+	Partitions,
+	Format,
+	CreateIso,
+	Browse,
+}
+
+impl Command {
+	const ALL: [Command; 15] = [
+		Command::PickSource,
+		Command::RefreshDevices,
+		Command::StartCopy,
+		Command::VerifyOnly,
+		Command::Cancel,
+		Command::NewJob,
+		Command::CloseJob,
+		Command::Settings,
+		Command::HashFile,
+		Command::Compare,
+		Command::BootParams,
+		Command::Partitions,
+		Command::Format,
+		Command::CreateIso,
+		Command::Browse,
+	];
+
+	fn label(self) -> &'static str {
+		match self {
+			Command::PickSource => "Pick source image",
+			Command::RefreshDevices => "Refresh devices",
+			Command::StartCopy => "Start writing",
+			Command::VerifyOnly => "Verify a device against the image without writing",
+			Command::Cancel => "Cancel writing",
+			Command::NewJob => "New job tab",
+			Command::CloseJob => "Close job tab",
+			Command::Settings => "Toggle settings",
+			Command::HashFile => "Hash a file",
+			Command::Compare => "Compare images or devices",
+			Command::BootParams => "Edit boot parameters",
+			Command::Partitions => "Edit the partitions on a device",
+			Command::Format => "Format a device or partition",
+			Command::CreateIso => "Create an ISO from a folder",
+			Command::Browse => "Browse the files in an ISO",
+		}
+	}
+
+	fn shortcut(self) -> &'static str {
+		match self {
+			Command::PickSource => "Ctrl+O",
+			Command::RefreshDevices => "Ctrl+R",
+			Command::StartCopy => "Ctrl+Enter",
+			Command::Cancel => "Esc",
+			Command::NewJob => "Ctrl+T",
+			Command::CloseJob => "Ctrl+W",
+			Command::Settings => "Ctrl+,",
+			Command::VerifyOnly | Command::HashFile | Command::Compare | Command::BootParams | Command::Partitions | Command::Format | Command::CreateIso | Command::Browse => "",
+		}
+	}
+
+	fn message(self) -> Message {
+		match self {
+			Command::PickSource => Message::SourceChanged,
+			Command::RefreshDevices => Message::RefreshDevices,
+			Command::StartCopy => Message::StartCopy,
+			Command::VerifyOnly => Message::StartVerify,
+			Command::Cancel => Message::Cancel,
+			Command::NewJob => Message::NewJob,
+			// Needs the active job, filled in by update
+			Command::CloseJob => Message::CloseJob(0),
+			Command::Settings => Message::ToggleSettings,
+			Command::HashFile => Message::OpenTool(Tool::Hash),
+			Command::Compare => Message::OpenTool(Tool::Compare),
+			Command::BootParams => Message::OpenTool(Tool::BootParams),
+			Command::Partitions => Message::OpenTool(Tool::Partitions),
+			Command::Format => Message::OpenTool(Tool::Format),
+			Command::CreateIso => Message::OpenTool(Tool::CreateIso),
+			Command::Browse => Message::OpenTool(Tool::Browse),
+		}
+	}
+
+	fn matches(self, query: &str) -> bool {
+		self.label().to_lowercase().contains(&query.trim().to_lowercase())
+	}
+}
+
+/// What to do about a job the last run didn't get to finish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+	/// Carry on from where it was last synced
+	Resume,
+	/// Compare the device with the image
+	Verify,
+	/// Set the job up again to start from scratch
+	Rewrite,
+	/// Wipe the half written partition table
+	CleanUp,
+	Dismiss,
+}
+
+/// Utilities that get their own tab next to the jobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+	Hash,
+	Compare,
+	BootParams,
+	Partitions,
+	Format,
+	CreateIso,
+	Browse,
+}
+
+/// What a job does with its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Mode {
+	/// Write the image onto the device as it is
+	#[default]
+	Image,
+	/// Format the device and copy the files in the ISO onto it, for Windows installers
+	FileCopy,
+	/// Partition the device and apply a Windows image to it
+	WindowsToGo,
+}
+
+impl Mode {
+	const ALL: [Mode; 3] = [Mode::Image, Mode::FileCopy, Mode::WindowsToGo];
+}
+
+impl std::fmt::Display for Mode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Mode::Image => "Write image",
+			Mode::FileCopy => "Copy files",
+			Mode::WindowsToGo => "Windows To Go",
+		})
+	}
+}
+
+/// Where on the device an image goes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteTo {
+	Disk,
+	/// Just the one partition, the rest of the disk and its table are left alone
+	Partition(Partition),
+}
+
+impl std::fmt::Display for WriteTo {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			WriteTo::Disk => f.write_str("The whole disk"),
+			WriteTo::Partition(partition) => partition.fmt(f),
+		}
+	}
+}
+
+/// Where swapping the stick a duplicating job wrote for the next one has got.
+#[derive(Debug, Clone, PartialEq)]
+enum Swap {
+	/// Waiting for the written stick, by path and serial, to be taken out
+	Out(String, Option<String>),
+	/// Waiting for a stick that isn't one of these
+	In(Vec<(String, Option<String>)>),
+}
+
+/// A single write job, each tab in the window owns one.
+pub struct Job {
+	id: usize,
+	/// Free text the job is known by, a ticket number or who's running it
+	job_label: String,
 	source: String,
 	dest: String,
+	mode: Mode,
+	/// Editions in the source, for Windows To Go
+	wim_images: Vec<WimImage>,
+	wim_image: Option<WimImage>,
+	/// autounattend.xml put on the stick in file-copy mode
+	answer_file: Option<String>,
+	/// Preseed, kickstart or cloud-init file for Linux installers in file-copy mode
+	automation: Option<(Automation, String)>,
+	/// Files and folders copied onto the stick once it's written
+	payload: Vec<String>,
+	/// Label for the partition file-copy mode makes, empty keeps the ISO's
+	label: String,
+	/// Turn the space a hybrid ISO leaves unused into an exFAT partition
+	data_partition: bool,
+	/// Empty uses the default
+	data_label: String,
+	/// Grow the image's last partition to the end of the device instead
+	expand: bool,
+	/// Set up Raspberry Pi OS on its boot partition once it's written
+	customize_pi: bool,
+	pi: pi::Settings,
+	/// Script run once on the written image's first boot
+	first_boot: Option<String>,
+	/// Whether the source has a partition table, `None` when it isn't a local ISO
+	layout: Option<Layout>,
+	/// Give a plain ISO the partition table it needs to boot from USB
+	hybridize: bool,
+	/// How the source's UEFI loader fares with Secure Boot, `None` without one
+	secure_boot: Option<SecureBoot>,
+	/// Block map found next to the source, only its mapped blocks get written
+	bmap: Option<Bmap>,
 	progress: f32,
 	total: u64,
 	is_copying: bool,
+	/// Cancelled, but the write hasn't returned yet and may still have the device open
+	stopping: bool,
+	/// Counts the runs, so what an earlier one reports late isn't taken for the current one's
+	run: u64,
+	/// The device's partitions once the write is done
+	partitions: Option<Result<Vec<partition::Entry>, String>>,
+	/// The boot files UEFI firmware would find on it, once they've been looked at
+	uefi: Option<Result<uefi::Boot, String>>,
+	/// Whether a QEMU window is open
+	test_booting: bool,
+	/// Why the last test boot failed
+	test_boot_error: Option<String>,
+	phase: Phase,
+	/// Waiting for the user to confirm the destination will be erased
+	confirming: bool,
+	/// Why `dest` was picked without the user picking it
+	dest_note: Option<&'static str>,
+	/// Device path typed in by hand, for devices the list doesn't show
+	dest_input: String,
+	/// The device `dest_input` was found to be, once it's used
+	manual_dest: Option<Device>,
+	/// The one partition of the device the image goes to, `None` for the whole disk
+	partition: Option<Partition>,
+	/// The extra confirmation writing to a single partition asks for
+	partition_confirmed: bool,
+	/// Acknowledged that the manual destination isn't removable
+	fixed_confirmed: bool,
+	/// Write as root through pkexec, the device couldn't be opened as the user
+	elevated: bool,
+	/// The last run only compared the device with the image
+	verify_only: bool,
+	/// Path or URL being typed or pasted in, becomes the source once it's used
+	source_input: String,
+	/// Checksum published for the source, to verify it against
+	checksum: String,
+	algorithm: Algorithm,
+	hashing: bool,
+	hash_progress: f32,
+	/// Digest of the source once it has been hashed
+	source_hash: Option<String>,
+	/// Quick fingerprint of the source, to remember which device it was written to
+	fingerprint: Option<String>,
+	/// Verification a profile asked for, `None` goes by the settings
+	verify: Option<Option<Algorithm>>,
+	/// The devices the template the job was loaded from is for
+	target: Option<Target>,
 	error: Option<String>,
 	cancel_tx: Option<mpsc::Sender<()>>,
+	/// How much of the device the write has synced, kept after it stops
+	synced: Arc<AtomicU64>,
+	/// The device already had the image, so the write left it as it was
+	unchanged: Arc<AtomicBool>,
+	/// How much a delta write found different, `None` when the write wasn't one
+	rewritten: Option<Arc<AtomicU64>>,
+	/// What the image has unpacked to, and how much of its file has been read out of how much
+	unpacked: Arc<AtomicU64>,
+	read: (u64, u64),
+	/// What the kernel still has to write out while syncing
+	unsynced: u64,
+	/// Serial of the device being written, to know it again should it be unplugged
+	dest_serial: Option<String>,
+	/// The serial of the device a write was cut off on and how much of it was synced, to
+	/// carry on once it's plugged back in
+	resume: Option<(String, u64)>,
+	/// Where the next write starts, set when resuming
+	resume_from: u64,
+	/// When the running write last made progress
+	last_progress: Instant,
+	/// Nothing happened for longer than the settings allow, the device may hang
+	stalled: bool,
+	/// Speed and time left of the running phase
+	estimate: Estimate,
+	/// What the last dry run found the write would do
+	dry_run: Option<Vec<String>>,
+	/// The running job's line in the audit log, finished once it ends
+	audit: Option<audit::Entry>,
+	/// The running job as it's kept on disk, in case the app doesn't get to see it end
+	record: Option<recovery::Record>,
+	/// Write each stick swapped in for the last one, for duplicating
+	duplicate: bool,
+	/// Sticks written while duplicating
+	copies: u32,
+	swap: Option<Swap>,
 }
 
-impl Default for IsoMaker {
-	fn default() -> Self {
+impl Job {
+	fn new(id: usize) -> Self {
 		Self {
+			id,
+			job_label: String::new(),
 			source: String::new(),
 			dest: String::new(),
+			mode: Mode::Image,
+			wim_images: Vec::new(),
+			wim_image: None,
+			answer_file: None,
+			automation: None,
+			payload: Vec::new(),
+			label: String::new(),
+			data_partition: false,
+			data_label: String::new(),
+			expand: false,
+			customize_pi: false,
+			pi: pi::Settings::default(),
+			first_boot: None,
+			layout: None,
+			hybridize: true,
+			secure_boot: None,
+			bmap: None,
 			progress: 0.,
 			total: 0,
 			is_copying: false,
+			stopping: false,
+			run: 0,
+			partitions: None,
+			uefi: None,
+			test_booting: false,
+			test_boot_error: None,
+			phase: Phase::Writing,
+			confirming: false,
+			dest_note: None,
+			dest_input: String::new(),
+			manual_dest: None,
+			partition: None,
+			partition_confirmed: false,
+			elevated: false,
+			verify_only: false,
+			fixed_confirmed: false,
+			source_input: String::new(),
+			checksum: String::new(),
+			algorithm: Algorithm::default(),
+			hashing: false,
+			hash_progress: 0.,
+			source_hash: None,
+			fingerprint: None,
+			verify: None,
+			target: None,
 			error: None,
 			cancel_tx: None,
+			synced: Arc::default(),
+			unchanged: Arc::default(),
+			rewritten: None,
+			unpacked: Arc::default(),
+			read: (0, 0),
+			unsynced: 0,
+			dest_serial: None,
+			resume: None,
+			resume_from: 0,
+			last_progress: Instant::now(),
+			stalled: false,
+			estimate: Estimate::default(),
+			dry_run: None,
+			audit: None,
+			record: None,
+			duplicate: false,
+			copies: 0,
+			swap: None,
 		}
 	}
-}
 
-pub fn update(iso_maker: &mut IsoMaker, message: Message) -> Task<Message> {
-	match message {
-		Message::SourceChanged => {
-			let user_home = match dirs::home_dir() {
-				Some(home) => home,
-				None => dirs::document_dir()
-				.unwrap_or_else(|| dirs::public_dir()
-					.unwrap()
-				),
-			};
+	fn label(&self) -> String {
+		let name = match self.job_label.trim() {
+			"" => std::path::Path::new(&self.source)
+				.file_name()
+				.map(|name| name.to_string_lossy().to_string())
+				.unwrap_or_else(|| format!("Job {}", self.id + 1)),
+			label => label.to_string(),
+		};
+
+		if self.is_copying {
+			format!("{name} ({})", units::percent(self.progress, 0))
+		} else {
+			name
+		}
+	}
+
+	fn data_label(&self) -> &str {
+		if self.data_label.is_empty() { DATA_LABEL } else { &self.data_label }
+	}
 
-			let file = FileDialog::new()
-				.set_directory(user_home.to_string_lossy().to_string())
-				.pick_file();
+	// Typed in by hand and not a removable device, it could be an internal disk
+	fn needs_fixed_confirmation(&self) -> bool {
+		self.manual_dest.as_ref().is_some_and(|device| device.path == self.dest && !device.removable)
+	}
 
-			match file {
-				Some(f) => iso_maker.source = f.to_string_lossy().to_string(),
-				None => if iso_maker.source.is_empty() {
-					iso_maker.error = Some("Source file picking was cancelled.".to_string());
-				},
-			}
+	// The partition picked, as long as it's still on the device picked
+	fn partition(&self) -> Option<&Partition> {
+		self.partition.as_ref()
+			.filter(|partition| self.mode == Mode::Image && partition::is_partition_of(&partition.path, &self.dest))
+	}
+
+	// What the image is written to and compared with, the partition if one was picked
+	fn write_path(&self) -> String {
+		self.partition().map_or_else(|| self.dest.clone(), |partition| partition.path.clone())
+	}
+
+	// Only raw written images have a boot partition to customize
+	fn pi_settings(&self) -> Option<&pi::Settings> {
+		(self.mode == Mode::Image && self.customize_pi && !self.pi.is_empty()).then_some(&self.pi)
+	}
+
+	// What's done to the device after an image write
+	fn customizations(&self) -> Customizations {
+		Customizations {
+			payload: self.payload.iter().map(Into::into).collect(),
+			data_partition: self.data_partition.then(|| self.data_label().to_string()),
+			expand: self.mode == Mode::Image && self.expand,
+			pi: self.pi_settings().cloned(),
+			first_boot: self.first_boot.as_ref().filter(|_| self.mode == Mode::Image).map(Into::into),
+			..Customizations::default()
 		}
-		Message::DestChanged(device) => {
-			println!("Chosen device: {device}");
-			iso_maker.dest = device.clone();
+	}
 
-			let disk_info = nusb::list_devices().unwrap()
-				.find(|device| device.product_string().unwrap().to_string() == device.clone());
+	/// The job's setup as a profile called `name`, verifying with `verify` unless the job has its own.
+	fn profile(&self, name: String, verify: Option<Algorithm>) -> Profile {
+		Profile {
+			name,
+			source: self.source.clone(),
+			mode: self.mode,
+			verify: self.verify.unwrap_or(verify),
+			checksum: self.checksum.clone(),
+			algorithm: self.algorithm,
+			hybridize: self.hybridize,
+			label: self.label.clone(),
+			answer_file: self.answer_file.clone(),
+			automation: self.automation.clone(),
+			payload: self.payload.clone(),
+			data_partition: self.data_partition.then(|| self.data_label().to_string()),
+			expand: self.expand,
+			first_boot: self.first_boot.clone(),
+		}
+	}
 
-			let iface = match disk_info.detach_and_claim_interface() {
-				Ok(iface) => iface,
-				Err(e) => {
-					iso_maker.error = "Could not get access to the USB device.";
-					return Task::none();
-				}
-			}
-		},
-		Message::StartCopy => {
-			if iso_maker.source.is_empty() || iso_maker.dest.is_empty() {
-				iso_maker.error = Some("Source and Destination are both required".into());
-				return Task::none();
-			}
+	fn load_profile(&mut self, profile: &Profile) -> Task<Message> {
+		self.verify = Some(profile.verify);
+		self.checksum = profile.checksum.clone();
+		self.algorithm = profile.algorithm;
+		self.hybridize = profile.hybridize;
+		self.label = profile.label.clone();
+		self.answer_file = profile.answer_file.clone();
+		self.automation = profile.automation.clone();
+		self.payload = profile.payload.clone();
+		self.data_partition = profile.data_partition.is_some();
+		self.data_label = profile.data_partition.clone().unwrap_or_default();
+		self.expand = profile.expand;
+		self.first_boot = profile.first_boot.clone();
+		self.target = None;
 
-			iso_maker.is_copying = true;
-			iso_maker.error = None;
+		let task = self.set_source(profile.source.clone());
 
-			let (cancel_tx, cancel_rx) = mpsc::channel(1);
-			let (progress_tx, mut progress_rx) = mpsc::channel(100);
-			iso_maker.cancel_tx = Some(cancel_tx);
+		// After set_source, which guesses the mode from the file
+		self.mode = profile.mode;
+		task
+	}
 
-			return Task::batch(vec![
-				Task::perform({
-					copy_with_progress(iso_maker.source.clone(), iso_maker.dest.clone(), cancel_rx, progress_tx)
-				}, Message::CopyComplete),
-				Task::perform(
-					async move {
-						let mut last_progress = 0;
-						while let Some(bytes) = progress_rx.recv().await {
-							last_progress = bytes;
-						}
-						last_progress
-					}, Message::CopyProgress),
-			])
-		},
-		Message::CopyProgress(bytes) => iso_maker.progress = bytes as f32 / iso_maker.total as f32,
-		Message::CopyComplete(result) => {
-			iso_maker.is_copying = false;
-			match result {
-				Ok(_) => iso_maker.progress = 1.,
-				Err(e) => iso_maker.error = Some(e),
-			}
-		},
-		Message::Cancel => {
-			if let Some(tx) = iso_maker.cancel_tx.take() {
-				let _ = tx.blocking_send(());
-			}
+	// URLs are streamed when the write starts, local files get looked at now
+	fn set_source(&mut self, source: String) -> Task<Message> {
+		self.source_input = source.clone();
+		self.source = source;
+		self.source_hash = None;
+		self.fingerprint = None;
+		self.error = None;
 
-			iso_maker.is_copying = false;
+		if wim::is_wim(&self.source) {
+			self.mode = Mode::WindowsToGo;
 		}
+
+		let (id, source) = (self.id, self.source.clone());
+		let fingerprint = Task::perform(async move {
+			tokio::task::spawn_blocking(move || hash::fingerprint(&source)).await.ok().flatten()
+		}, move |fingerprint| Message::SourceFingerprinted(id, fingerprint));
+
+		Task::batch(vec![self.load_wim_images(), self.inspect_source(), fingerprint])
 	}
 
-	Task::none()
-}
+	// Windows To Go needs to know which edition to apply before it can start
+	fn load_wim_images(&mut self) -> Task<Message> {
+		self.wim_images.clear();
+		self.wim_image = None;
 
-pub fn view(iso_maker: &IsoMaker) -> Element<Message> {
-	// Get the USB disk names
-	let disks: Vec<String> = nusb::list_devices().unwrap()
-		// Get Removeable Storage Devices, which require a product string
-		.filter(|device| device.class() == 0 && device.product_string() != None)
-		// Double check that this is actually a USB Mass Storage Device
-		.filter(|device| {
-			match device.interfaces().next() {
-				Some(interface) => interface.interface_string() == None,
-				None => false,
-			}
-		})
-		// For some reason fingerprint readers get through, so filter them out as well
-		.filter(|device| !device.product_string().unwrap().to_string().contains("Fingerprint"))
-		// Map the USB device name as a string and put it into the Vec
-		.map(|device| device.product_string().unwrap().to_string())
-		.collect();
+		if self.mode != Mode::WindowsToGo || !wim::is_wim(&self.source) {
+			return Task::none();
+		}
 
-	
-	let controls = column![
-		text("ISO Maker")
-			.size(24)
-			.font(Font {
-				weight: Weight::Bold,
-				..Font::DEFAULT
-			}),
-		
-		row![
-			button("Pick Source")
-				.on_press(Message::SourceChanged)
-				.padding([8, 16]),
-				pick_list(disks, Some(iso_maker.dest.clone()), Message::DestChanged)	
-		].spacing(20),
+		let id = self.id;
+		Task::perform(wim::images(self.source.clone()), move |result| Message::WimImagesLoaded(id, result))
+	}
 
-		row![
-			button("Start")
-				.on_press(Message::StartCopy)
-				.padding([8, 16]),
+	// Partition table to write in place of a plain ISO's empty first sector
+	fn mbr(&self) -> Option<[u8; 512]> {
+		// A new first sector wouldn't match the bmap's checksums, and a partition has no table
+		let (Some(Layout::Plain(Some(efi))), None, None) = (self.layout, &self.bmap, self.partition()) else {
+			return None;
+		};
 
-			button("Cancel")
-				.on_press(Message::Cancel)
-				.padding([8, 16]),
-		].spacing(20),
+		let len = std::fs::metadata(&self.source).ok()?.len();
+		self.hybridize.then(|| hybrid::mbr(len, efi))
+	}
 
-		progress_bar(0.0..=1.0, iso_maker.progress)
-			.height(20),
+	// What the ISO needs to boot from USB, whether it will with Secure Boot on, and any bmap next to it
+	fn inspect_source(&mut self) -> Task<Message> {
+		self.layout = None;
+		self.secure_boot = None;
+		self.bmap = None;
 
-		if let Some(err) = &iso_maker.error {
-			text(err).color([0.8, 0.2, 0.2])
-		} else {
-			text(match (iso_maker.is_copying, iso_maker.progress) {
-				(true, _) => format!("Copying: {:.1}%", iso_maker.progress * 100.0),
-				(false, 1.0) => "Complete!".into(),
-				_ => "Ready".into(),
+		if source::is_url(&self.source) {
+			return Task::none();
+		}
+
+		let (id, source) = (self.id, self.source.clone());
+		Task::perform(async move {
+			tokio::task::spawn_blocking(move || {
+				let bmap = Bmap::find(&source).map(|path| Bmap::load(&path));
+				(hybrid::layout(&source), secureboot::check(&source), bmap)
 			})
+			.await
+			.unwrap_or_default()
+		}, move |(layout, secure_boot, bmap)| Message::SourceInspected(id, layout, secure_boot, bmap))
+	}
+
+	// A new run, or one that got further, has nothing to wait for yet
+	fn start_watchdog(&mut self) {
+		self.last_progress = Instant::now();
+		self.stalled = false;
+	}
+
+	// Running, or cancelled and not done with the device yet
+	fn busy(&self) -> bool {
+		self.is_copying || self.stopping
+	}
+
+	// A new run, whatever the last one still sends is ignored
+	fn next_run(&mut self) -> u64 {
+		self.run += 1;
+		self.run
+	}
+
+	fn cancel(&mut self) {
+		if let Some(tx) = self.cancel_tx.take() {
+			let _ = tx.try_send(());
 		}
-	].spacing(20).padding(20);
 
-	controls.into()
+		// It's busy until CopyComplete says the write let go of the device
+		self.stopping |= self.is_copying;
+		self.is_copying = false;
+		self.hashing = false;
+	}
 }
 
-pub fn theme(_iso_maker: &IsoMaker) -> iced::Theme {
-	iced::Theme::TokyoNight
+pub struct IsoMaker {
+	jobs: Vec<Job>,
+	active: usize,
+	next_id: usize,
+	/// Tool shown instead of the active job
+	tool: Option<Tool>,
+	hash_tool: HashTool,
+	compare_tool: CompareTool,
+	boot_tool: BootTool,
+	partition_tool: PartitionTool,
+	format_tool: FormatTool,
+	iso_tool: IsoTool,
+	browse_tool: BrowseTool,
+	/// Takes over the window in kiosk mode, see [`kiosk`]
+	kiosk: Option<Kiosk>,
+	/// Where `devices` comes from
+	enumerator: Box<dyn DeviceEnumerator>,
+	devices: Vec<Device>,
+	config: Config,
+	settings_open: bool,
+	settings_error: Option<String>,
+	/// Whether the udev rule for writing without root is in place
+	udev_rule: bool,
+	/// Whether iso_maker opens disk images from the file manager
+	file_types: bool,
+	/// Where running jobs are kept, see [`recovery`]
+	recovery: recovery::Store,
+	/// Jobs the last run didn't get to finish
+	interrupted: Vec<Leftover>,
+	recovery_error: Option<String>,
+	/// The job whose device is waiting for the user to confirm it will be wiped
+	cleaning_up: Option<PathBuf>,
+	/// Name to save the active job as a profile under, or of the profile picked
+	profile_name: String,
+	/// URLs of the images the providers set up have
+	provider_images: Vec<String>,
+	/// Why providers couldn't list their images, e.g. a catalog that isn't signed
+	provider_errors: Vec<String>,
+	palette_open: bool,
+	palette_query: String,
 }
 
-async fn copy_with_progress(
-	source: String,
-	dest: String,
-	mut cancel_rx: mpsc::Receiver<()>,
-	progress_tx: mpsc::Sender<u64>,
-) -> Result<(), String>
-{
-	use tokio::fs::File;
-	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+impl Default for IsoMaker {
+	fn default() -> Self {
+		let mut iso_maker = Self::with_devices(Box::new(devices::System));
+		iso_maker.recovery = recovery::Store::default();
+		iso_maker.interrupted = iso_maker.recovery.leftover();
+
+		iso_maker
+	}
+}
+
+/// The window's starting state, with `image` already picked when it was given, or written
+/// to every stick plugged in with `kiosk`.
+pub fn start(image: Option<String>, kiosk: bool) -> (IsoMaker, Task<Message>) {
+	let mut iso_maker = IsoMaker::default();
+
+	if kiosk && let Some(image) = image {
+		iso_maker.kiosk = Some(Kiosk::new(image, &iso_maker.devices));
+
+		let fullscreen = window::get_latest().and_then(|id| window::change_mode(id, window::Mode::Fullscreen));
+		return (iso_maker, fullscreen);
+	}
+
+	let task = image.map_or_else(Task::none, |image| Task::done(Message::OpenImage(image)));
+
+	(iso_maker, Task::batch([task, provider_images()]))
+}
+
+// Providers may have to go to the network to list theirs
+fn provider_images() -> Task<Message> {
+	Task::perform(async {
+		let (images, errors) = tokio::task::spawn_blocking(provider::catalog)
+			.await
+			.unwrap_or_else(|e| (Vec::new(), vec![format!("Provider error: {e}")]));
+
+		(images.into_iter().map(|(url, _)| url).collect(), errors)
+	}, |(images, errors)| Message::ProviderImagesLoaded(images, errors))
+}
+
+impl IsoMaker {
+	/// Starts out with the devices `enumerator` finds, rather than the attached ones.
+	fn with_devices(enumerator: Box<dyn DeviceEnumerator>) -> Self {
+		// A broken config is shown, rather than quietly replaced with the defaults
+		let (config, config_error) = Config::try_load();
+
+		Self {
+			jobs: vec![Job::new(0)],
+			active: 0,
+			next_id: 1,
+			tool: None,
+			hash_tool: HashTool::default(),
+			compare_tool: CompareTool::default(),
+			boot_tool: BootTool::default(),
+			partition_tool: PartitionTool::default(),
+			format_tool: FormatTool::default(),
+			iso_tool: IsoTool::default(),
+			browse_tool: BrowseTool::default(),
+			kiosk: None,
+			devices: enumerator.list(),
+			enumerator,
+			config,
+			settings_open: config_error.is_some(),
+			settings_error: config_error,
+			udev_rule: udev::installed(),
+			file_types: mime::registered(),
+			recovery: recovery::Store::at(None),
+			interrupted: Vec::new(),
+			recovery_error: None,
+			cleaning_up: None,
+			palette_open: false,
+			palette_query: String::new(),
+			profile_name: String::new(),
+			provider_images: Vec::new(),
+			provider_errors: Vec::new(),
+		}
+	}
+
+	// The active job, or a new one when it's under way, with nothing in front of it
+	fn idle_job(&mut self) -> &mut Job {
+		let job = self.active_job();
+		if job.busy() || job.hashing || job.confirming {
+			self.new_job();
+		}
+
+		self.tool = None;
+		self.settings_open = false;
+		self.palette_open = false;
+
+		self.active_job_mut()
+	}
+
+	// Sets a job up from `template`, on the device it's for when there's just the one
+	fn load_template(&mut self, template: Template) -> Task<Message> {
+		let job = self.idle_job();
+		let task = job.load_profile(&template.profile);
+
+		job.verify = template.verify;
+		job.target = Some(template.target);
+		if !template.label.is_empty() {
+			job.job_label = template.label;
+		}
+
+		// It's for other devices than the one picked, if any
+		job.dest.clear();
+		job.dest_note = None;
+		job.manual_dest = None;
+		job.partition = None;
+		self.select_remembered();
+
+		task
+	}
+
+	// Sets a job up again the way `interrupted` had it, writing to `dest`
+	fn recover(&mut self, interrupted: &Interrupted, dest: String) -> Task<Message> {
+		let job = self.idle_job();
+		let task = job.set_source(interrupted.source.clone());
+
+		job.mode = interrupted.mode;
+		job.dest = dest;
+		job.dest_note = None;
+		job.manual_dest = None;
+		job.resume = interrupted.serial.clone()
+			.filter(|_| interrupted.resumable())
+			.map(|serial| (serial, interrupted.synced));
+
+		task
+	}
+
+	// The stick a cut off job was writing, to be wiped. Only ever found by its serial, as after a
+	// reboot its path may well be another device's, and only when it's removable and not in use
+	fn cleanup_target(&self, leftover: &Leftover) -> Result<&Device, String> {
+		let Some(serial) = &leftover.job.serial else {
+			return Err(format!("{} had no serial to be found again by, wipe it from the Format tab instead", leftover.job.dest));
+		};
+
+		let device = self.devices.iter()
+			.find(|device| device.serial.as_ref() == Some(serial))
+			.ok_or_else(|| format!("Plug in the device that was {} first", leftover.job.dest))?;
+
+		if !device.removable {
+			return Err(format!("{} isn't a removable device and won't be wiped", device.path));
+		}
+
+		if device.holds_system() {
+			return Err(format!("{} holds the running system and can't be wiped", device.path));
+		}
+
+		if self.jobs.iter().any(|job| job.busy() && job.dest == device.path) {
+			return Err(format!("{} is being written by another job", device.path));
+		}
+
+		Ok(device)
+	}
+
+	fn new_job(&mut self) {
+		let id = self.next_id;
+		self.next_id += 1;
+		self.jobs.push(Job::new(id));
+		self.active = id;
+		self.tool = None;
+		self.select_remembered();
+	}
 
-	let mut src = File::open(&source)
-		.await
-		.map_err(|e| format!("Source error: {e}"))?;
+	fn active_job(&self) -> &Job {
+		self.jobs.iter()
+			.find(|job| job.id == self.active)
+			.unwrap_or(&self.jobs[0])
+	}
+
+	fn active_job_mut(&mut self) -> &mut Job {
+		let index = self.jobs.iter()
+			.position(|job| job.id == self.active)
+			.unwrap_or(0);
+
+		&mut self.jobs[index]
+	}
+
+	fn job_mut(&mut self, id: usize) -> Option<&mut Job> {
+		self.jobs.iter_mut().find(|job| job.id == id)
+	}
+
+	// The devices jobs are writing to right now
+	fn writing(&self) -> Vec<String> {
+		self.jobs.iter().filter(|job| job.busy()).map(|job| job.dest.clone()).collect()
+	}
 
-	let total = src.metadata().await.map_err(|e| format!("Metadata error: {e}"))?.len();
-	let mut dest = File::create(&dest)
-		.await
-		.map_err(|e| format!("Dest error: {e}"))?;
+	// Whether another job is writing to the active job's device
+	fn dest_busy(&self) -> bool {
+		let active = self.active_job();
+		self.jobs.iter().any(|job| job.id != active.id && job.busy() && job.dest == active.dest)
+	}
+
+	// Picks the device each image went to last time, or the only stick there is, unless one's been picked already
+	fn select_remembered(&mut self) {
+		let eligible: Vec<&Device> = self.devices.iter().filter(|device| !device.holds_system()).collect();
 
-	let mut buffer = vec![0; 4096 * 1024]; // 4MB buffer
-	let mut copied = 0;
+		for job in self.jobs.iter_mut().filter(|job| !job.busy() && job.manual_dest.is_none() && (job.dest.is_empty() || job.dest_note.is_some())) {
+			// A stick pulled out during its write goes back to the job, under whatever path it has now
+			let reconnected = job.resume.as_ref()
+				.and_then(|(serial, _)| self.devices.iter().find(|device| device.serial.as_ref() == Some(serial)));
 
-	loop {
-		tokio::select! {
-			_ = cancel_rx.recv() => return Err("Cancelled".into()),
-			result = src.read(&mut buffer) => {
-				let n = result.map_err(|e| format!("Read error: {e}"))?;
+			if let Some(device) = reconnected {
+				job.dest = device.path.clone();
+				job.dest_note = Some(RECONNECTED);
+				continue;
+			}
 
-				if n == 0 { break; }
+			// A template says which devices it's for, it's picked among those or not at all
+			if let Some(target) = &job.target {
+				match target.pick(&self.devices) {
+					Ok(device) => {
+						job.dest = device.path.clone();
+						job.dest_note = Some(TEMPLATE_MATCH);
+					},
+					Err(_) => {
+						job.dest.clear();
+						job.dest_note = None;
+					},
+				}
+				continue;
+			}
 
-				dest.write_all(&buffer[..n])
-					.await
-					.map_err(|e| format!("Write error: {e}"))?;
+			let remembered = job.fingerprint.as_deref()
+				.and_then(|fingerprint| self.config.last_device(fingerprint))
+				.and_then(|serial| self.devices.iter().find(|device| device.serial.as_deref() == Some(serial)));
 
-				copied += n as u64;
-				let progress = copied as f32 / total as f32;
-				let _ = progress_tx.send(progress as u64).await;
+			if let Some(device) = remembered {
+				job.dest = device.path.clone();
+				job.dest_note = Some("last used for this image");
+			} else if let [device] = eligible.as_slice() {
+				job.dest = device.path.clone();
+				job.dest_note = Some(AUTO_SELECTED);
+			} else if job.dest_note == Some(AUTO_SELECTED) {
+				// Another stick was plugged in, which one is meant isn't obvious any more
+				job.dest.clear();
+				job.dest_note = None;
 			}
 		}
 	}
 
-	Ok(())
-}
\ No newline at end of file
+	// Starts a duplicating job on the next stick once the one it wrote is swapped for it. Only
+	// the job in front can go on, it's the one the write starts from
+	fn next_copy(&mut self) -> Task<Message> {
+		let now: Vec<(String, Option<String>)> = self.devices.iter()
+			.map(|device| (device.path.clone(), device.serial.clone()))
+			.collect();
+		let eligible: Vec<(String, Option<String>)> = self.devices.iter()
+			.filter(|device| device.removable && !device.holds_system())
+			.map(|device| (device.path.clone(), device.serial.clone()))
+			.collect();
+
+		let job = self.active_job_mut();
+		if !job.duplicate || job.busy() || job.confirming {
+			return Task::none();
+		}
+
+		match job.swap.take() {
+			Some(Swap::Out(path, serial)) if !now.contains(&(path.clone(), serial.clone())) => job.swap = Some(Swap::In(now)),
+			Some(Swap::In(before)) => {
+				let Some((path, _)) = eligible.into_iter().find(|device| !before.contains(device)) else {
+					// Sticks taken out in the meantime aren't in the way of the next
+					job.swap = Some(Swap::In(now));
+					return Task::none();
+				};
+
+				job.dest = path;
+				job.dest_note = Some(NEXT_COPY);
+				job.manual_dest = None;
+				job.partition = None;
+				job.error = None;
+
+				// Duplicating was the go-ahead for every stick swapped in
+				job.confirming = true;
+				return Task::done(Message::ConfirmCopy);
+			},
+			swap => job.swap = swap,
+		}
+
+		Task::none()
+	}
+
+	// Serial of the active job's device, from the list or the device typed in
+	fn dest_serial(&self) -> Option<String> {
+		let job = self.active_job();
+
+		self.devices.iter()
+			.chain(job.manual_dest.as_ref())
+			.find(|device| device.path == job.dest)
+			.and_then(|device| device.serial.clone())
+	}
+
+	fn save_config(&mut self) {
+		self.settings_error = self.config.save().err();
+	}
+
+	// Average progress over every running job, for the taskbar
+	fn update_taskbar(&self) {
+		let running: Vec<f32> = self.jobs.iter()
+			.filter(|job| job.is_copying)
+			.map(|job| job.progress)
+			.collect();
+
+		if running.is_empty() {
+			taskbar::set_progress(None);
+		} else {
+			taskbar::set_progress(Some(running.iter().sum::<f32>() / running.len() as f32));
+		}
+	}
+}
+
+pub fn update(iso_maker: &mut IsoMaker, message: Message) -> Task<Message> {
+	match message {
+		Message::SourceChanged => {
+			let job = iso_maker.active_job_mut();
+			match pick_file() {
+				Some(f) => return job.set_source(f),
+				None => if job.source.is_empty() {
+					job.error = Some("Source file picking was cancelled.".to_string());
+				},
+			}
+		}
+		Message::ModeChanged(mode) => {
+			let job = iso_maker.active_job_mut();
+			job.mode = mode;
+
+			return job.load_wim_images();
+		},
+		Message::WimImagesLoaded(id, result) => {
+			if let Some(job) = iso_maker.job_mut(id) {
+				match result {
+					Ok(images) => {
+						// Most WIMs only have the one edition
+						job.wim_image = (images.len() == 1).then(|| images[0].clone());
+						job.wim_images = images;
+					},
+					Err(e) => job.error = Some(e),
+				}
+			}
+		},
+		Message::WimImageChanged(image) => iso_maker.active_job_mut().wim_image = Some(image),
+		Message::PickAnswerFile => {
+			if let Some(path) = pick_file() {
+				iso_maker.active_job_mut().answer_file = Some(path);
+			}
+		},
+		Message::ClearAnswerFile => iso_maker.active_job_mut().answer_file = None,
+		Message::LabelChanged(label) => iso_maker.active_job_mut().label = label,
+		Message::SourceFingerprinted(id, fingerprint) => {
+			if let Some(job) = iso_maker.job_mut(id) {
+				job.fingerprint = fingerprint;
+			}
+
+			iso_maker.select_remembered();
+		},
+		Message::SourceInspected(id, layout, secure_boot, bmap) => {
+			if let Some(job) = iso_maker.job_mut(id) {
+				job.layout = layout;
+				job.secure_boot = secure_boot;
+
+				// A broken bmap is worth knowing about, the whole image still gets written
+				match bmap {
+					Some(Ok(bmap)) => job.bmap = Some(bmap),
+					Some(Err(e)) => job.error = Some(e),
+					None => {},
+				}
+			}
+		},
+		Message::HybridizeToggled(enabled) => iso_maker.active_job_mut().hybridize = enabled,
+		// Both would take the space after the image
+		Message::DataPartitionToggled(enabled) => {
+			let job = iso_maker.active_job_mut();
+			job.data_partition = enabled;
+			job.expand &= !enabled;
+		},
+		Message::ExpandToggled(enabled) => {
+			let job = iso_maker.active_job_mut();
+			job.expand = enabled;
+			job.data_partition &= !enabled;
+		},
+		Message::DuplicateToggled(enabled) => {
+			let job = iso_maker.active_job_mut();
+			job.duplicate = enabled;
+
+			// The stick that's just been written is the first copy
+			let written = !job.is_copying && !job.verify_only && job.error.is_none() && job.progress == 1.;
+			job.copies = u32::from(enabled && written);
+			job.swap = (enabled && written).then(|| Swap::Out(job.dest.clone(), job.dest_serial.clone()));
+		},
+		Message::DataLabelChanged(label) => iso_maker.active_job_mut().data_label = label,
+		Message::JobLabelChanged(label) => iso_maker.active_job_mut().job_label = label,
+		Message::PiToggled(enabled) => iso_maker.active_job_mut().customize_pi = enabled,
+		Message::PiHostnameChanged(hostname) => iso_maker.active_job_mut().pi.hostname = hostname,
+		Message::PiSshToggled(enabled) => iso_maker.active_job_mut().pi.ssh = enabled,
+		Message::PiUserChanged(user) => iso_maker.active_job_mut().pi.user = user,
+		Message::PiPasswordChanged(password) => iso_maker.active_job_mut().pi.password = password,
+		Message::PiSsidChanged(ssid) => iso_maker.active_job_mut().pi.ssid = ssid,
+		Message::PiWifiPasswordChanged(password) => iso_maker.active_job_mut().pi.wifi_password = password,
+		Message::PiCountryChanged(country) => iso_maker.active_job_mut().pi.country = country,
+		Message::PickFirstBoot => {
+			if let Some(path) = pick_file() {
+				iso_maker.active_job_mut().first_boot = Some(path);
+			}
+		},
+		Message::ClearFirstBoot => iso_maker.active_job_mut().first_boot = None,
+		Message::AddPayloadFiles => {
+			if let Some(files) = file_dialog().pick_files() {
+				iso_maker.active_job_mut().payload.extend(files.iter().map(|file| file.to_string_lossy().to_string()));
+			}
+		},
+		Message::AddPayloadFolder => {
+			if let Some(folder) = file_dialog().pick_folder() {
+				iso_maker.active_job_mut().payload.push(folder.to_string_lossy().to_string());
+			}
+		},
+		Message::RemovePayload(index) => {
+			let job = iso_maker.active_job_mut();
+			if index < job.payload.len() {
+				job.payload.remove(index);
+			}
+		},
+		Message::PickAutomation => {
+			if let Some(path) = pick_file() {
+				iso_maker.active_job_mut().automation = Some((Automation::guess(&path), path));
+			}
+		},
+		Message::AutomationKindChanged(kind) => {
+			if let Some((automation, _)) = &mut iso_maker.active_job_mut().automation {
+				*automation = kind;
+			}
+		},
+		Message::ClearAutomation => iso_maker.active_job_mut().automation = None,
+		Message::SourceInputChanged(input) => iso_maker.active_job_mut().source_input = input,
+		Message::UseSource => {
+			let job = iso_maker.active_job_mut();
+
+			match source::resolve(&job.source_input) {
+				Ok(source) => return job.set_source(source),
+				Err(e) => job.error = Some(e),
+			}
+		},
+		Message::ChecksumChanged(checksum) => iso_maker.active_job_mut().checksum = checksum,
+		Message::AlgorithmChanged(algorithm) => {
+			let job = iso_maker.active_job_mut();
+			job.algorithm = algorithm;
+			job.source_hash = None;
+		},
+		Message::VerifySource => {
+			let job = iso_maker.active_job_mut();
+			if job.hashing || job.is_copying {
+				return Task::none();
+			}
+
+			if job.source.is_empty() {
+				job.error = Some("Pick a source to verify".into());
+				return Task::none();
+			}
+
+			if source::is_url(&job.source) {
+				job.error = Some("URL sources are checked against the checksum while they stream".into());
+				return Task::none();
+			}
+
+			job.total = match std::fs::metadata(&job.source) {
+				Ok(metadata) => metadata.len(),
+				Err(e) => {
+					job.error = Some(format!("Source error: {e}"));
+					return Task::none();
+				}
+			};
+
+			job.hashing = true;
+			job.hash_progress = 0.;
+			job.source_hash = None;
+			job.error = None;
+
+			let (cancel_tx, cancel_rx) = mpsc::channel(1);
+			let (progress_tx, progress_rx) = mpsc::channel(100);
+			job.cancel_tx = Some(cancel_tx);
+
+			let id = job.id;
+			return Task::batch(vec![
+				Task::perform(
+					hash_file(job.source.clone(), job.algorithm, cancel_rx, progress_tx),
+					move |result| Message::SourceHashed(id, result),
+				),
+				Task::run(progress_stream(progress_rx), move |bytes| Message::HashProgress(id, bytes)),
+			]);
+		},
+		Message::HashProgress(id, bytes) => {
+			if let Some(job) = iso_maker.job_mut(id) && job.total > 0 {
+				job.hash_progress = (bytes as f32 / job.total as f32).min(1.);
+			}
+		},
+		Message::SourceHashed(id, result) => {
+			if let Some(job) = iso_maker.job_mut(id) {
+				job.hashing = false;
+				job.cancel_tx = None;
+
+				match result {
+					Ok(digest) => job.source_hash = Some(digest),
+					Err(e) => job.error = Some(e),
+				}
+			}
+		},
+		Message::DestChanged(device) => {
+			println!("Chosen device: {device}");
+			let job = iso_maker.active_job_mut();
+			job.dest = device;
+			job.manual_dest = None;
+			job.partition = None;
+			job.dest_note = None;
+			job.elevated = false;
+		},
+		Message::WriteToChanged(write_to) => {
+			let job = iso_maker.active_job_mut();
+			job.partition = match write_to {
+				WriteTo::Disk => None,
+				WriteTo::Partition(partition) => Some(partition),
+			};
+			job.partition_confirmed = false;
+		},
+		Message::PartitionConfirmed(confirmed) => iso_maker.active_job_mut().partition_confirmed = confirmed,
+		Message::DestInputChanged(input) => iso_maker.active_job_mut().dest_input = input,
+		Message::UseDest => {
+			let job = iso_maker.active_job_mut();
+			let path = job.dest_input.trim().to_string();
+
+			if path.is_empty() {
+				job.error = Some("Type the path of a device, e.g. /dev/sdc".into());
+				return Task::none();
+			}
+
+			let found = iso_maker.enumerator.find(&path);
+			let job = iso_maker.active_job_mut();
+
+			match found {
+				Some(device) if device.holds_system() => {
+					job.error = Some(format!("{} holds the running system, it can't be written to", device.path));
+				},
+				Some(device) => {
+					job.dest = device.path.clone();
+					job.dest_note = None;
+					job.partition = None;
+					job.manual_dest = Some(device);
+					job.fixed_confirmed = false;
+					job.elevated = false;
+					job.error = None;
+				},
+				None => job.error = Some(format!("{path} isn't a whole disk, or doesn't exist")),
+			}
+		},
+		Message::FixedDiskConfirmed(confirmed) => iso_maker.active_job_mut().fixed_confirmed = confirmed,
+		Message::RefreshDevices => {
+			iso_maker.devices = iso_maker.enumerator.list();
+
+			// Drop the selection if the device went away, unless a job is still writing to it
+			for job in iso_maker.jobs.iter_mut().filter(|job| !job.busy() && job.manual_dest.is_none()) {
+				if !iso_maker.devices.iter().any(|device| device.path == job.dest) {
+					job.dest.clear();
+				}
+			}
+
+			// The stick an image went to last time may have just been plugged back in
+			iso_maker.select_remembered();
+
+			if let Some(kiosk) = &mut iso_maker.kiosk {
+				return kiosk.devices_changed(&iso_maker.devices, &iso_maker.config).map(Message::Kiosk);
+			}
+
+			return iso_maker.next_copy();
+		},
+		Message::StartCopy => {
+			let busy = iso_maker.dest_busy();
+
+			// Get a fresh look at what's on the devices for the confirmation step
+			iso_maker.devices = iso_maker.enumerator.list();
+
+			// The partition picked has to still be there, as it was
+			let partition_gone = {
+				let job = iso_maker.active_job();
+				job.partition().is_some_and(|picked| {
+					!iso_maker.devices.iter()
+						.chain(&job.manual_dest)
+						.filter(|device| device.path == job.dest)
+						.flat_map(|device| &device.partitions)
+						.any(|partition| partition.path == picked.path && partition.size == picked.size)
+				})
+			};
+
+			// A template's job only goes to the devices it's for
+			let off_target = {
+				let job = iso_maker.active_job();
+				job.target.as_ref().and_then(|target| {
+					let device = iso_maker.devices.iter().chain(&job.manual_dest).find(|device| device.path == job.dest)?;
+					target.check(device).err()
+				})
+			};
+
+			let job = iso_maker.active_job_mut();
+			if job.busy() || job.confirming {
+				return Task::none();
+			}
+
+			if job.source.is_empty() || job.dest.is_empty() {
+				job.error = Some("Source and Destination are both required".into());
+				return Task::none();
+			}
+
+			if let Some(e) = off_target {
+				job.error = Some(e);
+				return Task::none();
+			}
+
+			// Something typed in but never used, it would be easy to think it's the source
+			if job.source_input.trim() != job.source {
+				job.error = Some("Press Enter in the source field to use the path or URL typed there".into());
+				return Task::none();
+			}
+
+			if busy {
+				job.error = Some(format!("{} is already being written by another job", job.dest));
+				return Task::none();
+			}
+
+			if partition_gone {
+				job.partition = None;
+				job.error = Some("The partition picked isn't on the device any more, pick it again".into());
+				return Task::none();
+			}
+
+			// They all go looking for the image's partitions on the disk
+			if job.partition().is_some() && !job.customizations().is_empty() {
+				job.error = Some("Extra files, a data partition and first boot setup need the image written to the whole disk".into());
+				return Task::none();
+			}
+
+			if job.mode == Mode::FileCopy && source::is_url(&job.source) {
+				job.error = Some("Copying files needs a local ISO, download it first".into());
+				return Task::none();
+			}
+
+			if job.mode == Mode::WindowsToGo {
+				if !wim::is_wim(&job.source) {
+					job.error = Some("Windows To Go needs an install.wim or install.esd as the source".into());
+					return Task::none();
+				}
+
+				if job.wim_image.is_none() {
+					job.error = Some("Pick the Windows edition to apply".into());
+					return Task::none();
+				}
+			}
+
+			let label = match job.mode {
+				Mode::FileCopy if !job.label.is_empty() => Some((Filesystem::Fat32, job.label.as_str())),
+				Mode::Image if job.data_partition => Some((Filesystem::Exfat, job.data_label())),
+				_ => None,
+			};
+
+			if let Some((filesystem, label)) = label && let Err(e) = partition::check_label(filesystem, label) {
+				job.error = Some(e);
+				return Task::none();
+			}
+
+			if let Some(pi) = job.pi_settings() && let Err(e) = pi.check() {
+				job.error = Some(e);
+				return Task::none();
+			}
+
+			// URLs are only opened once the write starts
+			if !source::is_url(&job.source) && let Err(e) = std::fs::metadata(&job.source) {
+				job.error = Some(format!("Source error: {e}"));
+				return Task::none();
+			}
+
+			if job.hashing {
+				return Task::none();
+			}
+
+			job.error = None;
+			job.partition_confirmed = false;
+			job.confirming = true;
+		},
+		Message::StartVerify => {
+			let busy = iso_maker.dest_busy();
+			let mmap = iso_maker.config.mmap;
+
+			let job = iso_maker.active_job_mut();
+			if job.busy() || job.confirming {
+				return Task::none();
+			}
+
+			if job.source.is_empty() || job.dest.is_empty() {
+				job.error = Some("Pick the image and the device it was written to".into());
+				return Task::none();
+			}
+
+			if job.source_input.trim() != job.source {
+				job.error = Some("Press Enter in the source field to use the path or URL typed there".into());
+				return Task::none();
+			}
+
+			if busy {
+				job.error = Some(format!("{} is being written by another job", job.dest));
+				return Task::none();
+			}
+
+			// The other modes put files on the device rather than the image
+			if job.mode != Mode::Image {
+				job.error = Some("Only images written as they are can be compared with the device".into());
+				return Task::none();
+			}
+
+			if !source::is_url(&job.source) && let Err(e) = std::fs::metadata(&job.source) {
+				job.error = Some(format!("Source error: {e}"));
+				return Task::none();
+			}
+
+			job.verify_only = true;
+			job.dry_run = None;
+			job.is_copying = true;
+			let run = job.next_run();
+			job.start_watchdog();
+			job.estimate = Estimate::default();
+			job.phase = Phase::Verifying;
+			job.progress = 0.;
+			job.partitions = None;
+			job.uefi = None;
+			job.error = None;
+
+			let expected = (!job.checksum.trim().is_empty() && job.source_hash.is_none())
+				.then(|| (job.algorithm, job.checksum.clone()));
+			let options = writer::Options { verify: None, expected, mbr: job.mbr(), bmap: job.bmap.clone(), mmap, ..writer::Options::default() };
+
+			let (cancel_tx, cancel_rx) = mpsc::channel(1);
+			let (progress_tx, progress_rx) = mpsc::channel(100);
+			job.cancel_tx = Some(cancel_tx);
+
+			let (id, source, dest, elevated) = (job.id, job.source.clone(), job.write_path(), job.elevated);
+			let verify = Task::perform(async move {
+				if elevated {
+					elevate::copy_with_progress(source, dest, options, true, cancel_rx, progress_tx).await
+				} else {
+					writer::verify_only(source, dest, options, cancel_rx, progress_tx).await
+				}
+			}, move |result| Message::CopyComplete(id, run, result));
+
+			return Task::batch(vec![
+				verify,
+				Task::run(progress_stream(progress_rx), move |(phase, done, total)| Message::CopyProgress(id, run, phase, done, total)),
+			]);
+		},
+		Message::ConfirmCopy => {
+			let verify = iso_maker.config.verify.then_some(iso_maker.config.verify_algorithm);
+			let mmap = iso_maker.config.mmap;
+			let checkpoint = iso_maker.config.checkpoint();
+			let skip_unchanged = iso_maker.config.skip_unchanged;
+			let delta = iso_maker.config.delta;
+			let serial = iso_maker.dest_serial();
+			let store = iso_maker.recovery.clone();
+			let audit = {
+				let job = iso_maker.active_job();
+				let operation = match job.mode {
+					Mode::Image => audit::Operation::Write,
+					Mode::FileCopy => audit::Operation::Format,
+					Mode::WindowsToGo => audit::Operation::WindowsToGo,
+				};
+
+				audit::Entry::new(&iso_maker.config, operation, &job.write_path(), serial.clone(), &job.source).labelled(&job.job_label)
+			};
+
+			let job = iso_maker.active_job_mut();
+			if !job.confirming {
+				return Task::none();
+			}
+
+			let verify = job.verify.unwrap_or(verify);
+
+			if job.needs_fixed_confirmation() && !job.fixed_confirmed {
+				return Task::none();
+			}
+
+			if job.partition().is_some() && !job.partition_confirmed {
+				return Task::none();
+			}
+
+			job.audit = Some(audit);
+			job.confirming = false;
+			job.verify_only = false;
+			job.dry_run = None;
+			job.is_copying = true;
+			let run = job.next_run();
+			job.start_watchdog();
+			job.estimate = Estimate::default();
+			job.synced.store(0, Ordering::Relaxed);
+			job.unchanged.store(false, Ordering::Relaxed);
+			job.rewritten = (delta && job.mode == Mode::Image).then(Arc::default);
+			job.unpacked.store(0, Ordering::Relaxed);
+			job.read = (0, 0);
+			job.dest_serial = serial;
+			job.resume = None;
+			let resume = std::mem::take(&mut job.resume_from);
+			job.record = store.start(Interrupted {
+				source: job.source.clone(),
+				dest: job.write_path(),
+				// Found again by its serial, it would be the whole disk that's picked up
+				serial: job.dest_serial.clone().filter(|_| job.partition().is_none()),
+				mode: job.mode,
+				synced: resume,
+			});
+			job.phase = Phase::Writing;
+			job.progress = 0.;
+			job.partitions = None;
+			job.uefi = None;
+			job.error = None;
+
+			// Without an up front check, hash the image while it's being written instead
+			let expected = (!job.checksum.trim().is_empty() && job.source_hash.is_none())
+				.then(|| (job.algorithm, job.checksum.clone()));
+
+			let (cancel_tx, cancel_rx) = mpsc::channel(1);
+			let (progress_tx, progress_rx) = mpsc::channel(100);
+			job.cancel_tx = Some(cancel_tx);
+
+			let id = job.id;
+			let gate = job.audit.clone();
+			let write = match (job.mode, &job.wim_image) {
+				(Mode::WindowsToGo, Some(image)) => Task::perform({
+					gated(gate, wim::windows_to_go(job.source.clone(), image.index, job.dest.clone(), cancel_rx, progress_tx))
+				}, move |result| Message::CopyComplete(id, run, result)),
+				(Mode::FileCopy, _) => {
+					let customizations = Customizations {
+						// Windows Setup looks for the answer file in the root of removable drives
+						files: job.answer_file.iter()
+							.map(|path| (path.into(), "autounattend.xml".to_string()))
+							.collect(),
+						payload: job.payload.iter().map(Into::into).collect(),
+						automation: job.automation.as_ref().map(|(kind, path)| (*kind, path.into())),
+						data_partition: None,
+						expand: false,
+						pi: None,
+						first_boot: None,
+					};
+
+					Task::perform({
+						let label = (!job.label.is_empty()).then(|| job.label.clone());
+						gated(gate, filecopy::copy_files(job.source.clone(), job.dest.clone(), label, customizations, cancel_rx, progress_tx))
+					}, move |result| Message::CopyComplete(id, run, result))
+				},
+				_ => {
+					let customizations = job.customizations();
+					let options = writer::Options {
+						verify,
+						expected,
+						mbr: job.mbr(),
+						bmap: job.bmap.clone(),
+						mmap,
+						checkpoint,
+						synced: job.synced.clone(),
+						resume,
+						image_hash: job.audit.as_ref().map(|audit| audit.image_hash.clone()).unwrap_or_default(),
+						readback: job.audit.as_ref().map(|audit| audit.readback.clone()).unwrap_or_default(),
+						skip_unchanged,
+						unchanged: job.unchanged.clone(),
+						delta,
+						rewritten: job.rewritten.clone().unwrap_or_default(),
+						unpacked: job.unpacked.clone(),
+					};
+					let (source, dest, elevated) = (job.source.clone(), job.write_path(), job.elevated);
+					let finish_tx = progress_tx.clone();
+
+					Task::perform(gated(gate, async move {
+						if elevated {
+							elevate::copy_with_progress(source, dest.clone(), options, false, cancel_rx, progress_tx).await?;
+						} else {
+							copy_with_progress(source, dest.clone(), options, cancel_rx, progress_tx).await?;
+						}
+
+						if customizations.is_empty() {
+							return Ok(());
+						}
+
+						// Payload goes on the largest writable partition, the data partition if there is one
+						let _ = finish_tx.send((Phase::Finishing, 0, 1)).await;
+						tokio::task::spawn_blocking(move || customizations.apply_to_device(&dest))
+							.await
+							.map_err(|e| format!("Copy error: {e}"))?
+					}), move |result| Message::CopyComplete(id, run, result))
+				},
+			};
+
+			return Task::batch(vec![
+				write,
+				Task::run(progress_stream(progress_rx), move |(phase, done, total)| Message::CopyProgress(id, run, phase, done, total)),
+			])
+		},
+		Message::CopyProgress(id, run, phase, done, total) => {
+			if let Some(job) = iso_maker.job_mut(id) && job.run == run && job.is_copying && total > 0 {
+				// Each phase goes at its own speed
+				if phase != job.phase {
+					job.estimate = Estimate::default();
+				}
+				job.estimate.update(done, total);
+
+				// Syncing repeats itself while it waits, only getting further counts
+				if phase != job.phase || done > job.read.0 {
+					job.start_watchdog();
+				}
+
+				job.phase = phase;
+				job.progress = (done as f32 / total as f32).min(1.);
+				job.read = (done, total);
+				job.unsynced = total.saturating_sub(done);
+
+				if let Some(record) = &mut job.record {
+					record.synced(job.synced.load(Ordering::Relaxed));
+				}
+			}
+
+			iso_maker.update_taskbar();
+		},
+		Message::CopyComplete(id, run, result) => {
+			let mut read_table = Task::none();
+			let mut written = None;
+			let sound = iso_maker.config.sound;
+
+			if let Some(job) = iso_maker.job_mut(id) && job.run == run {
+				let aborted = std::mem::take(&mut job.stopping) && job.error.is_some();
+				job.is_copying = false;
+				job.cancel_tx = None;
+
+				// Whoever cancelled it is already looking
+				match &result {
+					Ok(_) if sound => sound::play(Sound::Complete),
+					Err(e) if sound && e != "Cancelled" => sound::play(Sound::Failed),
+					_ => {},
+				}
+
+				let result = audit::finish(job.audit.take().as_ref(), result);
+				if let Some(record) = job.record.take() {
+					record.finish();
+				}
+
+				// The next stick is written once this one's swapped for it, whichever way it went
+				if job.duplicate && !job.verify_only {
+					match &result {
+						Err(e) if e == "Cancelled" => {
+							job.duplicate = false;
+							job.swap = None;
+						},
+						result => {
+							job.copies += u32::from(result.is_ok());
+							job.swap = Some(Swap::Out(job.dest.clone(), job.dest_serial.clone()));
+						},
+					}
+				}
+
+				match result {
+					Ok(_) => {
+						job.progress = 1.;
+						written = job.fingerprint.clone()
+							.filter(|_| !job.verify_only)
+							.map(|fingerprint| (fingerprint, job.dest.clone()));
+
+						// Show what the stick ended up with, once the kernel has seen the new table
+						let dest = job.dest.clone();
+						read_table = Task::perform(async move {
+							tokio::task::spawn_blocking(move || partition::reread(&dest).and_then(|()| partition::read_table(&dest)))
+								.await
+								.map_err(|e| format!("Partition error: {e}"))?
+						}, move |result| Message::PartitionsRead(id, result));
+					},
+					Err(e) => {
+						// Failing after the write was done, there's nothing to resume
+						if !matches!(job.phase, Phase::Writing | Phase::Syncing) {
+							job.synced.store(0, Ordering::Relaxed);
+						}
+
+						// Unplugged part way, it can carry on from the last checkpoint once it's back
+						let synced = job.synced.load(Ordering::Relaxed);
+						// The stick may come back under another name, which the partition doesn't follow
+						if writer::is_disconnected(&e) && job.mode == Mode::Image && !job.verify_only && synced > 0 && job.partition().is_none() {
+							job.resume = job.dest_serial.clone().map(|serial| (serial, synced));
+						}
+
+						// Whatever the write ended with, it was given up on first
+						if !aborted {
+							job.error = Some(e);
+						}
+					},
+				}
+			}
+
+			// Devices without a serial can't be told apart next time
+			let serial = written.as_ref().and_then(|(_, dest)| {
+				iso_maker.devices.iter().find(|device| device.path == *dest)?.serial.clone()
+			});
+
+			if let (Some((fingerprint, _)), Some(serial)) = (written, serial) {
+				iso_maker.config.remember_device(fingerprint, serial);
+				iso_maker.save_config();
+			}
+
+			iso_maker.update_taskbar();
+			return read_table;
+		},
+		Message::PartitionsRead(id, result) => {
+			if let Some(job) = iso_maker.job_mut(id) {
+				// Only sticks meant to boot on UEFI machines are worth looking at
+				let check = match &result {
+					Ok(partitions) if !job.verify_only && job.partition().is_none() => {
+						let meant = job.secure_boot.is_some() || job.mode != Mode::Image || uefi::boot_partition(partitions).is_some_and(|entry| entry.kind == "EFI System");
+						meant.then(|| partitions.clone())
+					},
+					_ => None,
+				};
+
+				job.partitions = Some(result);
+
+				if let Some(partitions) = check {
+					return Task::perform(async move {
+						tokio::task::spawn_blocking(move || uefi::check(&partitions))
+							.await
+							.map_err(|e| format!("Mount error: {e}"))?
+					}, move |result| Message::UefiChecked(id, result));
+				}
+			}
+		},
+		Message::UefiChecked(id, result) => {
+			if let Some(job) = iso_maker.job_mut(id) {
+				job.uefi = Some(result);
+			}
+		},
+		Message::TestBoot(disk, firmware) => {
+			let job = iso_maker.active_job_mut();
+			if job.test_booting || job.busy() {
+				return Task::none();
+			}
+
+			job.test_booting = true;
+			job.test_boot_error = None;
+
+			let id = job.id;
+			return Task::perform(qemu::boot(disk, firmware), move |result| Message::TestBootEnded(id, result));
+		},
+		Message::TestBootEnded(id, result) => {
+			if let Some(job) = iso_maker.job_mut(id) {
+				job.test_booting = false;
+				job.test_boot_error = result.err();
+			}
+		},
+		Message::CheckStalled => {
+			let limit = Duration::from_secs(iso_maker.config.stall_secs);
+
+			// The steps after the write report nothing while they run
+			for job in iso_maker.jobs.iter_mut().filter(|job| job.is_copying) {
+				job.stalled = matches!(job.phase, Phase::Writing | Phase::Syncing | Phase::Checking | Phase::Verifying)
+					&& job.last_progress.elapsed() >= limit;
+			}
+		},
+		Message::AbortStalled => {
+			// The writer thread is stuck in the kernel, it's left to it. It still has the device
+			// open, which stays busy, and on record in case the app goes first, until it returns
+			let job = iso_maker.active_job_mut();
+			job.cancel();
+			let aborted = Err::<(), _>("Aborted, the device stopped responding. Unplug it and plug it back in before using it again".into());
+			job.error = audit::finish(job.audit.take().as_ref(), aborted).err();
+			iso_maker.update_taskbar();
+		},
+		Message::Resume => {
+			let serial = iso_maker.dest_serial();
+
+			let job = iso_maker.active_job_mut();
+			let Some((expected, offset)) = job.resume.clone().filter(|_| !job.busy()) else {
+				return Task::none();
+			};
+
+			// The device picked since might not be the one that was cut off
+			if serial != Some(expected) {
+				job.error = Some("Resuming needs the device the write was cut off on, pick it again".into());
+				job.resume = None;
+				return Task::none();
+			}
+
+			job.resume_from = offset;
+			job.confirming = true;
+			return Task::done(Message::ConfirmCopy);
+		},
+		Message::RetryBusy => {
+			let job = iso_maker.active_job_mut();
+			if job.busy() || !job.error.as_deref().is_some_and(busy::is_busy) {
+				return Task::none();
+			}
+
+			// The device was confirmed before the first try
+			job.confirming = true;
+			return Task::done(Message::ConfirmCopy);
+		},
+		Message::RetryAsRoot => {
+			let job = iso_maker.active_job_mut();
+			if job.busy() {
+				return Task::none();
+			}
+
+			job.elevated = true;
+			if job.verify_only {
+				return Task::done(Message::StartVerify);
+			}
+
+			// The device was confirmed before the first try
+			job.confirming = true;
+			return Task::done(Message::ConfirmCopy);
+		},
+		Message::DryRun => {
+			let verify = iso_maker.config.verify.then_some(iso_maker.config.verify_algorithm);
+			let mmap = iso_maker.config.mmap;
+			let checkpoint = iso_maker.config.checkpoint();
+			let skip_unchanged = iso_maker.config.skip_unchanged;
+			let delta = iso_maker.config.delta;
+
+			let job = iso_maker.active_job_mut();
+			if !job.confirming || job.mode != Mode::Image {
+				return Task::none();
+			}
+
+			// Nothing gets written, the device needn't be confirmed
+			job.confirming = false;
+			job.verify_only = false;
+			job.dry_run = None;
+			job.is_copying = true;
+			let run = job.next_run();
+			job.start_watchdog();
+			job.estimate = Estimate::default();
+			job.unpacked.store(0, Ordering::Relaxed);
+			job.read = (0, 0);
+			job.phase = Phase::Reading;
+			job.progress = 0.;
+			job.error = None;
+
+			let options = writer::Options {
+				verify: job.verify.unwrap_or(verify),
+				expected: (!job.checksum.trim().is_empty()).then(|| (job.algorithm, job.checksum.clone())),
+				mbr: job.mbr(),
+				bmap: job.bmap.clone(),
+				mmap,
+				checkpoint,
+				synced: Arc::default(),
+				resume: job.resume_from,
+				image_hash: Arc::default(),
+				readback: Arc::default(),
+				skip_unchanged,
+				unchanged: Arc::default(),
+				delta,
+				rewritten: Arc::default(),
+				unpacked: job.unpacked.clone(),
+			};
+
+			let (cancel_tx, cancel_rx) = mpsc::channel(1);
+			let (progress_tx, progress_rx) = mpsc::channel(100);
+			job.cancel_tx = Some(cancel_tx);
+
+			let id = job.id;
+			let customizations = job.customizations();
+			let dry_run = writer::dry_run(job.source.clone(), job.write_path(), options, cancel_rx, progress_tx);
+
+			return Task::batch(vec![
+				Task::perform(async move {
+					let mut report = dry_run.await?;
+					report.extend(customizations.dry_run()?);
+					Ok(report)
+				}, move |result| Message::DryRunComplete(id, run, result)),
+				Task::run(progress_stream(progress_rx), move |(phase, done, total)| Message::CopyProgress(id, run, phase, done, total)),
+			]);
+		},
+		Message::DryRunComplete(id, run, result) => {
+			if let Some(job) = iso_maker.job_mut(id) && job.run == run {
+				job.is_copying = false;
+				job.stopping = false;
+				job.cancel_tx = None;
+
+				match result {
+					Ok(report) => job.dry_run = Some(report),
+					Err(e) => job.error = Some(e),
+				}
+			}
+
+			iso_maker.update_taskbar();
+		},
+		Message::BackFromConfirm => iso_maker.active_job_mut().confirming = false,
+		Message::Cancel => {
+			iso_maker.active_job_mut().cancel();
+			iso_maker.update_taskbar();
+		},
+		Message::ProfileSelected(name) => {
+			iso_maker.profile_name = name.clone();
+
+			if let Some(profile) = iso_maker.config.profile(&name).cloned() {
+				return iso_maker.active_job_mut().load_profile(&profile);
+			}
+		},
+		Message::ProfileNameChanged(name) => iso_maker.profile_name = name,
+		Message::SaveProfile => {
+			let name = iso_maker.profile_name.trim().to_string();
+			let verify = iso_maker.config.verify.then_some(iso_maker.config.verify_algorithm);
+			let job = iso_maker.active_job_mut();
+
+			if name.is_empty() || job.source.is_empty() {
+				job.error = Some("Pick a source and name the profile to save it".into());
+				return Task::none();
+			}
+
+			let profile = job.profile(name, verify);
+			iso_maker.config.save_profile(profile);
+			iso_maker.save_config();
+		},
+		Message::RunProfile => {
+			let Some(profile) = iso_maker.config.profile(iso_maker.profile_name.trim()).cloned() else {
+				iso_maker.active_job_mut().error = Some("Pick a profile to run".into());
+				return Task::none();
+			};
+
+			// Once the source has been looked at and the device it went to last time picked
+			let job = iso_maker.active_job_mut();
+			return job.load_profile(&profile).chain(Task::done(Message::StartCopy));
+		},
+		Message::LoadTemplate => {
+			let Some(path) = file_dialog().add_filter("Job templates", &["toml"]).pick_file() else {
+				return Task::none();
+			};
+
+			match Template::load(&path.to_string_lossy()) {
+				Ok(template) => return iso_maker.load_template(template),
+				Err(e) => iso_maker.active_job_mut().error = Some(e),
+			}
+		},
+		Message::DeleteProfile => {
+			let name = iso_maker.profile_name.trim().to_string();
+			iso_maker.config.profiles.retain(|profile| profile.name != name);
+			iso_maker.profile_name.clear();
+			iso_maker.save_config();
+		},
+		Message::Instance(request) => {
+			let focus = window::get_latest().and_then(window::gain_focus);
+
+			return match request {
+				Request::Focus => focus,
+				Request::Open(image) => Task::batch([Task::done(Message::OpenImage(image)), focus]),
+			};
+		},
+		Message::Recover(path, action) => {
+			let Some(leftover) = iso_maker.interrupted.iter().find(|leftover| leftover.path == path).cloned() else {
+				return Task::none();
+			};
+			iso_maker.recovery_error = None;
+			iso_maker.cleaning_up = None;
+
+			if action == Recovery::Dismiss {
+				recovery::forget(&leftover);
+				iso_maker.interrupted.retain(|leftover| leftover.path != path);
+				return Task::none();
+			}
+
+			if action == Recovery::CleanUp {
+				match iso_maker.cleanup_target(&leftover) {
+					Ok(_) => iso_maker.cleaning_up = Some(path),
+					Err(e) => iso_maker.recovery_error = Some(e),
+				}
+				return Task::none();
+			}
+
+			// After a reboot its path may well be another device's, the serial says which it is
+			let dest = match &leftover.job.serial {
+				Some(serial) => iso_maker.devices.iter()
+					.find(|device| device.serial.as_ref() == Some(serial))
+					.map(|device| device.path.clone()),
+				None => Some(leftover.job.dest.clone()),
+			};
+			let Some(dest) = dest else {
+				iso_maker.recovery_error = Some(format!("Plug in the device that was {} first", leftover.job.dest));
+				return Task::none();
+			};
+
+			recovery::forget(&leftover);
+			iso_maker.interrupted.retain(|leftover| leftover.path != path);
+
+			let task = iso_maker.recover(&leftover.job, dest);
+			let next = match action {
+				Recovery::Resume => Task::done(Message::Resume),
+				Recovery::Verify => Task::done(Message::StartVerify),
+				_ => Task::none(),
+			};
+
+			return Task::batch([task, next]);
+		},
+		Message::ConfirmCleanUp => {
+			let Some(path) = iso_maker.cleaning_up.take() else {
+				return Task::none();
+			};
+			let Some(leftover) = iso_maker.interrupted.iter().find(|leftover| leftover.path == path).cloned() else {
+				return Task::none();
+			};
+
+			// It may have been swapped for another since the confirmation showed it
+			iso_maker.devices = iso_maker.enumerator.list();
+			let device = match iso_maker.cleanup_target(&leftover) {
+				Ok(device) => device.clone(),
+				Err(e) => {
+					iso_maker.recovery_error = Some(e);
+					return Task::none();
+				},
+			};
+
+			let audit = audit::Entry::new(&iso_maker.config, audit::Operation::Wipe, &device.path, device.serial.clone(), &leftover.job.source);
+			return Task::perform(async move {
+				tokio::task::spawn_blocking(move || {
+					// Nothing gets erased without a record of it
+					audit.started()?;
+					audit::finish(Some(&audit), partition::wipe(&device.path))
+				}).await.unwrap_or_else(|e| Err(e.to_string()))
+			}, move |result| Message::CleanedUp(path.clone(), result));
+		},
+		Message::BackFromCleanUp => iso_maker.cleaning_up = None,
+		Message::CleanedUp(path, result) => match result {
+			Ok(()) => {
+				if let Some(leftover) = iso_maker.interrupted.iter().find(|leftover| leftover.path == path) {
+					recovery::forget(leftover);
+				}
+				iso_maker.interrupted.retain(|leftover| leftover.path != path);
+			},
+			Err(e) => iso_maker.recovery_error = Some(e),
+		},
+		Message::NewJob => iso_maker.new_job(),
+		Message::OpenImage(path) => {
+			// Whatever sent it may have sent something that isn't there, or can't be read
+			let job = iso_maker.idle_job();
+			let task = job.set_source(path);
+			if !source::is_url(&job.source) && let Err(e) = std::fs::File::open(&job.source) {
+				job.error = Some(format!("Can't open {}: {e}", job.source));
+			}
+
+			return task;
+		},
+		Message::ProviderImagesLoaded(images, errors) => {
+			iso_maker.provider_images = images;
+			iso_maker.provider_errors = errors;
+		},
+		Message::ProviderImagePicked(url) => return iso_maker.active_job_mut().set_source(url),
+		Message::CloseJob(id) => {
+			// There is always at least one tab, and a running job has to be cancelled, and done stopping, first
+			if iso_maker.jobs.len() == 1 || iso_maker.jobs.iter().any(|job| job.id == id && job.busy()) {
+				return Task::none();
+			}
+
+			if let Some(index) = iso_maker.jobs.iter().position(|job| job.id == id) {
+				iso_maker.jobs.remove(index);
+
+				if iso_maker.active == id {
+					iso_maker.active = iso_maker.jobs[index.saturating_sub(1)].id;
+				}
+			}
+		},
+		Message::SelectJob(id) => {
+			iso_maker.active = id;
+			iso_maker.tool = None;
+		},
+		Message::OpenTool(tool) => {
+			// Usually it's the stick that was just written
+			if tool == Tool::BootParams {
+				let dest = &iso_maker.active_job().dest;
+				iso_maker.boot_tool.preselect(iso_maker.devices.iter().find(|device| device.path == *dest));
+			}
+
+			if tool == Tool::Partitions {
+				let dest = &iso_maker.active_job().dest;
+				iso_maker.partition_tool.preselect(iso_maker.devices.iter().find(|device| device.path == *dest));
+			}
+
+			if tool == Tool::Format {
+				let dest = &iso_maker.active_job().dest;
+				iso_maker.format_tool.preselect(iso_maker.devices.iter().find(|device| device.path == *dest));
+			}
+
+			iso_maker.tool = Some(tool);
+
+			// Show what's in the image the active job is about to write
+			if tool == Tool::Browse {
+				let source = iso_maker.active_job().source.clone();
+				return iso_maker.browse_tool.open(&source).map(Message::BrowseTool);
+			}
+		},
+		Message::HashTool(message) => return iso_maker.hash_tool.update(message).map(Message::HashTool),
+		Message::CompareTool(message) => return iso_maker.compare_tool.update(message).map(Message::CompareTool),
+		Message::BootTool(message) => return iso_maker.boot_tool.update(message).map(Message::BootTool),
+		Message::PartitionTool(message) => {
+			let writing = iso_maker.writing();
+			return iso_maker.partition_tool.update(message, &iso_maker.config, &writing).map(Message::PartitionTool);
+		},
+		Message::FormatTool(message) => {
+			let writing = iso_maker.writing();
+			return iso_maker.format_tool.update(message, &iso_maker.config, &writing).map(Message::FormatTool);
+		},
+		Message::Kiosk(message) => if let Some(kiosk) = &mut iso_maker.kiosk {
+			return kiosk.update(message).map(Message::Kiosk);
+		},
+		Message::IsoTool(message) => return iso_maker.iso_tool.update(message).map(Message::IsoTool),
+		Message::BrowseTool(message) => return iso_maker.browse_tool.update(message).map(Message::BrowseTool),
+		Message::ToggleSettings => iso_maker.settings_open = !iso_maker.settings_open,
+		Message::ThemeChanged(theme) => {
+			iso_maker.config.theme = theme;
+			iso_maker.save_config();
+		},
+		// Only once it's a color, not for every character on the way
+		Message::AccentChanged(color) => {
+			let valid = Color::parse(&color).is_some();
+			iso_maker.config.palette.accent = color;
+			if valid {
+				iso_maker.save_config();
+			}
+		},
+		Message::BackgroundChanged(color) => {
+			let valid = Color::parse(&color).is_some();
+			iso_maker.config.palette.background = color;
+			if valid {
+				iso_maker.save_config();
+			}
+		},
+		Message::DangerChanged(color) => {
+			let valid = Color::parse(&color).is_some();
+			iso_maker.config.palette.danger = color;
+			if valid {
+				iso_maker.save_config();
+			}
+		},
+		Message::ScaleChanged(scale) => {
+			iso_maker.config.scale_factor = scale;
+			iso_maker.save_config();
+		},
+		Message::InstallUdevRule => {
+			return Task::perform(async {
+				tokio::task::spawn_blocking(udev::install).await.unwrap_or_else(|e| Err(e.to_string()))
+			}, Message::UdevRuleChanged);
+		},
+		Message::RemoveUdevRule => {
+			return Task::perform(async {
+				tokio::task::spawn_blocking(udev::uninstall).await.unwrap_or_else(|e| Err(e.to_string()))
+			}, Message::UdevRuleChanged);
+		},
+		Message::UdevRuleChanged(result) => {
+			iso_maker.udev_rule = udev::installed();
+			iso_maker.settings_error = result.err();
+		},
+		Message::RegisterFileTypes => {
+			return Task::perform(async {
+				tokio::task::spawn_blocking(mime::register).await.unwrap_or_else(|e| Err(e.to_string()))
+			}, Message::FileTypesChanged);
+		},
+		Message::UnregisterFileTypes => {
+			return Task::perform(async {
+				tokio::task::spawn_blocking(mime::unregister).await.unwrap_or_else(|e| Err(e.to_string()))
+			}, Message::FileTypesChanged);
+		},
+		Message::FileTypesChanged(result) => {
+			iso_maker.file_types = mime::registered();
+			iso_maker.settings_error = result.err();
+		},
+		Message::UnitsChanged(units) => {
+			iso_maker.config.units = units;
+			units::prefer(units);
+			iso_maker.save_config();
+		},
+		Message::ColorBlindToggled(color_blind) => {
+			iso_maker.config.color_blind = color_blind;
+			iso_maker.save_config();
+		},
+		Message::SoundToggled(sound) => {
+			iso_maker.config.sound = sound;
+			iso_maker.save_config();
+		},
+		Message::SkipUnchangedToggled(skip_unchanged) => {
+			iso_maker.config.skip_unchanged = skip_unchanged;
+			iso_maker.save_config();
+		},
+		Message::DeltaToggled(delta) => {
+			iso_maker.config.delta = delta;
+			iso_maker.save_config();
+		},
+		Message::MmapToggled(mmap) => {
+			iso_maker.config.mmap = mmap;
+			iso_maker.save_config();
+		},
+		Message::StallSecsChanged(secs) => {
+			let secs = secs.trim();
+			let parsed = if secs.is_empty() { Ok(0) } else { secs.parse() };
+
+			if let Ok(secs) = parsed {
+				iso_maker.config.stall_secs = secs;
+				iso_maker.save_config();
+			}
+		},
+		Message::CheckpointChanged(megabytes) => {
+			// Cleared is never, anything else that isn't a number is ignored
+			let megabytes = megabytes.trim();
+			let parsed = if megabytes.is_empty() { Ok(0) } else { megabytes.parse() };
+
+			if let Ok(megabytes) = parsed {
+				iso_maker.config.checkpoint_mb = megabytes;
+				iso_maker.save_config();
+			}
+		},
+		Message::VerifyToggled(verify) => {
+			iso_maker.config.verify = verify;
+			iso_maker.save_config();
+		},
+		Message::VerifyAlgorithmChanged(algorithm) => {
+			iso_maker.config.verify_algorithm = algorithm;
+			iso_maker.save_config();
+		},
+		Message::Escape => {
+			if iso_maker.palette_open {
+				iso_maker.palette_open = false;
+			} else if iso_maker.cleaning_up.is_some() {
+				iso_maker.cleaning_up = None;
+			} else if iso_maker.active_job().confirming {
+				iso_maker.active_job_mut().confirming = false;
+			} else if iso_maker.active_job().is_copying {
+				return update(iso_maker, Message::Cancel);
+			}
+		},
+		Message::TogglePalette => {
+			iso_maker.palette_open = !iso_maker.palette_open;
+			iso_maker.palette_query.clear();
+
+			if iso_maker.palette_open {
+				return text_input::focus(PALETTE_INPUT);
+			}
+		},
+		Message::PaletteQueryChanged(query) => iso_maker.palette_query = query,
+		Message::PaletteSubmit => {
+			// Run the first command that matches what was typed
+			if let Some(command) = Command::ALL.into_iter().find(|c| c.matches(&iso_maker.palette_query)) {
+				return update(iso_maker, Message::PaletteCommand(command));
+			}
+		},
+		Message::PaletteCommand(command) => {
+			iso_maker.palette_open = false;
+			iso_maker.palette_query.clear();
+
+			let message = match command {
+				Command::CloseJob => Message::CloseJob(iso_maker.active),
+				command => command.message(),
+			};
+
+			return update(iso_maker, message);
+		},
+	}
+
+	Task::none()
+}
+
+pub fn subscription(iso_maker: &IsoMaker) -> Subscription<Message> {
+	// Nothing but looking out for sticks, no shortcuts lead out of it
+	if iso_maker.kiosk.is_some() {
+		return iced::time::every(Duration::from_secs(2)).map(|_| Message::RefreshDevices);
+	}
+
+	let mut subscriptions = vec![
+		keyboard::on_key_press(shortcut),
+		Subscription::run(instance::requests).map(Message::Instance),
+	];
+
+	// Looks out for a stick that was unplugged during its write coming back, or the next one to duplicate to
+	if iso_maker.jobs.iter().any(|job| (job.resume.is_some() || job.swap.is_some()) && !job.busy()) {
+		subscriptions.push(iced::time::every(Duration::from_secs(2)).map(|_| Message::RefreshDevices));
+	}
+
+	// Watches for writes that stopped getting anywhere
+	if iso_maker.config.stall_secs > 0 && iso_maker.jobs.iter().any(|job| job.is_copying) {
+		subscriptions.push(iced::time::every(Duration::from_secs(1)).map(|_| Message::CheckStalled));
+	}
+
+	Subscription::batch(subscriptions)
+}
+
+fn shortcut(key: Key, modifiers: Modifiers) -> Option<Message> {
+	match key.as_ref() {
+		Key::Character("o") if modifiers.command() => Some(Message::SourceChanged),
+		Key::Character("r") if modifiers.command() => Some(Message::RefreshDevices),
+		Key::Character("k") if modifiers.command() => Some(Message::TogglePalette),
+		Key::Character(",") if modifiers.command() => Some(Message::ToggleSettings),
+		Key::Character("t") if modifiers.command() => Some(Message::NewJob),
+		Key::Character("w") if modifiers.command() => Some(Message::PaletteCommand(Command::CloseJob)),
+		Key::Named(Named::Enter) if modifiers.command() => Some(Message::StartCopy),
+		Key::Named(Named::Escape) => Some(Message::Escape),
+		_ => None,
+	}
+}
+
+// Opens the native file picker in the user's home directory
+fn pick_file() -> Option<String> {
+	file_dialog()
+		.pick_file()
+		.map(|file| file.to_string_lossy().to_string())
+}
+
+fn file_dialog() -> FileDialog {
+	let user_home = match dirs::home_dir() {
+		Some(home) => home,
+		None => dirs::document_dir()
+		.unwrap_or_else(|| dirs::public_dir()
+			.unwrap()
+		),
+	};
+
+	FileDialog::new()
+		.set_directory(user_home.to_string_lossy().to_string())
+}
+
+// Nothing gets erased without a record of it. The hook for before a job may take a while to
+// decide, so it's asked along with the write rather than holding up the window, and a refusal
+// ends the job like a failed write
+async fn gated(audit: Option<audit::Entry>, write: impl Future<Output = Result<(), String>>) -> Result<(), String> {
+	if let Some(audit) = audit {
+		tokio::task::spawn_blocking(move || audit.started())
+			.await
+			.map_err(|e| format!("Audit log error: {e}"))??;
+	}
+
+	write.await
+}
+
+// Turns a progress channel into a stream so that every update reaches the UI
+fn progress_stream<T>(rx: mpsc::Receiver<T>) -> impl Stream<Item = T> {
+	stream::unfold(rx, |mut rx| async move {
+		rx.recv().await.map(|update| (update, rx))
+	})
+}
+
+// Hashes a file on the blocking pool, reporting the bytes read so far
+async fn hash_file(
+	path: String,
+	algorithm: Algorithm,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<u64>,
+) -> Result<String, String>
+{
+	tokio::task::spawn_blocking(move || {
+		let file = std::fs::File::open(&path).map_err(|e| format!("Source error: {e}"))?;
+
+		hash::hash_reader(file, algorithm, |bytes| {
+			let _ = progress_tx.blocking_send(bytes);
+			cancel_rx.try_recv().is_err()
+		})
+	})
+	.await
+	.map_err(|e| format!("Hashing failed: {e}"))?
+}
+
+pub fn title(iso_maker: &IsoMaker) -> String {
+	let job = iso_maker.active_job();
+
+	if job.is_copying {
+		format!("ISO Maker — {}", units::percent(job.progress, 0))
+	} else {
+		"ISO Maker".into()
+	}
+}
+
+pub fn view(iso_maker: &IsoMaker) -> Element<'_, Message> {
+	if let Some(kiosk) = &iso_maker.kiosk {
+		return kiosk.view().map(Message::Kiosk);
+	}
+
+	let job = iso_maker.active_job();
+
+	let controls = column![
+		text("ISO Maker")
+			.size(24)
+			.font(Font {
+				weight: Weight::Bold,
+				..Font::DEFAULT
+			}),
+
+		interrupted(iso_maker),
+		
+		profile_row(iso_maker),
+
+		text_input("Job label, e.g. a ticket number or who's running it (optional)", &job.job_label)
+			.on_input(Message::JobLabelChanged)
+			.padding(8),
+
+		row![
+			text_input("Path or URL of the image, Enter to use it", &job.source_input)
+				.on_input(Message::SourceInputChanged)
+				.on_submit(Message::UseSource)
+				.padding(8),
+			button("Pick Source")
+				.on_press(Message::SourceChanged)
+				.padding([8, 16]),
+			provider_picker(iso_maker, job),
+			test_boot(job, &job.source),
+		].spacing(20).align_y(Alignment::Center),
+
+		mode_row(job),
+
+		payload_list(job),
+
+		row![
+			text_input("Expected checksum (optional)", &job.checksum)
+				.on_input(Message::ChecksumChanged)
+				.padding(8),
+			pick_list(&Algorithm::ALL[..], Some(job.algorithm), Message::AlgorithmChanged),
+			button("Verify")
+				.on_press(Message::VerifySource)
+				.padding([8, 16]),
+		].spacing(20).align_y(Alignment::Center),
+
+		checksum_status(job),
+
+		row![
+			text("Destination"),
+			button("Refresh")
+				.on_press(Message::RefreshDevices)
+				.padding([8, 16]),
+		].spacing(20).align_y(Alignment::Center),
+
+		device_list(iso_maker, job),
+
+		write_to(iso_maker, job),
+
+		row![
+			text_input("Or type a device path, e.g. /dev/sdc", &job.dest_input)
+				.on_input(Message::DestInputChanged)
+				.on_submit(Message::UseDest)
+				.padding(8)
+				.width(300),
+			button("Use Device")
+				.style(button::secondary)
+				.on_press(Message::UseDest)
+				.padding([8, 16]),
+			text(match &job.manual_dest {
+				Some(device) if device.path == job.dest => device.to_string(),
+				_ => String::new(),
+			}),
+		].spacing(20).align_y(Alignment::Center),
+
+		row![
+			button("Start")
+				.on_press(Message::StartCopy)
+				.padding([8, 16]),
+
+			button("Verify Only")
+				.style(button::secondary)
+				.on_press(Message::StartVerify)
+				.padding([8, 16]),
+
+			button("Cancel")
+				.on_press(Message::Cancel)
+				.padding([8, 16]),
+		].spacing(20),
+
+		progress_bar(0.0..=1.0, job.progress)
+			.height(20),
+
+		result(job, iso_maker.udev_rule),
+	].spacing(20).padding(20);
+
+	let content = if iso_maker.settings_open {
+		column![job_tabs(iso_maker), settings(iso_maker)]
+	} else if let Some(Tool::Hash) = iso_maker.tool {
+		column![job_tabs(iso_maker), iso_maker.hash_tool.view().map(Message::HashTool)]
+	} else if let Some(Tool::Compare) = iso_maker.tool {
+		column![job_tabs(iso_maker), iso_maker.compare_tool.view(&iso_maker.devices).map(Message::CompareTool)]
+	} else if let Some(Tool::BootParams) = iso_maker.tool {
+		column![job_tabs(iso_maker), iso_maker.boot_tool.view(&iso_maker.devices).map(Message::BootTool)]
+	} else if let Some(Tool::Partitions) = iso_maker.tool {
+		column![job_tabs(iso_maker), iso_maker.partition_tool.view(&iso_maker.devices).map(Message::PartitionTool)]
+	} else if let Some(Tool::Format) = iso_maker.tool {
+		column![job_tabs(iso_maker), iso_maker.format_tool.view(&iso_maker.devices).map(Message::FormatTool)]
+	} else if let Some(Tool::CreateIso) = iso_maker.tool {
+		column![job_tabs(iso_maker), iso_maker.iso_tool.view().map(Message::IsoTool)]
+	} else if let Some(Tool::Browse) = iso_maker.tool {
+		column![job_tabs(iso_maker), iso_maker.browse_tool.view().map(Message::BrowseTool)]
+	} else if job.confirming {
+		column![job_tabs(iso_maker), confirmation(iso_maker, job)]
+	} else {
+		column![job_tabs(iso_maker), controls]
+	};
+
+	if iso_maker.palette_open {
+		return column![command_palette(iso_maker), content].into();
+	}
+
+	content.into()
+}
+
+// Nothing without providers set up, and below it whatever a provider couldn't list
+fn provider_picker<'a>(iso_maker: &'a IsoMaker, job: &Job) -> Element<'a, Message> {
+	let mut picker = column![].spacing(4);
+
+	if !iso_maker.provider_images.is_empty() {
+		let selected = iso_maker.provider_images.iter().find(|url| **url == job.source).cloned();
+		picker = picker.push(
+			pick_list(&iso_maker.provider_images[..], selected, Message::ProviderImagePicked)
+				.placeholder("Provider images"),
+		);
+	}
+
+	for e in &iso_maker.provider_errors {
+		picker = picker.push(status::warning(e).size(12));
+	}
+
+	picker.into()
+}
+
+fn profile_row(iso_maker: &IsoMaker) -> Element<'_, Message> {
+	let names: Vec<String> = iso_maker.config.profiles.iter().map(|profile| profile.name.clone()).collect();
+	let selected = names.iter().find(|name| **name == iso_maker.profile_name.trim()).cloned();
+
+	row![
+		pick_list(names, selected.clone(), Message::ProfileSelected)
+			.placeholder("Profiles"),
+		button("Run")
+			.on_press_maybe(selected.is_some().then_some(Message::RunProfile))
+			.padding([8, 16]),
+		text_input("Profile name", &iso_maker.profile_name)
+			.on_input(Message::ProfileNameChanged)
+			.on_submit(Message::SaveProfile)
+			.padding(8)
+			.width(200),
+		button("Save Profile")
+			.style(button::secondary)
+			.on_press(Message::SaveProfile)
+			.padding([8, 16]),
+		button("Delete")
+			.style(button::secondary)
+			.on_press_maybe(selected.is_some().then_some(Message::DeleteProfile))
+			.padding([8, 16]),
+		button("Load Template")
+			.style(button::secondary)
+			.on_press(Message::LoadTemplate)
+			.padding([8, 16]),
+	].spacing(20).align_y(Alignment::Center).into()
+}
+
+fn mode_row(job: &Job) -> Element<'_, Message> {
+	let mut mode = row![
+		text("Mode"),
+		pick_list(&Mode::ALL[..], Some(job.mode), Message::ModeChanged),
+	].spacing(20).align_y(Alignment::Center);
+
+	match job.mode {
+		Mode::WindowsToGo => {
+			mode = mode.push(
+				pick_list(&job.wim_images[..], job.wim_image.clone(), Message::WimImageChanged)
+					.placeholder("Windows edition")
+			);
+		},
+		Mode::FileCopy => {
+			mode = mode.push(tooltip(
+				text_input("Label (the ISO's)", &job.label)
+					.on_input(Message::LabelChanged)
+					.padding(8)
+					.width(200),
+				container(text("FAT32 label, up to 11 characters. Live systems that look for the ISO's label may not boot with another").size(12))
+					.padding(8)
+					.style(container::rounded_box),
+				tooltip::Position::Bottom,
+			));
+
+			let mut answer_file = row![
+				button("Answer File")
+					.on_press(Message::PickAnswerFile)
+					.padding([8, 16]),
+				text(job.answer_file.as_deref().unwrap_or("No autounattend.xml")),
+			].spacing(20).align_y(Alignment::Center);
+
+			if job.answer_file.is_some() {
+				answer_file = answer_file.push(clear_button(Message::ClearAnswerFile));
+			}
+
+			let mut automation = row![
+				button("Automation File")
+					.on_press(Message::PickAutomation)
+					.padding([8, 16]),
+			].spacing(20).align_y(Alignment::Center);
+
+			match &job.automation {
+				Some((kind, path)) => {
+					automation = automation
+						.push(pick_list(&Automation::ALL[..], Some(*kind), Message::AutomationKindChanged))
+						.push(text(path))
+						.push(clear_button(Message::ClearAutomation));
+				},
+				None => automation = automation.push(text("No preseed, kickstart or cloud-init file")),
+			}
+
+			return column![mode, answer_file, automation].spacing(10).into();
+		},
+		Mode::Image => {
+			let mut data_partition = row![
+				checkbox("Add an exFAT data partition in the free space", job.data_partition)
+					.on_toggle(Message::DataPartitionToggled),
+			].spacing(20).align_y(Alignment::Center);
+
+			if job.data_partition {
+				data_partition = data_partition.push(
+					text_input(DATA_LABEL, &job.data_label)
+						.on_input(Message::DataLabelChanged)
+						.padding(8)
+						.width(200)
+				);
+			}
+
+			let expand = checkbox("Grow the last partition to fill the device", job.expand)
+				.on_toggle(Message::ExpandToggled);
+
+			let hybrid: Element<'_, Message> = match job.layout {
+				Some(Layout::Plain(Some(_))) => checkbox("Add a partition table so it boots from USB (UEFI only)", job.hybridize)
+					.on_toggle(Message::HybridizeToggled)
+					.into(),
+				Some(Layout::Plain(None)) => status::danger("This ISO has no partition table or UEFI boot image, written raw it won't boot from USB. Try Copy files mode")
+					.into(),
+				_ => column![].into(),
+			};
+
+			let mut first_boot = row![
+				button("First Boot Script")
+					.on_press(Message::PickFirstBoot)
+					.padding([8, 16]),
+				text(job.first_boot.as_deref().unwrap_or("No script to run on the first boot")),
+			].spacing(20).align_y(Alignment::Center);
+
+			if job.first_boot.is_some() {
+				first_boot = first_boot.push(clear_button(Message::ClearFirstBoot));
+			}
+
+			return column![mode, data_partition, expand, hybrid, pi_options(job), first_boot].spacing(10).into();
+		},
+	}
+
+	mode.into()
+}
+
+fn pi_options(job: &Job) -> Element<'_, Message> {
+	let toggle = checkbox("Customize Raspberry Pi OS (hostname, SSH, Wi-Fi, user)", job.customize_pi)
+		.on_toggle(Message::PiToggled);
+
+	if !job.customize_pi {
+		return toggle.into();
+	}
+
+	let field = |placeholder, value, on_input: fn(String) -> Message| {
+		text_input(placeholder, value)
+			.on_input(on_input)
+			.padding(8)
+			.width(200)
+	};
+
+	column![
+		toggle,
+		row![
+			field("Hostname (the image's)", &job.pi.hostname, Message::PiHostnameChanged),
+			checkbox("Enable SSH", job.pi.ssh)
+				.on_toggle(Message::PiSshToggled),
+		].spacing(20).align_y(Alignment::Center),
+		row![
+			field("User name", &job.pi.user, Message::PiUserChanged),
+			field("Password", &job.pi.password, Message::PiPasswordChanged)
+				.secure(true),
+		].spacing(20).align_y(Alignment::Center),
+		row![
+			field("Wi-Fi network", &job.pi.ssid, Message::PiSsidChanged),
+			field("Wi-Fi password", &job.pi.wifi_password, Message::PiWifiPasswordChanged)
+				.secure(true),
+			text_input("Country", &job.pi.country)
+				.on_input(Message::PiCountryChanged)
+				.padding(8)
+				.width(80),
+		].spacing(20).align_y(Alignment::Center),
+	].spacing(10).padding(padding::left(20)).into()
+}
+
+fn payload_list(job: &Job) -> Element<'_, Message> {
+	if job.mode == Mode::WindowsToGo {
+		return column![].into();
+	}
+
+	let mut payload = column![
+		row![
+			text("Extra files"),
+			button("Add Files")
+				.on_press(Message::AddPayloadFiles)
+				.padding([8, 16]),
+			button("Add Folder")
+				.on_press(Message::AddPayloadFolder)
+				.padding([8, 16]),
+		].spacing(20).align_y(Alignment::Center),
+	].spacing(6);
+
+	for (i, path) in job.payload.iter().enumerate() {
+		payload = payload.push(
+			row![text(path).size(12), clear_button(Message::RemovePayload(i))]
+				.spacing(10)
+				.align_y(Alignment::Center)
+		);
+	}
+
+	payload.into()
+}
+
+fn result(job: &Job, udev_rule: bool) -> Element<'_, Message> {
+	if let Some(err) = &job.error {
+		if writer::is_permission_denied(err) {
+			return permission_help(job, err, udev_rule);
+		}
+
+		// Nothing wrong with the write itself when the stick was pulled out
+		let disconnected = writer::is_disconnected(err);
+		let mut failed = column![if disconnected { status::warning(err) } else { status::error(err) }].spacing(10);
+
+		// Where the last checkpoint left the device
+		let synced = job.synced.load(Ordering::Relaxed);
+		if synced > 0 && !job.verify_only {
+			failed = failed.push(text(format!("The first {} were synced to the device before it stopped", units::size(synced))).size(13));
+		}
+
+		// Closing whatever's using it is all it takes
+		if busy::is_busy(err) && !job.dest.is_empty() {
+			failed = failed.push(row![
+				button("Retry")
+					.on_press(Message::RetryBusy)
+					.padding([8, 16]),
+				text("Unmounts it and starts the job again").size(13),
+			].spacing(10).align_y(Alignment::Center));
+		}
+
+		if disconnected {
+			let hint: Element<'_, Message> = match job.resume {
+				Some((_, offset)) if job.dest_note == Some(RECONNECTED) && !job.dest.is_empty() => row![
+					button("Resume")
+						.on_press(Message::Resume)
+						.padding([8, 16]),
+					text(format!("Writes the rest of it from {} on, then verifies the whole device", units::size(offset))).size(13),
+				].spacing(10).align_y(Alignment::Center).into(),
+				Some(_) => text("Plug it back in to resume the write where it was cut off").size(13).into(),
+				None => text("Plug it back in, pick it again and press Start to write it again").size(13).into(),
+			};
+
+			failed = failed.push(hint);
+		}
+
+		if job.duplicate {
+			failed = failed.push(duplicating(job));
+		}
+
+		return failed.into();
+	}
+
+	if !job.is_copying && let Some(report) = &job.dry_run {
+		let mut lines = column![text("Dry run, nothing was written to the device:")].spacing(6);
+
+		for line in report {
+			lines = lines.push(text(format!("• {line}")).size(13));
+		}
+
+		return lines.into();
+	}
+
+	match (job.is_copying, job.progress) {
+		(false, _) if job.stopping => text("Cancelling, waiting for the write to let go of the device…").into(),
+		(true, _) => {
+			let synced = job.synced.load(Ordering::Relaxed);
+
+			let mut status = if job.phase == Phase::Syncing {
+				format!("{}: {} remaining", job.phase, units::size(job.unsynced))
+			} else if job.phase == Phase::Writing && synced > 0 {
+				format!("{}: {}, {} synced to the device", job.phase, units::percent(job.progress, 1), units::size(synced))
+			} else {
+				format!("{}: {}", job.phase, units::percent(job.progress, 1))
+			};
+
+			if let Some(estimate) = job.estimate.text() {
+				status = format!("{status} — {estimate}");
+			}
+
+			// Progress goes by the file, which for a compressed one is a fraction of what's written
+			let (read, file_len) = job.read;
+			let status: Element<Message> = match writer::unpacked(job.phase, read, job.unpacked.load(Ordering::Relaxed)) {
+				Some(unpacked) => column![
+					text(status),
+					text(match file_len {
+						// A download that didn't say how big it is
+						0 => format!("{} of the file read, {unpacked}", units::size(read)),
+						_ => format!("{} of {} of the file read, {unpacked}", units::size(read), units::size(file_len)),
+					}).size(13),
+				].spacing(4).into(),
+				None => text(status).into(),
+			};
+
+			if !job.stalled {
+				return status;
+			}
+
+			column![
+				status,
+				status::warning(format!("Nothing has happened for {} s, the device appears stalled", job.last_progress.elapsed().as_secs())),
+				row![
+					button("Abort")
+						.style(button::danger)
+						.on_press(Message::AbortStalled)
+						.padding([8, 16]),
+					text("Stops waiting for it, the device is left half written").size(13),
+				].spacing(10).align_y(Alignment::Center),
+			].spacing(10).into()
+		},
+		(false, 1.0) if job.verify_only => column![
+			status::success("Verified, the device matches the image"),
+			partition_table(job),
+		].spacing(10).into(),
+		(false, 1.0) if job.unchanged.load(Ordering::Relaxed) => column![
+			status::success("Already up to date, the device has the image and nothing was written"),
+			partition_table(job),
+		].spacing(10).into(),
+		(false, 1.0) => {
+			let mut result = column![status::success("Complete!")].spacing(10);
+
+			if let Some(rewritten) = &job.rewritten {
+				let rewritten = units::size(rewritten.load(Ordering::Relaxed));
+				result = result.push(text(format!("Only {rewritten} differed from what the device had and was written")).size(13));
+			}
+
+			// Windows To Go puts its own boot files on the stick
+			if job.mode != Mode::WindowsToGo && let Some(warning) = job.secure_boot.as_ref().and_then(SecureBoot::warning) {
+				result = result.push(status::warning(format!("Secure Boot: {warning}")));
+			}
+
+			match &job.uefi {
+				Some(Ok(boot)) => if let Some(warning) = boot.warning() {
+					result = result.push(status::warning(format!("UEFI: {warning}")));
+				},
+				// Mounting needs root, which a write through the udev rule doesn't have
+				Some(Err(e)) => result = result.push(text(format!("Couldn't check the UEFI boot files: {e}")).size(13)),
+				None => {},
+			}
+
+			result.push(duplicating(job)).push(test_boot(job, &job.dest)).push(partition_table(job)).into()
+		},
+		_ => text("Ready").into(),
+	}
+}
+
+// Writing each stick swapped in for the one just written, and how many have been
+fn duplicating(job: &Job) -> Element<'_, Message> {
+	let mut duplicating = row![
+		checkbox("Write each stick swapped in for this one", job.duplicate)
+			.on_toggle(Message::DuplicateToggled),
+	].spacing(20).align_y(Alignment::Center);
+
+	if job.duplicate {
+		let next = match job.swap {
+			Some(Swap::Out(..)) => ", take it out and plug in the next",
+			Some(Swap::In(_)) => ", plug in the next stick",
+			None => "",
+		};
+
+		let copies = match job.copies {
+			1 => "1 copy".to_string(),
+			copies => format!("{copies} copies"),
+		};
+		duplicating = duplicating.push(text(format!("{copies} written{next}")).size(13));
+	}
+
+	duplicating.into()
+}
+
+// Ways round a device only root can write to
+fn permission_help<'a>(job: &'a Job, err: &'a str, udev_rule: bool) -> Element<'a, Message> {
+	let mut help = column![status::error(err)].spacing(10);
+
+	// The steps after the write need root as well, and only run in this process
+	if job.mode == Mode::Image && (job.verify_only || job.customizations().is_empty()) {
+		help = help.push(row![
+			button("Retry as Root")
+				.on_press(Message::RetryAsRoot)
+				.padding([8, 16]),
+			text("Asks for the root password and writes with a root copy of iso_maker").size(13),
+		].spacing(10).align_y(Alignment::Center));
+	}
+
+	if cfg!(target_os = "linux") && !udev_rule {
+		help = help.push(row![
+			button("Install udev Rule")
+				.style(button::secondary)
+				.on_press(Message::InstallUdevRule)
+				.padding([8, 16]),
+			text("Lets you write to removable disks from now on, then press Start again").size(13),
+		].spacing(10).align_y(Alignment::Center));
+	}
+
+	help.push(
+		text(format!(
+			"Or run it from a terminal: sudo {} {} \"{}\" {}",
+			env!("CARGO_PKG_NAME"),
+			if job.verify_only { "verify" } else { "write" },
+			job.source,
+			job.dest,
+		)).size(13)
+	).into()
+}
+
+// Buttons booting `disk` in QEMU, when it's installed
+fn test_boot<'a>(job: &'a Job, disk: &str) -> Element<'a, Message> {
+	// Streamed sources aren't anywhere QEMU can open
+	if !qemu::available() || disk.is_empty() || source::is_url(disk) || job.is_copying {
+		return column![].into();
+	}
+
+	if job.test_booting {
+		return text("Test booting in QEMU, close its window to carry on").size(13).into();
+	}
+
+	let mut buttons = row![].spacing(10).align_y(Alignment::Center);
+	for firmware in qemu::firmwares() {
+		buttons = buttons.push(
+			button(text(format!("Test Boot ({firmware})")))
+				.style(button::secondary)
+				.on_press(Message::TestBoot(disk.to_string(), firmware))
+				.padding([8, 16])
+		);
+	}
+
+	if let Some(e) = &job.test_boot_error {
+		buttons = buttons.push(status::error(e).size(13));
+	}
+
+	buttons.into()
+}
+
+fn partition_table(job: &Job) -> Element<'_, Message> {
+	let partitions = match &job.partitions {
+		None => return text("Reading the partition table...").size(13).into(),
+		Some(Err(e)) => return status::warning(format!("Couldn't read the partition table: {e}")).size(13).into(),
+		Some(Ok(partitions)) if partitions.is_empty() => return text("The device has no partitions").size(13).into(),
+		Some(Ok(partitions)) => partitions,
+	};
+
+	let mut table = column![
+		row![
+			text("Partition").size(13).width(140),
+			text("Type").size(13).width(180),
+			text("Size").size(13).width(100),
+			text("Filesystem").size(13).width(100),
+			text("Label").size(13).width(140),
+			text("Boot").size(13),
+		].spacing(10),
+	].spacing(4);
+
+	for partition in partitions {
+		table = table.push(row![
+			text(&partition.path).size(13).width(140),
+			text(&partition.kind).size(13).width(180),
+			text(units::size(partition.size)).size(13).width(100),
+			text(partition.filesystem.as_deref().unwrap_or("-")).size(13).width(100),
+			text(partition.label.as_deref().unwrap_or("-")).size(13).width(140),
+			text(if partition.bootable { "Yes" } else { "" }).size(13),
+		].spacing(10));
+	}
+
+	table.into()
+}
+
+fn clear_button<'a>(message: Message) -> Element<'a, Message> {
+	button("x")
+		.style(button::secondary)
+		.on_press(message)
+		.padding([4, 8])
+		.into()
+}
+
+fn checksum_status(job: &Job) -> Element<'_, Message> {
+	if job.hashing {
+		return text(format!("Hashing source: {}", units::percent(job.hash_progress, 1))).into();
+	}
+
+	match &job.source_hash {
+		Some(digest) if job.checksum.trim().is_empty() => {
+			text(format!("{}: {digest}", job.algorithm)).size(12).into()
+		},
+		Some(digest) if hash::matches(digest, &job.checksum) => {
+			status::success(format!("{} checksum matches", job.algorithm)).into()
+		},
+		Some(digest) => {
+			status::error(format!("{} checksum does NOT match, got {digest}", job.algorithm)).into()
+		},
+		None => text("").into(),
+	}
+}
+
+fn confirmation<'a>(iso_maker: &'a IsoMaker, job: &'a Job) -> Element<'a, Message> {
+	let device = iso_maker.devices.iter()
+		.chain(&job.manual_dest)
+		.find(|device| device.path == job.dest);
+	let name = device.map_or(job.dest.clone(), |device| device.to_string());
+	let name = match job.partition() {
+		Some(partition) => format!("{partition}, on {name}"),
+		None => name,
+	};
+
+	let mut summary = column![
+		text("Confirm Write")
+			.size(24)
+			.font(Font {
+				weight: Weight::Bold,
+				..Font::DEFAULT
+			}),
+		text(format!("Source: {}", job.source)),
+		text(format!("Destination: {name}")),
+	].spacing(10);
+
+	if let (Mode::WindowsToGo, Some(image)) = (job.mode, &job.wim_image) {
+		summary = summary.push(text(format!("Windows To Go: {image}, the device will be repartitioned")));
+	}
+
+	if job.mode == Mode::FileCopy {
+		summary = summary.push(text("The device will be formatted as FAT32 and the files in the ISO copied onto it"));
+
+		if !job.label.is_empty() {
+			summary = summary.push(text(format!("Label: {}", partition::fat_label(&job.label))));
+		}
+
+		if let Some(answer_file) = &job.answer_file {
+			summary = summary.push(text(format!("Answer file: {answer_file}")));
+		}
+
+		if let Some((kind, path)) = &job.automation {
+			summary = summary.push(text(format!("{kind} file: {path}, added to the boot menu")));
+		}
+	}
+
+	if job.mode != Mode::WindowsToGo && let Some(secure_boot) = &job.secure_boot {
+		summary = summary.push(text(format!("UEFI loader {secure_boot}")));
+	}
+
+	if job.mode == Mode::Image && job.mbr().is_some() {
+		summary = summary.push(text("The ISO has no partition table, one pointing UEFI firmware at its boot image replaces its empty first sector"));
+	}
+
+	if job.mode == Mode::Image && let Some(bmap) = &job.bmap {
+		summary = summary.push(text(format!(
+			"Using the bmap next to the image, {} of {} will be written and checked",
+			units::size(bmap.mapped()), units::size(bmap.image_size),
+		)));
+	}
+
+	if let Some(pi) = job.pi_settings() {
+		let mut changes = Vec::new();
+
+		if !pi.hostname.is_empty() {
+			changes.push(format!("hostname {}", pi.hostname));
+		}
+		if pi.ssh {
+			changes.push("SSH on".to_string());
+		}
+		if !pi.user.is_empty() {
+			changes.push(format!("user {}", pi.user));
+		}
+		if !pi.ssid.is_empty() {
+			changes.push(format!("Wi-Fi {}", pi.ssid));
+		}
+
+		summary = summary.push(text(format!("Raspberry Pi OS gets {} on its first boot", changes.join(", "))));
+	}
+
+	if job.mode == Mode::Image && let Some(script) = &job.first_boot {
+		summary = summary.push(text(format!("{script} runs once on the first boot, from a systemd service on the image's root filesystem")));
+	}
+
+	if job.mode == Mode::Image && job.data_partition {
+		summary = summary.push(text(format!("The free space after the image becomes an exFAT partition labelled {}", job.data_label())));
+	}
+
+	if job.mode == Mode::Image && job.expand {
+		summary = summary.push(text("The last partition and its filesystem grow to fill the device"));
+	}
+
+	if job.mode != Mode::WindowsToGo && !job.payload.is_empty() {
+		summary = summary.push(text(format!("{} extra file(s) or folder(s) will be copied onto the stick", job.payload.len())));
+	}
+
+	if let Some(digest) = &job.source_hash && !job.checksum.trim().is_empty() && !hash::matches(digest, &job.checksum) {
+		summary = summary.push(
+			status::danger(format!("The source does not match the expected {} checksum", job.algorithm))
+		);
+	}
+
+	match device {
+		_ if job.partition().is_some() => {},
+		Some(device) if device.has_data() => {
+			let mut destroyed = column![
+				status::danger("WARNING: this device contains data that will be destroyed")
+					.size(18)
+					.font(Font {
+						weight: Weight::Bold,
+						..Font::DEFAULT
+					}),
+			].spacing(6);
+
+			for line in device.existing_data() {
+				destroyed = destroyed.push(text(format!("• {line}")));
+			}
+
+			let (used, unknown) = device.data_at_risk();
+			if used > 0 {
+				destroyed = destroyed.push(
+					status::danger(format!("~{} of data will be destroyed", units::size(used)))
+						.size(18)
+				);
+			}
+
+			if unknown > 0 {
+				destroyed = destroyed.push(text(format!("Usage of {unknown} filesystem(s) could not be determined")));
+			}
+
+			summary = summary.push(
+				container(destroyed)
+					.padding(12)
+					.style(container::bordered_box)
+			);
+		},
+		_ => summary = summary.push(text("Everything on the destination will be overwritten.")),
+	}
+
+	// Easy to pick the wrong one, they're only told apart by number
+	if let Some(partition) = job.partition() {
+		let mut overwritten = column![
+			status::danger(format!("Only {} will be overwritten", partition.path))
+				.size(18)
+				.font(Font {
+					weight: Weight::Bold,
+					..Font::DEFAULT
+				}),
+			text(format!(
+				"The rest of {} and its partition table are left as they are. The image has to fit in the partition's {}, and it should hold a filesystem rather than a whole disk.",
+				job.dest, units::size(partition.size),
+			)),
+		].spacing(6);
+
+		if let Some(filesystem) = &partition.filesystem {
+			let label = partition.label.as_deref().unwrap_or("unlabeled");
+			let used = partition.used.map(|used| format!(", {} used", units::size(used))).unwrap_or_default();
+			overwritten = overwritten.push(status::danger(format!("It holds a {filesystem} filesystem \"{label}\"{used}, which will be destroyed")));
+		}
+
+		summary = summary.push(
+			container(overwritten.push(
+				checkbox(format!("I'm sure {} is the partition to overwrite", partition.path), job.partition_confirmed)
+					.on_toggle(Message::PartitionConfirmed),
+			))
+				.padding(12)
+				.style(container::bordered_box)
+		);
+	}
+
+	let confirmed = (!job.needs_fixed_confirmation() || job.fixed_confirmed) && (job.partition().is_none() || job.partition_confirmed);
+	if job.needs_fixed_confirmation() {
+		summary = summary.push(
+			container(column![
+				status::danger(format!("{} IS NOT A REMOVABLE DEVICE", job.dest))
+					.size(18)
+					.font(Font {
+						weight: Weight::Bold,
+						..Font::DEFAULT
+					}),
+				text("It was typed in by hand and may well be an internal disk. Writing to the wrong one wipes an operating system or your files, check the model and size above."),
+				checkbox("I'm sure this is the disk to erase", job.fixed_confirmed)
+					.on_toggle(Message::FixedDiskConfirmed),
+			].spacing(6))
+				.padding(12)
+				.style(container::bordered_box)
+		);
+	}
+
+	column![
+		summary,
+		row![
+			button("Erase and Write")
+				.style(button::danger)
+				.on_press_maybe(confirmed.then_some(Message::ConfirmCopy))
+				.padding([8, 16]),
+			tooltip(
+				button("Dry Run")
+					.style(button::secondary)
+					.on_press_maybe((job.mode == Mode::Image).then_some(Message::DryRun))
+					.padding([8, 16]),
+				container(text("Goes through the write without writing anything, and says what it would do").size(12))
+					.padding(8)
+					.style(container::rounded_box),
+				tooltip::Position::Top,
+			),
+			button("Back")
+				.style(button::secondary)
+				.on_press(Message::BackFromConfirm)
+				.padding([8, 16]),
+		].spacing(20),
+	].spacing(20).padding(20).into()
+}
+
+fn device_list<'a>(iso_maker: &'a IsoMaker, job: &'a Job) -> Element<'a, Message> {
+	if iso_maker.devices.is_empty() {
+		return text("No removable devices found").into();
+	}
+
+	let entries = iso_maker.devices.iter().map(|device| {
+		let style = if device.path == job.dest { button::primary } else { button::secondary };
+
+		let label = match job.dest_note {
+			Some(note) if device.path == job.dest => format!("{device} ({note})"),
+			_ => device.to_string(),
+		};
+
+		let entry = button(text(label))
+			.style(style)
+			.on_press(Message::DestChanged(device.path.clone()))
+			.padding([6, 12]);
+
+		// Hovering shows what's on the device so it can be recognized before picking it
+		tooltip(
+			entry,
+			container(text(device.describe()).size(12))
+				.padding(8)
+				.style(container::rounded_box),
+			tooltip::Position::Right,
+		).into()
+	});
+
+	Column::with_children(entries).spacing(6).into()
+}
+
+// The whole disk or one of its partitions, for images that only hold a partition's filesystem
+fn write_to<'a>(iso_maker: &'a IsoMaker, job: &'a Job) -> Element<'a, Message> {
+	let device = iso_maker.devices.iter()
+		.chain(&job.manual_dest)
+		.find(|device| device.path == job.dest);
+
+	let Some(device) = device.filter(|device| job.mode == Mode::Image && !device.partitions.is_empty()) else {
+		return column![].into();
+	};
+
+	let choices: Vec<WriteTo> = std::iter::once(WriteTo::Disk)
+		.chain(device.partitions.iter().cloned().map(WriteTo::Partition))
+		.collect();
+	let picked = job.partition().cloned().map_or(WriteTo::Disk, WriteTo::Partition);
+
+	row![
+		text("Write to"),
+		pick_list(choices, Some(picked), Message::WriteToChanged),
+		text("A partition takes an image of just that filesystem, e.g. a rootfs").size(13),
+	].spacing(20).align_y(Alignment::Center).into()
+}
+
+fn job_tabs(iso_maker: &IsoMaker) -> Element<'_, Message> {
+	let tabs = iso_maker.jobs.iter().map(|job| {
+		let active = job.id == iso_maker.active && iso_maker.tool.is_none();
+		let style = if active { button::primary } else { button::secondary };
+
+		let mut tab = row![
+			button(text(job.label()))
+				.style(style)
+				.on_press(Message::SelectJob(job.id))
+				.padding([4, 12]),
+		];
+
+		if iso_maker.jobs.len() > 1 && !job.busy() {
+			tab = tab.push(
+				button("x")
+					.style(style)
+					.on_press(Message::CloseJob(job.id))
+					.padding([4, 8])
+			);
+		}
+
+		tab.into()
+	});
+
+	row![
+		Row::with_children(tabs).spacing(4),
+		button("+")
+			.on_press(Message::NewJob)
+			.padding([4, 12]),
+		horizontal_space(),
+		tool_tab("Hash a File", Tool::Hash, iso_maker.tool),
+		tool_tab("Compare", Tool::Compare, iso_maker.tool),
+		tool_tab("Boot Parameters", Tool::BootParams, iso_maker.tool),
+		tool_tab("Partitions", Tool::Partitions, iso_maker.tool),
+		tool_tab("Format", Tool::Format, iso_maker.tool),
+		tool_tab("Create ISO", Tool::CreateIso, iso_maker.tool),
+		tool_tab("ISO Contents", Tool::Browse, iso_maker.tool),
+	].spacing(8).padding([10, 20]).into()
+}
+
+fn tool_tab(label: &str, tool: Tool, open: Option<Tool>) -> Element<'_, Message> {
+	let style = if open == Some(tool) { button::primary } else { button::secondary };
+
+	button(text(label))
+		.style(style)
+		.on_press(Message::OpenTool(tool))
+		.padding([4, 12])
+		.into()
+}
+
+fn settings(iso_maker: &IsoMaker) -> Element<'_, Message> {
+	let config = &iso_maker.config;
+
+	let mut settings = column![
+		text("Settings")
+			.size(24)
+			.font(Font {
+				weight: Weight::Bold,
+				..Font::DEFAULT
+			}),
+
+		row![
+			text("Theme").width(120),
+			pick_list(config::theme_names(), Some(config.theme.clone()), Message::ThemeChanged),
+		].spacing(20).align_y(Alignment::Center),
+
+		row![
+			text("UI scale").width(120),
+			slider(config::SCALE_RANGE, config.scale_factor(), Message::ScaleChanged)
+				.step(0.25)
+				.width(200),
+			text(units::percent(config.scale_factor(), 0)),
+		].spacing(20).align_y(Alignment::Center),
+
+		checkbox("Color-blind-safe status colors", config.color_blind)
+			.on_toggle(Message::ColorBlindToggled),
+
+		row![
+			text("Sizes in").width(120),
+			pick_list(&Units::ALL[..], Some(config.units), Message::UnitsChanged),
+		].spacing(20).align_y(Alignment::Center),
+
+		row![
+			checkbox("Verify after writing", config.verify)
+				.on_toggle(Message::VerifyToggled),
+			pick_list(&Algorithm::ALL[..], Some(config.verify_algorithm), Message::VerifyAlgorithmChanged),
+		].spacing(20).align_y(Alignment::Center),
+
+		checkbox("Play a sound when a job completes or fails", config.sound)
+			.on_toggle(Message::SoundToggled),
+
+		checkbox("Skip the write when the device already has the image", config.skip_unchanged)
+			.on_toggle(Message::SkipUnchangedToggled),
+
+		checkbox("Only write the blocks that differ from the device (delta)", config.delta)
+			.on_toggle(Message::DeltaToggled),
+
+		checkbox("Memory-map uncompressed images (less CPU on big images)", config.mmap)
+			.on_toggle(Message::MmapToggled),
+
+		row![
+			text("Sync to the device every"),
+			text_input("0", &config.checkpoint_mb.to_string())
+				.on_input(Message::CheckpointChanged)
+				.width(80),
+			text("MB while writing (0 only syncs at the end)"),
+		].spacing(10).align_y(Alignment::Center),
+
+		row![
+			text("Warn when a write makes no progress for"),
+			text_input("0", &config.stall_secs.to_string())
+				.on_input(Message::StallSecsChanged)
+				.width(80),
+			text("seconds (0 never)"),
+		].spacing(10).align_y(Alignment::Center),
+	].spacing(20).padding(20);
+
+	if cfg!(target_os = "linux") {
+		settings = settings.push(device_access(iso_maker.udev_rule));
+	}
+
+	if mime::SUPPORTED {
+		settings = settings.push(file_types(iso_maker.file_types));
+	}
+
+	if config.theme == config::CUSTOM_THEME {
+		settings = settings
+			.push(color_input("Accent", &config.palette.accent, Message::AccentChanged))
+			.push(color_input("Background", &config.palette.background, Message::BackgroundChanged))
+			.push(color_input("Danger", &config.palette.danger, Message::DangerChanged));
+	}
+
+	if let Some(err) = &iso_maker.settings_error {
+		settings = settings.push(status::error(err));
+	}
+
+	settings
+		.push(
+			button("Done")
+				.on_press(Message::ToggleSettings)
+				.padding([8, 16])
+		)
+		.into()
+}
+
+// Writing needs root unless the udev rule hands the disks to the user's group
+fn device_access<'a>(installed: bool) -> Element<'a, Message> {
+	let (explanation, action) = if installed {
+		(
+			format!("Removable disks can be written without root, the udev rule gives the {} group, which you were added to, access to them. \
+				Log out and back in if writing still asks for root.", udev::GROUP),
+			button("Remove Rule").style(button::secondary).on_press(Message::RemoveUdevRule),
+		)
+	} else {
+		(
+			format!("Writing to a device needs root. Installing a udev rule gives an {} group access to removable and USB disks instead, \
+				and adds you to it, so iso_maker can write images to them as you once you log in again. Any program you run can then \
+				write to those disks too, other users can't. Installing it asks for the root password once.", udev::GROUP),
+			button("Install Rule").on_press(Message::InstallUdevRule),
+		)
+	};
+
+	column![
+		text("Device access"),
+		text(explanation).size(14),
+		action.padding([8, 16]),
+	].spacing(10).into()
+}
+
+// Jobs the last run didn't get to finish, and what can be done about them
+fn interrupted(iso_maker: &IsoMaker) -> Element<'_, Message> {
+	let mut list = column![].spacing(10);
+
+	for leftover in &iso_maker.interrupted {
+		let job = &leftover.job;
+		let serial = job.serial.as_ref().map(|serial| format!(" (serial {serial})")).unwrap_or_default();
+		let mut message = format!("A job writing {} to {}{serial} was cut off before it finished", job.source, job.dest);
+		if job.synced > 0 {
+			message.push_str(&format!(", the first {} were synced to the device", units::size(job.synced)));
+		}
+
+		// The stick is shown as it is now, like a write's confirmation shows it
+		if iso_maker.cleaning_up.as_ref() == Some(&leftover.path) && let Ok(device) = iso_maker.cleanup_target(leftover) {
+			let mut confirm = column![
+				status::danger(format!("The partition table and filesystem signatures on {device} will be wiped")),
+			].spacing(6);
+			for line in device.existing_data() {
+				confirm = confirm.push(text(format!("• {line}")));
+			}
+
+			list = list.push(column![
+				status::warning(message),
+				container(confirm).padding(12).style(container::bordered_box),
+				row![
+					button("Wipe").style(button::danger).on_press(Message::ConfirmCleanUp).padding([6, 12]),
+					button("Back").style(button::secondary).on_press(Message::BackFromCleanUp).padding([6, 12]),
+				].spacing(10),
+			].spacing(6));
+			continue;
+		}
+
+		let (again, action) = if job.resumable() { ("Resume", Recovery::Resume) } else { ("Write Again", Recovery::Rewrite) };
+		let mut actions = row![button(again).on_press(Message::Recover(leftover.path.clone(), action)).padding([6, 12])].spacing(10);
+
+		// A device that was formatted or had files copied onto it has nothing to compare with the image
+		if job.mode == Mode::Image {
+			actions = actions.push(
+				button("Verify").style(button::secondary).on_press(Message::Recover(leftover.path.clone(), Recovery::Verify)).padding([6, 12]),
+			);
+		}
+
+		actions = actions
+			.push(tooltip(
+				button("Clean Up").style(button::danger).on_press(Message::Recover(leftover.path.clone(), Recovery::CleanUp)).padding([6, 12]),
+				container(text("Wipes the partition table, so the half written device isn't taken for one that works").size(12))
+					.padding(8)
+					.style(container::rounded_box),
+				tooltip::Position::Bottom,
+			))
+			.push(button("Dismiss").style(button::secondary).on_press(Message::Recover(leftover.path.clone(), Recovery::Dismiss)).padding([6, 12]));
+
+		list = list.push(column![status::warning(message), actions].spacing(6));
+	}
+
+	if let Some(err) = &iso_maker.recovery_error {
+		list = list.push(status::error(err));
+	}
+
+	list.into()
+}
+
+fn file_types<'a>(registered: bool) -> Element<'a, Message> {
+	let (explanation, action) = if registered {
+		(
+			"Disk images open in iso_maker from the file manager, ready to pick a device for.",
+			button("Unregister").style(button::secondary).on_press(Message::UnregisterFileTypes),
+		)
+	} else {
+		(
+			"Registering makes iso_maker the app ISO and IMG files open with, so double-clicking one starts it with the image picked.",
+			button("Register").on_press(Message::RegisterFileTypes),
+		)
+	};
+
+	column![
+		text("Disk images"),
+		text(explanation).size(14),
+		action.padding([8, 16]),
+	].spacing(10).into()
+}
+
+fn color_input<'a>(label: &'a str, value: &'a str, on_input: fn(String) -> Message) -> Element<'a, Message> {
+	// Small swatch so the user can see what the hex value looks like
+	let swatch = container(text(""))
+		.width(24)
+		.height(24)
+		.style(move |_theme: &iced::Theme| container::Style {
+			background: Color::parse(value).map(Background::Color),
+			border: Border::default().rounded(4).width(1).color(Color::from_rgb(0.5, 0.5, 0.5)),
+			..container::Style::default()
+		});
+
+	row![
+		text(label).width(120),
+		text_input("#rrggbb", value)
+			.on_input(on_input)
+			.width(120)
+			.padding(6),
+		swatch,
+	].spacing(20).align_y(Alignment::Center).into()
+}
+
+fn command_palette(iso_maker: &IsoMaker) -> Element<'_, Message> {
+	let commands = Command::ALL.into_iter()
+		.filter(|command| command.matches(&iso_maker.palette_query))
+		.map(|command| {
+			button(
+				row![
+					text(command.label()),
+					text(command.shortcut()).size(12),
+				].spacing(20)
+			)
+				.on_press(Message::PaletteCommand(command))
+				.padding([4, 16])
+				.into()
+		});
+
+	column![
+		text_input("Type a command...", &iso_maker.palette_query)
+			.id(PALETTE_INPUT)
+			.on_input(Message::PaletteQueryChanged)
+			.on_submit(Message::PaletteSubmit)
+			.padding(8),
+		Column::with_children(commands).spacing(4),
+	].spacing(8).padding([10, 20]).into()
+}
+
+pub fn theme(iso_maker: &IsoMaker) -> iced::Theme {
+	iso_maker.config.theme()
+}
+
+pub fn scale_factor(iso_maker: &IsoMaker) -> f64 {
+	iso_maker.config.scale_factor()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{stick, FakeDevices, TempDir};
+
+	// The tasks it hands back would talk to the system, they're not run
+	fn send(iso_maker: &mut IsoMaker, message: Message) {
+		let _ = update(iso_maker, message);
+	}
+
+	// A window's worth of state over `devices`, with the defaults rather than the user's settings
+	fn app(devices: &FakeDevices) -> IsoMaker {
+		let mut iso_maker = IsoMaker::with_devices(Box::new(devices.clone()));
+		iso_maker.config = Config::default();
+
+		send(&mut iso_maker, Message::RefreshDevices);
+		iso_maker
+	}
+
+	#[test]
+	fn no_devices_leaves_the_destination_empty() {
+		let devices = FakeDevices::default();
+		let mut iso_maker = app(&devices);
+
+		assert!(iso_maker.devices.is_empty());
+		assert!(iso_maker.active_job().dest.is_empty());
+
+		send(&mut iso_maker, Message::StartCopy);
+		assert!(iso_maker.active_job().error.is_some());
+		assert!(!iso_maker.active_job().confirming);
+
+		let _ = view(&iso_maker);
+	}
+
+	#[test]
+	fn the_only_stick_is_picked() {
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let iso_maker = app(&devices);
+
+		let job = iso_maker.active_job();
+		assert_eq!(job.dest, "/dev/sdb");
+		assert_eq!(job.dest_note, Some(AUTO_SELECTED));
+	}
+
+	#[test]
+	fn many_sticks_leave_the_choice_to_the_user() {
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+
+		// Which one is meant isn't obvious any more once another's plugged in
+		let many = (b'b'..=b'k').map(|letter| stick(&format!("/dev/sd{}", letter as char), &letter.to_string())).collect();
+		devices.set(many);
+		send(&mut iso_maker, Message::RefreshDevices);
+
+		assert_eq!(iso_maker.devices.len(), 10);
+		assert!(iso_maker.active_job().dest.is_empty());
+		assert_eq!(iso_maker.active_job().dest_note, None);
+
+		let picked = iso_maker.devices[3].path.clone();
+		send(&mut iso_maker, Message::DestChanged(picked));
+		assert_eq!(iso_maker.active_job().dest, "/dev/sde");
+
+		let _ = view(&iso_maker);
+	}
+
+	#[test]
+	fn the_system_disk_isnt_picked() {
+		let mut system = stick("/dev/sda", "S");
+		system.partitions.push(devices::Partition {
+			path: "/dev/sda1".into(),
+			size: system.size,
+			label: None,
+			filesystem: Some("ext4".into()),
+			mount_points: vec!["/".into()],
+			used: None,
+		});
+
+		let devices = FakeDevices::new(vec![system, stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+		assert_eq!(iso_maker.active_job().dest, "/dev/sdb");
+
+		iso_maker.active_job_mut().dest_input = "/dev/sda".into();
+		send(&mut iso_maker, Message::UseDest);
+		assert_eq!(iso_maker.active_job().dest, "/dev/sdb");
+		assert!(iso_maker.active_job().error.as_ref().is_some_and(|e| e.contains("running system")));
+	}
+
+	#[test]
+	fn providers_that_cant_list_their_images_say_why() {
+		let devices = FakeDevices::default();
+		let mut iso_maker = app(&devices);
+
+		let unsigned = "Catalog error: the checksum file isn't signed with the key in /etc/fedora.asc, it can't be trusted";
+		send(&mut iso_maker, Message::ProviderImagesLoaded(vec!["ubuntu://noble".into()], vec![unsigned.into()]));
+		assert_eq!(iso_maker.provider_images, ["ubuntu://noble"]);
+		assert_eq!(iso_maker.provider_errors, [unsigned]);
+		let _ = view(&iso_maker);
+	}
+
+	#[test]
+	fn growing_the_last_partition_and_adding_one_take_turns() {
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+
+		send(&mut iso_maker, Message::DataPartitionToggled(true));
+		send(&mut iso_maker, Message::ExpandToggled(true));
+		let customizations = iso_maker.active_job().customizations();
+		assert!(customizations.expand && customizations.data_partition.is_none());
+
+		send(&mut iso_maker, Message::DataPartitionToggled(true));
+		assert!(!iso_maker.active_job().customizations().expand);
+		let _ = view(&iso_maker);
+	}
+
+	#[test]
+	fn a_partition_is_only_written_once_its_confirmed() {
+		let dir = TempDir::new("app-partition");
+		let source = dir.file("rootfs.img");
+		std::fs::write(&source, b"rootfs").unwrap();
+
+		let mut sdb = stick("/dev/sdb", "A");
+		let rootfs = devices::Partition {
+			path: "/dev/sdb2".into(),
+			size: 4_000_000_000,
+			label: Some("rootfs".into()),
+			filesystem: Some("ext4".into()),
+			mount_points: Vec::new(),
+			used: None,
+		};
+		sdb.partitions.push(rootfs.clone());
+
+		let devices = FakeDevices::new(vec![sdb.clone()]);
+		let mut iso_maker = app(&devices);
+		let job = iso_maker.active_job_mut();
+		job.source = source.clone();
+		job.source_input = source;
+
+		send(&mut iso_maker, Message::WriteToChanged(WriteTo::Partition(rootfs)));
+		assert_eq!(iso_maker.active_job().write_path(), "/dev/sdb2");
+		assert_eq!(iso_maker.active_job().mbr(), None);
+
+		send(&mut iso_maker, Message::StartCopy);
+		send(&mut iso_maker, Message::ConfirmCopy);
+		assert!(iso_maker.active_job().confirming && !iso_maker.active_job().is_copying);
+		send(&mut iso_maker, Message::PartitionConfirmed(true));
+		let _ = view(&iso_maker);
+
+		// Repartitioned since, it has to be picked again
+		send(&mut iso_maker, Message::BackFromConfirm);
+		sdb.partitions.clear();
+		devices.set(vec![sdb]);
+		send(&mut iso_maker, Message::StartCopy);
+		assert!(!iso_maker.active_job().confirming);
+		assert_eq!(iso_maker.active_job().write_path(), "/dev/sdb");
+	}
+
+	#[test]
+	fn a_stick_pulled_out_mid_job_can_be_resumed() {
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+		let id = iso_maker.active;
+
+		// What ConfirmCopy leaves behind, without starting a real write
+		let job = iso_maker.active_job_mut();
+		job.source = "image.iso".into();
+		job.is_copying = true;
+		job.dest_serial = Some("A".into());
+		job.synced.store(1024 * 1024, Ordering::Relaxed);
+
+		// The list going empty doesn't take the device away from the write
+		devices.set(Vec::new());
+		send(&mut iso_maker, Message::RefreshDevices);
+		assert_eq!(iso_maker.active_job().dest, "/dev/sdb");
+
+		let error = "Disconnected: /dev/sdb went away during the write, it was unplugged or lost power";
+		send(&mut iso_maker, Message::CopyComplete(id, 0, Err(error.into())));
+		assert_eq!(iso_maker.active_job().resume, Some(("A".into(), 1024 * 1024)));
+
+		// Nothing's there to write to until it's back
+		send(&mut iso_maker, Message::RefreshDevices);
+		assert!(iso_maker.active_job().dest.is_empty());
+		let _ = view(&iso_maker);
+
+		// It comes back under another name, and a different stick isn't taken for it
+		devices.set(vec![stick("/dev/sdc", "B"), stick("/dev/sdd", "A")]);
+		send(&mut iso_maker, Message::RefreshDevices);
+
+		let job = iso_maker.active_job();
+		assert_eq!(job.dest, "/dev/sdd");
+		assert_eq!(job.dest_note, Some(RECONNECTED));
+
+		send(&mut iso_maker, Message::Resume);
+		let job = iso_maker.active_job();
+		assert!(job.confirming);
+		assert_eq!(job.resume_from, 1024 * 1024);
+	}
+
+	#[test]
+	fn duplicating_writes_each_stick_swapped_in() {
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A"), stick("/dev/sdc", "B")]);
+		let mut iso_maker = app(&devices);
+		let id = iso_maker.active;
+
+		let job = iso_maker.active_job_mut();
+		job.dest = "/dev/sdb".into();
+		job.dest_serial = Some("A".into());
+		job.is_copying = true;
+		send(&mut iso_maker, Message::CopyComplete(id, 0, Ok(())));
+		send(&mut iso_maker, Message::DuplicateToggled(true));
+		assert_eq!(iso_maker.active_job().copies, 1);
+
+		// Nothing starts while the written stick is still in, the other one was there all along
+		send(&mut iso_maker, Message::RefreshDevices);
+		assert!(!iso_maker.active_job().confirming);
+
+		devices.set(vec![stick("/dev/sdc", "B")]);
+		send(&mut iso_maker, Message::RefreshDevices);
+		assert!(!iso_maker.active_job().confirming);
+		let _ = view(&iso_maker);
+
+		// The next one can come back at the same path
+		devices.set(vec![stick("/dev/sdb", "C"), stick("/dev/sdc", "B")]);
+		send(&mut iso_maker, Message::RefreshDevices);
+
+		let job = iso_maker.active_job();
+		assert!(job.confirming);
+		assert_eq!((job.dest.as_str(), job.dest_note), ("/dev/sdb", Some(NEXT_COPY)));
+	}
+
+	#[test]
+	fn duplicating_goes_on_in_a_tab_opened_after_another_was_closed() {
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+		let first = iso_maker.active;
+		send(&mut iso_maker, Message::NewJob);
+		send(&mut iso_maker, Message::CloseJob(first));
+
+		// The one job left isn't at the index its id would be
+		let job = iso_maker.active_job_mut();
+		assert_ne!(job.id, 0);
+		job.dest = "/dev/sdb".into();
+		job.duplicate = true;
+		job.swap = Some(Swap::Out("/dev/sdb".into(), Some("A".into())));
+
+		devices.set(Vec::new());
+		send(&mut iso_maker, Message::RefreshDevices);
+		devices.set(vec![stick("/dev/sdb", "B")]);
+		send(&mut iso_maker, Message::RefreshDevices);
+
+		let job = iso_maker.active_job();
+		assert!(job.confirming);
+		assert_eq!((job.dest.as_str(), job.dest_note), ("/dev/sdb", Some(NEXT_COPY)));
+	}
+
+	#[test]
+	fn a_failed_write_isnt_resumable() {
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+		let id = iso_maker.active;
+
+		let job = iso_maker.active_job_mut();
+		job.is_copying = true;
+		job.dest_serial = Some("A".into());
+		job.synced.store(1024 * 1024, Ordering::Relaxed);
+
+		send(&mut iso_maker, Message::CopyComplete(id, 0, Err("Write error: Input/output error (os error 5)".into())));
+		assert_eq!(iso_maker.active_job().resume, None);
+	}
+
+	#[test]
+	fn a_cancelled_job_keeps_its_device_until_the_write_stops() {
+		let dir = TempDir::new("app-cancel");
+		let source = dir.file("image.img");
+		std::fs::write(&source, b"image").unwrap();
+
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+		let id = iso_maker.active;
+
+		let job = iso_maker.active_job_mut();
+		job.source = source.clone();
+		job.source_input = source.clone();
+		job.is_copying = true;
+		let run = job.next_run();
+
+		send(&mut iso_maker, Message::Cancel);
+		assert!(iso_maker.active_job().stopping);
+		let _ = view(&iso_maker);
+
+		// Neither it nor another tab starts on the device, and it can't be closed
+		send(&mut iso_maker, Message::StartCopy);
+		assert!(!iso_maker.active_job().confirming);
+
+		send(&mut iso_maker, Message::NewJob);
+		let other = iso_maker.active_job_mut();
+		other.source = source.clone();
+		other.source_input = source;
+		assert_eq!(other.dest, "/dev/sdb");
+		send(&mut iso_maker, Message::StartCopy);
+		assert!(iso_maker.active_job().error.as_ref().is_some_and(|e| e.contains("already being written")));
+
+		send(&mut iso_maker, Message::CloseJob(id));
+		assert_eq!(iso_maker.jobs.len(), 2);
+
+		// An earlier run finishing late isn't this one
+		send(&mut iso_maker, Message::CopyComplete(id, run - 1, Ok(())));
+		assert!(iso_maker.jobs[0].stopping);
+
+		send(&mut iso_maker, Message::CopyComplete(id, run, Err("Cancelled".into())));
+		assert!(!iso_maker.jobs[0].busy());
+		send(&mut iso_maker, Message::CloseJob(id));
+		assert_eq!(iso_maker.jobs.len(), 1);
+	}
+
+	#[test]
+	fn a_stalled_sync_is_noticed_and_aborting_keeps_the_device_busy() {
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+		iso_maker.config.stall_secs = 30;
+		let id = iso_maker.active;
+
+		let job = iso_maker.active_job_mut();
+		job.is_copying = true;
+		let run = job.next_run();
+
+		// The sync saying the same thing over and over isn't getting anywhere
+		send(&mut iso_maker, Message::CopyProgress(id, run, Phase::Syncing, 10, 100));
+		let a_while_ago = Instant::now().checked_sub(Duration::from_secs(60)).unwrap();
+		iso_maker.active_job_mut().last_progress = a_while_ago;
+		send(&mut iso_maker, Message::CopyProgress(id, run, Phase::Syncing, 10, 100));
+		send(&mut iso_maker, Message::CheckStalled);
+		assert!(iso_maker.active_job().stalled);
+
+		send(&mut iso_maker, Message::CopyProgress(id, run, Phase::Syncing, 20, 100));
+		send(&mut iso_maker, Message::CheckStalled);
+		assert!(!iso_maker.active_job().stalled);
+
+		// Given up on, the write still has the device until it returns
+		iso_maker.active_job_mut().last_progress = a_while_ago;
+		send(&mut iso_maker, Message::CheckStalled);
+		send(&mut iso_maker, Message::AbortStalled);
+		send(&mut iso_maker, Message::NewJob);
+		iso_maker.active_job_mut().dest = "/dev/sdb".into();
+		assert!(iso_maker.dest_busy());
+
+		send(&mut iso_maker, Message::CopyComplete(id, run, Err("Sync error: /dev/sdb: Input/output error (os error 5)".into())));
+		assert!(!iso_maker.dest_busy());
+		assert!(iso_maker.jobs[0].error.as_ref().is_some_and(|e| e.starts_with("Aborted")));
+	}
+
+	#[test]
+	fn the_pre_write_hook_is_asked_along_with_the_write() {
+		let dir = TempDir::new("app-hook");
+		let config = Config {
+			audit_log: Some(dir.file("audit.log").into()),
+			pre_write_hook: Some("test \"$ISO_MAKER_SERIAL\" = B || { echo \"$ISO_MAKER_SERIAL isn't approved\" >&2; exit 1; }".into()),
+			..Config::default()
+		};
+		let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+		let write = |serial: &str| {
+			let audit = Some(audit::Entry::new(&config, audit::Operation::Write, "/dev/sdb", Some(serial.into()), "/tmp/image.iso"));
+			let written = Arc::new(AtomicBool::new(false));
+			let writing = written.clone();
+			let result = runtime.block_on(gated(audit.clone(), async move {
+				writing.store(true, Ordering::Relaxed);
+				Ok(())
+			}));
+
+			(audit, result, written.load(Ordering::Relaxed))
+		};
+
+		// Turned down, the device is never touched
+		let (audit, result, written) = write("A");
+		assert!(!written && result.as_ref().is_err_and(|e| crate::hooks::refused(e)));
+
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+		let id = iso_maker.active;
+		let job = iso_maker.active_job_mut();
+		job.audit = audit;
+		job.is_copying = true;
+		let run = job.next_run();
+		send(&mut iso_maker, Message::CopyComplete(id, run, result));
+		assert!(iso_maker.active_job().error.as_ref().is_some_and(|e| e.ends_with("A isn't approved")));
+
+		// It's in the log as refused, and only that
+		let log = std::fs::read_to_string(dir.file("audit.log")).unwrap();
+		assert_eq!(log.lines().map(|line| line.rsplit('\t').next().unwrap().split(':').next().unwrap()).collect::<Vec<_>>(), ["refused"]);
+
+		let (_, result, written) = write("B");
+		assert!(written && result.is_ok());
+	}
+
+	#[test]
+	fn an_image_opened_from_outside_gets_a_job() {
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "A")]);
+		let mut iso_maker = app(&devices);
+		iso_maker.settings_open = true;
+
+		send(&mut iso_maker, Message::OpenImage("/tmp/first.iso".into()));
+		assert!(!iso_maker.settings_open);
+		assert_eq!(iso_maker.jobs.len(), 1);
+		assert_eq!(iso_maker.active_job().source, "/tmp/first.iso");
+		assert_eq!(iso_maker.active_job().dest, "/dev/sdb");
+
+		// It isn't there, which the job says rather than failing once it's started
+		assert!(iso_maker.active_job().error.as_ref().is_some_and(|e| e.starts_with("Can't open /tmp/first.iso")));
+
+		// One that's being written keeps its image
+		iso_maker.active_job_mut().is_copying = true;
+		send(&mut iso_maker, Message::OpenImage("/tmp/second.iso".into()));
+		assert_eq!(iso_maker.jobs.len(), 2);
+		assert_eq!(iso_maker.jobs[0].source, "/tmp/first.iso");
+		assert_eq!(iso_maker.active_job().source, "/tmp/second.iso");
+	}
+
+	#[test]
+	fn a_write_cut_off_by_a_crash_can_be_resumed() {
+		let dir = TempDir::new("app-recovery");
+		let store = recovery::Store::at(Some(dir.file("jobs").into()));
+		let interrupted = Interrupted {
+			source: "/tmp/image.iso".into(),
+			dest: "/dev/sdb".into(),
+			serial: Some("A".into()),
+			mode: Mode::Image,
+			synced: 1 << 20,
+		};
+
+		// The app went down with the write, it never got to finish the record
+		let _ = store.start(interrupted.clone());
+
+		let devices = FakeDevices::default();
+		let mut iso_maker = app(&devices);
+		iso_maker.interrupted = store.leftover();
+		let path = iso_maker.interrupted[0].path.clone();
+		let _ = view(&iso_maker);
+
+		send(&mut iso_maker, Message::Recover(path.clone(), Recovery::Resume));
+		assert!(iso_maker.recovery_error.is_some());
+		assert_eq!(iso_maker.interrupted.len(), 1);
+
+		// Back after a reboot, under another name
+		devices.set(vec![stick("/dev/sdc", "A")]);
+		send(&mut iso_maker, Message::RefreshDevices);
+		send(&mut iso_maker, Message::Recover(path, Recovery::Resume));
+
+		let job = iso_maker.active_job();
+		assert_eq!(job.source, "/tmp/image.iso");
+		assert_eq!(job.dest, "/dev/sdc");
+		assert_eq!(job.resume, Some(("A".into(), 1 << 20)));
+		assert!(iso_maker.interrupted.is_empty());
+		assert!(store.leftover().is_empty());
+	}
+
+	#[test]
+	fn only_the_same_removable_stick_is_wiped_and_only_once_confirmed() {
+		let dir = TempDir::new("app-cleanup");
+		let store = recovery::Store::at(Some(dir.file("jobs").into()));
+		let cut_off = |dest: &str, serial: Option<&str>| Interrupted {
+			source: "/tmp/image.iso".into(),
+			dest: dest.into(),
+			serial: serial.map(Into::into),
+			mode: Mode::Image,
+			synced: 0,
+		};
+		let _ = store.start(cut_off("/dev/sdb", None));
+		let _ = store.start(cut_off("/dev/sdb", Some("A")));
+
+		// Another stick has the path now
+		let devices = FakeDevices::new(vec![stick("/dev/sdb", "B")]);
+		let mut iso_maker = app(&devices);
+		iso_maker.interrupted = store.leftover();
+		let [no_serial, path] = [None, Some("A")].map(|serial| iso_maker.interrupted.iter()
+			.find(|leftover| leftover.job.serial.as_deref() == serial)
+			.unwrap().path.clone());
+
+		for path in [&no_serial, &path] {
+			send(&mut iso_maker, Message::Recover(path.clone(), Recovery::CleanUp));
+			assert!(iso_maker.recovery_error.is_some());
+			assert!(iso_maker.cleaning_up.is_none());
+		}
+
+		// Back, but as a fixed disk
+		devices.set(vec![stick("/dev/sdb", "B"), Device { removable: false, ..stick("/dev/sdc", "A") }]);
+		send(&mut iso_maker, Message::RefreshDevices);
+		send(&mut iso_maker, Message::Recover(path.clone(), Recovery::CleanUp));
+		assert!(iso_maker.recovery_error.as_ref().is_some_and(|e| e.contains("removable")));
+
+		// The stick itself is asked about first
+		devices.set(vec![stick("/dev/sdb", "B"), stick("/dev/sdc", "A")]);
+		send(&mut iso_maker, Message::RefreshDevices);
+		send(&mut iso_maker, Message::Recover(path.clone(), Recovery::CleanUp));
+		assert!(iso_maker.recovery_error.is_none());
+		assert_eq!(iso_maker.cleaning_up, Some(path));
+		let _ = view(&iso_maker);
+
+		// Swapped out before it was confirmed
+		devices.set(vec![stick("/dev/sdb", "B")]);
+		send(&mut iso_maker, Message::ConfirmCleanUp);
+		assert!(iso_maker.recovery_error.is_some());
+		assert_eq!(iso_maker.interrupted.len(), 2);
+	}
+}
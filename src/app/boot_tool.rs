@@ -0,0 +1,150 @@
+// Adds kernel parameters (nomodeset, console=...) to the boot menus on a written stick.
+
+use iced::font::Weight;
+use iced::widget::{button, column, pick_list, row, text, text_input, Column};
+use iced::{Alignment, Element, Font, Task};
+
+use crate::bootcfg::{self, KernelLine};
+use crate::devices::{self, Device};
+use super::status;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+	DeviceChanged(Device),
+	ParamsChanged(String),
+	Scan,
+	Apply,
+	Done(Result<Vec<KernelLine>, String>),
+}
+
+#[derive(Default)]
+pub struct BootTool {
+	device: Option<Device>,
+	params: String,
+	busy: bool,
+	lines: Vec<KernelLine>,
+	/// Whether `lines` are from after applying the parameters
+	applied: bool,
+	error: Option<String>,
+}
+
+impl BootTool {
+	/// Starts with `device` picked, unless something else already is.
+	pub fn preselect(&mut self, device: Option<&Device>) {
+		if self.device.is_none() {
+			self.device = device.cloned();
+		}
+	}
+
+	pub fn update(&mut self, message: Message) -> Task<Message> {
+		match message {
+			Message::DeviceChanged(device) => {
+				self.device = Some(device);
+				self.lines.clear();
+				self.error = None;
+			},
+			Message::ParamsChanged(params) => self.params = params,
+			Message::Scan | Message::Apply => {
+				if self.busy {
+					return Task::none();
+				}
+
+				let Some(device) = self.device.clone() else {
+					self.error = Some("Pick the device to edit".into());
+					return Task::none();
+				};
+
+				let params: Vec<String> = match message {
+					Message::Apply => self.params.split_whitespace().map(String::from).collect(),
+					_ => Vec::new(),
+				};
+
+				if matches!(message, Message::Apply) && params.is_empty() {
+					self.error = Some("Type the parameters to add, e.g. nomodeset".into());
+					return Task::none();
+				}
+
+				self.busy = true;
+				self.applied = !params.is_empty();
+				self.error = None;
+
+				// Mounting and unmounting blocks, and the partitions may have changed since the device was picked
+				let edit = async move {
+					tokio::task::spawn_blocking(move || {
+						let device = devices::list().into_iter()
+							.find(|current| current.path == device.path)
+							.ok_or_else(|| format!("{} is no longer connected", device.path))?;
+
+						bootcfg::edit_device(&device, &params)
+					})
+					.await
+					.map_err(|e| format!("Boot config error: {e}"))?
+				};
+
+				return Task::perform(edit, Message::Done);
+			},
+			Message::Done(result) => {
+				self.busy = false;
+
+				match result {
+					Ok(lines) => self.lines = lines,
+					Err(e) => {
+						self.lines.clear();
+						self.error = Some(e);
+					},
+				}
+			},
+		}
+
+		Task::none()
+	}
+
+	pub fn view<'a>(&'a self, devices: &'a [Device]) -> Element<'a, Message> {
+		let status: Element<'_, Message> = if let Some(err) = &self.error {
+			status::error(err).into()
+		} else if self.busy {
+			text("Working...").into()
+		} else if self.lines.is_empty() {
+			text("Scan to see the kernel lines on the device").into()
+		} else if self.applied {
+			status::success(format!("Updated, {} kernel lines:", self.lines.len())).into()
+		} else {
+			text(format!("{} kernel lines:", self.lines.len())).into()
+		};
+
+		let lines = self.lines.iter().map(|line| {
+			text(format!("{} {}: {}", line.partition, line.file, line.line)).size(12).into()
+		});
+
+		column![
+			text("Boot Parameters")
+				.size(24)
+				.font(Font {
+					weight: Weight::Bold,
+					..Font::DEFAULT
+				}),
+
+			row![
+				pick_list(devices, self.device.clone(), Message::DeviceChanged)
+					.placeholder("Device"),
+				button("Scan")
+					.on_press(Message::Scan)
+					.padding([8, 16]),
+			].spacing(20).align_y(Alignment::Center),
+
+			row![
+				text_input("Parameters to add, e.g. nomodeset console=ttyS0,115200", &self.params)
+					.on_input(Message::ParamsChanged)
+					.on_submit(Message::Apply)
+					.padding(8),
+				button("Add")
+					.on_press(Message::Apply)
+					.padding([8, 16]),
+			].spacing(20).align_y(Alignment::Center),
+
+			status,
+
+			Column::with_children(lines).spacing(4),
+		].spacing(20).padding(20).into()
+	}
+}
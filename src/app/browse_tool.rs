@@ -0,0 +1,263 @@
+// Read-only view of the files inside an ISO, to check what's on it before writing or remastering,
+// and to copy some or all of them out.
+
+use std::collections::HashSet;
+
+use iced::font::Weight;
+use iced::widget::{button, column, progress_bar, row, scrollable, text, Column};
+use iced::{padding, Alignment, Element, Font, Length, Task};
+use tokio::sync::mpsc;
+
+use crate::isofs::{self, Entry};
+use crate::units;
+use super::status;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+	Pick,
+	Loaded(Result<Entry, String>),
+	Toggle(String),
+	Select(String),
+	/// The selection, or the whole image with `true`
+	Extract(bool),
+	Progress(u64),
+	/// The folder extracted to
+	Extracted(Result<String, String>),
+	Cancel,
+}
+
+#[derive(Default)]
+pub struct BrowseTool {
+	path: String,
+	root: Option<Entry>,
+	/// Paths of the directories shown open
+	expanded: HashSet<String>,
+	/// Path of the entry to extract
+	selected: Option<String>,
+	loading: bool,
+	extracting: bool,
+	total: u64,
+	progress: f32,
+	/// Folder the last extraction went to
+	extracted: Option<String>,
+	error: Option<String>,
+	cancel_tx: Option<mpsc::Sender<()>>,
+}
+
+impl BrowseTool {
+	/// Shows the contents of the ISO at `path`, unless it's already the one shown.
+	pub fn open(&mut self, path: &str) -> Task<Message> {
+		if path.is_empty() || path == self.path || crate::source::is_url(path) {
+			return Task::none();
+		}
+
+		self.path = path.to_string();
+		self.root = None;
+		self.expanded.clear();
+		self.selected = None;
+		self.extracted = None;
+		self.loading = true;
+		self.error = None;
+
+		let path = path.to_string();
+		let read = async move {
+			tokio::task::spawn_blocking(move || isofs::read_tree(&path))
+				.await
+				.map_err(|e| format!("ISO error: {e}"))?
+		};
+
+		Task::perform(read, Message::Loaded)
+	}
+
+	pub fn update(&mut self, message: Message) -> Task<Message> {
+		match message {
+			Message::Pick => {
+				if let Some(path) = super::pick_file() {
+					// Picking the same file again reloads it
+					self.path.clear();
+					return self.open(&path);
+				}
+			},
+			Message::Loaded(result) => {
+				self.loading = false;
+
+				match result {
+					Ok(root) => self.root = Some(root),
+					Err(e) => self.error = Some(e),
+				}
+			},
+			Message::Toggle(path) => {
+				if !self.expanded.remove(&path) {
+					self.expanded.insert(path.clone());
+				}
+
+				self.selected = Some(path);
+			},
+			Message::Select(path) => self.selected = Some(path),
+			Message::Extract(all) => {
+				if self.extracting {
+					return Task::none();
+				}
+
+				let entry = match (&self.root, &self.selected) {
+					(Some(root), _) if all => root.clone(),
+					(Some(root), Some(selected)) => match root.find(selected) {
+						Some(entry) => entry.clone(),
+						None => return Task::none(),
+					},
+					_ => {
+						self.error = Some("Pick what to extract".into());
+						return Task::none();
+					},
+				};
+
+				let Some(folder) = super::file_dialog().pick_folder() else {
+					return Task::none();
+				};
+
+				self.extracting = true;
+				self.total = entry.size;
+				self.progress = 0.;
+				self.extracted = None;
+				self.error = None;
+
+				let (cancel_tx, mut cancel_rx) = mpsc::channel(1);
+				let (progress_tx, progress_rx) = mpsc::channel(100);
+				self.cancel_tx = Some(cancel_tx);
+
+				let iso = self.path.clone();
+				let extract = async move {
+					tokio::task::spawn_blocking(move || {
+						isofs::extract(&iso, &entry, &folder, &mut |copied| {
+							let _ = progress_tx.blocking_send(copied);
+							cancel_rx.try_recv().is_err()
+						})
+						.map(|()| folder.to_string_lossy().to_string())
+					})
+					.await
+					.map_err(|e| format!("Extract error: {e}"))?
+				};
+
+				return Task::batch(vec![
+					Task::perform(extract, Message::Extracted),
+					Task::run(super::progress_stream(progress_rx), Message::Progress),
+				]);
+			},
+			Message::Progress(copied) => {
+				if self.total > 0 {
+					self.progress = (copied as f32 / self.total as f32).min(1.);
+				}
+			},
+			Message::Extracted(result) => {
+				self.extracting = false;
+				self.cancel_tx = None;
+
+				match result {
+					Ok(folder) => {
+						self.progress = 1.;
+						self.extracted = Some(folder);
+					},
+					Err(e) => self.error = Some(e),
+				}
+			},
+			Message::Cancel => {
+				if let Some(tx) = self.cancel_tx.take() {
+					let _ = tx.try_send(());
+				}
+
+				self.extracting = false;
+			},
+		}
+
+		Task::none()
+	}
+
+	pub fn view(&self) -> Element<'_, Message> {
+		let status: Element<'_, Message> = if let Some(err) = &self.error {
+			status::error(err).into()
+		} else if self.loading {
+			text("Reading...").into()
+		} else if self.extracting {
+			text(format!("Extracting: {}", units::percent(self.progress, 1))).into()
+		} else if let Some(folder) = &self.extracted {
+			status::success(format!("Extracted to {folder}")).into()
+		} else if let Some(root) = &self.root {
+			text(format!("{} in total", units::size(root.size))).into()
+		} else {
+			text("Pick an ISO to see what's inside").into()
+		};
+
+		let mut rows = Vec::new();
+		if let Some(root) = &self.root {
+			self.push_rows(root, "", 0, &mut rows);
+		}
+
+		column![
+			text("ISO Contents")
+				.size(24)
+				.font(Font {
+					weight: Weight::Bold,
+					..Font::DEFAULT
+				}),
+
+			row![
+				button("Pick ISO")
+					.on_press(Message::Pick)
+					.padding([8, 16]),
+				text(if self.path.is_empty() { "No ISO selected" } else { &self.path }),
+			].spacing(20).align_y(Alignment::Center),
+
+			row![
+				button("Extract Selected")
+					.on_press(Message::Extract(false))
+					.padding([8, 16]),
+				button("Extract All")
+					.on_press(Message::Extract(true))
+					.padding([8, 16]),
+				button("Cancel")
+					.on_press(Message::Cancel)
+					.padding([8, 16]),
+				text(self.selected.as_deref().unwrap_or("Nothing selected")),
+			].spacing(20).align_y(Alignment::Center),
+
+			progress_bar(0.0..=1.0, self.progress)
+				.height(20),
+
+			status,
+
+			scrollable(Column::with_children(rows).spacing(2))
+				.height(Length::Fill),
+		].spacing(20).padding(20).into()
+	}
+
+	// One row per entry in `dir`, with the open directories' contents below them
+	fn push_rows<'a>(&self, dir: &'a Entry, parent: &str, depth: u16, rows: &mut Vec<Element<'a, Message>>) {
+		for entry in dir.children.iter().flatten() {
+			let path = format!("{parent}/{}", entry.name);
+			let open = self.expanded.contains(&path);
+			let selected = self.selected.as_ref() == Some(&path);
+
+			// Clicking a directory opens or closes it as well as selecting it
+			let (label, message) = if entry.is_dir() {
+				(format!("{} {}/", if open { "v" } else { ">" }, entry.name), Message::Toggle(path.clone()))
+			} else {
+				(entry.name.clone(), Message::Select(path.clone()))
+			};
+
+			let name = button(text(label).size(13))
+				.style(if selected { button::primary } else { button::text })
+				.on_press(message)
+				.padding([0, 4]);
+
+			rows.push(row![
+				row![name].padding(padding::left(depth * 16)).width(Length::Fill),
+				text(units::size(entry.size)).size(13).width(100),
+				text(&entry.modified).size(13).width(140),
+			].spacing(20).align_y(Alignment::Center).into());
+
+			if open {
+				self.push_rows(entry, &path, depth + 1, rows);
+			}
+		}
+	}
+}
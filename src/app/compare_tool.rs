@@ -0,0 +1,218 @@
+// Compares two images or devices and reports where they first differ.
+
+use iced::font::Weight;
+use iced::widget::{button, column, pick_list, progress_bar, row, text};
+use iced::{Alignment, Element, Font, Task};
+use tokio::sync::mpsc;
+
+use crate::compare::{self, Comparison};
+use crate::devices::Device;
+use crate::units;
+use super::status;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+	A,
+	B,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+	PickFile(Side),
+	DeviceChanged(Side, Device),
+	Start,
+	Progress(u64),
+	Done(Result<Comparison, String>),
+	Cancel,
+}
+
+#[derive(Default)]
+pub struct CompareTool {
+	a: String,
+	b: String,
+	total: u64,
+	progress: f32,
+	comparing: bool,
+	result: Option<Comparison>,
+	error: Option<String>,
+	cancel_tx: Option<mpsc::Sender<()>>,
+}
+
+impl CompareTool {
+	fn side_mut(&mut self, side: Side) -> &mut String {
+		match side {
+			Side::A => &mut self.a,
+			Side::B => &mut self.b,
+		}
+	}
+
+	pub fn update(&mut self, message: Message) -> Task<Message> {
+		match message {
+			Message::PickFile(side) => {
+				if let Some(path) = super::pick_file() {
+					*self.side_mut(side) = path;
+					self.result = None;
+				}
+			},
+			Message::DeviceChanged(side, device) => {
+				*self.side_mut(side) = device.path;
+				self.result = None;
+			},
+			Message::Start => {
+				if self.comparing {
+					return Task::none();
+				}
+
+				if self.a.is_empty() || self.b.is_empty() {
+					self.error = Some("Pick both things to compare".into());
+					return Task::none();
+				}
+
+				if self.a == self.b {
+					self.error = Some("Both sides are the same file".into());
+					return Task::none();
+				}
+
+				self.total = match (compare::size(&self.a), compare::size(&self.b)) {
+					(Ok(a), Ok(b)) => a.min(b),
+					(Err(e), _) | (_, Err(e)) => {
+						self.error = Some(e);
+						return Task::none();
+					}
+				};
+
+				self.comparing = true;
+				self.progress = 0.;
+				self.result = None;
+				self.error = None;
+
+				let (cancel_tx, mut cancel_rx) = mpsc::channel::<()>(1);
+				let (progress_tx, progress_rx) = mpsc::channel(100);
+				self.cancel_tx = Some(cancel_tx);
+
+				let (a, b) = (self.a.clone(), self.b.clone());
+				let compare = async move {
+					tokio::task::spawn_blocking(move || {
+						compare::compare(&a, &b, |bytes| {
+							let _ = progress_tx.blocking_send(bytes);
+							cancel_rx.try_recv().is_err()
+						})
+					})
+					.await
+					.map_err(|e| format!("Compare failed: {e}"))?
+				};
+
+				return Task::batch(vec![
+					Task::perform(compare, Message::Done),
+					Task::run(super::progress_stream(progress_rx), Message::Progress),
+				]);
+			},
+			Message::Progress(bytes) => {
+				if self.total > 0 {
+					self.progress = (bytes as f32 / self.total as f32).min(1.);
+				}
+			},
+			Message::Done(result) => {
+				self.comparing = false;
+				self.cancel_tx = None;
+
+				match result {
+					Ok(comparison) => {
+						self.progress = 1.;
+						self.result = Some(comparison);
+					},
+					Err(e) => self.error = Some(e),
+				}
+			},
+			Message::Cancel => {
+				if let Some(tx) = self.cancel_tx.take() {
+					let _ = tx.try_send(());
+				}
+
+				self.comparing = false;
+			},
+		}
+
+		Task::none()
+	}
+
+	pub fn view<'a>(&'a self, devices: &'a [Device]) -> Element<'a, Message> {
+		let status: Element<'_, Message> = if let Some(err) = &self.error {
+			status::error(err).into()
+		} else if self.comparing {
+			text(format!("Comparing: {}", units::percent(self.progress, 1))).into()
+		} else if let Some(result) = &self.result {
+			result_view(result)
+		} else {
+			text("Ready").into()
+		};
+
+		column![
+			text("Compare")
+				.size(24)
+				.font(Font {
+					weight: Weight::Bold,
+					..Font::DEFAULT
+				}),
+
+			side_picker("A", Side::A, &self.a, devices),
+			side_picker("B", Side::B, &self.b, devices),
+
+			row![
+				button("Compare")
+					.on_press(Message::Start)
+					.padding([8, 16]),
+				button("Cancel")
+					.on_press(Message::Cancel)
+					.padding([8, 16]),
+			].spacing(20),
+
+			progress_bar(0.0..=1.0, self.progress)
+				.height(20),
+
+			status,
+		].spacing(20).padding(20).into()
+	}
+}
+
+fn side_picker<'a>(label: &'a str, side: Side, path: &'a str, devices: &'a [Device]) -> Element<'a, Message> {
+	let selected = devices.iter().find(|device| device.path == path).cloned();
+
+	row![
+		text(label).width(20),
+		button("Pick File")
+			.on_press(Message::PickFile(side))
+			.padding([8, 16]),
+		pick_list(devices, selected, move |device| Message::DeviceChanged(side, device))
+			.placeholder("or a device"),
+		text(if path.is_empty() { "Nothing selected" } else { path }),
+	].spacing(20).align_y(Alignment::Center).into()
+}
+
+fn result_view(result: &Comparison) -> Element<'_, Message> {
+	let mut lines = column![].spacing(6);
+
+	if result.identical() {
+		lines = lines.push(
+			status::success(format!("Identical over {}", units::size(result.compared)))
+		);
+	}
+
+	if let Some(offset) = result.first_difference {
+		lines = lines.push(
+			status::error(format!("Different, first at byte {offset} (0x{offset:x})"))
+		);
+	}
+
+	// A device is normally bigger than the image on it, so this is only informational
+	if result.len_a != result.len_b {
+		lines = lines.push(text(format!(
+			"Sizes differ: A is {}, B is {}, only the first {} were compared",
+			units::size(result.len_a),
+			units::size(result.len_b),
+			units::size(result.compared),
+		)));
+	}
+
+	lines.into()
+}
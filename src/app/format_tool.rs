@@ -0,0 +1,441 @@
+// Makes a fresh filesystem on a stick or one of its partitions, for turning a written ISO back
+// into a plain drive for files.
+//
+// The whole disk gets a new MBR partition table with a single partition, which the most systems
+// read. Like a write, it's confirmed first with what's on the device, the disk the system runs
+// from is refused and it's recorded in the audit log.
+//
+// A quick format only writes the new filesystem. A full one zeroes every block first, and can
+// read them all back, so nothing of the old data is left and a failing stick shows itself.
+
+use iced::font::Weight;
+use iced::widget::{button, column, pick_list, progress_bar, row, text, text_input, Column};
+use iced::{Alignment, Element, Font, Task};
+use tokio::sync::mpsc;
+
+use crate::audit;
+use crate::config::Config;
+use crate::devices::{self, Device};
+use crate::partition::{self, Filesystem};
+use crate::units;
+use crate::writer::{self, Phase};
+use super::{status, WriteTo};
+
+const FILESYSTEMS: [Filesystem; 4] = [Filesystem::Exfat, Filesystem::Fat32, Filesystem::Ntfs, Filesystem::Ext4];
+
+// Used when no label is typed
+const DEFAULT_LABEL: &str = "USB";
+
+/// Cluster size in bytes, `None` leaves it to mkfs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cluster(Option<u32>);
+
+impl std::fmt::Display for Cluster {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self.0 {
+			None => f.write_str("Default cluster size"),
+			Some(bytes) if bytes >= 1 << 20 => write!(f, "{} MiB clusters", bytes >> 20),
+			Some(bytes) => write!(f, "{} KiB clusters", bytes >> 10),
+		}
+	}
+}
+
+/// How thorough a format is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+	Quick,
+	/// Zero every block before making the filesystem
+	Zero,
+	/// And read them all back
+	Verify,
+}
+
+impl Method {
+	const ALL: [Method; 3] = [Method::Quick, Method::Zero, Method::Verify];
+}
+
+impl std::fmt::Display for Method {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Method::Quick => "Quick format",
+			Method::Zero => "Full format, zeroing every block",
+			Method::Verify => "Full format, zeroing and verifying every block",
+		})
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+	DeviceChanged(Device),
+	TargetChanged(WriteTo),
+	FilesystemChanged(Filesystem),
+	LabelChanged(String),
+	ClusterChanged(Cluster),
+	MethodChanged(Method),
+	Format,
+	Confirm,
+	Back,
+	Progress((Phase, u64, u64)),
+	Cancel,
+	Done(Result<String, String>),
+}
+
+pub struct FormatTool {
+	device: Option<Device>,
+	target: WriteTo,
+	filesystem: Filesystem,
+	label: String,
+	cluster: Cluster,
+	method: Method,
+	confirming: bool,
+	busy: bool,
+	/// Where a full format has got to
+	progress: Option<(Phase, u64, u64)>,
+	cancel_tx: Option<mpsc::Sender<()>>,
+	/// The partition the last filesystem was made on
+	done: Option<String>,
+	error: Option<String>,
+}
+
+impl Default for FormatTool {
+	fn default() -> Self {
+		Self {
+			device: None,
+			target: WriteTo::Disk,
+			filesystem: Filesystem::Exfat,
+			label: String::new(),
+			cluster: Cluster(None),
+			method: Method::Quick,
+			confirming: false,
+			busy: false,
+			progress: None,
+			cancel_tx: None,
+			done: None,
+			error: None,
+		}
+	}
+}
+
+impl FormatTool {
+	/// Starts with `device` picked, unless something else already is.
+	pub fn preselect(&mut self, device: Option<&Device>) {
+		if self.device.is_none() {
+			self.device = device.cloned();
+		}
+	}
+
+	fn label(&self) -> &str {
+		match self.label.trim() {
+			"" => DEFAULT_LABEL,
+			label => label,
+		}
+	}
+
+	/// `writing` has the devices other jobs are writing to, which are left alone.
+	pub fn update(&mut self, message: Message, config: &Config, writing: &[String]) -> Task<Message> {
+		if self.busy && !matches!(message, Message::Progress(_) | Message::Cancel | Message::Done(_)) {
+			return Task::none();
+		}
+
+		match message {
+			Message::DeviceChanged(device) => {
+				self.device = Some(device);
+				self.target = WriteTo::Disk;
+				self.done = None;
+				self.error = None;
+			},
+			Message::TargetChanged(target) => self.target = target,
+			Message::FilesystemChanged(filesystem) => {
+				self.filesystem = filesystem;
+				self.cluster = Cluster(None);
+			},
+			Message::LabelChanged(label) => self.label = label,
+			Message::ClusterChanged(cluster) => self.cluster = cluster,
+			Message::MethodChanged(method) => self.method = method,
+			Message::Format => {
+				let Some(device) = &self.device else {
+					self.error = Some("Pick the device to format".into());
+					return Task::none();
+				};
+
+				if device.holds_system() {
+					self.error = Some(format!("{} holds the running system and can't be formatted", device.path));
+					return Task::none();
+				}
+
+				if writing.contains(&device.path) {
+					self.error = Some(format!("{} is being written by another job", device.path));
+					return Task::none();
+				}
+
+				if let Err(e) = partition::check_label(self.filesystem, self.label()) {
+					self.error = Some(e);
+					return Task::none();
+				}
+
+				self.confirming = true;
+				self.done = None;
+				self.error = None;
+			},
+			Message::Back => self.confirming = false,
+			Message::Confirm => {
+				let Some(device) = self.device.clone() else {
+					return Task::none();
+				};
+
+				self.confirming = false;
+
+				// A job may have started on it while this was being confirmed
+				if writing.contains(&device.path) {
+					self.error = Some(format!("{} is being written by another job", device.path));
+					return Task::none();
+				}
+
+				self.busy = true;
+
+				let partition = match &self.target {
+					WriteTo::Disk => None,
+					WriteTo::Partition(partition) => Some(partition.path.clone()),
+				};
+				let (filesystem, label, cluster, method) = (self.filesystem, self.label().to_string(), self.cluster.0, self.method);
+				let config = config.clone();
+
+				let (cancel_tx, cancel_rx) = mpsc::channel(1);
+				let (progress_tx, progress_rx) = mpsc::channel(100);
+				self.cancel_tx = Some(cancel_tx);
+				self.progress = None;
+
+				let format = async move {
+					// It may have been unplugged, or mounted as the system, since it was picked
+					let device = tokio::task::spawn_blocking(move || devices::find(&device.path).ok_or_else(|| format!("{} is no longer connected", device.path)))
+						.await
+						.map_err(|e| format!("Format error: {e}"))??;
+					if device.holds_system() {
+						return Err(format!("{} holds the running system and can't be formatted", device.path));
+					}
+
+					let target = partition.clone().unwrap_or_else(|| device.path.clone());
+					let audit = audit::Entry::new(&config, audit::Operation::Mkfs, &target, device.serial.clone(), &format!("{filesystem} \"{label}\", {}", method.to_string().to_lowercase()));
+					audit.started()?;
+
+					let result = async {
+						if method != Method::Quick {
+							writer::zero(target, method == Method::Verify, cancel_rx, progress_tx).await?;
+						}
+
+						tokio::task::spawn_blocking(move || partition::make_filesystem(&device.path, partition.as_deref(), filesystem, &label, cluster))
+							.await
+							.map_err(|e| format!("Format error: {e}"))?
+					}.await;
+
+					audit::finish(Some(&audit), result)
+				};
+
+				return Task::batch(vec![
+					Task::perform(format, Message::Done),
+					Task::run(super::progress_stream(progress_rx), Message::Progress),
+				]);
+			},
+			Message::Progress(progress) => self.progress = Some(progress),
+			// Stops at the next block, the device is left as far as it got
+			Message::Cancel => {
+				if let Some(tx) = self.cancel_tx.take() {
+					let _ = tx.try_send(());
+				}
+			},
+			Message::Done(result) => {
+				self.busy = false;
+				self.cancel_tx = None;
+				self.progress = None;
+
+				match result {
+					Ok(path) => self.done = Some(path),
+					Err(e) => self.error = Some(e),
+				}
+			},
+		}
+
+		Task::none()
+	}
+
+	pub fn view<'a>(&'a self, devices: &'a [Device]) -> Element<'a, Message> {
+		let title = text("Format")
+			.size(24)
+			.font(Font {
+				weight: Weight::Bold,
+				..Font::DEFAULT
+			});
+
+		if self.confirming && let Some(device) = &self.device {
+			return self.confirmation(device, title.into());
+		}
+
+		let status: Element<'_, Message> = if let Some(err) = &self.error {
+			status::error(err).into()
+		} else if let Some((phase, done, total)) = self.progress && self.busy {
+			let phase = match phase {
+				Phase::Writing => "Zeroing".to_string(),
+				other => other.to_string(),
+			};
+			text(format!("{phase}: {} ({} of {})", units::percent(done as f64 / total.max(1) as f64, 1), units::size(done), units::size(total))).into()
+		} else if self.busy {
+			text("Formatting...").into()
+		} else if let Some(path) = &self.done {
+			status::success(format!("{path} is now {} \"{}\"", self.filesystem, self.label())).into()
+		} else {
+			text("Pick the device, and a partition to format only that one").into()
+		};
+
+		let targets: Vec<WriteTo> = std::iter::once(WriteTo::Disk)
+			.chain(self.device.iter().flat_map(|device| device.partitions.iter().cloned().map(WriteTo::Partition)))
+			.collect();
+
+		let progress: Element<'_, Message> = match self.progress {
+			Some((_, done, total)) if self.busy => progress_bar(0.0..=1.0, done as f32 / total.max(1) as f32).height(20).into(),
+			_ => column![].into(),
+		};
+
+		let clusters: Vec<Cluster> = std::iter::once(Cluster(None))
+			.chain(self.filesystem.cluster_sizes().iter().map(|&bytes| Cluster(Some(bytes))))
+			.collect();
+
+		column![
+			title,
+
+			row![
+				pick_list(devices, self.device.clone(), Message::DeviceChanged)
+					.placeholder("Device"),
+				pick_list(targets, Some(self.target.clone()), Message::TargetChanged),
+			].spacing(20).align_y(Alignment::Center),
+
+			row![
+				pick_list(FILESYSTEMS, Some(self.filesystem), Message::FilesystemChanged),
+				text_input(DEFAULT_LABEL, &self.label)
+					.on_input(Message::LabelChanged)
+					.on_submit(Message::Format)
+					.padding(8)
+					.width(200),
+				pick_list(clusters, Some(self.cluster), Message::ClusterChanged),
+			].spacing(20).align_y(Alignment::Center),
+
+			row![
+				pick_list(Method::ALL, Some(self.method), Message::MethodChanged),
+				button("Format")
+					.on_press_maybe((!self.busy).then_some(Message::Format))
+					.padding([8, 16]),
+				button("Cancel")
+					.style(button::secondary)
+					.on_press_maybe(self.cancel_tx.as_ref().filter(|_| self.progress.is_some()).map(|_| Message::Cancel))
+					.padding([8, 16]),
+			].spacing(20).align_y(Alignment::Center),
+
+			progress,
+			status,
+		].spacing(20).padding(20).into()
+	}
+
+	fn confirmation<'a>(&'a self, device: &'a Device, title: Element<'a, Message>) -> Element<'a, Message> {
+		let (what, lost): (String, Vec<String>) = match &self.target {
+			WriteTo::Disk => (format!("All of {}", device.describe()), device.existing_data()),
+			WriteTo::Partition(partition) => (partition.to_string(), vec![partition.to_string()]),
+		};
+
+		let lost = lost.into_iter().map(|line| text(line).size(13).into());
+
+		column![
+			title,
+			text(format!("{what} becomes {} \"{}\"", self.filesystem, self.label())),
+			text(match self.method {
+				Method::Quick => "Quick format, the old data is only left behind the new filesystem".to_string(),
+				method => format!("{method}, which takes as long as writing the whole {}", match &self.target {
+					WriteTo::Disk => units::size(device.size),
+					WriteTo::Partition(partition) => units::size(partition.size),
+				}),
+			}),
+			status::danger("Everything on it will be lost:"),
+			Column::with_children(lost).spacing(4),
+			row![
+				button("Back")
+					.style(button::secondary)
+					.on_press(Message::Back)
+					.padding([8, 16]),
+				button("Format")
+					.style(button::danger)
+					.on_press(Message::Confirm)
+					.padding([8, 16]),
+			].spacing(20),
+		].spacing(20).padding(20).into()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::devices::Partition;
+	use crate::testing::stick;
+
+	#[test]
+	fn only_a_device_that_can_take_the_label_is_asked_about() {
+		let config = Config::default();
+		let mut tool = FormatTool::default();
+
+		let _ = tool.update(Message::Format, &config, &[]);
+		assert!(!tool.confirming && tool.error.is_some());
+
+		// The system's own disk
+		let root = Partition { path: "/dev/sda2".into(), size: 1 << 30, label: None, filesystem: Some("ext4".into()), mount_points: vec!["/".into()], used: None };
+		let _ = tool.update(Message::DeviceChanged(Device { partitions: vec![root], ..stick("/dev/sda", "S") }), &config, &[]);
+		let _ = tool.update(Message::Format, &config, &[]);
+		assert!(!tool.confirming && tool.error.as_ref().is_some_and(|e| e.contains("running system")));
+
+		let _ = tool.update(Message::DeviceChanged(stick("/dev/sdb", "A")), &config, &[]);
+		assert!(tool.error.is_none());
+
+		// FAT labels are 11 characters at most
+		let _ = tool.update(Message::FilesystemChanged(Filesystem::Fat32), &config, &[]);
+		let _ = tool.update(Message::LabelChanged("FAR TOO LONG A LABEL".into()), &config, &[]);
+		let _ = tool.update(Message::Format, &config, &[]);
+		assert!(!tool.confirming && tool.error.is_some());
+
+		// A blank label gets the default
+		let _ = tool.update(Message::LabelChanged("  ".into()), &config, &[]);
+		assert_eq!(tool.label(), DEFAULT_LABEL);
+		let _ = tool.update(Message::ClusterChanged(Cluster(Some(32 << 10))), &config, &[]);
+		let _ = tool.update(Message::Format, &config, &[]);
+		assert!(tool.confirming && tool.error.is_none());
+		let _ = tool.view(&[]);
+
+		// Another filesystem has its own cluster sizes
+		let _ = tool.update(Message::Back, &config, &[]);
+		let _ = tool.update(Message::FilesystemChanged(Filesystem::Ext4), &config, &[]);
+		assert_eq!(tool.cluster, Cluster(None));
+		assert_eq!(Cluster(Some(32 << 10)).to_string(), "32 KiB clusters");
+		assert_eq!(Cluster(Some(2 << 20)).to_string(), "2 MiB clusters");
+
+		// How it went is shown once it's done
+		tool.busy = true;
+		let _ = tool.update(Message::Done(Err("mkfs.ext4 error: it broke".into())), &config, &[]);
+		assert!(!tool.busy && tool.error.is_some());
+		let _ = tool.update(Message::Format, &config, &[]);
+		tool.busy = true;
+		let _ = tool.update(Message::Done(Ok("/dev/sdb1".into())), &config, &[]);
+		assert_eq!(tool.done.as_deref(), Some("/dev/sdb1"));
+	}
+
+	#[test]
+	fn a_device_being_written_isnt_formatted() {
+		let config = Config::default();
+		let mut tool = FormatTool::default();
+		let writing = ["/dev/sdb".to_string()];
+
+		let _ = tool.update(Message::DeviceChanged(stick("/dev/sdb", "A")), &config, &writing);
+		let _ = tool.update(Message::Format, &config, &writing);
+		assert!(!tool.confirming && tool.error.as_ref().is_some_and(|e| e.contains("being written")));
+
+		// Or started writing after the format was asked about
+		let _ = tool.update(Message::Format, &config, &[]);
+		assert!(tool.confirming);
+		let _ = tool.update(Message::Confirm, &config, &writing);
+		assert!(!tool.busy && tool.error.as_ref().is_some_and(|e| e.contains("being written")));
+	}
+}
@@ -0,0 +1,185 @@
+// Standalone tool for hashing any file, e.g. to check a download against its published sums.
+
+use iced::widget::{button, column, pick_list, progress_bar, row, text, text_input};
+use iced::{clipboard, Alignment, Element, Font, Task};
+use iced::font::Weight;
+use tokio::sync::mpsc;
+
+use crate::hash::{self, Algorithm};
+use crate::units;
+use super::status;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+	Pick,
+	AlgorithmChanged(Algorithm),
+	ExpectedChanged(String),
+	Start,
+	Progress(u64),
+	Done(Result<String, String>),
+	Cancel,
+	CopyDigest,
+}
+
+#[derive(Default)]
+pub struct HashTool {
+	path: String,
+	algorithm: Algorithm,
+	expected: String,
+	total: u64,
+	progress: f32,
+	hashing: bool,
+	digest: Option<String>,
+	error: Option<String>,
+	cancel_tx: Option<mpsc::Sender<()>>,
+}
+
+impl HashTool {
+	pub fn update(&mut self, message: Message) -> Task<Message> {
+		match message {
+			Message::Pick => {
+				if let Some(path) = super::pick_file() {
+					self.path = path;
+					self.digest = None;
+					self.error = None;
+				}
+			},
+			Message::AlgorithmChanged(algorithm) => {
+				self.algorithm = algorithm;
+				self.digest = None;
+			},
+			Message::ExpectedChanged(expected) => self.expected = expected,
+			Message::Start => {
+				if self.hashing {
+					return Task::none();
+				}
+
+				if self.path.is_empty() {
+					self.error = Some("Pick a file to hash".into());
+					return Task::none();
+				}
+
+				self.total = match std::fs::metadata(&self.path) {
+					Ok(metadata) => metadata.len(),
+					Err(e) => {
+						self.error = Some(format!("Source error: {e}"));
+						return Task::none();
+					}
+				};
+
+				self.hashing = true;
+				self.progress = 0.;
+				self.digest = None;
+				self.error = None;
+
+				let (cancel_tx, cancel_rx) = mpsc::channel(1);
+				let (progress_tx, progress_rx) = mpsc::channel(100);
+				self.cancel_tx = Some(cancel_tx);
+
+				return Task::batch(vec![
+					Task::perform(
+						super::hash_file(self.path.clone(), self.algorithm, cancel_rx, progress_tx),
+						Message::Done,
+					),
+					Task::run(super::progress_stream(progress_rx), Message::Progress),
+				]);
+			},
+			Message::Progress(bytes) => {
+				if self.total > 0 {
+					self.progress = (bytes as f32 / self.total as f32).min(1.);
+				}
+			},
+			Message::Done(result) => {
+				self.hashing = false;
+				self.cancel_tx = None;
+
+				match result {
+					Ok(digest) => {
+						self.progress = 1.;
+						self.digest = Some(digest);
+					},
+					Err(e) => self.error = Some(e),
+				}
+			},
+			Message::Cancel => {
+				if let Some(tx) = self.cancel_tx.take() {
+					let _ = tx.try_send(());
+				}
+
+				self.hashing = false;
+			},
+			Message::CopyDigest => {
+				if let Some(digest) = &self.digest {
+					return clipboard::write(digest.clone());
+				}
+			},
+		}
+
+		Task::none()
+	}
+
+	pub fn view(&self) -> Element<'_, Message> {
+		let status: Element<'_, Message> = if let Some(err) = &self.error {
+			status::error(err).into()
+		} else if self.hashing {
+			text(format!("Hashing: {}", units::percent(self.progress, 1))).into()
+		} else if let Some(digest) = &self.digest {
+			let verdict = if self.expected.trim().is_empty() {
+				text("")
+			} else if hash::matches(digest, &self.expected) {
+				status::success("Matches the expected checksum")
+			} else {
+				status::error("Does NOT match the expected checksum")
+			};
+
+			column![
+				row![
+					text(format!("{}: {digest}", self.algorithm)).size(14),
+					button("Copy")
+						.on_press(Message::CopyDigest)
+						.padding([4, 12]),
+				].spacing(20).align_y(Alignment::Center),
+				verdict,
+			].spacing(10).into()
+		} else {
+			text("Ready").into()
+		};
+
+		column![
+			text("Hash a File")
+				.size(24)
+				.font(Font {
+					weight: Weight::Bold,
+					..Font::DEFAULT
+				}),
+
+			row![
+				button("Pick File")
+					.on_press(Message::Pick)
+					.padding([8, 16]),
+				text(if self.path.is_empty() { "No file selected" } else { &self.path }),
+			].spacing(20).align_y(Alignment::Center),
+
+			row![
+				pick_list(&Algorithm::ALL[..], Some(self.algorithm), Message::AlgorithmChanged),
+				text_input("Expected checksum (optional)", &self.expected)
+					.on_input(Message::ExpectedChanged)
+					.padding(8),
+			].spacing(20).align_y(Alignment::Center),
+
+			row![
+				button("Hash")
+					.on_press(Message::Start)
+					.padding([8, 16]),
+				button("Cancel")
+					.on_press(Message::Cancel)
+					.padding([8, 16]),
+			].spacing(20),
+
+			progress_bar(0.0..=1.0, self.progress)
+				.height(20),
+
+			status,
+		].spacing(20).padding(20).into()
+	}
+}
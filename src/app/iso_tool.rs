@@ -0,0 +1,334 @@
+// Creates an ISO image from a folder, with the metadata that ends up in its volume descriptor,
+// or remasters an existing one with extra files.
+
+use iced::font::Weight;
+use iced::widget::{button, checkbox, column, pick_list, progress_bar, row, text, text_input, Column};
+use iced::{Alignment, Element, Font, Task};
+use tokio::sync::mpsc;
+
+use crate::iso::{self, Metadata, Naming, Options};
+use crate::units;
+use super::status;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+	PickFolder,
+	OpenIso,
+	CloseIso,
+	AddFiles,
+	AddFolder,
+	RemoveAddition(usize),
+	DirectoryChanged(String),
+	PickOutput,
+	VolumeIdChanged(String),
+	PublisherChanged(String),
+	ApplicationChanged(String),
+	PreparerChanged(String),
+	ModifiedChanged(String),
+	JolietToggled(bool),
+	RockRidgeToggled(bool),
+	NamingChanged(Naming),
+	UdfToggled(bool),
+	Start,
+	Progress(u64),
+	Done(Result<(), String>),
+	Cancel,
+}
+
+#[derive(Default)]
+pub struct IsoTool {
+	folder: String,
+	/// Existing image to remaster instead of building one from `folder`
+	base: Option<String>,
+	additions: Vec<String>,
+	/// Where in the image the additions go
+	directory: String,
+	output: String,
+	metadata: Metadata,
+	options: Options,
+	progress: f32,
+	creating: bool,
+	done: bool,
+	error: Option<String>,
+	cancel_tx: Option<mpsc::Sender<()>>,
+}
+
+impl IsoTool {
+	pub fn update(&mut self, message: Message) -> Task<Message> {
+		match message {
+			Message::PickFolder => {
+				if let Some(folder) = super::file_dialog().pick_folder() {
+					// Name the volume after the folder until told otherwise
+					if self.metadata.volume_id.is_empty() && let Some(name) = folder.file_name() {
+						self.metadata.volume_id = name.to_string_lossy().to_uppercase().chars().take(32).collect();
+					}
+
+					self.folder = folder.to_string_lossy().to_string();
+					self.done = false;
+					self.error = None;
+				}
+			},
+			Message::OpenIso => {
+				if let Some(iso) = super::file_dialog().add_filter("ISO image", &["iso"]).pick_file() {
+					self.base = Some(iso.to_string_lossy().to_string());
+					self.done = false;
+					self.error = None;
+				}
+			},
+			Message::CloseIso => self.base = None,
+			Message::AddFiles => {
+				if let Some(files) = super::file_dialog().pick_files() {
+					self.additions.extend(files.iter().map(|file| file.to_string_lossy().to_string()));
+				}
+			},
+			Message::AddFolder => {
+				if let Some(folder) = super::file_dialog().pick_folder() {
+					self.additions.push(folder.to_string_lossy().to_string());
+				}
+			},
+			Message::RemoveAddition(index) => {
+				if index < self.additions.len() {
+					self.additions.remove(index);
+				}
+			},
+			Message::DirectoryChanged(directory) => self.directory = directory,
+			Message::PickOutput => {
+				if let Some(output) = super::file_dialog().add_filter("ISO image", &["iso"]).save_file() {
+					self.output = output.to_string_lossy().to_string();
+					self.done = false;
+				}
+			},
+			Message::VolumeIdChanged(volume_id) => self.metadata.volume_id = volume_id,
+			Message::PublisherChanged(publisher) => self.metadata.publisher = publisher,
+			Message::ApplicationChanged(application) => self.metadata.application = application,
+			Message::PreparerChanged(preparer) => self.metadata.preparer = preparer,
+			Message::ModifiedChanged(modified) => self.metadata.modified = modified,
+			Message::JolietToggled(enabled) => self.options.joliet = enabled,
+			Message::RockRidgeToggled(enabled) => self.options.rock_ridge = enabled,
+			Message::NamingChanged(naming) => self.options.naming = naming,
+			Message::UdfToggled(enabled) => self.options.udf = enabled,
+			Message::Start => {
+				if self.creating {
+					return Task::none();
+				}
+
+				if (self.folder.is_empty() && self.base.is_none()) || self.output.is_empty() {
+					self.error = Some("Pick the folder to put in the image and where to save it".into());
+					return Task::none();
+				}
+
+				if self.base.is_some() && self.additions.is_empty() {
+					self.error = Some("Add the files to put in the image".into());
+					return Task::none();
+				}
+
+				let checked = match self.base {
+					Some(_) => self.metadata.check(),
+					None => self.metadata.check().and_then(|()| self.options.check(&self.folder, &self.metadata)),
+				};
+
+				if let Err(e) = checked {
+					self.error = Some(e);
+					return Task::none();
+				}
+
+				self.creating = true;
+				self.done = false;
+				self.progress = 0.;
+				self.error = None;
+
+				let (cancel_tx, cancel_rx) = mpsc::channel(1);
+				let (progress_tx, progress_rx) = mpsc::channel(100);
+				self.cancel_tx = Some(cancel_tx);
+
+				let write = match &self.base {
+					Some(base) => {
+						let directory = self.directory.trim().trim_matches('/');
+						let additions = self.additions.iter()
+							.map(|path| {
+								let name = std::path::Path::new(path).file_name().unwrap_or_default().to_string_lossy();
+								(path.clone(), format!("{directory}/{name}"))
+							})
+							.collect();
+
+						Task::perform(
+							iso::remaster(base.clone(), self.output.clone(), additions, self.metadata.clone(), self.options.joliet, cancel_rx, progress_tx),
+							Message::Done,
+						)
+					},
+					None => Task::perform(
+						iso::create(self.folder.clone(), self.output.clone(), self.metadata.clone(), self.options, cancel_rx, progress_tx),
+						Message::Done,
+					),
+				};
+
+				return Task::batch(vec![
+					write,
+					Task::run(super::progress_stream(progress_rx), Message::Progress),
+				]);
+			},
+			Message::Progress(percent) => self.progress = (percent as f32 / 100.).min(1.),
+			Message::Done(result) => {
+				self.creating = false;
+				self.cancel_tx = None;
+
+				match result {
+					Ok(()) => {
+						self.progress = 1.;
+						self.done = true;
+					},
+					Err(e) => self.error = Some(e),
+				}
+			},
+			Message::Cancel => {
+				if let Some(tx) = self.cancel_tx.take() {
+					let _ = tx.try_send(());
+				}
+
+				self.creating = false;
+			},
+		}
+
+		Task::none()
+	}
+
+	pub fn view(&self) -> Element<'_, Message> {
+		let status: Element<'_, Message> = if let Some(err) = &self.error {
+			status::error(err).into()
+		} else if self.creating {
+			text(format!("Creating: {}", units::percent(self.progress, 0))).into()
+		} else if self.done {
+			status::success(format!("Created {}", self.output)).into()
+		} else {
+			text("Ready").into()
+		};
+
+		// Remastering keeps whatever the original image has unless told otherwise
+		let keep = if self.base.is_some() { "Keep the original's" } else { "" };
+
+		let field = |label, value, on_input: fn(String) -> Message| {
+			row![
+				text(label).width(120),
+				text_input(keep, value)
+					.on_input(on_input)
+					.padding(8),
+			].spacing(20).align_y(Alignment::Center)
+		};
+
+		column![
+			text("Create ISO")
+				.size(24)
+				.font(Font {
+					weight: Weight::Bold,
+					..Font::DEFAULT
+				}),
+
+			self.source(),
+
+			row![
+				button("Save As")
+					.on_press(Message::PickOutput)
+					.padding([8, 16]),
+				text(if self.output.is_empty() { "No output file selected" } else { &self.output }),
+			].spacing(20).align_y(Alignment::Center),
+
+			field("Volume ID", &self.metadata.volume_id, Message::VolumeIdChanged),
+			field("Publisher", &self.metadata.publisher, Message::PublisherChanged),
+			field("Application", &self.metadata.application, Message::ApplicationChanged),
+			field("Preparer", &self.metadata.preparer, Message::PreparerChanged),
+
+			row![
+				text("Modified").width(120),
+				text_input(if self.base.is_some() { keep } else { "Now, or e.g. 2024-05-01 12:30:00 (UTC)" }, &self.metadata.modified)
+					.on_input(Message::ModifiedChanged)
+					.padding(8),
+			].spacing(20).align_y(Alignment::Center),
+
+			self.extensions(),
+
+			row![
+				button("Create")
+					.on_press(Message::Start)
+					.padding([8, 16]),
+				button("Cancel")
+					.on_press(Message::Cancel)
+					.padding([8, 16]),
+			].spacing(20),
+
+			progress_bar(0.0..=1.0, self.progress)
+				.height(20),
+
+			status,
+		].spacing(20).padding(20).into()
+	}
+
+	fn source(&self) -> Element<'_, Message> {
+		let Some(base) = &self.base else {
+			return row![
+				button("Pick Folder")
+					.on_press(Message::PickFolder)
+					.padding([8, 16]),
+				button("Open ISO")
+					.on_press(Message::OpenIso)
+					.padding([8, 16]),
+				text(if self.folder.is_empty() { "No folder selected, or open an ISO to remaster" } else { &self.folder }),
+			].spacing(20).align_y(Alignment::Center).into();
+		};
+
+		let additions = self.additions.iter().enumerate().map(|(i, path)| {
+			row![
+				text(path).size(12),
+				button("x")
+					.style(button::secondary)
+					.on_press(Message::RemoveAddition(i))
+					.padding([4, 8]),
+			].spacing(10).align_y(Alignment::Center).into()
+		});
+
+		column![
+			row![
+				text(format!("Remastering {base}")),
+				button("x")
+					.style(button::secondary)
+					.on_press(Message::CloseIso)
+					.padding([4, 8]),
+			].spacing(10).align_y(Alignment::Center),
+
+			row![
+				button("Add Files")
+					.on_press(Message::AddFiles)
+					.padding([8, 16]),
+				button("Add Folder")
+					.on_press(Message::AddFolder)
+					.padding([8, 16]),
+				text("into"),
+				text_input("/", &self.directory)
+					.on_input(Message::DirectoryChanged)
+					.padding(8)
+					.width(200),
+			].spacing(20).align_y(Alignment::Center),
+
+			Column::with_children(additions).spacing(6),
+		].spacing(10).into()
+	}
+
+	fn extensions(&self) -> Element<'_, Message> {
+		let joliet = checkbox("Joliet (Windows)", self.options.joliet)
+			.on_toggle(Message::JolietToggled);
+
+		// The rest is decided by the image being remastered
+		if self.base.is_some() {
+			return joliet.into();
+		}
+
+		row![
+			joliet,
+			checkbox("Rock Ridge (Linux, macOS)", self.options.rock_ridge)
+				.on_toggle(Message::RockRidgeToggled),
+			text("ISO 9660 names"),
+			pick_list(&Naming::ALL[..], Some(self.options.naming), Message::NamingChanged),
+			checkbox("UDF (files over 4 GB)", self.options.udf)
+				.on_toggle(Message::UdfToggled),
+		].spacing(20).align_y(Alignment::Center).into()
+	}
+}
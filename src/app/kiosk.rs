@@ -0,0 +1,259 @@
+// Kiosk mode, for duplication stations: `iso_maker kiosk <image>` fills the screen with the
+// one image and writes it to every stick that's plugged in, verifying each, with a big green
+// or red result to tell the good ones from the bad. There's nothing to pick or press, whoever
+// runs the station only plugs sticks in and takes them out.
+//
+// Only sticks plugged in after it starts are written, the ones already there could be
+// anything. Disks the system runs from are never touched, and each write is audited as one
+// from the window would be.
+
+use iced::font::Weight;
+use iced::widget::{column, container, progress_bar, row, text, Column};
+use iced::{Alignment, Element, Font, Length, Task, Theme};
+use tokio::sync::mpsc;
+
+use crate::audit;
+use crate::bmap::Bmap;
+use crate::config::Config;
+use crate::devices::Device;
+use crate::units;
+use crate::writer::{self, Phase};
+
+#[derive(Debug, Clone)]
+pub enum Message {
+	Progress(String, (Phase, u64, u64)),
+	Done(String, Result<(), String>),
+}
+
+enum Outcome {
+	Writing(Phase, u64, u64),
+	Passed,
+	Failed(String),
+}
+
+struct Stick {
+	device: Device,
+	outcome: Outcome,
+	/// Kept for as long as the write runs, dropping it would cancel it
+	cancel_tx: Option<mpsc::Sender<()>>,
+}
+
+pub struct Kiosk {
+	image: String,
+	/// The devices there last time, by path and serial, so a stick swapped for another at
+	/// the same path still counts as new
+	seen: Vec<(String, Option<String>)>,
+	sticks: Vec<Stick>,
+	/// Sticks written and verified since it started, and ones that failed
+	passed: u32,
+	failed: u32,
+}
+
+impl Kiosk {
+	/// Writes `image` to sticks plugged in from now on, leaving `devices` as they are.
+	pub fn new(image: String, devices: &[Device]) -> Self {
+		Self { image, seen: seen(devices), sticks: Vec::new(), passed: 0, failed: 0 }
+	}
+
+	/// Starts writing to the sticks in `devices` that weren't there before.
+	pub fn devices_changed(&mut self, devices: &[Device], config: &Config) -> Task<Message> {
+		let writes: Vec<Task<Message>> = self.arrived(devices)
+			.into_iter()
+			.map(|device| self.write(device, config))
+			.collect();
+
+		Task::batch(writes)
+	}
+
+	// The sticks that are new in `devices`, the results of ones taken out are cleared
+	fn arrived(&mut self, devices: &[Device]) -> Vec<Device> {
+		let now = seen(devices);
+		let new: Vec<Device> = devices.iter()
+			.filter(|device| !self.seen.contains(&(device.path.clone(), device.serial.clone())))
+			.filter(|device| device.removable && !device.holds_system())
+			.filter(|device| !self.sticks.iter().any(|stick| stick.device.path == device.path && stick.cancel_tx.is_some()))
+			.cloned()
+			.collect();
+
+		// A write that's still going finds out for itself when its stick is pulled
+		self.sticks.retain(|stick| stick.cancel_tx.is_some() || now.contains(&(stick.device.path.clone(), stick.device.serial.clone())));
+		self.sticks.retain(|stick| !new.iter().any(|device| device.path == stick.device.path));
+		self.seen = now;
+
+		new
+	}
+
+	fn write(&mut self, device: Device, config: &Config) -> Task<Message> {
+		let (cancel_tx, cancel_rx) = mpsc::channel(1);
+		let (progress_tx, progress_rx) = mpsc::channel(100);
+
+		self.sticks.push(Stick { device: device.clone(), outcome: Outcome::Writing(Phase::Writing, 0, 0), cancel_tx: Some(cancel_tx) });
+
+		let (image, config) = (self.image.clone(), config.clone());
+		let path = device.path.clone();
+		let write = async move {
+			// A write that can't be recorded doesn't go ahead
+			let audit = audit::Entry::new(&config, audit::Operation::Write, &device.path, device.serial.clone(), &image);
+			audit.started()?;
+
+			// Every stick is read back, a duplicate that may not work is no good
+			let options = writer::Options {
+				verify: Some(config.verify_algorithm),
+				bmap: Bmap::find(&image).and_then(|path| Bmap::load(&path).ok()),
+				mmap: config.mmap,
+				checkpoint: config.checkpoint(),
+				image_hash: audit.image_hash.clone(),
+				readback: audit.readback.clone(),
+				..writer::Options::default()
+			};
+
+			let result = writer::copy_with_progress(image, device.path, options, cancel_rx, progress_tx).await;
+
+			audit::finish(Some(&audit), result)
+		};
+
+		let progress_path = path.clone();
+		Task::batch(vec![
+			Task::perform(write, move |result| Message::Done(path.clone(), result)),
+			Task::run(super::progress_stream(progress_rx), move |progress| Message::Progress(progress_path.clone(), progress)),
+		])
+	}
+
+	pub fn update(&mut self, message: Message) -> Task<Message> {
+		match message {
+			Message::Progress(path, (phase, done, total)) => {
+				if let Some(stick) = writing(&mut self.sticks, &path) {
+					stick.outcome = Outcome::Writing(phase, done, total);
+				}
+			},
+			Message::Done(path, result) => {
+				if let Some(stick) = writing(&mut self.sticks, &path) {
+					stick.cancel_tx = None;
+					stick.outcome = match result {
+						Ok(()) => {
+							self.passed += 1;
+							Outcome::Passed
+						},
+						Err(e) => {
+							self.failed += 1;
+							Outcome::Failed(e)
+						},
+					};
+				}
+			},
+		}
+
+		Task::none()
+	}
+
+	pub fn view(&self) -> Element<'_, Message> {
+		let name = std::path::Path::new(&self.image)
+			.file_name()
+			.map_or_else(|| self.image.clone(), |name| name.to_string_lossy().into_owned());
+
+		let title = text(name)
+			.size(40)
+			.font(Font {
+				weight: Weight::Bold,
+				..Font::DEFAULT
+			});
+
+		let hint = match self.sticks.is_empty() {
+			true => "Plug in a stick to write it",
+			false => "Take out the finished sticks and plug in the next",
+		};
+
+		let count = match self.failed {
+			0 => format!("{} written", self.passed),
+			failed => format!("{} written, {failed} failed", self.passed),
+		};
+
+		let sticks = self.sticks.iter().map(|stick| card(stick));
+
+		column![
+			title,
+			text(count).size(32),
+			text(hint).size(24),
+			row(sticks).spacing(20).wrap(),
+		]
+			.spacing(30)
+			.padding(40)
+			.align_x(Alignment::Center)
+			.width(Length::Fill)
+			.into()
+	}
+}
+
+fn seen(devices: &[Device]) -> Vec<(String, Option<String>)> {
+	devices.iter().map(|device| (device.path.clone(), device.serial.clone())).collect()
+}
+
+// The stick at `path` that's being written
+fn writing<'a>(sticks: &'a mut [Stick], path: &str) -> Option<&'a mut Stick> {
+	sticks.iter_mut().find(|stick| stick.device.path == path && stick.cancel_tx.is_some())
+}
+
+// The stick's result, big enough to read from across the room
+fn card(stick: &Stick) -> Element<'_, Message> {
+	let (verdict, detail): (String, Element<'_, Message>) = match &stick.outcome {
+		Outcome::Writing(phase, done, total) => (
+			format!("{phase} {}", units::percent(*done as f64 / (*total).max(1) as f64, 0)),
+			progress_bar(0.0..=1.0, *done as f32 / (*total).max(1) as f32).height(16).into(),
+		),
+		Outcome::Passed => ("✔ Done".into(), text("Written and verified").size(18).into()),
+		Outcome::Failed(e) => ("✖ Failed".into(), text(e).size(14).into()),
+	};
+
+	let content: Column<'_, Message> = column![
+		text(verdict).size(48).font(Font { weight: Weight::Bold, ..Font::DEFAULT }),
+		text(&stick.device.path).size(20),
+		text(stick.device.serial.as_deref().unwrap_or_default()).size(14),
+		detail,
+	].spacing(10).align_x(Alignment::Center);
+
+	let outcome = match stick.outcome {
+		Outcome::Writing(..) => None,
+		Outcome::Passed => Some(true),
+		Outcome::Failed(_) => Some(false),
+	};
+
+	container(content)
+		.width(320)
+		.padding(30)
+		.style(move |theme: &Theme| {
+			let palette = theme.extended_palette();
+			let pair = match outcome {
+				None => palette.background.weak,
+				Some(true) => palette.success.base,
+				Some(false) => palette.danger.base,
+			};
+
+			container::Style {
+				background: Some(pair.color.into()),
+				text_color: Some(pair.text),
+				border: iced::border::rounded(12),
+				..container::Style::default()
+			}
+		})
+		.into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::stick;
+
+	#[test]
+	fn only_sticks_plugged_in_afterwards_are_written() {
+		let mut kiosk = Kiosk::new("/tmp/golden.img".into(), &[stick("/dev/sdb", "A")]);
+		assert!(kiosk.arrived(&[stick("/dev/sdb", "A")]).is_empty());
+
+		let mut fixed = stick("/dev/sdd", "D");
+		fixed.removable = false;
+		let arrived = kiosk.arrived(&[stick("/dev/sdb", "A"), stick("/dev/sdc", "B"), fixed]);
+		assert_eq!(arrived.iter().map(|device| device.path.as_str()).collect::<Vec<_>>(), ["/dev/sdc"]);
+
+		// Another stick at the same path between two looks is new too
+		assert_eq!(kiosk.arrived(&[stick("/dev/sdb", "C")]).len(), 1);
+	}
+}
@@ -0,0 +1,451 @@
+// Small changes to a written stick's partition table: deleting a partition, resizing one along
+// with its filesystem, or adding one in the space that's left, e.g. shrinking the data
+// partition an image brought along to make room for swap.
+//
+// Every change is spelled out and has to be applied once more before the table is touched,
+// the disk the system runs from is refused, and it's recorded in the audit log like a write.
+
+use iced::font::Weight;
+use iced::widget::{button, column, pick_list, row, text, text_input, Column};
+use iced::{Alignment, Element, Font, Task};
+
+use crate::audit;
+use crate::config::Config;
+use crate::devices::{self, Device};
+use crate::partition::{self, Edit, Entry, Filesystem, Kind, Spec};
+use crate::units;
+use super::status;
+
+/// What a new partition is made with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewPartition {
+	Format(Filesystem),
+	/// Left empty, for an image to be written to
+	Empty,
+}
+
+impl NewPartition {
+	const ALL: [NewPartition; 6] = [
+		NewPartition::Format(Filesystem::Exfat),
+		NewPartition::Format(Filesystem::Fat32),
+		NewPartition::Format(Filesystem::Ntfs),
+		NewPartition::Format(Filesystem::Ext4),
+		NewPartition::Format(Filesystem::Swap),
+		NewPartition::Empty,
+	];
+}
+
+impl std::fmt::Display for NewPartition {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			NewPartition::Format(filesystem) => filesystem.fmt(f),
+			NewPartition::Empty => f.write_str("Empty"),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+	DeviceChanged(Device),
+	Scan,
+	Scanned(Result<Vec<Entry>, String>),
+	/// Pick the partition at this index to resize
+	Select(usize),
+	SizeChanged(String),
+	Resize,
+	Delete(usize),
+	NewSizeChanged(String),
+	NewKindChanged(NewPartition),
+	NewLabelChanged(String),
+	Add,
+	Apply,
+	Back,
+	Done(Result<Vec<Entry>, String>),
+}
+
+pub struct PartitionTool {
+	device: Option<Device>,
+	partitions: Vec<Entry>,
+	/// Index in `partitions` of the one being resized
+	selected: Option<usize>,
+	size: String,
+	new_size: String,
+	new_kind: NewPartition,
+	new_label: String,
+	/// Change waiting to be applied
+	pending: Option<Edit>,
+	busy: bool,
+	/// The last change, once it's been made
+	done: Option<Edit>,
+	error: Option<String>,
+}
+
+impl Default for PartitionTool {
+	fn default() -> Self {
+		Self {
+			device: None,
+			partitions: Vec::new(),
+			selected: None,
+			size: String::new(),
+			new_size: String::new(),
+			new_kind: NewPartition::Format(Filesystem::Exfat),
+			new_label: String::new(),
+			pending: None,
+			busy: false,
+			done: None,
+			error: None,
+		}
+	}
+}
+
+impl PartitionTool {
+	/// Starts with `device` picked, unless something else already is.
+	pub fn preselect(&mut self, device: Option<&Device>) {
+		if self.device.is_none() {
+			self.device = device.cloned();
+		}
+	}
+
+	/// `writing` has the devices other jobs are writing to, which are left alone.
+	pub fn update(&mut self, message: Message, config: &Config, writing: &[String]) -> Task<Message> {
+		if self.busy && !matches!(message, Message::Scanned(_) | Message::Done(_)) {
+			return Task::none();
+		}
+
+		match message {
+			Message::DeviceChanged(device) => {
+				*self = Self { device: Some(device), ..Self::default() };
+				return self.update(Message::Scan, config, writing);
+			},
+			Message::Scan => {
+				let Some(device) = self.device.clone() else {
+					self.error = Some("Pick the device to edit".into());
+					return Task::none();
+				};
+
+				self.busy = true;
+				self.error = None;
+				self.pending = None;
+
+				let path = device.path;
+				let read = async move {
+					tokio::task::spawn_blocking(move || partition::read_table(&path))
+						.await
+						.map_err(|e| format!("Partition error: {e}"))?
+				};
+
+				return Task::perform(read, Message::Scanned);
+			},
+			Message::Scanned(result) | Message::Done(result) => {
+				self.busy = false;
+				self.selected = None;
+
+				match result {
+					Ok(partitions) => self.partitions = partitions,
+					Err(e) => {
+						self.partitions.clear();
+						self.done = None;
+						self.error = Some(e);
+					},
+				}
+			},
+			Message::Select(index) => {
+				let Some(entry) = self.partitions.get(index) else {
+					return Task::none();
+				};
+
+				if !partition::can_resize(entry.filesystem.as_deref()) {
+					self.error = Some(format!("{} has {}, which can't be resized", entry.path, entry.filesystem.as_deref().unwrap_or_default()));
+					return Task::none();
+				}
+
+				self.selected = Some(index);
+				// In a form it parses back
+				self.size = format!("{}M", entry.size >> 20);
+				self.error = None;
+			},
+			Message::SizeChanged(size) => self.size = size,
+			Message::Resize => {
+				let Some(entry) = self.selected.and_then(|index| self.partitions.get(index)) else {
+					return Task::none();
+				};
+
+				match partition::parse_size(&self.size) {
+					Ok(to) if to == entry.size => self.error = Some(format!("{} is already {}", entry.path, units::size(to))),
+					Ok(to) => self.propose(Edit::Resize {
+						path: entry.path.clone(),
+						filesystem: entry.filesystem.clone(),
+						from: entry.size,
+						to,
+					}),
+					Err(e) => self.error = Some(e),
+				}
+			},
+			Message::Delete(index) => {
+				if let Some(entry) = self.partitions.get(index) {
+					self.propose(Edit::Delete(entry.path.clone()));
+				}
+			},
+			Message::NewSizeChanged(size) => self.new_size = size,
+			Message::NewKindChanged(kind) => self.new_kind = kind,
+			Message::NewLabelChanged(label) => self.new_label = label,
+			Message::Add => {
+				// Left empty, it takes the rest of the device
+				let size = match self.new_size.trim() {
+					"" => None,
+					size => match partition::parse_size(size) {
+						Ok(size) => Some(size),
+						Err(e) => {
+							self.error = Some(e);
+							return Task::none();
+						},
+					},
+				};
+
+				let (kind, filesystem) = match self.new_kind {
+					NewPartition::Format(Filesystem::Swap) => (Kind::Swap, Some(Filesystem::Swap)),
+					NewPartition::Format(Filesystem::Ext4) => (Kind::Linux, Some(Filesystem::Ext4)),
+					NewPartition::Format(filesystem) => (Kind::Data, Some(filesystem)),
+					NewPartition::Empty => (Kind::Linux, None),
+				};
+
+				let label = self.new_label.trim().to_string();
+				if let Some(filesystem) = filesystem
+					&& let Err(e) = partition::check_label(filesystem, &label)
+				{
+					self.error = Some(e);
+					return Task::none();
+				}
+
+				self.propose(Edit::Add(Spec { size, kind, filesystem, label }));
+			},
+			Message::Back => self.pending = None,
+			Message::Apply => {
+				let (Some(device), Some(edit)) = (self.device.clone(), self.pending.take()) else {
+					return Task::none();
+				};
+
+				if writing.contains(&device.path) {
+					self.error = Some(format!("{} is being written by another job", device.path));
+					return Task::none();
+				}
+
+				self.busy = true;
+				self.error = None;
+				self.done = Some(edit.clone());
+
+				let config = config.clone();
+				let apply = async move {
+					tokio::task::spawn_blocking(move || {
+						// It may have been unplugged, or mounted as the system, since it was picked
+						let device = devices::find(&device.path).ok_or_else(|| format!("{} is no longer connected", device.path))?;
+						if device.holds_system() {
+							return Err(format!("{} holds the running system and can't be edited", device.path));
+						}
+
+						let audit = audit::Entry::new(&config, audit::Operation::Repartition, &device.path, device.serial.clone(), &edit.to_string());
+						audit.started()?;
+						audit::finish(Some(&audit), partition::edit(&device.path, &edit))?;
+						partition::read_table(&device.path)
+					})
+					.await
+					.map_err(|e| format!("Partition error: {e}"))?
+				};
+
+				return Task::perform(apply, Message::Done);
+			},
+		}
+
+		Task::none()
+	}
+
+	// To be applied once it's been read
+	fn propose(&mut self, edit: Edit) {
+		self.pending = Some(edit);
+		self.done = None;
+		self.error = None;
+	}
+
+	pub fn view<'a>(&'a self, devices: &'a [Device]) -> Element<'a, Message> {
+		let title = text("Partitions")
+			.size(24)
+			.font(Font {
+				weight: Weight::Bold,
+				..Font::DEFAULT
+			});
+
+		if let (Some(edit), Some(device)) = (&self.pending, &self.device) {
+			return self.confirmation(edit, device, title.into());
+		}
+
+		let status: Element<'_, Message> = if let Some(err) = &self.error {
+			status::error(err).into()
+		} else if self.busy {
+			text("Working...").into()
+		} else if let Some(done) = &self.done {
+			status::success(format!("Done: {done}")).into()
+		} else if self.device.is_none() {
+			text("Pick the device to edit").into()
+		} else if self.partitions.is_empty() {
+			text("The device has no partitions").into()
+		} else {
+			text(format!("{} partitions:", self.partitions.len())).into()
+		};
+
+		let mut table = Column::new().spacing(4);
+		for (index, entry) in self.partitions.iter().enumerate() {
+			table = table.push(row![
+				text(&entry.path).size(13).width(140),
+				text(&entry.kind).size(13).width(180),
+				text(units::size(entry.size)).size(13).width(100),
+				text(entry.filesystem.as_deref().unwrap_or("-")).size(13).width(100),
+				text(entry.label.as_deref().unwrap_or("-")).size(13).width(140),
+				button(text("Resize").size(13))
+					.style(button::secondary)
+					.on_press_maybe((!self.busy).then_some(Message::Select(index)))
+					.padding([4, 8]),
+				button(text("Delete").size(13))
+					.style(button::danger)
+					.on_press_maybe((!self.busy).then_some(Message::Delete(index)))
+					.padding([4, 8]),
+			].spacing(10).align_y(Alignment::Center));
+
+			if self.selected == Some(index) {
+				table = table.push(row![
+					text("New size").size(13).width(140),
+					text_input("e.g. 2G", &self.size)
+						.on_input(Message::SizeChanged)
+						.on_submit(Message::Resize)
+						.padding(6)
+						.width(200),
+					button(text("Resize").size(13))
+						.on_press(Message::Resize)
+						.padding([4, 8]),
+				].spacing(10).align_y(Alignment::Center));
+			}
+		}
+
+		let add = row![
+			text("Add").width(40),
+			text_input("Size, empty for the rest", &self.new_size)
+				.on_input(Message::NewSizeChanged)
+				.padding(8)
+				.width(200),
+			pick_list(NewPartition::ALL, Some(self.new_kind), Message::NewKindChanged),
+			text_input("Label", &self.new_label)
+				.on_input(Message::NewLabelChanged)
+				.on_submit(Message::Add)
+				.padding(8)
+				.width(160),
+			button("Add")
+				.on_press_maybe((!self.busy && self.device.is_some()).then_some(Message::Add))
+				.padding([8, 16]),
+		].spacing(10).align_y(Alignment::Center);
+
+		column![
+			title,
+
+			row![
+				pick_list(devices, self.device.clone(), Message::DeviceChanged)
+					.placeholder("Device"),
+				button("Scan")
+					.on_press_maybe((!self.busy).then_some(Message::Scan))
+					.padding([8, 16]),
+			].spacing(20).align_y(Alignment::Center),
+
+			status,
+			table,
+			add,
+		].spacing(20).padding(20).into()
+	}
+
+	fn confirmation<'a>(&'a self, edit: &'a Edit, device: &'a Device, title: Element<'a, Message>) -> Element<'a, Message> {
+		let warning = match edit {
+			Edit::Delete(path) => format!("Everything on {path} will be lost"),
+			Edit::Resize { to, from, .. } if to < from => "Shrinking a partition can't be undone, and fails if the files don't fit. Back up anything important first".into(),
+			Edit::Resize { .. } => "Resizing rewrites the partition table, back up anything important first".into(),
+			Edit::Add(_) => "The partition table will be rewritten, nothing on the other partitions is touched".into(),
+		};
+
+		column![
+			title,
+			text(format!("Device: {}", device.describe())),
+			text(edit.to_string()).size(18),
+			status::danger(warning),
+			row![
+				button("Back")
+					.style(button::secondary)
+					.on_press(Message::Back)
+					.padding([8, 16]),
+				button("Apply")
+					.style(button::danger)
+					.on_press(Message::Apply)
+					.padding([8, 16]),
+			].spacing(20),
+		].spacing(20).padding(20).into()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::stick;
+
+	fn entry(path: &str, size: u64, filesystem: Option<&str>) -> Entry {
+		Entry { path: path.into(), size, kind: "Linux filesystem".into(), bootable: false, label: None, filesystem: filesystem.map(Into::into) }
+	}
+
+	// A stick already scanned, with a data partition to shrink and an ISO 9660 one that can't be
+	fn scanned() -> PartitionTool {
+		let mut tool = PartitionTool { device: Some(stick("/dev/sdb", "A")), ..PartitionTool::default() };
+		let _ = tool.update(Message::Scanned(Ok(vec![entry("/dev/sdb1", 4 << 30, Some("iso9660")), entry("/dev/sdb2", 8 << 30, Some("ext4"))])), &Config::default(), &[]);
+
+		tool
+	}
+
+	#[test]
+	fn changes_are_spelled_out_before_theyre_made() {
+		let config = Config::default();
+		let mut tool = scanned();
+
+		let _ = tool.update(Message::Select(0), &config, &[]);
+		assert!(tool.selected.is_none() && tool.error.as_ref().is_some_and(|e| e.contains("can't be resized")));
+
+		let _ = tool.update(Message::Select(1), &config, &[]);
+		assert_eq!(tool.size, "8192M");
+		let _ = tool.update(Message::Resize, &config, &[]);
+		assert!(tool.pending.is_none() && tool.error.as_ref().is_some_and(|e| e.contains("already")));
+
+		let _ = tool.update(Message::SizeChanged("6G".into()), &config, &[]);
+		let _ = tool.update(Message::Resize, &config, &[]);
+		assert!(matches!(&tool.pending, Some(Edit::Resize { path, from, to, .. }) if path == "/dev/sdb2" && *from == 8 << 30 && *to == 6 << 30));
+		assert!(tool.error.is_none());
+		let _ = tool.view(&[]);
+
+		let _ = tool.update(Message::Back, &config, &[]);
+		let _ = tool.update(Message::NewSizeChanged("2G".into()), &config, &[]);
+		let _ = tool.update(Message::NewKindChanged(NewPartition::Format(Filesystem::Swap)), &config, &[]);
+		let _ = tool.update(Message::NewLabelChanged(" swap ".into()), &config, &[]);
+		let _ = tool.update(Message::Add, &config, &[]);
+		assert!(matches!(&tool.pending, Some(Edit::Add(spec)) if spec.size == Some(2 << 30) && spec.kind == Kind::Swap && spec.label == "swap"));
+
+		// Nothing is proposed that mkfs would turn down
+		let _ = tool.update(Message::Back, &config, &[]);
+		let _ = tool.update(Message::NewKindChanged(NewPartition::Format(Filesystem::Fat32)), &config, &[]);
+		let _ = tool.update(Message::NewLabelChanged("FAR TOO LONG A LABEL".into()), &config, &[]);
+		let _ = tool.update(Message::Add, &config, &[]);
+		assert!(tool.pending.is_none() && tool.error.is_some());
+	}
+
+	#[test]
+	fn a_device_being_written_isnt_edited() {
+		let mut tool = scanned();
+		let writing = ["/dev/sdb".to_string()];
+
+		let _ = tool.update(Message::Delete(1), &Config::default(), &writing);
+		assert!(tool.pending.is_some());
+		let _ = tool.update(Message::Apply, &Config::default(), &writing);
+		assert!(!tool.busy && tool.done.is_none());
+		assert_eq!(tool.error.as_deref(), Some("/dev/sdb is being written by another job"));
+	}
+}
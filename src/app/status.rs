@@ -0,0 +1,30 @@
+// How outcomes and warnings are shown: a shape in front as well as a color, so they read
+// the same without telling red from green. The colors come from the theme, which has a
+// color-blind-safe version of its own (see `Config::color_blind`), apart from the warning
+// orange that's told apart from both anyway.
+
+use std::fmt::Display;
+
+use iced::widget::{text, Text};
+
+const WARNING: [f32; 3] = [0.9, 0.6, 0.2];
+
+/// Something that worked, e.g. a checksum that matches.
+pub fn success<'a>(message: impl Display) -> Text<'a> {
+	text(format!("✔ {message}")).style(text::success)
+}
+
+/// Something to look out for that doesn't stop anything.
+pub fn warning<'a>(message: impl Display) -> Text<'a> {
+	text(format!("▲ {message}")).color(WARNING)
+}
+
+/// What's about to be lost, e.g. the data on a device.
+pub fn danger<'a>(message: impl Display) -> Text<'a> {
+	text(format!("▲ {message}")).style(text::danger)
+}
+
+/// Something that failed.
+pub fn error<'a>(message: impl Display) -> Text<'a> {
+	text(format!("✖ {message}")).style(text::danger)
+}
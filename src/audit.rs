@@ -0,0 +1,334 @@
+// An append-only record of every job that erases a device, for machines several people share.
+//
+// Each job gets a line when it starts and one when it ends, tab separated: the time (UTC),
+// the user, what was done, the device and its serial, the image and its checksum, what a
+// verified write hashed going onto the device and reading it back, the job's label, and how it
+// went. The file is only ever opened to append, so lines from two jobs (or two instances of
+// the app) ending at once don't overwrite each other. How a job ended is also sent wherever
+// the config asks for notifications, see notify.rs. The hooks in hooks.rs run around it: the
+// one before a job can refuse it, the one after runs when it succeeded.
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::hooks::{self, Hooks};
+use crate::notify::{self, Targets};
+use crate::writer::Readback;
+
+/// Records how the job ended with `audit`, if there is one, and gives back its result. A job
+/// that can't be recorded as ended fails with why, even though it went through: whoever relies
+/// on the log has to know it's missing from it.
+///
+/// A job that was refused, or couldn't be recorded as starting, never started and has no end.
+pub fn finish<T>(audit: Option<&Entry>, result: Result<T, String>) -> Result<T, String> {
+	let Some(audit) = audit.filter(|audit| audit.started.load(Ordering::Relaxed)) else {
+		return result;
+	};
+
+	match (audit.finished(&result.as_ref().map(|_| ()).map_err(Clone::clone)), result) {
+		(Ok(()), result) => result,
+		(Err(e), Ok(_)) => Err(format!("{e}. The job itself went through, but isn't in the log")),
+		(Err(e), Err(failed)) => Err(format!("{failed}. {e}")),
+	}
+}
+
+/// What a job does to the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+	/// An image written over the whole device
+	Write,
+	/// Repartitioned and formatted as FAT32, then files copied onto it
+	Format,
+	/// Repartitioned, formatted, and a Windows image applied to it
+	WindowsToGo,
+	/// Repartitioned from a layout file, and an image written to each partition that has one
+	Provision,
+	/// A partition deleted, resized or added with the partition editor
+	Repartition,
+	/// A fresh filesystem made on a device or partition with the format tool
+	Mkfs,
+	/// The partition table of a device a cut off job left half written wiped
+	Wipe,
+}
+
+impl fmt::Display for Operation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Operation::Write => "write",
+			Operation::Format => "format",
+			Operation::WindowsToGo => "windows-to-go",
+			Operation::Provision => "provision",
+			Operation::Repartition => "repartition",
+			Operation::Mkfs => "mkfs",
+			Operation::Wipe => "wipe",
+		})
+	}
+}
+
+/// One job in the log, recorded as it starts and again as it ends.
+#[derive(Debug, Clone)]
+pub struct Entry {
+	/// `None` when there's nowhere to keep the log
+	log: Option<PathBuf>,
+	operation: Operation,
+	device: String,
+	serial: Option<String>,
+	image: String,
+	/// Filled in by the write once it's read the whole image, see [`crate::writer::Options`]
+	pub image_hash: Arc<Mutex<Option<String>>>,
+	/// Filled in by a verified write once it's read the device back
+	pub readback: Arc<Mutex<Option<Readback>>>,
+	/// What the job was labelled with, a ticket number or who ran it
+	label: Option<String>,
+	/// Whether the start was recorded, shared with the clones the job itself takes along
+	started: Arc<AtomicBool>,
+	notify: Targets,
+	hooks: Hooks,
+}
+
+impl Entry {
+	/// The job goes unrecorded when there's nowhere to keep the log, its hooks and
+	/// notifications still run.
+	pub fn new(config: &Config, operation: Operation, device: &str, serial: Option<String>, image: &str) -> Self {
+		Self {
+			log: config.audit_log.clone().or_else(default_path),
+			operation,
+			device: device.to_string(),
+			serial,
+			image: image.to_string(),
+			image_hash: Arc::default(),
+			readback: Arc::default(),
+			label: None,
+			started: Arc::default(),
+			notify: Targets::new(config),
+			hooks: Hooks::new(config),
+		}
+	}
+
+	/// The entry with `label` recorded, a blank one is left out.
+	pub fn labelled(mut self, label: &str) -> Self {
+		self.label = Some(label.trim().to_string()).filter(|label| !label.is_empty());
+		self
+	}
+
+	/// Records that the job is starting, once the hook for before a job lets it. A job that
+	/// can't be recorded, or that the hook refuses, shouldn't go ahead.
+	pub fn started(&self) -> Result<(), String> {
+		// A refusal is kept too, it's why a device wasn't written
+		if let Err(e) = hooks::before(&self.hooks, &self.job("started", None)) {
+			let _ = self.record(&format!("refused: {e}"));
+			return Err(e);
+		}
+
+		self.record("started")?;
+		self.started.store(true, Ordering::Relaxed);
+
+		Ok(())
+	}
+
+	/// Records how the job ended, and sends it to whoever wants to know.
+	pub fn finished(&self, result: &Result<(), String>) -> Result<(), String> {
+		let (outcome, error) = match result {
+			Ok(()) => ("done", None),
+			Err(e) if e == "Cancelled" => ("cancelled", None),
+			Err(e) => ("failed", Some(e.clone())),
+		};
+
+		let recorded = self.record(&match &error {
+			Some(e) => format!("{outcome}: {e}"),
+			None => outcome.to_string(),
+		});
+
+		// Sent even when the log couldn't be written, the dashboard still wants to know
+		let job = self.job(outcome, error);
+		if result.is_ok() {
+			hooks::after(&self.hooks, job.clone());
+		}
+		notify::send(&self.notify, job);
+
+		recorded
+	}
+
+	// The job as hooks and notifications get it
+	fn job(&self, outcome: &str, error: Option<String>) -> notify::Job {
+		let (image_hash, written_hash, device_hash) = self.hashes();
+
+		notify::Job {
+			time: timestamp(SystemTime::now()),
+			host: sysinfo::System::host_name().unwrap_or_else(|| "unknown".into()),
+			user: user(),
+			label: self.label.clone(),
+			operation: self.operation.to_string(),
+			device: self.device.clone(),
+			serial: self.serial.clone(),
+			image: self.image.clone(),
+			image_hash,
+			written_hash,
+			device_hash,
+			result: outcome.to_string(),
+			error,
+		}
+	}
+
+	// The image's checksum, and what a verified write wrote and read back, as `<algorithm>:<digest>`
+	fn hashes(&self) -> (Option<String>, Option<String>, Option<String>) {
+		let image_hash = self.image_hash.lock().ok().and_then(|hash| hash.clone());
+		let readback = self.readback.lock().ok().and_then(|readback| readback.clone());
+
+		match readback {
+			Some(readback) => {
+				let id = readback.algorithm.id();
+				(image_hash, Some(format!("{id}:{}", readback.written)), Some(format!("{id}:{}", readback.device)))
+			},
+			None => (image_hash, None, None),
+		}
+	}
+
+	fn record(&self, outcome: &str) -> Result<(), String> {
+		let Some(log) = &self.log else {
+			return Ok(());
+		};
+
+		if let Some(dir) = log.parent() {
+			std::fs::create_dir_all(dir).map_err(|e| format!("Audit log error: {e}"))?;
+		}
+
+		let mut file = OpenOptions::new()
+			.append(true)
+			.create(true)
+			.open(log)
+			.map_err(|e| format!("Audit log error: {}: {e}", log.display()))?;
+
+		// One write for the whole line, so lines from two processes don't interleave
+		file.write_all(self.line(SystemTime::now(), outcome).as_bytes())
+			.map_err(|e| format!("Audit log error: {e}"))
+	}
+
+	fn line(&self, time: SystemTime, outcome: &str) -> String {
+		let (image_hash, written, device) = self.hashes();
+
+		let fields = [
+			timestamp(time),
+			user(),
+			self.operation.to_string(),
+			self.device.clone(),
+			self.serial.clone().unwrap_or_else(|| "-".into()),
+			self.image.clone(),
+			image_hash.unwrap_or_else(|| "-".into()),
+			written.unwrap_or_else(|| "-".into()),
+			device.unwrap_or_else(|| "-".into()),
+			self.label.clone().unwrap_or_else(|| "-".into()),
+			outcome.to_string(),
+		];
+
+		// A tab or newline in an error message or path would split the line
+		let fields: Vec<String> = fields.iter().map(|field| field.replace(['\t', '\n', '\r'], " ")).collect();
+		format!("{}\n", fields.join("\t"))
+	}
+}
+
+fn default_path() -> Option<PathBuf> {
+	dirs::data_local_dir().map(|dir| dir.join("iso_maker").join("audit.log"))
+}
+
+// Who's running it, and who ran sudo to get there
+fn user() -> String {
+	let name = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "unknown".into());
+
+	match std::env::var("SUDO_USER") {
+		Ok(sudo_user) if sudo_user != name => format!("{name} (sudo from {sudo_user})"),
+		_ => name,
+	}
+}
+
+/// The time in UTC, e.g. 2026-10-14T09:41:07Z.
+pub fn timestamp(time: SystemTime) -> String {
+	let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+	let (days, secs) = (secs / 86400, secs % 86400);
+
+	// Howard Hinnant's days_from_civil, backwards, with days counted from 0000-03-01
+	let days = days + 719_468;
+	let era = days / 146_097;
+	let day_of_era = days % 146_097;
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * month + 2) / 5 + 1;
+	let month = if month < 10 { month + 3 } else { month - 9 };
+	let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+	format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::hash::Algorithm;
+	use crate::testing::TempDir;
+	use std::time::Duration;
+
+	#[test]
+	fn timestamps_are_utc_dates() {
+		assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+		assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00Z");
+		assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(1_791_971_999)), "2026-10-14T09:59:59Z");
+	}
+
+	#[test]
+	fn jobs_are_appended_a_line_each() {
+		let dir = TempDir::new("audit");
+		let log = dir.file("audit.log");
+		let config = Config { audit_log: Some(log.clone().into()), ..Config::default() };
+
+		let first = Entry::new(&config, Operation::Write, "/dev/sdb", Some("A1".into()), "/tmp/image.iso");
+		first.started().unwrap();
+		*first.image_hash.lock().unwrap() = Some("sha256:abcd".into());
+		*first.readback.lock().unwrap() = Some(Readback { algorithm: Algorithm::Blake3, written: "ef01".into(), device: "ef01".into() });
+		first.finished(&Ok(())).unwrap();
+
+		let second = Entry::new(&config, Operation::Format, "/dev/sdc", None, "/tmp/image.iso").labelled(" INC-42\tlab ");
+		second.finished(&Err("Write error: it\tbroke\nbadly".into())).unwrap();
+
+		let contents = std::fs::read_to_string(&log).unwrap();
+		let lines: Vec<Vec<&str>> = contents.lines().map(|line| line.split('\t').collect()).collect();
+
+		assert_eq!(lines.len(), 3);
+		assert!(lines.iter().all(|fields| fields.len() == 11));
+		assert_eq!(lines[0][2..], ["write", "/dev/sdb", "A1", "/tmp/image.iso", "-", "-", "-", "-", "started"]);
+		assert_eq!(lines[1][6..], ["sha256:abcd", "blake3:ef01", "blake3:ef01", "-", "done"]);
+		assert_eq!(lines[2][2..5], ["format", "/dev/sdc", "-"]);
+		assert_eq!(lines[2][9..], ["INC-42 lab", "failed: Write error: it broke badly"]);
+	}
+
+	#[test]
+	fn a_job_that_cant_be_recorded_as_ended_says_so() {
+		let dir = TempDir::new("audit");
+		let config = Config { audit_log: Some(dir.file("audit.log").into()), ..Config::default() };
+		let audit = Some(Entry::new(&config, Operation::Write, "/dev/sdb", None, "/tmp/image.iso"));
+		audit.as_ref().unwrap().started().unwrap();
+
+		// Gone by the time the job ends
+		std::fs::remove_file(dir.file("audit.log")).unwrap();
+		std::fs::create_dir(dir.file("audit.log")).unwrap();
+
+		assert_eq!(finish(None, Ok(1)), Ok(1));
+		assert!(finish(audit.as_ref(), Ok(1)).is_err_and(|e| e.starts_with("Audit log error") && e.ends_with("isn't in the log")));
+		assert!(finish(audit.as_ref(), Err::<(), _>("Write error: it broke".into())).is_err_and(|e| e.starts_with("Write error: it broke. Audit log error")));
+	}
+
+	#[test]
+	fn the_hook_still_decides_without_a_log() {
+		let config = Config { pre_write_hook: Some("exit 3".into()), ..Config::default() };
+		let mut audit = Entry::new(&config, Operation::Write, "/dev/sdb", None, "/tmp/image.iso");
+		audit.log = None;
+
+		assert!(audit.started().is_err_and(|e| hooks::refused(&e)));
+		assert_eq!(finish(Some(&audit), Ok(1)), Ok(1));
+	}
+}
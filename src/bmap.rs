@@ -0,0 +1,266 @@
+// bmaptool style block maps, listing which blocks of a sparse image hold data.
+//
+// Embedded images are mostly empty space. With a .bmap next to the image only the mapped
+// blocks get written, each range checked against the checksum it was listed with.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::hash::{Algorithm, Hasher};
+
+/// A parsed .bmap file.
+#[derive(Debug, Clone)]
+pub struct Bmap {
+	pub image_size: u64,
+	/// Byte ranges of the image holding data, in order
+	ranges: Vec<Mapped>,
+	algorithm: Algorithm,
+}
+
+#[derive(Debug, Clone)]
+struct Mapped {
+	bytes: Range<u64>,
+	checksum: Option<String>,
+}
+
+impl Bmap {
+	/// The .bmap next to `image`, e.g. `image.img.bmap` or `image.bmap` for `image.img.xz`.
+	pub fn find(image: &str) -> Option<PathBuf> {
+		let image = Path::new(image);
+		let mut candidates = vec![PathBuf::from(format!("{}.bmap", image.display()))];
+
+		// Strip extensions one at a time, image.img.xz is mapped as image.img or plain image
+		let mut stem = image.to_path_buf();
+		while stem.extension().is_some() {
+			stem.set_extension("");
+			candidates.push(stem.with_extension("bmap"));
+			candidates.push(PathBuf::from(format!("{}.bmap", stem.display())));
+		}
+
+		candidates.into_iter().find(|candidate| candidate.is_file())
+	}
+
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let xml = std::fs::read_to_string(path).map_err(|e| format!("Bmap error: {}: {e}", path.display()))?;
+		Self::parse(&xml)
+	}
+
+	fn parse(xml: &str) -> Result<Self, String> {
+		let number = |name| -> Result<u64, String> {
+			tag(xml, name)
+				.and_then(|(value, _)| value.trim().parse().ok())
+				.ok_or_else(|| format!("Bmap error: missing or bad <{name}>"))
+		};
+
+		let image_size = number("ImageSize")?;
+		let block_size = number("BlockSize")?;
+
+		if block_size == 0 {
+			return Err("Bmap error: the block size is zero".into());
+		}
+
+		// Version 1 files only had SHA-1, in a differently named field
+		let algorithm = match tag(xml, "ChecksumType").map(|(value, _)| value.trim()) {
+			Some("sha256") => Algorithm::Sha256,
+			Some("sha1") | None => Algorithm::Sha1,
+			Some(other) => return Err(format!("Bmap error: unsupported checksum type {other}")),
+		};
+
+		check_file_checksum(xml, algorithm)?;
+
+		let block_map = tag(xml, "BlockMap").map(|(value, _)| value).ok_or("Bmap error: missing <BlockMap>")?;
+		let mut ranges = Vec::new();
+
+		for range in block_map.split("<Range").skip(1) {
+			let (attributes, rest) = range.split_once('>').ok_or("Bmap error: bad <Range>")?;
+			let blocks = rest.split_once("</Range>").map(|(blocks, _)| blocks.trim()).ok_or("Bmap error: bad <Range>")?;
+
+			let (first, last) = blocks.split_once('-').unwrap_or((blocks, blocks));
+			let (Ok(first), Ok(last)) = (first.trim().parse::<u64>(), last.trim().parse::<u64>()) else {
+				return Err(format!("Bmap error: bad range {blocks}"));
+			};
+
+			// The last block is cut short where the image ends
+			let (Some(start), Some(end)) = (first.checked_mul(block_size), last.checked_add(1).and_then(|end| end.checked_mul(block_size))) else {
+				return Err(format!("Bmap error: range {blocks} is outside the image"));
+			};
+			let bytes = start..end.min(image_size);
+			if first > last || bytes.start >= image_size || ranges.last().is_some_and(|previous: &Mapped| previous.bytes.end > bytes.start) {
+				return Err(format!("Bmap error: range {blocks} is out of order or outside the image"));
+			}
+
+			let checksum = attribute(attributes, "chksum").or_else(|| attribute(attributes, "sha1"));
+			ranges.push(Mapped { bytes, checksum: checksum.map(str::to_lowercase) });
+		}
+
+		Ok(Self { image_size, ranges, algorithm })
+	}
+
+	/// Bytes of data in the image, everything else can be skipped.
+	pub fn mapped(&self) -> u64 {
+		self.ranges.iter().map(|range| range.bytes.end - range.bytes.start).sum()
+	}
+
+	/// Byte ranges of the image holding data.
+	pub fn ranges(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+		self.ranges.iter().map(|range| range.bytes.clone())
+	}
+
+	pub fn filter(&self) -> Filter<'_> {
+		Filter { bmap: self, index: 0, hasher: None }
+	}
+}
+
+/// Picks the mapped parts out of the image as it streams past, checking each range once it's complete.
+pub struct Filter<'a> {
+	bmap: &'a Bmap,
+	/// First range not fully seen yet
+	index: usize,
+	hasher: Option<Hasher>,
+}
+
+impl Filter<'_> {
+	/// The parts of `data`, which starts at `offset` into the image, to write: where they go and where they are in `data`.
+	pub fn spans(&mut self, offset: u64, data: &[u8]) -> Result<Vec<(u64, Range<usize>)>, String> {
+		let end = offset + data.len() as u64;
+		let mut spans = Vec::new();
+
+		while let Some(range) = self.bmap.ranges.get(self.index) {
+			if range.bytes.start >= end {
+				break;
+			}
+
+			let start = range.bytes.start.max(offset);
+			let stop = range.bytes.end.min(end);
+
+			if start < stop {
+				let span = (start - offset) as usize..(stop - offset) as usize;
+
+				if range.checksum.is_some() {
+					self.hasher.get_or_insert_with(|| self.bmap.algorithm.hasher()).update(&data[span.clone()]);
+				}
+
+				spans.push((start, span));
+			}
+
+			// The range goes on in the next chunk
+			if range.bytes.end > end {
+				break;
+			}
+
+			if let (Some(expected), Some(hasher)) = (&range.checksum, self.hasher.take())
+				&& hasher.finalize() != *expected
+			{
+				return Err(format!(
+					"The image doesn't match its bmap, bytes {} to {} have the wrong checksum",
+					range.bytes.start, range.bytes.end,
+				));
+			}
+
+			self.index += 1;
+		}
+
+		Ok(spans)
+	}
+
+	/// Whether every range was seen, the image may have ended early.
+	pub fn finished(&self) -> bool {
+		self.index == self.bmap.ranges.len()
+	}
+}
+
+// Contents of the first <name> element and where they start
+fn tag<'a>(xml: &'a str, name: &str) -> Option<(&'a str, usize)> {
+	let open = format!("<{name}>");
+	let start = xml.find(&open)? + open.len();
+	let end = start + xml[start..].find(&format!("</{name}>"))?;
+
+	Some((&xml[start..end], start))
+}
+
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+	let start = attributes.find(&format!("{name}=\""))? + name.len() + 2;
+	let end = start + attributes[start..].find('"')?;
+
+	Some(&attributes[start..end])
+}
+
+// The file's own checksum is taken with the field holding it zeroed out
+fn check_file_checksum(xml: &str, algorithm: Algorithm) -> Result<(), String> {
+	let Some((field, start)) = tag(xml, "BmapFileChecksum").or_else(|| tag(xml, "BmapFileSHA1")) else {
+		return Ok(());
+	};
+
+	// Only the digest is zeroed, not the spaces around it
+	let expected = field.trim();
+	let start = start + field.find(expected).unwrap_or(0);
+
+	let mut zeroed = xml.to_string();
+	zeroed.replace_range(start..start + expected.len(), &"0".repeat(expected.len()));
+
+	let mut hasher = algorithm.hasher();
+	hasher.update(zeroed.as_bytes());
+
+	if hasher.finalize() != expected.to_lowercase() {
+		return Err("Bmap error: the .bmap file is corrupt, its own checksum doesn't match".into());
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A bmap for `image` in 4 KiB blocks, of `ranges` of blocks, signed with its own checksum
+	fn bmap(image: &[u8], ranges: &[(u64, u64)]) -> String {
+		let ranges: String = ranges.iter().map(|&(first, last)| {
+			let mut hasher = Algorithm::Sha256.hasher();
+			hasher.update(&image[first as usize * 4096..((last + 1) as usize * 4096).min(image.len())]);
+			format!("<Range chksum=\"{}\"> {first}-{last} </Range>\n", hasher.finalize())
+		}).collect();
+
+		let xml = format!(
+			"<?xml version=\"1.0\" ?>\n<bmap version=\"2.0\">\n<ImageSize> {} </ImageSize>\n<BlockSize> 4096 </BlockSize>\n\
+			<ChecksumType> sha256 </ChecksumType>\n<BmapFileChecksum> {} </BmapFileChecksum>\n<BlockMap>\n{ranges}</BlockMap>\n</bmap>\n",
+			image.len(), "0".repeat(64),
+		);
+
+		let mut hasher = Algorithm::Sha256.hasher();
+		hasher.update(xml.as_bytes());
+		xml.replace(&"0".repeat(64), &hasher.finalize())
+	}
+
+	#[test]
+	fn only_mapped_blocks_are_written_and_each_is_checked() {
+		let image = crate::testing::image(10 * 4096 + 100);
+		let xml = bmap(&image, &[(1, 2), (5, 5), (10, 10)]);
+		let parsed = Bmap::parse(&xml).unwrap();
+		assert_eq!(parsed.mapped(), 3 * 4096 + 100);
+
+		// Streamed past in chunks that don't line up with the blocks
+		let mut filter = parsed.filter();
+		let mut written = Vec::new();
+		for (i, chunk) in image.chunks(3000).enumerate() {
+			for (at, span) in filter.spans(i as u64 * 3000, chunk).unwrap() {
+				written.push((at, chunk[span].to_vec()));
+			}
+		}
+		assert!(filter.finished());
+		let starts: Vec<u64> = written.iter().map(|(at, _)| *at).collect();
+		assert_eq!(starts, [4096, 6000, 9000, 12000, 5 * 4096, 21000, 24000, 10 * 4096]);
+		assert_eq!(written.iter().map(|(_, data)| data.len() as u64).sum::<u64>(), parsed.mapped());
+
+		// An image that isn't what the bmap was made for
+		let mut other = image.clone();
+		other[5 * 4096 + 7] ^= 1;
+		let mut filter = parsed.filter();
+		assert!(filter.spans(0, &other).is_err());
+
+		// A changed, cut off or overflowing bmap
+		assert!(Bmap::parse(&xml.replace("5-5", "6-6")).unwrap_err().contains("corrupt"));
+		assert!(Bmap::parse(&xml[..xml.find("</BlockMap>").unwrap()]).is_err());
+		let overflowing = bmap(&image, &[]).replace("<BlockMap>\n", "<BlockMap>\n<Range> 18446744073709551615 </Range>\n");
+		assert!(Bmap::parse(&overflowing).is_err());
+	}
+}
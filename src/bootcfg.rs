@@ -0,0 +1,223 @@
+// Adding kernel parameters to the GRUB and syslinux configs on written media.
+
+use std::path::Path;
+
+use crate::devices::Device;
+use crate::mount::Mount;
+
+// Anything bigger isn't a boot menu
+const MAX_CONFIG: u64 = 1024 * 1024;
+
+// Directories bootloaders keep their configs in, relative to the root of the partition
+const CONFIG_DIRS: [&str; 4] = ["boot/grub", "syslinux", "isolinux", "extlinux"];
+
+/// A kernel line in one of the boot configs on a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelLine {
+	pub partition: String,
+	/// Path of the config, relative to the root of the partition
+	pub file: String,
+	pub line: String,
+}
+
+/// Adds `params` to the boot configs on every writable partition of `device`, mounting them as needed.
+///
+/// Returns the kernel lines found afterwards, so with no `params` it only looks.
+pub fn edit_device(device: &Device, params: &[String]) -> Result<Vec<KernelLine>, String> {
+	let partitions: Vec<_> = device.partitions.iter().filter(|partition| partition.is_writable()).collect();
+
+	if partitions.is_empty() {
+		return Err(format!("{} has no writable partitions, a raw written ISO keeps its boot menu on read-only ISO 9660", device.path));
+	}
+
+	let mut lines = Vec::new();
+
+	for partition in partitions {
+		// Use the desktop's mount if there is one
+		let mount;
+		let root = match partition.mount_points.first() {
+			Some(mount_point) => Path::new(mount_point),
+			None => {
+				mount = Mount::new(&partition.path)?;
+				mount.path()
+			},
+		};
+
+		add_params(root, params)?;
+
+		for path in configs(root)? {
+			let contents = std::fs::read_to_string(&path).unwrap_or_default();
+			let file = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().to_string();
+
+			lines.extend(contents.lines().filter(|line| kernel_line(line).is_some()).map(|line| KernelLine {
+				partition: partition.path.clone(),
+				file: file.clone(),
+				line: line.trim().to_string(),
+			}));
+		}
+	}
+
+	Ok(lines)
+}
+
+/// Adds `params` to every kernel line in the boot configs under `root`, returning how many files changed.
+///
+/// Parameters already on a line aren't added again, so running it twice is harmless.
+pub fn add_params(root: &Path, params: &[String]) -> Result<usize, String> {
+	if params.is_empty() {
+		return Ok(0);
+	}
+
+	let mut patched = 0;
+
+	for path in configs(root)? {
+		let contents = std::fs::read_to_string(&path).map_err(|e| format!("Boot config error: {}: {e}", path.display()))?;
+		let updated = patch(&contents, params);
+
+		if updated != contents {
+			std::fs::write(&path, updated).map_err(|e| format!("Boot config error: {}: {e}", path.display()))?;
+			patched += 1;
+		}
+	}
+
+	Ok(patched)
+}
+
+// The boot menus on a partition, only where bootloaders keep them: anything else on a root or
+// data partition that happens to end in .cfg is left alone
+fn configs(root: &Path) -> Result<Vec<std::path::PathBuf>, String> {
+	let mut found = Vec::new();
+
+	for dir in CONFIG_DIRS {
+		let dir = root.join(dir);
+		if dir.is_dir() {
+			found.extend(cfg_files(&dir)?);
+		}
+	}
+
+	// The one each EFI loader reads first, EFI/BOOT/grub.cfg, EFI/ubuntu/grub.cfg and so on
+	if let Ok(entries) = std::fs::read_dir(root.join("EFI")) {
+		for entry in entries.flatten() {
+			let path = entry.path().join("grub.cfg");
+			if path.metadata().is_ok_and(|metadata| metadata.is_file() && metadata.len() <= MAX_CONFIG) {
+				found.push(path);
+			}
+		}
+	}
+
+	Ok(found)
+}
+
+fn cfg_files(dir: &Path) -> Result<Vec<std::path::PathBuf>, String> {
+	let mut found = Vec::new();
+
+	for entry in std::fs::read_dir(dir).map_err(|e| format!("Boot config error: {}: {e}", dir.display()))? {
+		let entry = entry.map_err(|e| format!("Boot config error: {e}"))?;
+		let path = entry.path();
+		let metadata = entry.metadata().map_err(|e| format!("Boot config error: {e}"))?;
+
+		if metadata.is_dir() {
+			found.extend(cfg_files(&path)?);
+		} else if metadata.len() <= MAX_CONFIG && path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("cfg")) {
+			found.push(path);
+		}
+	}
+
+	Ok(found)
+}
+
+fn patch(contents: &str, params: &[String]) -> String {
+	contents.split_inclusive('\n')
+		.map(|line| {
+			// Keep whatever line ending the file uses
+			let body = line.trim_end_matches(['\r', '\n']);
+			let ending = &line[body.len()..];
+
+			match patch_line(body, params) {
+				Some(patched) => patched + ending,
+				None => line.to_string(),
+			}
+		})
+		.collect()
+}
+
+// Some(true) for GRUB kernel lines, Some(false) for syslinux ones
+fn kernel_line(line: &str) -> Option<bool> {
+	let keyword = line.split_whitespace().next()?;
+
+	// GRUB boots kernels with `linux`, syslinux passes their command line with `append`
+	if matches!(keyword, "linux" | "linuxefi" | "linux16") {
+		Some(true)
+	} else if keyword.eq_ignore_ascii_case("append") {
+		Some(false)
+	} else {
+		None
+	}
+}
+
+fn patch_line(line: &str, params: &[String]) -> Option<String> {
+	let grub = kernel_line(line)?;
+
+	let missing: Vec<String> = params.iter()
+		.map(|param| if grub { param.replace(';', "\\;") } else { param.clone() })
+		.filter(|param| !line.split_whitespace().any(|word| word == param))
+		.collect();
+
+	if missing.is_empty() {
+		return None;
+	}
+
+	let extra = missing.join(" ");
+
+	// Debian style installers copy everything after `---` to the installed system's command line
+	match line.find(" ---") {
+		Some(i) => Some(format!("{} {extra}{}", &line[..i], &line[i..])),
+		None => Some(format!("{line} {extra}")),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	fn params(params: &[&str]) -> Vec<String> {
+		params.iter().map(|param| param.to_string()).collect()
+	}
+
+	#[test]
+	fn parameters_go_before_the_installed_systems_part() {
+		let grub = "menuentry 'Install' {\n\tlinux /install/vmlinuz quiet --- console=ttyS0\r\n}\n";
+		assert_eq!(patch(grub, &params(&["nomodeset"])), "menuentry 'Install' {\n\tlinux /install/vmlinuz quiet nomodeset --- console=ttyS0\r\n}\n");
+
+		// GRUB would take a ; for the end of the command, syslinux passes it on as it is
+		assert_eq!(patch_line("linux /vmlinuz", &params(&["a=1;2"])).unwrap(), "linux /vmlinuz a=1\\;2");
+		assert_eq!(patch_line("  APPEND initrd=initrd.img", &params(&["a=1;2"])).unwrap(), "  APPEND initrd=initrd.img a=1;2");
+
+		// Only once, and only on kernel lines
+		assert_eq!(patch_line("linuxefi /vmlinuz quiet", &params(&["quiet"])), None);
+		assert_eq!(patch_line("menuentry linux", &params(&["quiet"])), None);
+	}
+
+	#[test]
+	fn only_boot_menus_are_touched() {
+		let dir = TempDir::new("bootcfg");
+		let write = |path: &str, contents: &str| {
+			let path = Path::new(&dir.file(path)).to_path_buf();
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(path, contents).unwrap();
+		};
+
+		let kernel = "linux /vmlinuz\n";
+		for menu in ["boot/grub/grub.cfg", "boot/grub/x86_64-efi/load.cfg", "EFI/ubuntu/grub.cfg", "isolinux/txt.cfg"] {
+			write(menu, kernel);
+		}
+		for other in ["etc/app/linux.cfg", "EFI/ubuntu/other.cfg", "home/user/notes.cfg"] {
+			write(other, kernel);
+		}
+
+		assert_eq!(add_params(Path::new(&dir.file("")), &params(&["nomodeset"])), Ok(4));
+		assert_eq!(std::fs::read_to_string(dir.file("EFI/ubuntu/grub.cfg")).unwrap(), "linux /vmlinuz nomodeset\n");
+		assert_eq!(std::fs::read_to_string(dir.file("etc/app/linux.cfg")).unwrap(), kernel);
+	}
+}
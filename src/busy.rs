@@ -0,0 +1,100 @@
+// What keeps a device from being unmounted, so the error can say what to close rather than
+// just that it's busy.
+//
+// Processes are found through /proc: by the files they have open, their working directory
+// and their root, anything under the mount point or the partition itself. Only the user's
+// own processes can be looked into without root, so the list may not be complete.
+
+/// Start of the error for a partition that couldn't be unmounted, see [`is_busy`].
+const BUSY: &str = "Busy";
+
+/// Whether a job failed because something was using the device, which closing it fixes.
+pub fn is_busy(error: &str) -> bool {
+	error.starts_with(BUSY)
+}
+
+/// A process using a mount point or device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holder {
+	pub pid: u32,
+	pub name: String,
+}
+
+impl std::fmt::Display for Holder {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} (pid {})", self.name, self.pid)
+	}
+}
+
+/// The error for `partition` not coming off `mount`, `reason` being what unmounting it said.
+pub fn error(partition: &str, mount: &str, reason: &str) -> String {
+	let holders = holders(&[partition, mount]);
+
+	if holders.is_empty() {
+		return format!("{BUSY}: {partition} couldn't be unmounted from {mount} ({reason}). Close anything using it, then retry");
+	}
+
+	let holders: Vec<String> = holders.iter().map(Holder::to_string).collect();
+	format!("{BUSY}: {partition} couldn't be unmounted from {mount}, it's in use by {}. Close them, then retry", holders.join(", "))
+}
+
+/// The processes with anything open under `paths`, by pid.
+#[cfg(target_os = "linux")]
+pub fn holders(paths: &[&str]) -> Vec<Holder> {
+	use std::path::Path;
+
+	let under = |path: &Path| paths.iter().any(|held| path.starts_with(held));
+	let Ok(entries) = std::fs::read_dir("/proc") else {
+		return Vec::new();
+	};
+
+	let mut holders: Vec<Holder> = entries
+		.filter_map(|entry| {
+			let entry = entry.ok()?;
+			let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+			let dir = entry.path();
+
+			// Someone else's, unless running as root
+			let fds = std::fs::read_dir(dir.join("fd")).ok()?;
+			let links = fds
+				.filter_map(|fd| Some(fd.ok()?.path()))
+				.chain(["cwd", "root", "exe"].map(|link| dir.join(link)));
+
+			if !links.filter_map(|link| std::fs::read_link(link).ok()).any(|target| under(&target)) {
+				return None;
+			}
+
+			let name = std::fs::read_to_string(dir.join("comm")).unwrap_or_default().trim().to_string();
+			Some(Holder { pid, name })
+		})
+		.collect();
+
+	holders.sort_by_key(|holder| holder.pid);
+	holders
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn holders(_paths: &[&str]) -> Vec<Holder> {
+	Vec::new()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	#[test]
+	fn finds_whoever_has_a_file_open() {
+		let dir = TempDir::new("busy");
+		let path = dir.file("open.txt");
+		std::fs::write(&path, b"held").unwrap();
+
+		let held = std::fs::File::open(&path).unwrap();
+		let found = holders(&[&path]);
+		assert!(found.iter().any(|holder| holder.pid == std::process::id()), "{found:?}");
+
+		drop(held);
+		let error = error("/dev/sdz1", &path, "target is busy");
+		assert!(is_busy(&error), "{error}");
+	}
+}
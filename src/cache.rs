@@ -0,0 +1,168 @@
+// Keeping a write out of the page cache, so flashing an 8 GB image doesn't push the rest
+// of the system's working set out of memory.
+//
+// The image is read once and the device written once, neither is worth caching. Pages are
+// dropped behind the copy as it goes: source pages once they've been read, device pages
+// once the kernel has written them out. Only Linux has the calls for it, elsewhere this
+// does nothing.
+
+use std::fs::File;
+use std::ops::Range;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+
+// How much is written before the kernel is told to start writing it out
+#[cfg(target_os = "linux")]
+const WINDOW: u64 = 32 * 1024 * 1024;
+
+/// Drops the cached pages of `file` in `range`, once they've been read.
+pub fn drop_read(file: &File, range: Range<u64>) {
+	dont_need(file, range.start, range.end - range.start);
+}
+
+// A `len` of 0 goes up to the end of the file. It's only advice, failing changes nothing
+// about the copy, so errors are ignored.
+#[cfg(target_os = "linux")]
+fn dont_need(file: &File, start: u64, len: u64) {
+	unsafe {
+		libc::posix_fadvise(file.as_raw_fd(), start as libc::off_t, len as libc::off_t, libc::POSIX_FADV_DONTNEED);
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+fn dont_need(_file: &File, _start: u64, _len: u64) {}
+
+/// Makes sure a synced block device has everything on the medium, for a write to count as done.
+///
+/// The fsync before this has the device flush its own cache already, BLKFLSBUF then
+/// writes out and throws away whatever the kernel still buffers for the device, so nothing
+/// read back afterwards comes from memory. It needs root; without it, the fsync has to do.
+#[cfg(target_os = "linux")]
+pub fn flush_device(file: &File) -> std::io::Result<()> {
+	use std::os::unix::fs::FileTypeExt;
+
+	// Not in libc, _IO(0x12, 97) from linux/fs.h
+	const BLKFLSBUF: libc::Ioctl = 0x1261;
+
+	if !file.metadata().is_ok_and(|metadata| metadata.file_type().is_block_device()) {
+		return Ok(());
+	}
+
+	if unsafe { libc::ioctl(file.as_raw_fd(), BLKFLSBUF, 0) } == 0 {
+		return Ok(());
+	}
+
+	match std::io::Error::last_os_error() {
+		e if e.kind() == std::io::ErrorKind::PermissionDenied => Ok(()),
+		e => Err(e),
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn flush_device(_file: &File) -> std::io::Result<()> {
+	// sync_all is the full flush elsewhere, F_FULLFSYNC on macOS and FlushFileBuffers on Windows
+	Ok(())
+}
+
+/// How much written data the kernel still has to put on the disks, `None` where that can't be told.
+///
+/// It's the whole system's, not just one device's, but while a stick is being synced that's
+/// mostly what there is.
+#[cfg(target_os = "linux")]
+pub fn pending() -> Option<u64> {
+	let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+
+	// Dirty pages are waiting to be written, Writeback ones are on their way
+	let kib: u64 = meminfo.lines()
+		.filter_map(|line| {
+			let (key, value) = line.split_once(':')?;
+			matches!(key, "Dirty" | "Writeback").then(|| value.trim().trim_end_matches("kB").trim().parse::<u64>().ok())?
+		})
+		.sum();
+
+	Some(kib * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pending() -> Option<u64> {
+	None
+}
+
+/// Drops a file's pages behind as it's read, for readers that don't know about offsets.
+pub struct DropBehind {
+	file: File,
+	position: u64,
+	dropped: u64,
+}
+
+impl DropBehind {
+	pub fn new(file: File) -> Self {
+		Self { file, position: 0, dropped: 0 }
+	}
+}
+
+impl std::io::Read for DropBehind {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let n = self.file.read(buf)?;
+		self.position += n as u64;
+
+		// A call per read would cost more than it saves, the rest goes at the end
+		if self.position - self.dropped >= 8 * 1024 * 1024 || (n == 0 && self.position > self.dropped) {
+			drop_read(&self.file, self.dropped..self.position);
+			self.dropped = self.position;
+		}
+
+		Ok(n)
+	}
+}
+
+/// Drops the device's pages behind the write, tracking how far it's got.
+#[derive(Default)]
+pub struct WriteBehind {
+	/// Where the window being written out starts
+	flushing: u64,
+	/// The end of that window, where the one being filled starts
+	filling: u64,
+}
+
+impl WriteBehind {
+	/// Called after each write with where it ended.
+	#[cfg(target_os = "linux")]
+	pub fn written(&mut self, file: &File, end: u64) {
+		if end < self.filling + WINDOW {
+			return;
+		}
+
+		let fd = file.as_raw_fd();
+
+		// Start writing out the window just filled, then wait for the one before it and drop
+		// its pages; there's always one window on its way to the device
+		unsafe {
+			libc::sync_file_range(fd, self.filling as libc::off64_t, (end - self.filling) as libc::off64_t, libc::SYNC_FILE_RANGE_WRITE);
+		}
+
+		if self.filling > self.flushing {
+			let (start, len) = (self.flushing, self.filling - self.flushing);
+			unsafe {
+				libc::sync_file_range(
+					fd,
+					start as libc::off64_t,
+					len as libc::off64_t,
+					libc::SYNC_FILE_RANGE_WAIT_BEFORE | libc::SYNC_FILE_RANGE_WRITE | libc::SYNC_FILE_RANGE_WAIT_AFTER,
+				);
+			}
+			dont_need(file, start, len);
+		}
+
+		self.flushing = self.filling;
+		self.filling = end;
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	pub fn written(&mut self, _file: &File, _end: u64) {}
+
+	/// Drops whatever's left, once the whole write has been synced.
+	pub fn finish(&self, file: &File) {
+		dont_need(file, self.flushing, 0);
+	}
+}
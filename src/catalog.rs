@@ -0,0 +1,251 @@
+// Catalogs of distro images, listed and checked by the checksum file the distro signs, e.g.
+// Fedora's CHECKSUM files or Ubuntu's SHA256SUMS and SHA256SUMS.gpg:
+//
+//     [[catalogs]]
+//     scheme = "fedora"
+//     base = "https://download.fedoraproject.org/pub/fedora/linux/releases/41/Workstation/x86_64/iso/"
+//     checksums = "Fedora-Workstation-41-1.4-x86_64-CHECKSUM"
+//     key = "/usr/share/distribution-gpg-keys/fedora/RPM-GPG-KEY-fedora-41-primary"
+//
+// `signature` names a detached signature next to the checksum file, without it the file is
+// clearsigned. The signature is checked against `key`, the distro's published key, before
+// anything in the file is trusted, so someone who can swap the image on a mirror can't just
+// swap its checksum along with it. `fedora://<name>` then downloads `<base><name>` and checks
+// it against the signed checksum, even when the job gives its own; an image the file doesn't
+// list can't be written at all.
+//
+// gpg does the checking, in a keyring of its own holding only `key`, removed again after.
+
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use crate::hash::Algorithm;
+use crate::provider::{Image, Provider};
+use crate::tools;
+
+// Checksum files are small, anything much bigger isn't one
+const MAX_CHECKSUMS: u64 = 4 * 1024 * 1024;
+
+/// A distro's images, as the config sets it up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Catalog {
+	pub scheme: String,
+	/// URL the image names and the checksum file are relative to, ending in /
+	pub base: String,
+	pub checksums: String,
+	/// Detached signature of the checksum file, `None` when it's clearsigned
+	pub signature: Option<String>,
+	/// File with the public key it's signed with
+	pub key: PathBuf,
+}
+
+impl Provider for Catalog {
+	fn scheme(&self) -> &str {
+		&self.scheme
+	}
+
+	fn list(&self) -> Result<Vec<Image>, String> {
+		let checksums = download(&format!("{}{}", self.base, self.checksums))?;
+		let signature = self.signature.as_ref().map(|signature| download(&format!("{}{signature}", self.base))).transpose()?;
+
+		let signed = verify(&checksums, signature.as_deref(), &self.key)?;
+		Ok(parse(&String::from_utf8_lossy(&signed)))
+	}
+
+	fn open(&self, name: &str) -> Result<(Box<dyn Read + Send>, u64), String> {
+		let response = ureq::get(&format!("{}{name}", self.base))
+			.call()
+			.map_err(|e| format!("Download error: {e}"))?;
+
+		let body = response.into_body();
+		let len = body.content_length().unwrap_or(0);
+
+		Ok((Box::new(body.into_reader()), len))
+	}
+
+	// Nothing the distro didn't sign for
+	fn checksum(&self, name: &str) -> Result<Option<(Algorithm, String)>, String> {
+		match self.list()?.into_iter().find(|image| image.name == name) {
+			Some(image) => Ok(image.checksum),
+			None => Err(format!("Catalog error: {name} isn't in {}'s signed {}", self.scheme, self.checksums)),
+		}
+	}
+
+	fn signed(&self) -> bool {
+		true
+	}
+}
+
+fn download(url: &str) -> Result<Vec<u8>, String> {
+	let response = ureq::get(url).call().map_err(|e| format!("Download error: {url}: {e}"))?;
+
+	let mut contents = Vec::new();
+	response.into_body().into_reader().take(MAX_CHECKSUMS).read_to_end(&mut contents).map_err(|e| format!("Download error: {url}: {e}"))?;
+
+	Ok(contents)
+}
+
+// What `key` signed of `checksums`: all of it with a detached `signature`, only the signed
+// part of a clearsigned file, as text around it could have been added by anyone
+fn verify(checksums: &[u8], signature: Option<&[u8]>, key: &std::path::Path) -> Result<Vec<u8>, String> {
+	let home = Keyring::new()?;
+	let gpg = |args: &[&str]| {
+		Command::new("gpg")
+			.arg("--homedir").arg(&home.0)
+			.args(["--batch", "--no-tty", "--status-fd", "1"])
+			.args(args)
+			.stdin(Stdio::null())
+			.output()
+			.map_err(|e| tools::not_found("gpg", e))
+	};
+
+	let imported = gpg(&["--import", &key.to_string_lossy()])?;
+	if !imported.status.success() {
+		return Err(format!("Catalog error: the key in {} can't be read: {}", key.display(), String::from_utf8_lossy(&imported.stderr).trim()));
+	}
+
+	let file = home.0.join("checksums");
+	std::fs::write(&file, checksums).map_err(|e| format!("Catalog error: {e}"))?;
+	let file = file.to_string_lossy().into_owned();
+
+	let (checked, signed) = match signature {
+		Some(signature) => {
+			let sig = home.0.join("checksums.sig");
+			std::fs::write(&sig, signature).map_err(|e| format!("Catalog error: {e}"))?;
+			(gpg(&["--verify", &sig.to_string_lossy(), &file])?, None)
+		},
+		None => {
+			let signed = home.0.join("signed");
+			(gpg(&["--output", &signed.to_string_lossy(), "--decrypt", &file])?, Some(signed))
+		},
+	};
+
+	// Good and made by a key that's there, the only one there is
+	let status = String::from_utf8_lossy(&checked.stdout);
+	if !checked.status.success() || !status.lines().any(|line| line.starts_with("[GNUPG:] VALIDSIG ")) {
+		return Err(format!("Catalog error: the checksum file isn't signed with the key in {}, it can't be trusted", key.display()));
+	}
+
+	match signed {
+		Some(signed) => std::fs::read(signed).map_err(|e| format!("Catalog error: {e}")),
+		None => Ok(checksums.to_vec()),
+	}
+}
+
+// Checksum lines, BSD style `SHA256 (golden.iso) = 9f86d0...` as Fedora has them, or
+// sha256sum's `9f86d0...  golden.iso`, going by the length for the algorithm
+fn parse(checksums: &str) -> Vec<Image> {
+	checksums.lines().filter_map(|line| {
+		let line = line.trim();
+
+		let (algorithm, name, digest) = if let Some((tag, rest)) = line.split_once(" (")
+			&& let Some((name, digest)) = rest.rsplit_once(") = ")
+		{
+			(tag.to_lowercase().parse().ok()?, name, digest)
+		} else {
+			let (digest, name) = line.split_once(char::is_whitespace)?;
+			let algorithm = match digest.len() {
+				32 => Algorithm::Md5,
+				40 => Algorithm::Sha1,
+				64 => Algorithm::Sha256,
+				128 => Algorithm::Sha512,
+				_ => return None,
+			};
+			(algorithm, name.trim_start().trim_start_matches('*'), digest)
+		};
+
+		if !digest.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+			return None;
+		}
+
+		Some(Image { name: name.to_string(), size: None, checksum: Some((algorithm, digest.to_lowercase())) })
+	}).collect()
+}
+
+// A gpg home of its own, so only the catalog's key counts
+struct Keyring(PathBuf);
+
+impl Keyring {
+	// Made fresh under a name nobody can guess, so nobody else's directory gets used for it
+	fn new() -> Result<Self, String> {
+		use std::hash::{BuildHasher, Hasher, RandomState};
+
+		let mut last = None;
+		for _ in 0..8 {
+			// RandomState is seeded from the system's random numbers
+			let mut hasher = RandomState::new().build_hasher();
+			hasher.write_u32(std::process::id());
+			let path = std::env::temp_dir().join(format!("iso_maker-gnupg-{:016x}", hasher.finish()));
+
+			let mut builder = std::fs::DirBuilder::new();
+			// gpg won't use a home anyone else can read
+			#[cfg(unix)]
+			std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+			match builder.create(&path) {
+				Ok(()) => return Ok(Self(path)),
+				Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => last = Some(e),
+				Err(e) => return Err(format!("Catalog error: {}: {e}", path.display())),
+			}
+		}
+
+		Err(format!("Catalog error: no new directory for the keyring: {}", last.map(|e| e.to_string()).unwrap_or_default()))
+	}
+}
+
+impl Drop for Keyring {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	#[test]
+	fn checksum_lines_are_read_either_way_theyre_written() {
+		let sums = parse("# Fedora-Workstation-41-1.4-x86_64-CHECKSUM\nSHA256 (Fedora-41.iso) = 9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08\n\
+			2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824 *ubuntu.iso\nnot a checksum\n");
+		assert_eq!(sums.iter().map(|image| image.name.as_str()).collect::<Vec<_>>(), ["Fedora-41.iso", "ubuntu.iso"]);
+		assert_eq!(sums[0].checksum.as_ref().map(|(algorithm, digest)| (*algorithm, &digest[..6])), Some((Algorithm::Sha256, "9f86d0")));
+	}
+
+	#[test]
+	fn only_what_the_distro_signed_is_trusted() {
+		if !Command::new("gpg").arg("--version").output().is_ok_and(|output| output.status.success()) {
+			eprintln!("Skipped, gpg isn't installed");
+			return;
+		}
+
+		// A distro's key and a checksum file signed with it
+		let dir = TempDir::new("catalog");
+		let gpg = |args: &[&str]| {
+			let output = Command::new("gpg").args(["--homedir", &dir.file(""), "--batch", "--passphrase", ""]).args(args).output().unwrap();
+			assert!(output.status.success(), "gpg {args:?}: {}", String::from_utf8_lossy(&output.stderr));
+		};
+		gpg(&["--quick-gen-key", "Distro <release@example.com>", "ed25519", "sign", "never"]);
+
+		let checksums = "SHA256 (Fedora-41.iso) = 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08\n";
+		std::fs::write(dir.file("CHECKSUM.txt"), checksums).unwrap();
+		gpg(&["--export", "--armor", "--output", &dir.file("key.asc")]);
+		gpg(&["--clearsign", "--output", &dir.file("CHECKSUM"), &dir.file("CHECKSUM.txt")]);
+		gpg(&["--detach-sign", "--output", &dir.file("CHECKSUM.sig"), &dir.file("CHECKSUM.txt")]);
+
+		let key = PathBuf::from(dir.file("key.asc"));
+		let clearsigned = std::fs::read(dir.file("CHECKSUM")).unwrap();
+		assert_eq!(verify(&clearsigned, None, &key).unwrap(), checksums.as_bytes());
+
+		let signature = std::fs::read(dir.file("CHECKSUM.sig")).unwrap();
+		assert!(verify(checksums.as_bytes(), Some(&signature), &key).is_ok());
+
+		// A swapped checksum doesn't check out
+		let swapped = checksums.replace("9f86d0", "000000");
+		assert!(verify(swapped.as_bytes(), Some(&signature), &key).is_err());
+		assert!(verify(&String::from_utf8_lossy(&clearsigned).replace("9f86d0", "000000").into_bytes(), None, &key).is_err());
+	}
+}
@@ -0,0 +1,750 @@
+// Command line mode for scripts, e.g. `xzcat image.xz | iso_maker write - /dev/sdb`.
+
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use crate::api;
+use crate::audit;
+use crate::bmap::Bmap;
+use crate::app::Mode;
+use crate::config::Config;
+use crate::customize::Customizations;
+use crate::devices;
+use crate::eta::Estimate;
+use crate::compare;
+use crate::hash::{self, Algorithm};
+use crate::hybrid::{self, Layout};
+use crate::provider;
+use crate::provision;
+use crate::remote;
+use crate::source;
+use crate::template::Template;
+use crate::units;
+use crate::writer::{self, Phase};
+
+const USAGE: &str = "\
+Usage:
+  iso_maker                                  Start the GUI
+  iso_maker <image>                          Start the GUI with the image picked
+  iso_maker write <image> <device> [options] Write an image to a device
+  iso_maker write --profile <name> <device>  Run a profile saved in the GUI on a device
+  iso_maker write --template <file> [<device>]
+                                             Run a job template, on the only device its target
+                                             matches unless one is given
+  iso_maker verify <image> <device> [options] Compare a device with an image without writing it
+  iso_maker provision <layout> <device> [--no-verify] [--label <text>]
+                                             Partition a device from a layout file and write
+                                             each partition's image to it
+  iso_maker serve [--listen <address>] [--token <secret>]
+                                             Take jobs over HTTP, for headless flashing
+                                             stations, see the README for the endpoints
+  iso_maker kiosk <image>                    Full screen, write and verify the image to every stick
+                                             plugged in, for duplicating in volume
+  iso_maker images                           List the images of the providers set up, a
+                                             tab-separated <url> <bytes> <algorithm>:<digest>
+                                             line each
+  iso_maker hash <file-or-device>... [--algorithm <name>] [--length <bytes>]
+                                             Print checksums like sha256sum, - hashes stdin
+
+<image> can be a file, an http(s), smb://, nfs://, s3:// or sftp:// URL, a \\\\server\\share UNC path,
+user@host:/path like scp takes it, the URL of a provider's image, or - to read from stdin.
+<device> can be on another machine, user@host:/dev/sdb or ssh://user@host:port/dev/sdb, written over SSH.
+
+Options:
+  --no-verify    Skip reading the device back after writing
+  --hybridize    Add a partition table to an ISO that has none, so it boots from USB on UEFI
+  --bmap <file>  Only write the blocks the bmap file lists, found next to the image by default
+  --no-bmap      Write the whole image even with a bmap file next to it
+  --profile <name>  Take the image and everything else from a saved profile
+  --label <text> Record the job under a label, e.g. a ticket number or who ran it
+  --verify <algorithm>  Verify with md5, sha1, sha256, sha512 or blake3 instead of the configured one
+  --checksum <algorithm>:<digest>  Fail if the image doesn't have this checksum, e.g. sha256:9f86d0...
+  --no-mmap      Read uncompressed images into buffers rather than memory-mapping them
+  --sync-every <MB>  Sync with the device every so many megabytes, 0 only syncs at the end
+  --resume-from <bytes>  Carry on with a write that was cut off, the start is only verified
+  --dry-run      Go through the write without writing anything, and say what it would do
+  --skip-unchanged  Compare the device with an image file first, and don't write it if it has it already
+  --no-skip-unchanged  Write it either way, when the settings skip it
+  --delta        Read the device ahead of the write and only write the blocks that differ
+  --no-delta     Write every block, when the settings say delta
+  --expand       Grow the last partition and its filesystem to the end of the device after writing
+  --machine-readable  Print progress as \"<phase> <done> <total> <unpacked>\" lines on stdout, and how much
+                      was synced as \"Synced <bytes>\" and the image's checksum as
+                      \"Image <algorithm>:<digest>\" at the end, \"Unchanged\" when nothing
+                      needed writing and \"Rewritten <bytes>\" after a delta write, a
+                      \"cancel\" line on stdin stops";
+
+/// Runs the command given on the command line, `None` means there was none and the GUI should
+/// start, with the image if one was given instead.
+pub fn run(args: &[String]) -> Option<ExitCode> {
+	let command = args.first()?;
+
+	// What a file manager's "Open with" runs
+	if args.len() == 1 && std::path::Path::new(command).is_file() {
+		return None;
+	}
+
+	Some(match command.as_str() {
+		"write" => write(&args[1..], false),
+		"verify" => write(&args[1..], true),
+		"provision" => provision(&args[1..]),
+		"serve" => serve(&args[1..]),
+		// Started as the GUI
+		"kiosk" if args.len() == 2 && !args[1].starts_with('-') => return None,
+		"kiosk" => usage_error("kiosk takes the image to write"),
+		"images" if args.len() == 1 => images(),
+		"images" => usage_error("images takes no options"),
+		"hash" => hash(&args[1..]),
+		"help" | "--help" | "-h" => {
+			println!("{USAGE}");
+			ExitCode::SUCCESS
+		},
+		other => usage_error(&format!("Unknown command: {other}")),
+	})
+}
+
+fn usage_error(message: &str) -> ExitCode {
+	eprintln!("{message}\n\n{USAGE}");
+	ExitCode::from(2)
+}
+
+// `verify_only` is the verify command, which takes the same options and leaves the device alone
+fn write(args: &[String], verify_only: bool) -> ExitCode {
+	let config = Config::load();
+	let mut verify = config.verify.then_some(config.verify_algorithm);
+	// --verify and --no-verify win over a profile or template, which win over the settings
+	let mut verify_given = false;
+	let mut hybridize = false;
+	let mut bmap = None;
+	let mut no_bmap = false;
+	let mut profile = None;
+	let mut expected = None;
+	let mut machine = false;
+	let mut mmap = config.mmap;
+	let mut checkpoint = config.checkpoint();
+	let mut resume = 0;
+	let mut dry_run = false;
+	let mut skip_unchanged = config.skip_unchanged;
+	let mut delta = config.delta;
+	let mut expand = false;
+	let mut label = String::new();
+	let mut template = None;
+	let mut positional = Vec::new();
+	let mut args = args.iter();
+
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--no-verify" => (verify, verify_given) = (None, true),
+			"--verify" => match args.next().map(|name| name.parse()) {
+				Some(Ok(algorithm)) => (verify, verify_given) = (Some(algorithm), true),
+				Some(Err(e)) => return usage_error(&e),
+				None => return usage_error("--verify needs an algorithm"),
+			},
+			"--checksum" => match args.next().and_then(|value| value.split_once(':')) {
+				Some((algorithm, digest)) => match algorithm.parse::<Algorithm>() {
+					Ok(algorithm) => expected = Some((algorithm, digest.to_string())),
+					Err(e) => return usage_error(&e),
+				},
+				None => return usage_error("--checksum needs an algorithm and digest, e.g. sha256:9f86d0..."),
+			},
+			"--machine-readable" => machine = true,
+			"--no-mmap" => mmap = false,
+			"--sync-every" => match args.next().map(|megabytes| megabytes.parse::<u64>()) {
+				Some(Ok(megabytes)) => checkpoint = megabytes.saturating_mul(1024 * 1024),
+				_ => return usage_error("--sync-every needs a number of megabytes"),
+			},
+			"--resume-from" => match args.next().map(|bytes| bytes.parse()) {
+				Some(Ok(bytes)) => resume = bytes,
+				_ => return usage_error("--resume-from needs the number of bytes already synced"),
+			},
+			"--dry-run" if !verify_only => dry_run = true,
+			"--skip-unchanged" if !verify_only => skip_unchanged = true,
+			"--no-skip-unchanged" if !verify_only => skip_unchanged = false,
+			"--delta" if !verify_only => delta = true,
+			"--no-delta" if !verify_only => delta = false,
+			"--hybridize" => hybridize = true,
+			"--expand" if !verify_only => expand = true,
+			"--bmap" => match args.next() {
+				Some(path) => bmap = Some(std::path::PathBuf::from(path)),
+				None => return usage_error("--bmap needs a file"),
+			},
+			"--no-bmap" => no_bmap = true,
+			"--label" => match args.next() {
+				Some(text) => label = text.clone(),
+				None => return usage_error("--label needs the text to record"),
+			},
+			"--profile" => match args.next().and_then(|name| config.profile(name)) {
+				Some(found) => profile = Some(found.clone()),
+				None => return usage_error("--profile needs the name of a saved profile"),
+			},
+			"--template" => match args.next().map(|path| Template::load(path)) {
+				Some(Ok(found)) => template = Some(found),
+				Some(Err(e)) => {
+					eprintln!("{e}");
+					return ExitCode::FAILURE;
+				},
+				None => return usage_error("--template needs a file"),
+			},
+			// A lone - is stdin, not an option
+			flag if flag.starts_with("--") => return usage_error(&format!("Unknown option: {flag}")),
+			_ => positional.push(arg.clone()),
+		}
+	}
+
+	let mut customizations = Customizations::default();
+
+	// A profile brings the image along, only the device is given
+	if let Some(profile) = &profile {
+		if profile.mode != Mode::Image {
+			eprintln!("Only profiles that write an image can be run from the command line, {} is {}", profile.name, profile.mode);
+			return ExitCode::FAILURE;
+		}
+
+		positional.insert(0, profile.source.clone());
+		if !verify_given {
+			verify = profile.verify;
+		}
+		hybridize |= profile.hybridize;
+		if expected.is_none() && !profile.checksum.trim().is_empty() {
+			expected = Some((profile.algorithm, profile.checksum.clone()));
+		}
+		if !verify_only {
+			customizations = profile.customizations();
+		}
+	}
+
+	// A template brings the image too, and can pick the device
+	if let Some(template) = &template {
+		if profile.is_some() {
+			return usage_error("--template and --profile can't go together");
+		}
+
+		positional.insert(0, template.profile.source.clone());
+		if !verify_given {
+			verify = template.verify.unwrap_or(verify);
+		}
+		if expected.is_none() && !template.profile.checksum.is_empty() {
+			expected = Some((template.profile.algorithm, template.profile.checksum.clone()));
+		}
+		if !verify_only {
+			customizations = template.profile.customizations();
+		}
+		if label.is_empty() {
+			label = template.label.clone();
+		}
+
+		let picked = match positional.get(1) {
+			Some(dest) => devices::find(dest).map_or(Ok(()), |device| template.target.check(&device)),
+			None => template.target.pick(&devices::list()).map(|device| positional.push(device.path.clone())),
+		};
+		if let Err(e) = picked {
+			eprintln!("{e}");
+			return ExitCode::FAILURE;
+		}
+	}
+
+	customizations.expand |= expand;
+	if customizations.expand && let Some(label) = &customizations.data_partition {
+		return usage_error(&format!("--expand would take the space the {label} data partition needs"));
+	}
+
+	let [source, dest] = positional.as_slice() else {
+		let command = if verify_only { "verify" } else { "write" };
+		return usage_error(&if profile.is_some() { format!("{command} --profile needs a device") } else { format!("{command} needs an image and a device") });
+	};
+
+	if remote::parse(dest).is_some() && !customizations.is_empty() {
+		return usage_error("Extra files, a data partition, expanding and first boot setup need the device at hand, it can't be on another machine");
+	}
+
+	let mbr = match hybridize.then(|| hybrid::layout(source)).flatten() {
+		Some(Layout::Plain(Some(efi))) => {
+			let len = std::fs::metadata(source).map(|metadata| metadata.len()).unwrap_or(0);
+			Some(hybrid::mbr(len, efi))
+		},
+		Some(Layout::Plain(None)) => {
+			eprintln!("{source} has no UEFI boot image to make bootable from USB");
+			return ExitCode::FAILURE;
+		},
+		// Already hybrid, or not an ISO at all
+		_ => None,
+	};
+
+	let bmap = match bmap.or_else(|| Bmap::find(source)).filter(|_| !no_bmap).map(|path| Bmap::load(&path)) {
+		Some(Ok(bmap)) => {
+			eprintln!("Using the bmap, {} of {} mapped", units::size(bmap.mapped()), units::size(bmap.image_size));
+			Some(bmap)
+		},
+		Some(Err(e)) => {
+			eprintln!("{e}");
+			return ExitCode::FAILURE;
+		},
+		None => None,
+	};
+
+	// Through pkexec the GUI keeps the log, it knows who's really running it
+	let audit = (!verify_only && !dry_run && !machine)
+		.then(|| audit::Entry::new(&config, audit::Operation::Write, dest, devices::find(dest).and_then(|device| device.serial), source).labelled(&label));
+	if let Some(audit) = &audit
+		&& let Err(e) = audit.started()
+	{
+		eprintln!("{e}");
+		return ExitCode::FAILURE;
+	}
+
+	let synced = Arc::new(AtomicU64::new(0));
+	let image_hash = audit.as_ref().map(|audit| audit.image_hash.clone()).unwrap_or_default();
+	let readback = audit.as_ref().map(|audit| audit.readback.clone()).unwrap_or_default();
+	let unchanged = Arc::new(AtomicBool::new(false));
+	let rewritten = Arc::new(AtomicU64::new(0));
+	let options = writer::Options {
+		verify,
+		expected,
+		mbr,
+		bmap,
+		mmap,
+		checkpoint,
+		synced: synced.clone(),
+		resume,
+		image_hash,
+		readback,
+		skip_unchanged,
+		unchanged: unchanged.clone(),
+		delta,
+		rewritten: rewritten.clone(),
+		unpacked: Arc::default(),
+	};
+
+	// 0 never warns
+	let stall = match config.stall_secs {
+		0 => Duration::MAX,
+		secs => Duration::from_secs(secs),
+	};
+
+	if dry_run {
+		let report = block_on_write(source.clone(), dest.clone(), options, machine, Action::DryRun, stall)
+			.and_then(|mut report| {
+				report.extend(customizations.dry_run()?);
+				Ok(report)
+			});
+
+		return match report {
+			Ok(report) => {
+				eprintln!("\nDry run, nothing was written:");
+				for line in report {
+					println!("{line}");
+				}
+				ExitCode::SUCCESS
+			},
+			Err(e) => {
+				eprintln!("\n{e}");
+				ExitCode::FAILURE
+			},
+		};
+	}
+
+	let action = if verify_only { Action::Verify } else { Action::Write };
+	let written = block_on_write(source.clone(), dest.clone(), options, machine, action, stall).and_then(|_| {
+		if customizations.is_empty() {
+			return Ok(());
+		}
+
+		eprintln!("\nFinishing");
+		customizations.apply_to_device(dest)
+	});
+
+	let written = audit::finish(audit.as_ref(), written);
+
+	match written {
+		Ok(()) => {
+			eprintln!("{}", if verify_only {
+				"\nThe device matches the image"
+			} else if unchanged.load(Ordering::Relaxed) {
+				"\nAlready up to date, the device has the image and nothing was written"
+			} else {
+				"\nComplete!"
+			});
+
+			if delta && !verify_only && !unchanged.load(Ordering::Relaxed) {
+				eprintln!("Only {} differed from what the device had and was written", units::size(rewritten.load(Ordering::Relaxed)));
+			}
+			ExitCode::SUCCESS
+		},
+		Err(e) => {
+			eprintln!("\n{e}");
+
+			let synced = synced.load(Ordering::Relaxed);
+			if synced > 0 && !verify_only {
+				eprintln!("The first {} were synced to the device before it stopped", units::size(synced));
+			}
+
+			if writer::is_disconnected(&e) {
+				if synced > 0 && !verify_only {
+					eprintln!("Plug it back in and run the write again with --resume-from {synced} to carry on from there");
+				} else {
+					eprintln!("Plug it back in and run the {} again", if verify_only { "verification" } else { "write" });
+				}
+			}
+
+			if writer::is_permission_denied(&e) {
+				eprintln!("Run it with sudo, or install the udev rule from the GUI's settings to write as yourself");
+			}
+
+			ExitCode::FAILURE
+		},
+	}
+}
+
+fn serve(args: &[String]) -> ExitCode {
+	let mut listen = api::DEFAULT_LISTEN.to_string();
+	// Out of the process list, unless it's given on the command line anyway
+	let mut token = std::env::var("ISO_MAKER_TOKEN").ok();
+	let mut args = args.iter();
+
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--listen" => match args.next() {
+				Some(address) => listen = address.clone(),
+				None => return usage_error("--listen needs an address, e.g. 0.0.0.0:8400"),
+			},
+			"--token" => match args.next() {
+				Some(secret) => token = Some(secret.clone()),
+				None => return usage_error("--token needs the secret clients send"),
+			},
+			other => return usage_error(&format!("Unknown option: {other}")),
+		}
+	}
+
+	let Some(token) = token else {
+		return usage_error("serve needs a token, with --token or in ISO_MAKER_TOKEN, for clients to send");
+	};
+
+	match api::serve(&listen, token) {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(e) => {
+			eprintln!("{e}");
+			ExitCode::FAILURE
+		},
+	}
+}
+
+fn provision(args: &[String]) -> ExitCode {
+	let config = Config::load();
+	let mut verify = config.verify.then_some(config.verify_algorithm);
+	let mut label = String::new();
+	let mut positional = Vec::new();
+	let mut args = args.iter();
+
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--no-verify" => verify = None,
+			"--label" => match args.next() {
+				Some(text) => label = text.clone(),
+				None => return usage_error("--label needs the text to record"),
+			},
+			flag if flag.starts_with("--") => return usage_error(&format!("Unknown option: {flag}")),
+			_ => positional.push(arg.clone()),
+		}
+	}
+
+	let [layout_file, dest] = positional.as_slice() else {
+		return usage_error("provision needs a layout file and a device");
+	};
+
+	// Everything it needs is checked before the device is touched
+	let layout = match provision::Layout::load(layout_file) {
+		Ok(layout) => layout,
+		Err(e) => {
+			eprintln!("{e}");
+			return ExitCode::FAILURE;
+		},
+	};
+
+	let audit = audit::Entry::new(&config, audit::Operation::Provision, dest, devices::find(dest).and_then(|device| device.serial), layout_file)
+		.labelled(&label);
+	if let Err(e) = audit.started() {
+		eprintln!("{e}");
+		return ExitCode::FAILURE;
+	}
+
+	let stall = match config.stall_secs {
+		0 => Duration::MAX,
+		secs => Duration::from_secs(secs),
+	};
+
+	eprintln!("Partitioning {dest}");
+	let written = layout.apply(dest).and_then(|()| {
+		let writes = layout.writes(dest);
+
+		for (n, (path, part)) in writes.iter().enumerate() {
+			let image = part.image.clone().unwrap_or_default();
+			eprintln!("\n{} of {}, {}: {image} to {path}", n + 1, writes.len(), part.spec.label);
+
+			let options = writer::Options { verify, mmap: config.mmap, checkpoint: config.checkpoint(), ..writer::Options::default() };
+			block_on_write(image, path.clone(), options, false, Action::Write, stall)?;
+		}
+
+		Ok(())
+	});
+
+	let written = audit::finish(Some(&audit), written);
+
+	match written {
+		Ok(()) => {
+			eprintln!("\nComplete!");
+			ExitCode::SUCCESS
+		},
+		Err(e) => {
+			eprintln!("\n{e}");
+			if writer::is_permission_denied(&e) {
+				eprintln!("Run it with sudo, partitioning needs root");
+			}
+			ExitCode::FAILURE
+		},
+	}
+}
+
+fn images() -> ExitCode {
+	let (images, errors) = provider::catalog();
+
+	for (url, image) in images {
+		let size = image.size.map_or_else(|| "-".to_string(), |size| size.to_string());
+		let checksum = image.checksum.map_or_else(|| "-".to_string(), |(algorithm, digest)| format!("{}:{digest}", algorithm.id()));
+		println!("{url}\t{size}\t{checksum}");
+	}
+
+	for e in &errors {
+		eprintln!("{e}");
+	}
+
+	match errors.is_empty() {
+		true => ExitCode::SUCCESS,
+		false => ExitCode::FAILURE,
+	}
+}
+
+fn hash(args: &[String]) -> ExitCode {
+	let mut algorithm = Algorithm::Sha256;
+	let mut length = None;
+	let mut paths = Vec::new();
+	let mut args = args.iter();
+
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--algorithm" => match args.next().map(|name| name.parse()) {
+				Some(Ok(parsed)) => algorithm = parsed,
+				Some(Err(e)) => return usage_error(&e),
+				None => return usage_error("--algorithm needs a name"),
+			},
+			// A device is bigger than the image on it, only the image's length is of interest
+			"--length" => match args.next().map(|value| value.parse::<u64>()) {
+				Some(Ok(bytes)) => length = Some(bytes),
+				_ => return usage_error("--length needs a number of bytes"),
+			},
+			flag if flag.starts_with("--") => return usage_error(&format!("Unknown option: {flag}")),
+			_ => paths.push(arg.clone()),
+		}
+	}
+
+	if paths.is_empty() {
+		return usage_error("hash needs a file or device");
+	}
+
+	let mut failed = false;
+
+	for path in &paths {
+		match hash_one(path, algorithm, length) {
+			// Same layout as sha256sum, so the output can be checked with it
+			Ok(digest) => println!("{digest}  {path}"),
+			Err(e) => {
+				eprintln!("{path}: {e}");
+				failed = true;
+			},
+		}
+	}
+
+	if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
+
+fn hash_one(path: &str, algorithm: Algorithm, length: Option<u64>) -> Result<String, String> {
+	let (reader, size): (Box<dyn std::io::Read>, u64) = if path == "-" {
+		(Box::new(std::io::stdin()), 0)
+	} else {
+		let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+		(Box::new(file), compare::size(path)?)
+	};
+
+	let (reader, total): (Box<dyn std::io::Read>, u64) = match length {
+		Some(length) => (Box::new(std::io::Read::take(reader, length)), if size == 0 { length } else { size.min(length) }),
+		None => (reader, size),
+	};
+
+	let mut last = String::new();
+	let digest = hash::hash_reader(reader, algorithm, |done| {
+		let line = if total == 0 {
+			format!("Hashing: {}", units::size(done))
+		} else {
+			format!("Hashing: {}", units::percent(done as f64 / total as f64, 0))
+		};
+
+		if line != last {
+			eprint!("\r{line}    ");
+			last = line;
+		}
+
+		true
+	});
+
+	// Clear the progress so the digest line stays clean
+	eprint!("\r{}\r", " ".repeat(last.len() + 4));
+	digest
+}
+
+// What block_on_write does with the device
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+	Write,
+	Verify,
+	DryRun,
+}
+
+// `machine` is for the GUI running a write as root, see elevate.rs. Only a dry run has
+// anything to say when it's done
+fn block_on_write(source: String, dest: String, options: writer::Options, machine: bool, action: Action, stall: Duration) -> Result<Vec<String>, String> {
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.build()
+		.map_err(|e| format!("Runtime error: {e}"))?;
+
+	let synced = options.synced.clone();
+	let image_hash = options.image_hash.clone();
+	let readback = options.readback.clone();
+	let unchanged = options.unchanged.clone();
+	let rewritten = options.delta.then(|| options.rewritten.clone());
+	let unpacked = options.unpacked.clone();
+
+	runtime.block_on(async move {
+		// Ctrl+C just kills the process, only a program driving it cancels
+		let (cancel_tx, cancel_rx) = mpsc::channel(1);
+		let (progress_tx, mut progress_rx) = mpsc::channel(100);
+
+		// Dropping every sender counts as cancelling, this one stays until the write is done
+		let _keep_open = cancel_tx.clone();
+
+		// Unless stdin is the image, over SSH from a remote write, see remote.rs
+		if machine && source != source::STDIN {
+			std::thread::spawn(move || {
+				if std::io::stdin().lines().map_while(Result::ok).any(|line| line.trim() == "cancel") {
+					let _ = cancel_tx.blocking_send(());
+				}
+			});
+		}
+
+		let copy = async move {
+			match action {
+				Action::Write => writer::copy_with_progress(source, dest, options, cancel_rx, progress_tx).await.map(|()| Vec::new()),
+				Action::Verify => writer::verify_only(source, dest, options, cancel_rx, progress_tx).await.map(|()| Vec::new()),
+				Action::DryRun => writer::dry_run(source, dest, options, cancel_rx, progress_tx).await,
+			}
+		};
+
+		let report = async move {
+			let mut last = String::new();
+			let mut last_phase = Phase::Writing;
+			let mut warned = false;
+			let mut estimate = Estimate::default();
+
+			loop {
+				// Nothing to be done about a device hanging in the kernel, but it's worth saying
+				let (phase, done, total) = match tokio::time::timeout(stall, progress_rx.recv()).await {
+					Ok(Some(progress)) => progress,
+					Ok(None) => break,
+					Err(_) => {
+						if !machine && !warned && matches!(last_phase, Phase::Writing | Phase::Syncing | Phase::Checking | Phase::Verifying) {
+							eprintln!("\nNothing has happened for {} s, the device appears stalled (Ctrl+C gives up on it)", stall.as_secs());
+							warned = true;
+						}
+						continue;
+					},
+				};
+				// Each phase goes at its own speed
+				if phase != last_phase {
+					estimate = Estimate::default();
+				}
+				last_phase = phase;
+				warned = false;
+
+				let unpacked = unpacked.load(Ordering::Relaxed);
+
+				if machine {
+					println!("{phase:?} {done} {total} {unpacked}");
+					continue;
+				}
+
+				estimate.update(done, total);
+
+				let mut line = progress_line(phase, done, total);
+				if let Some(unpacked) = writer::unpacked(phase, done, unpacked) {
+					line = format!("{line}, {unpacked}");
+				}
+				if let Some(estimate) = estimate.text() {
+					line = format!("{line}, {estimate}");
+				}
+
+				// Only redraw when something visible changed
+				if line != last {
+					eprint!("\r{line}    ");
+					last = line;
+				}
+			}
+
+			last_phase
+		};
+
+		let (result, last_phase) = tokio::join!(copy, report);
+
+		// Failing after the write was done, there's nothing to resume
+		if result.is_err() && !matches!(last_phase, Phase::Writing | Phase::Syncing) {
+			synced.store(0, Ordering::Relaxed);
+		}
+
+		// For a write as root to know how far it got, whether it worked or not
+		if machine && action != Action::DryRun {
+			println!("Synced {}", synced.load(Ordering::Relaxed));
+		}
+
+		// And what it wrote, for the audit log of whatever is driving it
+		if machine && let Some(hash) = image_hash.lock().ok().and_then(|hash| hash.clone()) {
+			println!("Image {hash}");
+		}
+
+		if machine && let Some(readback) = readback.lock().ok().and_then(|readback| readback.clone()) {
+			println!("Readback {} {} {}", readback.algorithm.id(), readback.written, readback.device);
+		}
+
+		if machine && unchanged.load(Ordering::Relaxed) {
+			println!("Unchanged");
+		}
+
+		if machine && action == Action::Write && let Some(rewritten) = &rewritten {
+			println!("Rewritten {}", rewritten.load(Ordering::Relaxed));
+		}
+
+		result
+	})
+}
+
+fn progress_line(phase: Phase, done: u64, total: u64) -> String {
+	if phase == Phase::Syncing {
+		format!("{phase}: {} remaining", units::size(total - done))
+	} else if total == 0 {
+		// Reading from a pipe, the total isn't known
+		format!("{phase}: {}", units::size(done))
+	} else {
+		format!("{phase}: {} ({} of {})", units::percent(done as f64 / total as f64, 1), units::size(done), units::size(total))
+	}
+}
@@ -0,0 +1,77 @@
+// Byte for byte comparison of images and devices.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparison {
+	pub len_a: u64,
+	pub len_b: u64,
+	/// Bytes compared, the length of the shorter of the two
+	pub compared: u64,
+	/// Offset of the first byte that differs
+	pub first_difference: Option<u64>,
+}
+
+impl Comparison {
+	/// Identical as far as the shorter side goes, which is what matters for image vs device.
+	pub fn identical(&self) -> bool {
+		self.first_difference.is_none()
+	}
+}
+
+/// Size of a file or block device, metadata says 0 for the latter so seek to the end instead.
+pub fn size(path: &str) -> Result<u64, String> {
+	let mut file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+	file.seek(SeekFrom::End(0)).map_err(|e| format!("{path}: {e}"))
+}
+
+/// Compares `a` and `b` up to the length of the shorter one.
+///
+/// `progress` gets the number of bytes compared so far and returns false to cancel.
+pub fn compare(a: &str, b: &str, mut progress: impl FnMut(u64) -> bool) -> Result<Comparison, String> {
+	let len_a = size(a)?;
+	let len_b = size(b)?;
+	let compared = len_a.min(len_b);
+
+	let mut file_a = File::open(a).map_err(|e| format!("{a}: {e}"))?;
+	let mut file_b = File::open(b).map_err(|e| format!("{b}: {e}"))?;
+
+	let mut buffer_a = vec![0; 4096 * 1024]; // 4MB buffers
+	let mut buffer_b = vec![0; 4096 * 1024];
+	let mut offset = 0;
+
+	while offset < compared {
+		let n = (compared - offset).min(buffer_a.len() as u64) as usize;
+
+		file_a.read_exact(&mut buffer_a[..n]).map_err(|e| format!("{a}: {e}"))?;
+		file_b.read_exact(&mut buffer_b[..n]).map_err(|e| format!("{b}: {e}"))?;
+
+		if buffer_a[..n] != buffer_b[..n] {
+			let index = buffer_a[..n].iter()
+				.zip(&buffer_b[..n])
+				.position(|(x, y)| x != y)
+				.unwrap_or(0);
+
+			return Ok(Comparison {
+				len_a,
+				len_b,
+				compared,
+				first_difference: Some(offset + index as u64),
+			});
+		}
+
+		offset += n as u64;
+
+		if !progress(offset) {
+			return Err("Cancelled".into());
+		}
+	}
+
+	Ok(Comparison {
+		len_a,
+		len_b,
+		compared,
+		first_difference: None,
+	})
+}
@@ -0,0 +1,314 @@
+// User settings, persisted as TOML in the platform config directory.
+
+use std::path::{Path, PathBuf};
+
+use iced::theme::Palette;
+use iced::{Color, Theme};
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::Catalog;
+use crate::hash::Algorithm;
+use crate::profile::Profile;
+use crate::provider::External;
+use crate::units::Units;
+
+/// Name used in the theme picker for the user defined palette.
+pub const CUSTOM_THEME: &str = "Custom";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+	/// Display name of a built-in iced theme, or [`CUSTOM_THEME`]
+	pub theme: String,
+	pub palette: CustomPalette,
+	/// Success and danger colors that don't rely on telling red from green, on any theme
+	pub color_blind: bool,
+	/// Multiplier applied to the whole UI, for HiDPI screens and accessibility
+	pub scale_factor: f64,
+	/// Whether sizes are shown in GB or GiB
+	pub units: Units,
+	/// Read the device back after writing and compare it against what was written
+	pub verify: bool,
+	pub verify_algorithm: Algorithm,
+	/// Play a sound when a job completes or fails
+	pub sound: bool,
+	/// Compare the device with the image before writing, and skip the write if it has it already
+	pub skip_unchanged: bool,
+	/// Only write the blocks that differ from what the device has
+	pub delta: bool,
+	/// Memory-map uncompressed images instead of reading them into buffers
+	pub mmap: bool,
+	/// Megabytes written between syncs with the device, 0 only syncs at the end
+	pub checkpoint_mb: u64,
+	/// Seconds a write can go without progress before it's said to be stalled, 0 never
+	pub stall_secs: u64,
+	/// Where the log of jobs that erased a device goes, None for `iso_maker/audit.log` in the
+	/// local data directory
+	pub audit_log: Option<PathBuf>,
+	/// URL every job that erased a device is POSTed to as JSON once it ends
+	pub webhook: Option<String>,
+	/// Address the same goes to by email, through sendmail
+	pub notify_email: Option<String>,
+	/// Shell command run before every job that erases a device, which it can refuse, see hooks.rs
+	pub pre_write_hook: Option<String>,
+	/// Shell command run after every job that erased a device and succeeded
+	pub post_write_hook: Option<String>,
+	/// Image sources of other schemes, run as commands, see provider.rs
+	pub providers: Vec<External>,
+	/// Distro images checked by their signed checksum files, see catalog.rs
+	pub catalogs: Vec<Catalog>,
+	/// Device each image was last written to, most recent first
+	pub last_devices: Vec<LastDevice>,
+	pub profiles: Vec<Profile>,
+}
+
+/// The serial of the device an image, by its [`crate::hash::fingerprint`], was last written to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastDevice {
+	pub image: String,
+	pub serial: String,
+}
+
+// Enough for a test bench, without the file growing forever
+const MAX_LAST_DEVICES: usize = 50;
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			theme: Theme::TokyoNight.to_string(),
+			palette: CustomPalette::default(),
+			color_blind: false,
+			scale_factor: 1.0,
+			units: Units::Decimal,
+			verify: true,
+			verify_algorithm: Algorithm::Blake3,
+			sound: false,
+			skip_unchanged: false,
+			delta: false,
+			mmap: true,
+			checkpoint_mb: 256,
+			stall_secs: 30,
+			audit_log: None,
+			webhook: None,
+			notify_email: None,
+			pre_write_hook: None,
+			post_write_hook: None,
+			providers: Vec::new(),
+			catalogs: Vec::new(),
+			last_devices: Vec::new(),
+			profiles: Vec::new(),
+		}
+	}
+}
+
+/// Colors for the custom theme, stored as `#rrggbb` hex strings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CustomPalette {
+	pub accent: String,
+	pub background: String,
+	pub danger: String,
+}
+
+impl Default for CustomPalette {
+	fn default() -> Self {
+		let base = Theme::TokyoNight.palette();
+
+		Self {
+			accent: to_hex(base.primary),
+			background: to_hex(base.background),
+			danger: to_hex(base.danger),
+		}
+	}
+}
+
+impl Config {
+	/// Bytes written between syncs, for [`crate::writer::Options::checkpoint`].
+	pub fn checkpoint(&self) -> u64 {
+		self.checkpoint_mb.saturating_mul(1024 * 1024)
+	}
+
+	/// Loads the config file, falling back to the defaults if it's missing or broken. Why a
+	/// broken one didn't load goes to stderr, see [`Config::try_load`].
+	pub fn load() -> Self {
+		let (config, error) = Self::try_load();
+		if let Some(e) = error {
+			eprintln!("{e}");
+		}
+
+		config
+	}
+
+	/// Loads the config file like [`Config::load`], with why it didn't load when it's broken.
+	pub fn try_load() -> (Self, Option<String>) {
+		match path().map(|path| load_from(&path)) {
+			Some(Err(e)) => (Self::default(), Some(e)),
+			Some(Ok(config)) => (config, None),
+			None => (Self::default(), None),
+		}
+	}
+
+	/// Saves the config file. One that doesn't load is kept as `config.toml.bak` first, it's
+	/// the user's profiles, sources and hooks, which the defaults would lose.
+	pub fn save(&self) -> Result<(), String> {
+		self.save_to(&path().ok_or("Could not find a config directory")?)
+	}
+
+	fn save_to(&self, path: &Path) -> Result<(), String> {
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent).map_err(|e| format!("Config error: {e}"))?;
+		}
+
+		if path.exists() && load_from(path).is_err() {
+			let backup = path.with_extension("toml.bak");
+			std::fs::rename(path, &backup)
+				.map_err(|e| format!("Config error: {} doesn't load and couldn't be moved to {}, it's left as it is: {e}", path.display(), backup.display()))?;
+		}
+
+		let contents = toml::to_string_pretty(self).map_err(|e| format!("Config error: {e}"))?;
+		std::fs::write(path, contents).map_err(|e| format!("Config error: {e}"))
+	}
+
+	/// Serial of the device the image with fingerprint `image` was last written to.
+	pub fn last_device(&self, image: &str) -> Option<&str> {
+		self.last_devices.iter()
+			.find(|last| last.image == image)
+			.map(|last| last.serial.as_str())
+	}
+
+	pub fn remember_device(&mut self, image: String, serial: String) {
+		self.last_devices.retain(|last| last.image != image);
+		self.last_devices.insert(0, LastDevice { image, serial });
+		self.last_devices.truncate(MAX_LAST_DEVICES);
+	}
+
+	pub fn profile(&self, name: &str) -> Option<&Profile> {
+		self.profiles.iter().find(|profile| profile.name == name)
+	}
+
+	/// Adds `profile`, replacing any with the same name.
+	pub fn save_profile(&mut self, profile: Profile) {
+		match self.profiles.iter_mut().find(|existing| existing.name == profile.name) {
+			Some(existing) => *existing = profile,
+			None => self.profiles.push(profile),
+		}
+	}
+
+	pub fn scale_factor(&self) -> f64 {
+		// Hand edited configs could contain anything
+		if self.scale_factor.is_finite() {
+			self.scale_factor.clamp(*SCALE_RANGE.start(), *SCALE_RANGE.end())
+		} else {
+			1.0
+		}
+	}
+
+	pub fn theme(&self) -> Theme {
+		let theme = if self.theme == CUSTOM_THEME {
+			Theme::custom(CUSTOM_THEME.to_string(), self.palette.to_palette())
+		} else {
+			Theme::ALL.iter()
+				.find(|theme| theme.to_string() == self.theme)
+				.cloned()
+				.unwrap_or(Theme::TokyoNight)
+		};
+
+		if !self.color_blind {
+			return theme;
+		}
+
+		// Blue and vermillion from the Okabe-Ito palette, lighter on dark backgrounds
+		let palette = theme.palette();
+		let (success, danger) = if luminance(palette.background) < 0.5 {
+			(Color::from_rgb(0.34, 0.71, 0.91), Color::from_rgb(0.96, 0.48, 0.16))
+		} else {
+			(Color::from_rgb(0.0, 0.45, 0.70), Color::from_rgb(0.80, 0.30, 0.0))
+		};
+
+		Theme::custom(theme.to_string(), Palette { success, danger, ..palette })
+	}
+}
+
+/// Smallest and largest allowed [`Config::scale_factor`].
+pub const SCALE_RANGE: std::ops::RangeInclusive<f64> = 0.5..=3.0;
+
+impl CustomPalette {
+	// Colors that don't parse (e.g. while still being typed) keep the base theme's value
+	fn to_palette(&self) -> Palette {
+		let base = Theme::TokyoNight.palette();
+		let background = Color::parse(&self.background).unwrap_or(base.background);
+
+		// Pick readable text for whatever background was chosen
+		let text = if luminance(background) < 0.5 { Palette::DARK.text } else { Palette::LIGHT.text };
+
+		Palette {
+			background,
+			text,
+			primary: Color::parse(&self.accent).unwrap_or(base.primary),
+			success: base.success,
+			danger: Color::parse(&self.danger).unwrap_or(base.danger),
+		}
+	}
+}
+
+/// Names for the theme picker, the built-in themes followed by the custom one.
+pub fn theme_names() -> Vec<String> {
+	Theme::ALL.iter()
+		.map(|theme| theme.to_string())
+		.chain(std::iter::once(CUSTOM_THEME.to_string()))
+		.collect()
+}
+
+// Perceived brightness, 0 for black to 1 for white
+fn luminance(color: Color) -> f32 {
+	0.299 * color.r + 0.587 * color.g + 0.114 * color.b
+}
+
+fn to_hex(color: Color) -> String {
+	let [r, g, b, _] = color.into_rgba8();
+	format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn path() -> Option<PathBuf> {
+	dirs::config_dir().map(|dir| dir.join("iso_maker").join("config.toml"))
+}
+
+// A file that isn't there is the defaults
+fn load_from(path: &Path) -> Result<Config, String> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(contents) => contents,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+		Err(e) => return Err(format!("Config error: {}: {e}", path.display())),
+	};
+
+	toml::from_str(&contents).map_err(|e| format!(
+		"Config error: {} doesn't load, the defaults are used until it's fixed and it's moved to config.toml.bak before anything is saved: {e}",
+		path.display(),
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	#[test]
+	fn a_config_that_doesnt_load_isnt_saved_over() {
+		let dir = TempDir::new("config");
+		let path = PathBuf::from(dir.file("config.toml"));
+		assert!(load_from(&path).is_ok());
+
+		let broken = "pre_write_hook = \"check-asset\"\n[[profiles]\n";
+		std::fs::write(&path, broken).unwrap();
+		assert!(load_from(&path).is_err());
+
+		Config::default().save_to(&path).unwrap();
+		assert_eq!(std::fs::read_to_string(dir.file("config.toml.bak")).unwrap(), broken);
+		assert!(load_from(&path).is_ok());
+
+		// Only ever a broken one
+		std::fs::remove_file(dir.file("config.toml.bak")).unwrap();
+		Config::default().save_to(&path).unwrap();
+		assert!(!std::path::Path::new(&dir.file("config.toml.bak")).exists());
+	}
+}
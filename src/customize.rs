@@ -0,0 +1,263 @@
+// Changes made to the media after the image is on it: extra files, automation and boot parameters.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bootcfg;
+use crate::devices;
+use crate::firstboot;
+use crate::mount::{self, Mount};
+use crate::partition::{self, Filesystem, Kind, Spec};
+use crate::pi;
+
+/// Installer automation formats, each ends up where its installer looks for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Automation {
+	/// Debian and Ubuntu's d-i
+	Preseed,
+	/// Fedora and RHEL's Anaconda
+	Kickstart,
+	/// cloud-init NoCloud user-data, which Ubuntu's autoinstall also uses
+	CloudInit,
+}
+
+impl Automation {
+	pub const ALL: [Automation; 3] = [Automation::Preseed, Automation::Kickstart, Automation::CloudInit];
+
+	/// Best guess from the file's contents, the user can still correct it.
+	pub fn guess(path: &str) -> Self {
+		let contents = std::fs::read_to_string(path).unwrap_or_default();
+
+		if contents.trim_start().starts_with("#cloud-config") {
+			Automation::CloudInit
+		} else if contents.lines().any(|line| line.starts_with("d-i ")) {
+			Automation::Preseed
+		} else {
+			Automation::Kickstart
+		}
+	}
+
+	// Where the installer looks for the file, relative to the root of the stick
+	fn destination(self) -> &'static str {
+		match self {
+			Automation::Preseed => "preseed.cfg",
+			Automation::Kickstart => "ks.cfg",
+			Automation::CloudInit => "nocloud/user-data",
+		}
+	}
+
+	// What tells the installer to use the file, `label` is the stick's filesystem label
+	fn params(self, label: &str) -> Vec<String> {
+		match self {
+			// d-i mounts the install media on /cdrom, USB sticks included
+			Automation::Preseed => vec![
+				"auto=true".into(),
+				"priority=critical".into(),
+				"preseed/file=/cdrom/preseed.cfg".into(),
+			],
+			Automation::Kickstart => vec![format!("inst.ks=hd:LABEL={label}:/ks.cfg")],
+			Automation::CloudInit => vec!["ds=nocloud;s=/cdrom/nocloud/".into()],
+		}
+	}
+}
+
+impl fmt::Display for Automation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Automation::Preseed => "Preseed",
+			Automation::Kickstart => "Kickstart",
+			Automation::CloudInit => "cloud-init",
+		})
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Customizations {
+	/// Local file and the path on the stick to copy it to
+	pub files: Vec<(PathBuf, String)>,
+	/// Files and folders copied into the root of the stick as they are
+	pub payload: Vec<PathBuf>,
+	pub automation: Option<(Automation, PathBuf)>,
+	/// Label of an exFAT partition to make in the space an image leaves free
+	pub data_partition: Option<String>,
+	/// Grow the image's last partition and its filesystem into that space instead
+	pub expand: bool,
+	/// Raspberry Pi OS settings for the first boot
+	pub pi: Option<pi::Settings>,
+	/// Script run once on the first boot of a Linux image
+	pub first_boot: Option<PathBuf>,
+}
+
+impl Customizations {
+	pub fn is_empty(&self) -> bool {
+		!self.has_files() && self.data_partition.is_none() && !self.expand && self.pi.is_none() && self.first_boot.is_none()
+	}
+
+	fn has_files(&self) -> bool {
+		!self.files.is_empty() || !self.payload.is_empty() || self.automation.is_some()
+	}
+
+	/// What applying them would do, a line each, for a dry run; a file that's gone is an error.
+	pub fn dry_run(&self) -> Result<Vec<String>, String> {
+		let files = self.files.iter().map(|(from, _)| from)
+			.chain(&self.payload)
+			.chain(self.automation.iter().map(|(_, file)| file))
+			.chain(&self.first_boot);
+
+		for file in files {
+			if !file.exists() {
+				return Err(format!("Copy error: {} doesn't exist", file.display()));
+			}
+		}
+
+		let mut lines = Vec::new();
+
+		if self.expand {
+			lines.push("The last partition and its filesystem would be grown to the end of the device".into());
+		}
+
+		if let Some(label) = &self.data_partition {
+			lines.push(format!("An exFAT partition labelled {label} would be made in the space after the image"));
+		}
+
+		if self.pi.is_some() {
+			lines.push("Raspberry Pi OS would be set up for its first boot".into());
+		}
+
+		if let Some(script) = &self.first_boot {
+			lines.push(format!("{} would run once on the first boot", script.display()));
+		}
+
+		if let Some((automation, file)) = &self.automation {
+			lines.push(format!("The {automation} file {} would be added to the boot menu", file.display()));
+		}
+
+		let copied = self.files.len() + self.payload.len();
+		if copied > 0 {
+			lines.push(format!("{copied} file(s) or folder(s) would be copied onto the largest writable partition"));
+		}
+
+		Ok(lines)
+	}
+
+	/// Applies everything to a device an image was just written to, files go on its largest writable partition.
+	pub fn apply_to_device(&self, device: &str) -> Result<(), String> {
+		// The kernel still has the old partition table
+		partition::reread(device)?;
+
+		if self.expand {
+			partition::expand_last(device)?;
+		}
+
+		if let Some(label) = &self.data_partition {
+			partition::append(device, &Spec {
+				size: None,
+				kind: Kind::Data,
+				filesystem: Some(Filesystem::Exfat),
+				label: label.clone(),
+			})?;
+		}
+
+		if let Some(pi) = &self.pi {
+			apply_pi(device, pi)?;
+		}
+
+		if let Some(script) = &self.first_boot {
+			install_first_boot(device, script)?;
+		}
+
+		if !self.has_files() {
+			return Ok(());
+		}
+
+		let partition = partitions(device)?
+			.into_iter()
+			.filter(|partition| partition.is_writable())
+			.max_by_key(|partition| partition.size)
+			.ok_or("The image has no writable partition to put the extra files on")?;
+
+		let mount = Mount::new(&partition.path)?;
+		self.apply(mount.path(), partition.label.as_deref().unwrap_or_default())
+	}
+
+	/// Applies everything to the media mounted at `root`, whose filesystem is labelled `label`.
+	pub fn apply(&self, root: &Path, label: &str) -> Result<(), String> {
+		if let Some((automation, file)) = &self.automation {
+			// Patch the configs first, the automation file is a .cfg itself
+			if bootcfg::add_params(root, &automation.params(label))? == 0 {
+				return Err("No GRUB or syslinux config found on the media to add the automation file to".into());
+			}
+
+			copy(file, &root.join(automation.destination()))?;
+
+			// NoCloud wants a meta-data file next to user-data, even an empty one
+			let meta_data = root.join("nocloud/meta-data");
+			if *automation == Automation::CloudInit && !meta_data.exists() {
+				std::fs::write(meta_data, "").map_err(|e| format!("Copy error: {e}"))?;
+			}
+		}
+
+		for (from, to) in &self.files {
+			copy(from, &root.join(to.trim_start_matches('/')))?;
+		}
+
+		for from in &self.payload {
+			let name = from.file_name().ok_or_else(|| format!("Copy error: {} has no name", from.display()))?;
+
+			if from.is_dir() {
+				mount::copy_dir(from, &root.join(name))?;
+			} else {
+				copy(from, &root.join(name))?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+fn partitions(device: &str) -> Result<Vec<devices::Partition>, String> {
+	devices::list().into_iter()
+		.find(|current| current.path == device)
+		.map(|current| current.partitions)
+		.ok_or_else(|| format!("{device} is no longer connected"))
+}
+
+// The boot partition is the FAT one with cmdline.txt on it, normally the first
+fn apply_pi(device: &str, settings: &pi::Settings) -> Result<(), String> {
+	for partition in partitions(device)? {
+		if partition.filesystem.as_deref() != Some("vfat") {
+			continue;
+		}
+
+		let mount = Mount::new(&partition.path)?;
+		if mount.path().join("cmdline.txt").is_file() {
+			return settings.apply(mount.path());
+		}
+	}
+
+	Err("The image has no Raspberry Pi boot partition (with a cmdline.txt) to customize".into())
+}
+
+fn install_first_boot(device: &str, script: &Path) -> Result<(), String> {
+	let candidates = partitions(device)?.into_iter()
+		.filter(|partition| partition.filesystem.as_deref().is_some_and(|fs| firstboot::ROOT_FILESYSTEMS.contains(&fs)));
+
+	for partition in candidates {
+		let mount = Mount::new(&partition.path)?;
+		if firstboot::is_root(mount.path()) {
+			return firstboot::install(mount.path(), script);
+		}
+	}
+
+	Err("The image has no Linux root filesystem with systemd to run the first boot script".into())
+}
+
+fn copy(from: &Path, to: &Path) -> Result<(), String> {
+	if let Some(parent) = to.parent() {
+		std::fs::create_dir_all(parent).map_err(|e| format!("Copy error: {e}"))?;
+	}
+
+	std::fs::copy(from, to).map(|_| ()).map_err(|e| format!("Copy error: {}: {e}", from.display()))
+}
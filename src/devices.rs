@@ -0,0 +1,600 @@
+// Discovery of removable block devices that can be written to.
+
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+use std::fmt;
+
+use sysinfo::Disks;
+
+use crate::units;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+	/// Device node to write to, e.g. `/dev/sdb`
+	pub path: String,
+	pub model: String,
+	pub serial: Option<String>,
+	pub size: u64,
+	/// `dos`, `gpt`, ... when the device has a partition table
+	pub partition_table: Option<String>,
+	pub partitions: Vec<Partition>,
+	/// Removable media or on USB, anything else could be an internal disk
+	pub removable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partition {
+	pub path: String,
+	pub size: u64,
+	pub label: Option<String>,
+	pub filesystem: Option<String>,
+	pub mount_points: Vec<String>,
+	/// Used bytes, from the mounted filesystem or its superblock when we can read it
+	pub used: Option<u64>,
+}
+
+impl fmt::Display for Device {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} ({}, {})", self.model, self.path, units::size(self.size))
+	}
+}
+
+impl fmt::Display for Partition {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.path)?;
+
+		if let Some(label) = &self.label {
+			write!(f, " \"{label}\"")?;
+		}
+
+		if let Some(filesystem) = &self.filesystem {
+			write!(f, " ({filesystem})")?;
+		}
+
+		write!(f, ", {}", units::size(self.size))
+	}
+}
+
+impl Device {
+	/// Multi-line summary of what's on the device, shown when hovering over it.
+	pub fn describe(&self) -> String {
+		let mut lines = vec![format!("{} — {}", self.path, units::size(self.size))];
+
+		if let Some(serial) = &self.serial {
+			lines.push(format!("Serial: {serial}"));
+		}
+
+		if self.partitions.is_empty() {
+			lines.push("No partitions".into());
+		}
+
+		for partition in &self.partitions {
+			let mut line = partition.to_string();
+
+			if let Some(used) = partition.used {
+				line.push_str(&format!(", {} used", units::size(used)));
+			}
+
+			if partition.mount_points.is_empty() {
+				line.push_str(", not mounted");
+			} else {
+				line.push_str(&format!(", mounted at {}", partition.mount_points.join(", ")));
+			}
+
+			lines.push(line);
+		}
+
+		lines.join("\n")
+	}
+
+	/// Whether the running system is on the device.
+	pub fn holds_system(&self) -> bool {
+		self.partitions.iter()
+			.flat_map(|partition| &partition.mount_points)
+			.any(|mount| matches!(mount.as_str(), "/" | "/boot" | "/boot/efi" | "/usr" | "/home" | "[SWAP]" | "C:\\"))
+	}
+
+	/// Whether writing to the device would destroy something that looks like data.
+	pub fn has_data(&self) -> bool {
+		self.partition_table.is_some() || self.partitions.iter().any(|partition| partition.filesystem.is_some())
+	}
+
+	/// One line per thing that will be destroyed, for the confirmation step.
+	pub fn existing_data(&self) -> Vec<String> {
+		let mut lines = Vec::new();
+
+		if let Some(table) = &self.partition_table {
+			let table = match table.as_str() {
+				"dos" => "MBR",
+				"gpt" => "GPT",
+				other => other,
+			};
+
+			lines.push(format!("{table} partition table with {} partition(s)", self.partitions.len()));
+		}
+
+		for partition in &self.partitions {
+			let label = partition.label.as_deref().unwrap_or("unlabeled");
+			let filesystem = partition.filesystem.as_deref().unwrap_or("unknown filesystem");
+
+			lines.push(format!("{} \"{label}\" ({filesystem}, {})", partition.path, units::size(partition.size)));
+		}
+
+		lines
+	}
+
+	/// Bytes of data the write would destroy, and how many filesystems couldn't be measured.
+	pub fn data_at_risk(&self) -> (u64, usize) {
+		let filesystems = self.partitions.iter().filter(|partition| partition.filesystem.is_some());
+
+		filesystems.fold((0, 0), |(used, unknown), partition| match partition.used {
+			Some(bytes) => (used + bytes, unknown),
+			None => (used, unknown + 1),
+		})
+	}
+}
+
+impl Partition {
+	/// Whether files can be put on it, ISO 9660 and friends are read-only by design.
+	pub fn is_writable(&self) -> bool {
+		self.filesystem.as_deref().is_some_and(|fs| !matches!(fs, "iso9660" | "udf" | "squashfs" | "swap"))
+	}
+}
+
+/// Estimates the used space of an unmounted filesystem from its superblock.
+///
+/// Only ext2/3/4, FAT32 and exFAT keep usage figures that are cheap to read,
+/// anything else (or a device we can't open) gives `None`.
+pub fn probe_used(path: &str, filesystem: &str) -> Option<u64> {
+	use std::io::Read;
+
+	let mut header = [0u8; 2048];
+	std::fs::File::open(path).ok()?.read_exact(&mut header).ok()?;
+
+	let u16_at = |offset: usize| u16::from_le_bytes([header[offset], header[offset + 1]]) as u64;
+	let u32_at = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap()) as u64;
+
+	match filesystem {
+		"ext2" | "ext3" | "ext4" => {
+			let sb = 1024;
+			if u16_at(sb + 0x38) != 0xEF53 {
+				return None;
+			}
+
+			// Checked, as a corrupt superblock can say anything
+			let block_size = 1024u64.checked_shl(u32_at(sb + 0x18).try_into().ok()?)?;
+			let mut blocks = u32_at(sb + 0x04);
+			let mut free = u32_at(sb + 0x0C);
+
+			// 64bit feature keeps the high halves further in
+			if u32_at(sb + 0x60) & 0x80 != 0 {
+				blocks |= u32_at(sb + 0x150) << 32;
+				free |= u32_at(sb + 0x158) << 32;
+			}
+
+			blocks.saturating_sub(free).checked_mul(block_size)
+		},
+		"vfat" => {
+			let bytes_per_sector = u16_at(11);
+			let sectors_per_cluster = header[13] as u64;
+			let reserved = u16_at(14);
+			let fats = header[16] as u64;
+			let total = u32_at(32);
+			let fat_size = u32_at(36);
+			let fs_info = u16_at(48) as usize;
+
+			// Only FAT32 has a free cluster count, in the FSInfo sector
+			if u16_at(22) != 0 || sectors_per_cluster == 0 || bytes_per_sector == 0 {
+				return None;
+			}
+
+			let offset = fs_info * bytes_per_sector as usize;
+			if offset + 512 > header.len() || u32_at(offset) != 0x41615252 || u32_at(offset + 484) != 0x61417272 {
+				return None;
+			}
+
+			let free = u32_at(offset + 488);
+			if free == 0xFFFF_FFFF {
+				return None;
+			}
+
+			let clusters = total.saturating_sub(reserved + fats * fat_size) / sectors_per_cluster;
+			Some(clusters.saturating_sub(free) * sectors_per_cluster * bytes_per_sector)
+		},
+		"exfat" => {
+			if &header[3..11] != b"EXFAT   " {
+				return None;
+			}
+
+			let cluster_size = 1u64.checked_shl(header[108] as u32 + header[109] as u32)?;
+			let percent = header[112] as u64;
+			if percent > 100 {
+				return None;
+			}
+
+			Some(u32_at(92).checked_mul(cluster_size)?.checked_mul(percent)? / 100)
+		},
+		_ => None,
+	}
+}
+
+/// Looks at the start of a device for an MBR or GPT, which needs read access to it.
+pub fn probe_partition_table(path: &str) -> Option<String> {
+	use std::io::Read;
+
+	let mut header = [0u8; 1024];
+	std::fs::File::open(path).ok()?.read_exact(&mut header).ok()?;
+
+	if &header[512..520] == b"EFI PART" {
+		Some("gpt".into())
+	} else if header[510..512] == [0x55, 0xAA] {
+		Some("dos".into())
+	} else {
+		None
+	}
+}
+
+/// Lists the removable devices currently attached.
+#[cfg(target_os = "linux")]
+pub fn list() -> Vec<Device> {
+	enumerate(false)
+}
+
+/// Any whole disk at `path`, removable or not, for paths typed in by hand.
+#[cfg(target_os = "linux")]
+pub fn find(path: &str) -> Option<Device> {
+	// /dev/disk/by-id/... links work too
+	let path = std::fs::canonicalize(path).ok()?.to_string_lossy().to_string();
+	enumerate(true).into_iter().find(|device| device.path == path)
+}
+
+#[cfg(target_os = "linux")]
+fn enumerate(fixed: bool) -> Vec<Device> {
+	let Ok(entries) = std::fs::read_dir("/sys/block") else {
+		return Vec::new();
+	};
+
+	let mounts = mount_points();
+	let used = used_space();
+	let block = std::path::Path::new("/sys/block");
+
+	let mut devices: Vec<Device> = entries
+		.filter_map(Result::ok)
+		.filter_map(|entry| {
+			let name = entry.file_name().to_string_lossy().to_string();
+			let sys = entry.path();
+
+			// Only removable media or anything hanging off a USB bus
+			let link = std::fs::read_link(&sys).unwrap_or_default();
+			let removable = read_trimmed(&sys.join("removable")).as_deref() == Some("1")
+				|| link.to_string_lossy().contains("/usb");
+			if !removable && !fixed {
+				return None;
+			}
+
+			// Empty card readers show up with a size of zero
+			let size = sectors(&sys)?;
+			if size == 0 {
+				return None;
+			}
+
+			let udev = udev_properties(&sys);
+			let model = udev.get("ID_MODEL")
+				.map(|model| model.replace('_', " "))
+				.or_else(|| read_trimmed(&sys.join("device/model")))
+				.unwrap_or_else(|| name.clone());
+
+			let mut partitions: Vec<Partition> = std::fs::read_dir(&sys)
+				.into_iter()
+				.flatten()
+				.filter_map(Result::ok)
+				.filter(|part| part.path().join("partition").exists())
+				.filter_map(|part| {
+					let part_name = part.file_name().to_string_lossy().to_string();
+					let path = format!("/dev/{part_name}");
+					let udev = udev_properties(&part.path());
+					let mut mount_points = mounts.get(&path).cloned().unwrap_or_default();
+					mount_points.extend(held_mounts(block, &part.path(), &mounts));
+					let filesystem = udev.get("ID_FS_TYPE").cloned();
+					let used = mount_points.iter()
+						.find_map(|mount| used.get(mount).copied())
+						.or_else(|| probe_used(&path, filesystem.as_deref()?));
+
+					Some(Partition {
+						size: sectors(&part.path())?,
+						label: udev.get("ID_FS_LABEL").cloned(),
+						filesystem,
+						mount_points,
+						used,
+						path,
+					})
+				})
+				.collect();
+			partitions.sort_by(|a, b| a.path.cmp(&b.path));
+
+			let path = format!("/dev/{name}");
+
+			// Superfloppy layout, the filesystem takes the whole device
+			if partitions.is_empty() && udev.contains_key("ID_FS_TYPE") {
+				let mut mount_points = mounts.get(&path).cloned().unwrap_or_default();
+				mount_points.extend(held_mounts(block, &sys, &mounts));
+				let filesystem = udev.get("ID_FS_TYPE").cloned();
+				partitions.push(Partition {
+					used: mount_points.iter()
+						.find_map(|mount| used.get(mount).copied())
+						.or_else(|| probe_used(&path, filesystem.as_deref()?)),
+					path: path.clone(),
+					size,
+					label: udev.get("ID_FS_LABEL").cloned(),
+					filesystem,
+					mount_points,
+				});
+			}
+
+			Some(Device {
+				serial: udev.get("ID_SERIAL_SHORT").cloned(),
+				partition_table: udev.get("ID_PART_TABLE_TYPE")
+					.cloned()
+					.or_else(|| probe_partition_table(&path)),
+				path,
+				model,
+				size,
+				partitions,
+				removable,
+			})
+		})
+		.collect();
+
+	devices.sort_by(|a, b| a.path.cmp(&b.path));
+
+	devices
+}
+
+/// Lists the removable devices currently attached.
+#[cfg(not(target_os = "linux"))]
+pub fn list() -> Vec<Device> {
+	// Without sysfs the best we have is the removable volumes sysinfo sees
+	Disks::new_with_refreshed_list()
+		.iter()
+		.filter(|disk| disk.is_removable())
+		.map(|disk| {
+			let path = disk.name().to_string_lossy().to_string();
+			let mount = disk.mount_point().to_string_lossy().to_string();
+
+			Device {
+				model: path.clone(),
+				serial: None,
+				partition_table: None,
+				size: disk.total_space(),
+				partitions: vec![Partition {
+					path: path.clone(),
+					size: disk.total_space(),
+					label: None,
+					filesystem: Some(disk.file_system().to_string_lossy().to_string()),
+					mount_points: vec![mount],
+					used: Some(disk.total_space() - disk.available_space()),
+				}],
+				path,
+				removable: true,
+			}
+		})
+		.collect()
+}
+
+/// Any disk at `path`, for paths typed in by hand, e.g. `\\.\PhysicalDrive2`.
+#[cfg(not(target_os = "linux"))]
+pub fn find(path: &str) -> Option<Device> {
+	// Only the removable volumes can be looked up, anything else is taken as a fixed disk
+	list().into_iter().find(|device| device.path == path).or_else(|| Some(Device {
+		path: path.to_string(),
+		model: "Unknown disk".into(),
+		serial: None,
+		size: 0,
+		partition_table: None,
+		partitions: Vec::new(),
+		removable: false,
+	}))
+}
+
+/// Where the app gets its devices from, so the tests can hand it made-up ones.
+pub trait DeviceEnumerator {
+	/// The removable devices attached, see [`list`].
+	fn list(&self) -> Vec<Device>;
+
+	/// A disk typed in by hand, see [`find`].
+	fn find(&self, path: &str) -> Option<Device>;
+}
+
+/// The devices actually attached to this machine.
+pub struct System;
+
+impl DeviceEnumerator for System {
+	fn list(&self) -> Vec<Device> {
+		list()
+	}
+
+	fn find(&self, path: &str) -> Option<Device> {
+		find(path)
+	}
+}
+
+#[cfg(target_os = "linux")]
+fn read_trimmed(path: &std::path::Path) -> Option<String> {
+	std::fs::read_to_string(path)
+		.ok()
+		.map(|contents| contents.trim().to_string())
+		.filter(|contents| !contents.is_empty())
+}
+
+// sysfs sizes are always in 512 byte sectors, whatever the logical block size
+#[cfg(target_os = "linux")]
+fn sectors(sys: &std::path::Path) -> Option<u64> {
+	read_trimmed(&sys.join("size"))?.parse::<u64>().ok().map(|sectors| sectors * 512)
+}
+
+// The udev database has labels, filesystem types and serials, and is readable without root
+#[cfg(target_os = "linux")]
+fn udev_properties(sys: &std::path::Path) -> HashMap<String, String> {
+	let Some(dev) = read_trimmed(&sys.join("dev")) else {
+		return HashMap::new();
+	};
+
+	std::fs::read_to_string(format!("/run/udev/data/b{dev}"))
+		.unwrap_or_default()
+		.lines()
+		.filter_map(|line| line.strip_prefix("E:"))
+		.filter_map(|line| line.split_once('='))
+		.map(|(key, value)| (key.to_string(), value.to_string()))
+		.collect()
+}
+
+// Device node to the places it's mounted, swap counts as mounted at [SWAP] like lsblk has it
+#[cfg(target_os = "linux")]
+fn mount_points() -> HashMap<String, Vec<String>> {
+	let read = |path| std::fs::read_to_string(path).unwrap_or_default();
+
+	parse_mounts(&read("/proc/self/mounts"), &read("/proc/swaps"))
+}
+
+#[cfg(target_os = "linux")]
+fn parse_mounts(mounts: &str, swaps: &str) -> HashMap<String, Vec<String>> {
+	let mut points: HashMap<String, Vec<String>> = HashMap::new();
+
+	// /dev/mapper/* and /dev/md/* are links to the dm-N and mdN nodes sysfs knows them by
+	let node = |device: &str| match device.starts_with("/dev/") {
+		true => std::fs::canonicalize(device).map(|path| path.to_string_lossy().to_string()).unwrap_or_else(|_| device.to_string()),
+		false => device.to_string(),
+	};
+
+	for line in mounts.lines() {
+		let mut fields = line.split_whitespace();
+
+		if let (Some(device), Some(mount)) = (fields.next(), fields.next()) {
+			points.entry(node(&unescape(device))).or_default().push(unescape(mount));
+		}
+	}
+
+	// The header line, then one swap file or device each
+	for line in swaps.lines().skip(1) {
+		if let Some(device) = line.split_whitespace().next() {
+			points.entry(node(&unescape(device))).or_default().push("[SWAP]".into());
+		}
+	}
+
+	points
+}
+
+// Where the LVM, LUKS or RAID devices built on top of a partition are mounted, however deep they go
+#[cfg(target_os = "linux")]
+fn held_mounts(block: &std::path::Path, sys: &std::path::Path, mounts: &HashMap<String, Vec<String>>) -> Vec<String> {
+	std::fs::read_dir(sys.join("holders"))
+		.into_iter()
+		.flatten()
+		.filter_map(Result::ok)
+		.flat_map(|holder| {
+			let name = holder.file_name().to_string_lossy().to_string();
+			let mut held = mounts.get(&format!("/dev/{name}")).cloned().unwrap_or_default();
+			held.extend(held_mounts(block, &block.join(&name), mounts));
+			held
+		})
+		.collect()
+}
+
+/// Undoes the octal escapes `/proc/self/mounts` puts in paths with spaces and friends, e.g. `\040`.
+#[cfg(target_os = "linux")]
+pub fn unescape(field: &str) -> String {
+	let mut out = Vec::with_capacity(field.len());
+	let bytes = field.as_bytes();
+	let mut i = 0;
+
+	while i < bytes.len() {
+		if bytes[i] == b'\\' && i + 3 < bytes.len() && bytes[i + 1..i + 4].iter().all(|b| (b'0'..=b'7').contains(b)) {
+			let value = u8::from_str_radix(&field[i + 1..i + 4], 8).unwrap_or(b'?');
+			out.push(value);
+			i += 4;
+		} else {
+			out.push(bytes[i]);
+			i += 1;
+		}
+	}
+
+	String::from_utf8_lossy(&out).to_string()
+}
+
+// Mount point to used bytes
+#[cfg(target_os = "linux")]
+fn used_space() -> HashMap<String, u64> {
+	Disks::new_with_refreshed_list()
+		.iter()
+		.map(|disk| (
+			disk.mount_point().to_string_lossy().to_string(),
+			disk.total_space().saturating_sub(disk.available_space()),
+		))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	#[test]
+	fn a_corrupt_exfat_boot_sector_has_no_usage() {
+		let dir = TempDir::new("devices");
+		let path = dir.file("exfat");
+		let exfat = |shifts: [u8; 2], clusters: u32| {
+			let mut header = vec![0u8; 2048];
+			header[3..11].copy_from_slice(b"EXFAT   ");
+			header[92..96].copy_from_slice(&clusters.to_le_bytes());
+			header[108..110].copy_from_slice(&shifts);
+			header[112] = 25;
+			std::fs::write(&path, header).unwrap();
+
+			probe_used(&path, "exfat")
+		};
+
+		// 512 byte sectors, 64 of them a cluster
+		assert_eq!(exfat([9, 6], 100_000), Some(100_000 * 32768 / 4));
+		assert_eq!(exfat([200, 100], 100_000), None);
+		assert_eq!(exfat([30, 30], u32::MAX), None);
+	}
+
+	#[test]
+	#[cfg(target_os = "linux")]
+	fn a_system_under_lvm_luks_or_swap_is_found() {
+		let mounts = parse_mounts(
+			"/dev/dm-1 / ext4 rw 0 0\n/dev/sdb1 /media/USB\\040Stick vfat rw 0 0\n",
+			"Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n/dev/sdc2                               partition\t8388604\t\t0\t\t-2\n",
+		);
+		assert_eq!(mounts["/dev/sdb1"], ["/media/USB Stick"]);
+		assert_eq!(mounts["/dev/sdc2"], ["[SWAP]"]);
+
+		// sda2 is a LUKS container, with an LVM volume on it holding /
+		let dir = TempDir::new("holders");
+		let block = std::path::Path::new(&dir.file("block")).to_path_buf();
+		for holders in ["sda/sda2/holders/dm-0", "dm-0/holders/dm-1", "dm-1/holders"] {
+			std::fs::create_dir_all(block.join(holders)).unwrap();
+		}
+		assert_eq!(held_mounts(&block, &block.join("sda/sda2"), &mounts), ["/"]);
+
+		let device = |partition: &str, mount_points: Vec<String>| Device {
+			path: "/dev/sdx".into(),
+			model: "Disk".into(),
+			serial: None,
+			size: 1 << 30,
+			partition_table: Some("gpt".into()),
+			partitions: vec![Partition {
+				path: partition.into(),
+				size: 1 << 30,
+				label: None,
+				filesystem: None,
+				mount_points,
+				used: None,
+			}],
+			removable: false,
+		};
+		assert!(device("/dev/sda2", held_mounts(&block, &block.join("sda/sda2"), &mounts)).holds_system());
+		assert!(device("/dev/sdc2", mounts["/dev/sdc2"].clone()).holds_system());
+		assert!(!device("/dev/sdb1", mounts["/dev/sdb1"].clone()).holds_system());
+	}
+}
@@ -0,0 +1,172 @@
+// Writing as root through pkexec, when the device can't be opened as the user.
+//
+// pkexec runs iso_maker's own command line write, which reports its progress as lines on
+// stdout and stops when it's sent a "cancel" line.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::Ordering;
+
+use tokio::sync::mpsc;
+
+use crate::tools;
+use crate::writer::{Options, Phase, Readback};
+
+/// Like [`crate::writer::copy_with_progress`], or [`crate::writer::verify_only`] with `verify_only`,
+/// in a copy of iso_maker running as root.
+pub async fn copy_with_progress(
+	source: String,
+	dest: String,
+	options: Options,
+	verify_only: bool,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	let exe = std::env::current_exe().map_err(|e| format!("Elevate error: {e}"))?;
+
+	// Root has its own config, so everything is spelled out
+	let mut args = args(&options, verify_only);
+	args.extend([source, dest]);
+
+	let mut child = Command::new("pkexec")
+		.arg(exe)
+		.args(&args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|e| tools::not_found("pkexec", e))?;
+
+	let reported = options.clone();
+	let mut stdin = child.stdin.take();
+	tokio::spawn(async move {
+		if cancel_rx.recv().await.is_some() && let Some(stdin) = &mut stdin {
+			let _ = stdin.write_all(b"cancel\n");
+		}
+	});
+
+	tokio::task::spawn_blocking(move || {
+		if let Some(stdout) = child.stdout.take() {
+			for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+				if let Some(progress) = machine_line(&line, &reported) {
+					let _ = progress_tx.blocking_send(progress);
+				}
+			}
+		}
+
+		let mut stderr = String::new();
+		if let Some(mut pipe) = child.stderr.take() {
+			let _ = pipe.read_to_string(&mut stderr);
+		}
+
+		let status = child.wait().map_err(|e| format!("Elevate error: {e}"))?;
+		match status.code() {
+			Some(0) => Ok(()),
+			// pkexec's own codes for a dismissed or refused password prompt
+			Some(126 | 127) => Err("Root access wasn't granted, the device wasn't written".into()),
+			// The write's error is the last thing it prints
+			_ => Err(stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("The write as root failed").trim().to_string()),
+		}
+	})
+	.await
+	.map_err(|e| format!("Elevate error: {e}"))?
+}
+
+/// The command line write (or verification) that does what `options` say, leaving out the
+/// image and device.
+pub fn args(options: &Options, verify_only: bool) -> Vec<String> {
+	let command = if verify_only { "verify" } else { "write" };
+	let mut args = vec![command.to_string(), "--machine-readable".into()];
+	match options.verify {
+		Some(algorithm) => args.extend(["--verify".into(), algorithm.to_string()]),
+		None => args.push("--no-verify".into()),
+	}
+	if let Some((algorithm, digest)) = &options.expected {
+		args.extend(["--checksum".into(), format!("{algorithm}:{digest}")]);
+	}
+	if options.mbr.is_some() {
+		args.push("--hybridize".into());
+	}
+	if !options.mmap {
+		args.push("--no-mmap".into());
+	}
+	args.extend(["--sync-every".into(), (options.checkpoint / (1024 * 1024)).to_string()]);
+	if options.resume > 0 {
+		args.extend(["--resume-from".into(), options.resume.to_string()]);
+	}
+	if !verify_only {
+		args.push(if options.skip_unchanged { "--skip-unchanged" } else { "--no-skip-unchanged" }.into());
+		args.push(if options.delta { "--delta" } else { "--no-delta" }.into());
+	}
+	// The same bmap is found next to the image, unless it was left out for being broken
+	if options.bmap.is_none() {
+		args.push("--no-bmap".into());
+	}
+	args
+}
+
+/// Takes in a line of the command line's `--machine-readable` output: what it says about the
+/// write goes into `options`, and progress is returned.
+pub fn machine_line(line: &str, options: &Options) -> Option<(Phase, u64, u64)> {
+	if let Some(Ok(done)) = line.strip_prefix("Synced ").map(str::parse) {
+		options.synced.store(done, Ordering::Relaxed);
+		return None;
+	}
+
+	if let Some(Ok(bytes)) = line.strip_prefix("Rewritten ").map(str::parse) {
+		options.rewritten.store(bytes, Ordering::Relaxed);
+		return None;
+	}
+
+	if let Some(hash) = line.strip_prefix("Image ") {
+		if let Ok(mut image_hash) = options.image_hash.lock() {
+			*image_hash = Some(hash.to_string());
+		}
+		return None;
+	}
+
+	if let Some(fields) = line.strip_prefix("Readback ") {
+		if let [algorithm, written, device] = fields.split_whitespace().collect::<Vec<_>>()[..]
+			&& let (Ok(algorithm), Ok(mut readback)) = (algorithm.parse(), options.readback.lock())
+		{
+			*readback = Some(Readback { algorithm, written: written.into(), device: device.into() });
+		}
+		return None;
+	}
+
+	if line == "Unchanged" {
+		options.unchanged.store(true, Ordering::Relaxed);
+		return None;
+	}
+
+	let mut fields = line.split_whitespace();
+	let (Some(phase), Some(Ok(done)), Some(Ok(total))) = (
+		fields.next().and_then(phase),
+		fields.next().map(str::parse),
+		fields.next().map(str::parse),
+	) else {
+		return None;
+	};
+
+	if let Some(Ok(bytes)) = fields.next().map(str::parse) {
+		options.unpacked.store(bytes, Ordering::Relaxed);
+	}
+
+	Some((phase, done, total))
+}
+
+// Phases as the command line prints them
+fn phase(name: &str) -> Option<Phase> {
+	Some(match name {
+		"Writing" => Phase::Writing,
+		"Reading" => Phase::Reading,
+		"Checking" => Phase::Checking,
+		"Syncing" => Phase::Syncing,
+		"Verifying" => Phase::Verifying,
+		"Partitioning" => Phase::Partitioning,
+		"Applying" => Phase::Applying,
+		"Finishing" => Phase::Finishing,
+		_ => return None,
+	})
+}
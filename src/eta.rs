@@ -0,0 +1,85 @@
+// Smoothed speed and time left for a running job.
+//
+// Progress comes in bursts, a chunk at a time and quicker while the kernel's cache fills
+// up, so the speed between two updates jumps around. The speed is an exponentially
+// weighted moving average over the last few seconds instead, and what's shown only
+// changes once a second.
+
+use std::time::{Duration, Instant};
+
+use crate::units;
+
+// Seconds the average mostly looks back over
+const TAU: f64 = 5.0;
+
+// Updates closer together than this are taken as one, a burst of chunks says little
+const MIN_SAMPLE: Duration = Duration::from_millis(250);
+
+const REFRESH: Duration = Duration::from_secs(1);
+
+// Too early to guess before this, the start is all cache and opening the source
+const WARM_UP: Duration = Duration::from_secs(2);
+
+/// Speed and time left for one phase of a job, fed with its progress.
+#[derive(Debug, Clone, Default)]
+pub struct Estimate {
+	started: Option<Instant>,
+	/// When the last sample was taken and how far along it was
+	sample: Option<(Instant, u64)>,
+	/// Bytes a second, smoothed
+	speed: Option<f64>,
+	shown: Option<Shown>,
+}
+
+// What's on screen, kept between refreshes
+#[derive(Debug, Clone, Copy)]
+struct Shown {
+	at: Instant,
+	speed: f64,
+	remaining: Option<Duration>,
+}
+
+impl Estimate {
+	/// Takes in that `done` of `total` bytes are done, a `total` of 0 isn't known.
+	pub fn update(&mut self, done: u64, total: u64) {
+		let now = Instant::now();
+		let started = *self.started.get_or_insert(now);
+
+		let Some((at, from)) = self.sample else {
+			self.sample = Some((now, done));
+			return;
+		};
+
+		let elapsed = now - at;
+		if elapsed < MIN_SAMPLE {
+			return;
+		}
+
+		// Samples come at uneven intervals, a longer one weighs more
+		let rate = done.saturating_sub(from) as f64 / elapsed.as_secs_f64();
+		let weight = 1.0 - (-elapsed.as_secs_f64() / TAU).exp();
+		let speed = match self.speed {
+			Some(speed) => speed + weight * (rate - speed),
+			None => rate,
+		};
+		self.speed = Some(speed);
+		self.sample = Some((now, done));
+
+		let due = self.shown.is_none_or(|shown| now - shown.at >= REFRESH);
+		if due && now - started >= WARM_UP {
+			let remaining = (speed > 0.0 && total > done).then(|| Duration::from_secs_f64((total - done) as f64 / speed));
+			self.shown = Some(Shown { at: now, speed, remaining });
+		}
+	}
+
+	/// E.g. "38.2 MB/s, about 2 min left", `None` until there's a fair guess.
+	pub fn text(&self) -> Option<String> {
+		let shown = self.shown?;
+		let speed = units::speed(shown.speed);
+
+		Some(match shown.remaining {
+			Some(remaining) => format!("{speed}, {}", units::time_left(remaining)),
+			None => speed,
+		})
+	}
+}
@@ -0,0 +1,155 @@
+// File-copy mode: formats the stick as FAT32 and copies the ISO's files onto it.
+//
+// Windows installer ISOs aren't hybrid, so written raw they don't boot from USB. Copied
+// onto a FAT32 partition they do, through UEFI's removable media path or the BIOS booting
+// the active partition, and the stick stays writable for answer files and the like.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use tokio::sync::mpsc;
+
+use crate::customize::Customizations;
+use crate::mount::Mount;
+use crate::partition::{self, Filesystem, Kind, Spec, Table};
+use crate::tools;
+use crate::writer::Phase;
+
+// Largest file FAT32 can hold
+const FAT32_MAX: u64 = 4 * 1024 * 1024 * 1024 - 1;
+
+// Size of the pieces a too big install.wim gets split into, in MB
+const SPLIT_SIZE: &str = "3800";
+
+/// Copies the files in the ISO at `iso` onto a fresh FAT32 partition on `device`, then applies `customizations`.
+///
+/// The partition is labelled `label`, or like the ISO without one.
+pub async fn copy_files(
+	iso: String,
+	device: String,
+	label: Option<String>,
+	customizations: Customizations,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	tokio::task::spawn_blocking(move || {
+		let _ = progress_tx.blocking_send((Phase::Partitioning, 0, 1));
+
+		// Linux installers find their files again by label, so keep the ISO's by default
+		let label = label.or_else(|| volume_label(&iso)).unwrap_or_else(|| "ISO_MAKER".into());
+		let partitions = partition::create(&device, Table::Mbr, &[
+			Spec { size: None, kind: Kind::Data, filesystem: Some(Filesystem::Fat32), label: label.clone() },
+		])?;
+
+		let image = Mount::image(&iso)?;
+		let stick = Mount::new(&partitions[0])?;
+
+		let total = tree_size(image.path())?;
+		let mut copied = 0;
+
+		copy_tree(image.path(), stick.path(), &mut copied, &mut |copied| {
+			let _ = progress_tx.blocking_send((Phase::Writing, copied, total));
+			cancel_rx.try_recv().is_err()
+		})?;
+
+		let _ = progress_tx.blocking_send((Phase::Finishing, 0, 1));
+
+		customizations.apply(stick.path(), &partition::fat_label(&label))?;
+
+		// Unmounting flushes everything to the stick
+		drop(stick);
+		Ok(())
+	})
+	.await
+	.map_err(|e| format!("Copy error: {e}"))?
+}
+
+/// Volume label from the ISO 9660 primary volume descriptor.
+pub fn volume_label(iso: &str) -> Option<String> {
+	let mut descriptor = [0; 2048];
+	let mut file = File::open(iso).ok()?;
+	file.seek(SeekFrom::Start(16 * 2048)).ok()?;
+	file.read_exact(&mut descriptor).ok()?;
+
+	if descriptor[0] != 1 || &descriptor[1..6] != b"CD001" {
+		return None;
+	}
+
+	let label = String::from_utf8_lossy(&descriptor[40..72]).trim().to_string();
+	(!label.is_empty()).then_some(label)
+}
+
+fn tree_size(dir: &Path) -> Result<u64, String> {
+	let mut size = 0;
+
+	for entry in std::fs::read_dir(dir).map_err(|e| format!("Copy error: {}: {e}", dir.display()))? {
+		let entry = entry.map_err(|e| format!("Copy error: {e}"))?;
+		let metadata = entry.metadata().map_err(|e| format!("Copy error: {e}"))?;
+
+		size += if metadata.is_dir() { tree_size(&entry.path())? } else { metadata.len() };
+	}
+
+	Ok(size)
+}
+
+// `progress` gets the bytes copied so far and returns false to cancel
+fn copy_tree(from: &Path, to: &Path, copied: &mut u64, progress: &mut impl FnMut(u64) -> bool) -> Result<(), String> {
+	std::fs::create_dir_all(to).map_err(|e| format!("Copy error: {e}"))?;
+
+	for entry in std::fs::read_dir(from).map_err(|e| format!("Copy error: {}: {e}", from.display()))? {
+		let entry = entry.map_err(|e| format!("Copy error: {e}"))?;
+		let metadata = entry.metadata().map_err(|e| format!("Copy error: {e}"))?;
+		let target = to.join(entry.file_name());
+
+		if metadata.is_dir() {
+			copy_tree(&entry.path(), &target, copied, progress)?;
+		} else if metadata.len() > FAT32_MAX {
+			split_wim(&entry.path(), &target)?;
+			*copied += metadata.len();
+
+			if !progress(*copied) {
+				return Err("Cancelled".into());
+			}
+		} else {
+			copy_file(&entry.path(), &target, copied, progress)?;
+		}
+	}
+
+	Ok(())
+}
+
+fn copy_file(from: &Path, to: &Path, copied: &mut u64, progress: &mut impl FnMut(u64) -> bool) -> Result<(), String> {
+	let mut source = File::open(from).map_err(|e| format!("Copy error: {}: {e}", from.display()))?;
+	let mut target = File::create(to).map_err(|e| format!("Copy error: {}: {e}", to.display()))?;
+	let mut buffer = vec![0; 4096 * 1024]; // 4MB buffer
+
+	loop {
+		let n = crate::source::read_full(&mut source, &mut buffer).map_err(|e| format!("Read error: {e}"))?;
+		if n == 0 {
+			break;
+		}
+
+		target.write_all(&buffer[..n]).map_err(|e| format!("Write error: {e}"))?;
+		*copied += n as u64;
+
+		if !progress(*copied) {
+			return Err("Cancelled".into());
+		}
+	}
+
+	Ok(())
+}
+
+// Windows Setup happily installs from install.swm pieces, anything else that big can't go on FAT32
+fn split_wim(from: &Path, to: &Path) -> Result<(), String> {
+	let is_wim = from.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("wim"));
+	if !is_wim {
+		return Err(format!("{} is over 4 GB, which FAT32 can't hold", from.display()));
+	}
+
+	let swm = to.with_extension("swm");
+	tools::run("wimlib-imagex", &["split", &from.to_string_lossy(), &swm.to_string_lossy(), SPLIT_SIZE])
+		.map(|_| ())
+}
@@ -0,0 +1,90 @@
+// A script of the user's run once on the first boot of a written Linux image, for provisioning.
+//
+// It goes on the image's root filesystem with a systemd unit that runs it after the network
+// is up. The unit marks itself done before running the script, so a failing script doesn't
+// run again on every boot; its output is in the journal.
+
+use std::fs;
+use std::path::Path;
+
+const SCRIPT: &str = "usr/local/lib/iso_maker/firstboot.sh";
+const DONE: &str = "var/lib/iso_maker/firstboot.done";
+const UNIT: &str = "iso_maker-firstboot.service";
+
+/// Filesystems a systemd root is likely on.
+pub const ROOT_FILESYSTEMS: [&str; 5] = ["ext4", "ext3", "btrfs", "xfs", "f2fs"];
+
+/// Whether the filesystem mounted at `root` is a systemd system's root.
+pub fn is_root(root: &Path) -> bool {
+	root.join("etc/systemd/system").is_dir()
+}
+
+/// Installs `script` on the root filesystem mounted at `root`.
+pub fn install(root: &Path, script: &Path) -> Result<(), String> {
+	// systemd runs it directly, so it has to say what runs it
+	let contents = fs::read(script).map_err(|e| format!("First boot error: {}: {e}", script.display()))?;
+	if !contents.starts_with(b"#!") {
+		return Err("The first boot script needs a #! line, e.g. #!/bin/sh".into());
+	}
+
+	let unit = format!(
+		"\
+[Unit]
+Description=First boot script added by iso_maker
+Wants=network-online.target
+After=network-online.target
+ConditionPathExists=!/{DONE}
+
+[Service]
+Type=oneshot
+ExecStartPre=/bin/mkdir -p /var/lib/iso_maker
+ExecStartPre=/bin/touch /{DONE}
+ExecStart=/{SCRIPT}
+StandardOutput=journal+console
+
+[Install]
+WantedBy=multi-user.target
+",
+	);
+
+	let target = root.join(SCRIPT);
+	if let Some(parent) = target.parent() {
+		fs::create_dir_all(parent).map_err(|e| format!("First boot error: {e}"))?;
+	}
+
+	fs::write(&target, contents).map_err(|e| format!("First boot error: {e}"))?;
+	make_executable(&target)?;
+
+	let units = root.join("etc/systemd/system");
+	fs::write(units.join(UNIT), unit).map_err(|e| format!("First boot error: {e}"))?;
+
+	// What `systemctl enable` would do
+	let wants = units.join("multi-user.target.wants");
+	fs::create_dir_all(&wants).map_err(|e| format!("First boot error: {e}"))?;
+	enable(&wants.join(UNIT), &format!("/etc/systemd/system/{UNIT}"))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<(), String> {
+	use std::os::unix::fs::PermissionsExt;
+
+	fs::set_permissions(path, fs::Permissions::from_mode(0o755)).map_err(|e| format!("First boot error: {e}"))
+}
+
+#[cfg(unix)]
+fn enable(link: &Path, unit: &str) -> Result<(), String> {
+	// Replaces the link from a previous run
+	let _ = fs::remove_file(link);
+	std::os::unix::fs::symlink(unit, link).map_err(|e| format!("First boot error: {e}"))
+}
+
+// Linux filesystems only get mounted on Linux anyway
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<(), String> {
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn enable(_link: &Path, _unit: &str) -> Result<(), String> {
+	Err("First boot scripts can only be installed from Linux".into())
+}
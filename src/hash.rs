@@ -0,0 +1,174 @@
+// Checksums of images and devices.
+
+use std::fmt;
+use std::io::Read;
+
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Algorithm {
+	Md5,
+	Sha1,
+	#[default]
+	Sha256,
+	Sha512,
+	/// Much faster than the SHA family, used for verifying writes
+	Blake3,
+}
+
+impl Algorithm {
+	pub const ALL: [Algorithm; 5] = [
+		Algorithm::Md5,
+		Algorithm::Sha1,
+		Algorithm::Sha256,
+		Algorithm::Sha512,
+		Algorithm::Blake3,
+	];
+
+	pub fn hasher(self) -> Hasher {
+		match self {
+			Algorithm::Md5 => Hasher::Md5(Md5::new()),
+			Algorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+			Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+			Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+			Algorithm::Blake3 => Hasher::Blake3(Box::default()),
+		}
+	}
+
+	/// Name as the command line takes it, e.g. `sha256`.
+	pub fn id(self) -> &'static str {
+		match self {
+			Algorithm::Md5 => "md5",
+			Algorithm::Sha1 => "sha1",
+			Algorithm::Sha256 => "sha256",
+			Algorithm::Sha512 => "sha512",
+			Algorithm::Blake3 => "blake3",
+		}
+	}
+}
+
+impl fmt::Display for Algorithm {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Algorithm::Md5 => "MD5",
+			Algorithm::Sha1 => "SHA-1",
+			Algorithm::Sha256 => "SHA-256",
+			Algorithm::Sha512 => "SHA-512",
+			Algorithm::Blake3 => "BLAKE3",
+		})
+	}
+}
+
+impl std::str::FromStr for Algorithm {
+	type Err = String;
+
+	/// Parses a name as written on the command line, e.g. `sha256` or `SHA-256`.
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		Algorithm::ALL.into_iter()
+			.find(|algorithm| algorithm.to_string().replace('-', "").eq_ignore_ascii_case(&name.replace('-', "")))
+			.ok_or_else(|| format!("Unknown checksum algorithm {name}, use md5, sha1, sha256, sha512 or blake3"))
+	}
+}
+
+/// Running digest for one of the supported [`Algorithm`]s.
+pub enum Hasher {
+	Md5(Md5),
+	Sha1(Sha1),
+	Sha256(Sha256),
+	Sha512(Sha512),
+	Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+	pub fn update(&mut self, data: &[u8]) {
+		match self {
+			Hasher::Md5(hasher) => hasher.update(data),
+			Hasher::Sha1(hasher) => hasher.update(data),
+			Hasher::Sha256(hasher) => hasher.update(data),
+			Hasher::Sha512(hasher) => hasher.update(data),
+			Hasher::Blake3(hasher) => {
+				hasher.update(data);
+			},
+		}
+	}
+
+	/// Lowercase hex digest.
+	pub fn finalize(self) -> String {
+		match self {
+			Hasher::Md5(hasher) => hex::encode(hasher.finalize()),
+			Hasher::Sha1(hasher) => hex::encode(hasher.finalize()),
+			Hasher::Sha256(hasher) => hex::encode(hasher.finalize()),
+			Hasher::Sha512(hasher) => hex::encode(hasher.finalize()),
+			Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+		}
+	}
+}
+
+/// Hashes everything `reader` produces, calling `progress` with the bytes read so far.
+pub fn hash_reader(
+	mut reader: impl Read,
+	algorithm: Algorithm,
+	mut progress: impl FnMut(u64) -> bool,
+) -> Result<String, String>
+{
+	let mut hasher = algorithm.hasher();
+	let mut buffer = vec![0; 4096 * 1024]; // 4MB buffer
+	let mut total = 0;
+
+	loop {
+		let n = match reader.read(&mut buffer) {
+			Ok(0) => break,
+			Ok(n) => n,
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(format!("Read error: {e}")),
+		};
+
+		hasher.update(&buffer[..n]);
+		total += n as u64;
+
+		// The callback returning false means the user gave up
+		if !progress(total) {
+			return Err("Cancelled".into());
+		}
+	}
+
+	Ok(hasher.finalize())
+}
+
+/// Quick identity for an image, without reading all of it: its size and first and last MiB, or a URL itself.
+pub fn fingerprint(path: &str) -> Option<String> {
+	use std::io::{Seek, SeekFrom};
+
+	const SAMPLE: u64 = 1024 * 1024;
+
+	let mut hasher = Algorithm::Sha256.hasher();
+
+	if crate::source::is_url(path) {
+		hasher.update(path.as_bytes());
+		return Some(hasher.finalize());
+	}
+
+	let mut file = std::fs::File::open(path).ok()?;
+	let len = file.metadata().ok()?.len();
+	let mut sample = Vec::new();
+
+	hasher.update(&len.to_le_bytes());
+	(&mut file).take(SAMPLE).read_to_end(&mut sample).ok()?;
+
+	file.seek(SeekFrom::Start(len.saturating_sub(SAMPLE))).ok()?;
+	file.take(SAMPLE).read_to_end(&mut sample).ok()?;
+
+	hasher.update(&sample);
+	Some(hasher.finalize())
+}
+
+/// Compares a digest against one pasted by the user, ignoring case and whitespace.
+pub fn matches(digest: &str, expected: &str) -> bool {
+	// Published sums are often pasted as "<digest>  <filename>"
+	let expected = expected.split_whitespace().next().unwrap_or("");
+
+	!expected.is_empty() && digest.eq_ignore_ascii_case(expected)
+}
@@ -0,0 +1,195 @@
+// Commands run around jobs that erase a device, to fit them into what a site already does
+// with its sticks, like printing a label for one or registering it in inventory.
+//
+// A hook is a shell command from the config, told about the job through the environment:
+// `ISO_MAKER_DEVICE`, `ISO_MAKER_SERIAL`, `ISO_MAKER_IMAGE`, `ISO_MAKER_IMAGE_HASH`,
+// `ISO_MAKER_LABEL` and `ISO_MAKER_OPERATION`, empty for what isn't known.
+//
+// The one before a job is a gate, e.g. asking an asset-management system whether the stick's
+// serial is approved: the job waits for it and doesn't go ahead unless it exits with 0, with
+// what it printed on stderr as the reason. The one after a job only runs when it succeeded,
+// in the background like notifications.
+
+use std::io::Read;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::notify::Job;
+
+static RUNNING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+// Long enough to ask a server, the job can't be left waiting forever
+const BEFORE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The commands to run, from the config.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+	pub before: Option<String>,
+	pub after: Option<String>,
+}
+
+impl Hooks {
+	pub fn new(config: &Config) -> Self {
+		let set = |value: &Option<String>| value.clone().filter(|command| !command.trim().is_empty());
+		Self { before: set(&config.pre_write_hook), after: set(&config.post_write_hook) }
+	}
+}
+
+/// The environment a hook gets for `job`.
+pub fn env(job: &Job) -> Vec<(&'static str, String)> {
+	let known = |value: &Option<String>| value.clone().unwrap_or_default();
+
+	vec![
+		("ISO_MAKER_DEVICE", job.device.clone()),
+		("ISO_MAKER_SERIAL", known(&job.serial)),
+		("ISO_MAKER_IMAGE", job.image.clone()),
+		("ISO_MAKER_IMAGE_HASH", known(&job.image_hash)),
+		("ISO_MAKER_LABEL", known(&job.label)),
+		("ISO_MAKER_OPERATION", job.operation.clone()),
+	]
+}
+
+/// Runs the hook for before a job and waits for it, `Err` with why when it refuses the job.
+pub fn before(hooks: &Hooks, job: &Job) -> Result<(), String> {
+	let Some(command) = &hooks.before else {
+		return Ok(());
+	};
+
+	let child = shell(command)
+		.envs(env(job))
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|e| format!("Pre-write hook error: {command}: {e}"))?;
+
+	let output = wait(child, BEFORE_TIMEOUT).map_err(|e| format!("Pre-write hook error: {command}: {e}"))?;
+	check(command, &output).map_err(|e| format!("The pre-write hook refused the job: {e}"))
+}
+
+/// Whether `e`, from [`before`], means the hook didn't let the job go ahead.
+pub fn refused(e: &str) -> bool {
+	e.starts_with("The pre-write hook refused the job") || e.starts_with("Pre-write hook error")
+}
+
+// Waits for `child` for up to `timeout`, then kills it
+fn wait(mut child: Child, timeout: Duration) -> Result<Output, String> {
+	let start = Instant::now();
+
+	let status = loop {
+		match child.try_wait().map_err(|e| e.to_string())? {
+			Some(status) => break status,
+			None if start.elapsed() >= timeout => {
+				let _ = child.kill();
+				let _ = child.wait();
+				return Err(format!("it took longer than {} s", timeout.as_secs()));
+			},
+			None => std::thread::sleep(Duration::from_millis(50)),
+		}
+	};
+
+	// Only a few lines of reason are expected, they fit in the pipe while it runs
+	let mut stderr = Vec::new();
+	if let Some(mut pipe) = child.stderr.take() {
+		let _ = pipe.read_to_end(&mut stderr);
+	}
+
+	Ok(Output { status, stdout: Vec::new(), stderr })
+}
+
+/// Runs the hook for after a job in the background, failures go to stderr.
+pub fn after(hooks: &Hooks, job: Job) {
+	let Some(command) = hooks.after.clone() else {
+		return;
+	};
+
+	let running = std::thread::spawn(move || {
+		if let Err(e) = run(&command, &job) {
+			eprintln!("Post-write hook error: {e}");
+		}
+	});
+
+	if let Ok(mut pending) = RUNNING.lock() {
+		pending.retain(|handle| !handle.is_finished());
+		pending.push(running);
+	}
+}
+
+/// Waits for hooks still running, before the process exits.
+pub fn flush() {
+	let pending = RUNNING.lock().map(|mut pending| std::mem::take(&mut *pending)).unwrap_or_default();
+
+	for handle in pending {
+		let _ = handle.join();
+	}
+}
+
+// The command through the shell, its error output being what went wrong
+fn run(command: &str, job: &Job) -> Result<(), String> {
+	let output = shell(command)
+		.envs(env(job))
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::piped())
+		.output()
+		.map_err(|e| format!("{command}: {e}"))?;
+
+	check(command, &output)
+}
+
+fn check(command: &str, output: &Output) -> Result<(), String> {
+	if output.status.success() {
+		return Ok(());
+	}
+
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	let status = output.status.code().map_or_else(|| "was killed".to_string(), |code| format!("exited with {code}"));
+
+	match stderr.trim() {
+		"" => Err(format!("{command} {status}")),
+		stderr => Err(format!("{command} {status}: {stderr}")),
+	}
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+	let mut shell = Command::new("sh");
+	shell.arg("-c").arg(command);
+	shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+	let mut shell = Command::new("cmd");
+	shell.arg("/C").arg(command);
+	shell
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn a_hook_is_told_about_the_job() {
+		let job = Job {
+			device: "/dev/sdb".into(),
+			serial: Some("A1".into()),
+			image: "/tmp/golden.img".into(),
+			image_hash: Some("sha256:abcd".into()),
+			operation: "write".into(),
+			..Job::default()
+		};
+
+		assert!(run("test \"$ISO_MAKER_DEVICE $ISO_MAKER_SERIAL $ISO_MAKER_IMAGE_HASH\" = '/dev/sdb A1 sha256:abcd' && test -z \"$ISO_MAKER_LABEL\"", &job).is_ok());
+		assert_eq!(run("echo nope >&2; exit 3", &job), Err("echo nope >&2; exit 3 exited with 3: nope".into()));
+
+		// Before a job, that's a refusal
+		let hooks = Hooks { before: Some("test \"$ISO_MAKER_SERIAL\" = A2 || { echo \"$ISO_MAKER_SERIAL isn't approved\" >&2; exit 1; }".into()), after: None };
+		let refused = before(&hooks, &job).unwrap_err();
+		assert!(refused.starts_with("The pre-write hook refused the job") && refused.ends_with("A1 isn't approved"), "{refused}");
+		assert!(super::refused(&refused));
+	}
+}
@@ -0,0 +1,203 @@
+// Hybridizing ISOs: giving one without a partition table an MBR, so it boots from USB.
+//
+// Hybrid ISOs carry a partition table in the otherwise unused first sectors, which is what
+// firmware looks at on a USB stick. Plain ones only have El Torito, which only CD drives
+// use. Like `isohybrid --uefi`, the MBR added here has one partition over the whole image
+// and an EFI system partition over its El Torito UEFI boot image, which UEFI firmware boots
+// from. There's no BIOS boot code to add without syslinux's, so BIOS machines still won't.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::isofs;
+
+const SECTOR: u64 = 2048;
+
+/// How an ISO's start looks to firmware on a USB stick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+	/// Has a partition table already
+	Hybrid,
+	/// Only El Torito, with the start and size in 512 byte sectors of its UEFI boot image if it has one
+	Plain(Option<(u64, u64)>),
+}
+
+/// Looks at the ISO at `path`, `None` when it isn't a local, uncompressed ISO.
+pub fn layout(path: &str) -> Option<Layout> {
+	let mut file = File::open(path).ok()?;
+	let mut descriptor = [0; SECTOR as usize];
+
+	file.seek(SeekFrom::Start(16 * SECTOR)).ok()?;
+	file.read_exact(&mut descriptor).ok()?;
+	if &descriptor[1..6] != b"CD001" {
+		return None;
+	}
+
+	let mut mbr = [0; 1024];
+	file.seek(SeekFrom::Start(0)).ok()?;
+	file.read_exact(&mut mbr).ok()?;
+
+	// GPT has a protective MBR too, but check its header in case
+	let partitioned = mbr[510..512] == [0x55, 0xAA] && (0..4).any(|i| mbr[446 + i * 16 + 4] != 0);
+	if partitioned || &mbr[512..520] == b"EFI PART" {
+		return Some(Layout::Hybrid);
+	}
+
+	Some(Layout::Plain(efi_image(&mut file, path)))
+}
+
+// The UEFI entry in the El Torito boot catalog
+fn efi_image(file: &mut File, path: &str) -> Option<(u64, u64)> {
+	let mut descriptor = [0; SECTOR as usize];
+
+	// The boot record comes among the volume descriptors, usually right after the primary one
+	let catalog = (16..64).find_map(|sector| {
+		file.seek(SeekFrom::Start(sector * SECTOR)).ok()?;
+		file.read_exact(&mut descriptor).ok()?;
+
+		(descriptor[0] == 0 && descriptor[7..30] == *b"EL TORITO SPECIFICATION")
+			.then(|| u32::from_le_bytes(descriptor[71..75].try_into().unwrap()) as u64)
+	})?;
+
+	let mut entries = [0; SECTOR as usize];
+	file.seek(SeekFrom::Start(catalog * SECTOR)).ok()?;
+	file.read_exact(&mut entries).ok()?;
+
+	// 32 byte entries: the validation entry with the first platform, the default entry, then
+	// section headers for further platforms, each followed by its entries
+	let mut platform = entries[1];
+	let mut found = None;
+
+	for entry in entries.chunks_exact(32).skip(1) {
+		match entry[0] {
+			0x90 | 0x91 => platform = entry[1],
+			0x88 if platform == 0xEF => {
+				let sectors = u16::from_le_bytes([entry[6], entry[7]]) as u64;
+				let start = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+				found = Some((start, sectors));
+				break;
+			},
+			_ if entry.iter().all(|&byte| byte == 0) => break,
+			_ => {},
+		}
+	}
+
+	let (start, mut sectors) = found?;
+
+	// The count only has 16 bits, so bigger boot images record 0 or 1 and the file says how big they really are
+	if sectors <= 1 && let Ok(root) = isofs::read_tree(path) {
+		sectors = find_extent(&root, start).map_or(sectors, |size| size.div_ceil(512));
+	}
+
+	(sectors > 0).then_some((start * 4, sectors))
+}
+
+fn find_extent(entry: &isofs::Entry, start: u64) -> Option<u64> {
+	match &entry.children {
+		Some(children) => children.iter().find_map(|child| find_extent(child, start)),
+		None => (entry.extents.first().map(|&(extent, _)| extent) == Some(start)).then_some(entry.size),
+	}
+}
+
+/// An MBR for an ISO of `len` bytes, with an EFI system partition at `efi` (start and size in 512 byte sectors).
+pub fn mbr(len: u64, efi: (u64, u64)) -> [u8; 512] {
+	let mut mbr = [0; 512];
+
+	// Something other than zero, Windows wants a disk signature
+	mbr[440..444].copy_from_slice(&0x1505_0000u32.wrapping_add(len as u32).to_le_bytes());
+
+	// The first partition spans the whole image from sector 0 like on isohybrid images, marked active
+	partition_entry(&mut mbr[446..462], 0x80, 0x00, 0, len.div_ceil(512));
+	partition_entry(&mut mbr[462..478], 0x00, 0xEF, efi.0, efi.1);
+
+	mbr[510] = 0x55;
+	mbr[511] = 0xAA;
+	mbr
+}
+
+fn partition_entry(entry: &mut [u8], status: u8, kind: u8, start: u64, sectors: u64) {
+	entry[0] = status;
+	// CHS addressing is long dead, firmware goes by the LBA fields
+	entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+	entry[4] = kind;
+	entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+	entry[8..12].copy_from_slice(&(start.min(u32::MAX as u64) as u32).to_le_bytes());
+	entry[12..16].copy_from_slice(&(sectors.min(u32::MAX as u64) as u32).to_le_bytes());
+}
+
+/// Swaps the first 512 bytes of what `inner` yields for `mbr`.
+pub struct WithMbr<R> {
+	inner: R,
+	mbr: [u8; 512],
+	position: u64,
+}
+
+impl<R> WithMbr<R> {
+	pub fn new(inner: R, mbr: [u8; 512]) -> Self {
+		Self { inner, mbr, position: 0 }
+	}
+}
+
+impl<R: Read> Read for WithMbr<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.inner.read(buf)?;
+
+		if let Ok(start) = usize::try_from(self.position) && start < self.mbr.len() {
+			let end = (start + n).min(self.mbr.len());
+			buf[..end - start].copy_from_slice(&self.mbr[start..end]);
+		}
+
+		self.position += n as u64;
+		Ok(n)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	#[test]
+	fn plain_isos_get_a_table_over_their_uefi_boot_image() {
+		let dir = TempDir::new("hybrid");
+		let path = dir.file("plain.iso");
+		let mut image = crate::testing::iso(&[("efiboot.img", &[0xEF; 3000])]);
+
+		// An El Torito boot record in place of the terminator, pointing at a catalog at the end
+		let catalog = image.len() / SECTOR as usize;
+		let record = 17 * SECTOR as usize;
+		image[record..record + 7].copy_from_slice(b"\0CD001\x01");
+		image[record + 7..record + 30].copy_from_slice(b"EL TORITO SPECIFICATION");
+		image[record + 71..record + 75].copy_from_slice(&(catalog as u32).to_le_bytes());
+
+		// Validation entry, the BIOS default, then a UEFI section whose one entry records a single sector
+		let mut entries = vec![0; SECTOR as usize];
+		entries[0] = 1;
+		entries[32] = 0x88;
+		entries[64..66].copy_from_slice(&[0x91, 0xEF]);
+		entries[96] = 0x88;
+		entries[102..104].copy_from_slice(&1u16.to_le_bytes());
+		entries[104..108].copy_from_slice(&19u32.to_le_bytes());
+		image.extend(entries);
+		std::fs::write(&path, &image).unwrap();
+
+		// The file has the real size of the boot image
+		let efi = (19 * 4, 3000u64.div_ceil(512));
+		assert_eq!(layout(&path), Some(Layout::Plain(Some(efi))));
+
+		let mbr = mbr(image.len() as u64, efi);
+		assert_eq!(&mbr[462 + 4..462 + 16], [&[0xEF, 0xFE, 0xFF, 0xFF][..], &76u32.to_le_bytes(), &6u32.to_le_bytes()].concat());
+
+		let mut hybrid = Vec::new();
+		WithMbr::new(image.as_slice(), mbr).read_to_end(&mut hybrid).unwrap();
+		assert_eq!((&hybrid[..512], &hybrid[512..]), (&mbr[..], &image[512..]));
+		std::fs::write(&path, &hybrid).unwrap();
+		assert_eq!(layout(&path), Some(Layout::Hybrid));
+
+		// Cut off before its volume descriptors, or not an ISO at all
+		std::fs::write(&path, &image[..16 * SECTOR as usize + 4]).unwrap();
+		assert_eq!(layout(&path), None);
+		std::fs::write(&path, crate::testing::image(64 * 1024)).unwrap();
+		assert_eq!(layout(&path), None);
+	}
+}
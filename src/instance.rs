@@ -0,0 +1,160 @@
+// One window at a time: starting iso_maker again hands what it was started with to the
+// window that's already open and exits, rather than opening a second one that fights the
+// first over the same devices.
+//
+// The first instance listens on a socket only the user can reach, a Unix socket in the
+// runtime directory, or on Windows a localhost port kept in a file in the local data
+// directory. Later ones connect and send a line asking for the window to be brought to the
+// front, or for an image to be opened in it.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use iced::futures::{stream, Stream};
+use tokio::sync::mpsc;
+
+/// What a later instance asks the running one for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+	/// Bring the window to the front
+	Focus,
+	/// Open the image in a job, see [`crate::app::Message::OpenImage`]
+	Open(String),
+}
+
+impl Request {
+	fn line(&self) -> String {
+		match self {
+			Request::Focus => "focus\n".into(),
+			Request::Open(image) => format!("open {image}\n"),
+		}
+	}
+
+	fn parse(line: &str) -> Option<Self> {
+		match line.split_once(' ') {
+			None if line == "focus" => Some(Request::Focus),
+			Some(("open", image)) if !image.is_empty() => Some(Request::Open(image.to_string())),
+			_ => None,
+		}
+	}
+}
+
+// Bound before the window opens, so nothing started meanwhile misses it
+static LISTENER: Mutex<Option<Listener>> = Mutex::new(None);
+
+/// Hands `request` to the instance that's already running, false when there isn't one.
+pub fn forward(request: &Request) -> bool {
+	connect().is_some_and(|mut stream| stream.write_all(request.line().as_bytes()).is_ok())
+}
+
+/// Starts taking requests from instances started after this one, see [`requests`].
+pub fn listen() {
+	if let (Some(listener), Ok(mut slot)) = (bind(), LISTENER.lock()) {
+		*slot = Some(listener);
+	}
+}
+
+/// The requests later instances send, once [`listen`] is listening. It ends straight away
+/// when it isn't, e.g. when two instances started at once and the other one got there first.
+pub fn requests() -> impl Stream<Item = Request> {
+	let (tx, rx) = mpsc::channel(8);
+
+	if let Some(listener) = LISTENER.lock().ok().and_then(|mut slot| slot.take()) {
+		std::thread::spawn(move || {
+			for stream in listener.incoming().map_while(Result::ok) {
+				let mut line = String::new();
+				if BufReader::new(stream).read_line(&mut line).is_err() {
+					continue;
+				}
+
+				if let Some(request) = Request::parse(line.trim_end_matches(['\r', '\n']))
+					&& tx.blocking_send(request).is_err()
+				{
+					break;
+				}
+			}
+		});
+	}
+
+	stream::unfold(rx, |mut rx| async move {
+		rx.recv().await.map(|request| (request, rx))
+	})
+}
+
+#[cfg(unix)]
+type Listener = std::os::unix::net::UnixListener;
+
+#[cfg(unix)]
+fn socket_path() -> PathBuf {
+	// The runtime directory is the user's own, the temp directory is shared
+	match dirs::runtime_dir() {
+		Some(dir) => dir.join("iso_maker.sock"),
+		None => {
+			let user = std::env::var("USER").unwrap_or_default();
+			std::env::temp_dir().join(format!("iso_maker-{user}.sock"))
+		},
+	}
+}
+
+#[cfg(unix)]
+fn connect() -> Option<std::os::unix::net::UnixStream> {
+	std::os::unix::net::UnixStream::connect(socket_path()).ok()
+}
+
+#[cfg(unix)]
+fn bind() -> Option<Listener> {
+	use std::os::unix::fs::PermissionsExt;
+
+	// Nothing answered on it, so it's left over from an instance that crashed
+	let path = socket_path();
+	let _ = std::fs::remove_file(&path);
+
+	let listener = Listener::bind(&path).ok()?;
+	std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).ok()?;
+
+	Some(listener)
+}
+
+#[cfg(not(unix))]
+type Listener = std::net::TcpListener;
+
+#[cfg(not(unix))]
+fn port_path() -> Option<PathBuf> {
+	dirs::data_local_dir().map(|dir| dir.join("iso_maker").join("instance.port"))
+}
+
+#[cfg(not(unix))]
+fn connect() -> Option<std::net::TcpStream> {
+	let port: u16 = std::fs::read_to_string(port_path()?).ok()?.trim().parse().ok()?;
+	let address = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+
+	std::net::TcpStream::connect_timeout(&address, std::time::Duration::from_millis(500)).ok()
+}
+
+#[cfg(not(unix))]
+fn bind() -> Option<Listener> {
+	let listener = Listener::bind(("127.0.0.1", 0)).ok()?;
+	let path = port_path()?;
+
+	std::fs::create_dir_all(path.parent()?).ok()?;
+	std::fs::write(&path, listener.local_addr().ok()?.port().to_string()).ok()?;
+
+	Some(listener)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn requests_survive_the_trip() {
+		for request in [Request::Focus, Request::Open("/home/me/My Images/debian 12.iso".into())] {
+			let line = request.line();
+			assert_eq!(Request::parse(line.trim_end()), Some(request));
+		}
+
+		assert_eq!(Request::parse("open "), None);
+		assert_eq!(Request::parse("write /dev/sda"), None);
+	}
+}
@@ -0,0 +1,394 @@
+// Authoring ISO images from a folder, with xorriso's mkisofs emulation doing the writing.
+//
+// xorriso doesn't do UDF, so ISO+UDF bridge images come from genisoimage instead, which
+// takes the same arguments. Remastering an existing image is left to xorriso's own
+// commands, which can replay its boot records into the new one.
+
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use tokio::sync::mpsc;
+
+use crate::tools;
+
+// Largest file plain ISO 9660 can hold in one extent
+const EXTENT_MAX: u64 = 4 * 1024 * 1024 * 1024 - 2048;
+
+/// What the primary volume descriptor says about the image.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+	pub volume_id: String,
+	pub publisher: String,
+	pub application: String,
+	pub preparer: String,
+	/// `YYYY-MM-DD HH:MM:SS` in UTC, empty for the time of writing
+	pub modified: String,
+}
+
+impl Metadata {
+	/// Checks every field fits in its slot of the volume descriptor.
+	pub fn check(&self) -> Result<(), String> {
+		for (name, value, max) in [
+			("Volume ID", &self.volume_id, 32),
+			("Publisher", &self.publisher, 128),
+			("Application", &self.application, 128),
+			("Preparer", &self.preparer, 128),
+		] {
+			if !value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+				return Err(format!("{name} can only use plain ASCII characters"));
+			}
+
+			if value.len() > max {
+				return Err(format!("{name} can be at most {max} characters"));
+			}
+		}
+
+		if !self.modified.trim().is_empty() {
+			modification_date(&self.modified)?;
+		}
+
+		Ok(())
+	}
+
+	// mkisofs style arguments, or xorriso's own commands with `native`, for the fields that are set
+	fn args(&self, native: bool) -> Result<Vec<String>, String> {
+		let mut args = Vec::new();
+
+		for (flag, command, value) in [
+			("-V", "-volid", &self.volume_id),
+			("-publisher", "-publisher", &self.publisher),
+			("-A", "-application_id", &self.application),
+			("-p", "-preparer_id", &self.preparer),
+		] {
+			if !value.is_empty() {
+				args.extend([if native { command } else { flag }.to_string(), value.clone()]);
+			}
+		}
+
+		if !self.modified.trim().is_empty() {
+			let date = modification_date(&self.modified)?;
+
+			if native {
+				args.extend(["-volume_date".into(), "m".into(), date]);
+			} else {
+				args.push(format!("--modification-date={date}"));
+			}
+		}
+
+		Ok(args)
+	}
+}
+
+/// How far plain ISO 9660 names get mangled, for systems that see neither Joliet nor Rock Ridge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Naming {
+	/// Level 1, upper case 8.3 names that anything can read
+	Strict,
+	/// Level 2, upper case names of up to 31 characters
+	#[default]
+	Standard,
+	/// Level 3, keeping lower case and most punctuation, which some old systems choke on
+	Relaxed,
+}
+
+impl Naming {
+	pub const ALL: [Naming; 3] = [Naming::Strict, Naming::Standard, Naming::Relaxed];
+}
+
+impl fmt::Display for Naming {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Naming::Strict => "Strict (8.3 names)",
+			Naming::Standard => "Standard (31 characters)",
+			Naming::Relaxed => "Relaxed",
+		})
+	}
+}
+
+/// Filesystem extensions, which carry the real long file names.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+	/// Unicode names for Windows
+	pub joliet: bool,
+	/// Names, permissions and symlinks for Linux and other Unix systems
+	pub rock_ridge: bool,
+	pub naming: Naming,
+	/// Adds a UDF filesystem next to ISO 9660, for files over 4 GB
+	pub udf: bool,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Self { joliet: true, rock_ridge: true, naming: Naming::default(), udf: false }
+	}
+}
+
+impl Options {
+	/// Checks the options can hold everything in `folder` and go with `metadata`.
+	pub fn check(&self, folder: &str, metadata: &Metadata) -> Result<(), String> {
+		if self.udf && !metadata.modified.trim().is_empty() {
+			return Err("genisoimage can't set the modification date of UDF images, leave it empty".into());
+		}
+
+		// Level 3 splits big files into several extents, which is all Linux needs but Windows only reads through UDF
+		if !self.udf && self.naming != Naming::Relaxed && let Some(big) = find_larger(Path::new(folder), EXTENT_MAX)? {
+			return Err(format!("{} is over 4 GB, turn on UDF or use Relaxed names", big.display()));
+		}
+
+		Ok(())
+	}
+
+	fn program(&self) -> &'static str {
+		if self.udf { "genisoimage" } else { "xorriso" }
+	}
+
+	fn args(&self) -> Vec<&'static str> {
+		let mut args = match self.naming {
+			Naming::Strict => vec!["-iso-level", "1"],
+			Naming::Standard => vec!["-iso-level", "2"],
+			Naming::Relaxed => vec!["-iso-level", "3", "-relaxed-filenames", "-allow-lowercase"],
+		};
+
+		if self.joliet {
+			args.push("-J");
+
+			// Joliet itself stops at 64 characters, Windows reads up to 103
+			if self.naming == Naming::Relaxed {
+				args.push("-joliet-long");
+			}
+		}
+
+		// -r rather than -R, so files don't keep the owner and permissions they had on this machine
+		if self.rock_ridge {
+			args.push("-r");
+		}
+
+		// ISO 9660 then lists big files with a capped size and only UDF has them whole
+		if self.udf {
+			args.extend(["-udf", "-allow-limited-size"]);
+		}
+
+		args
+	}
+}
+
+// "2024-05-01 12:30:00" as the YYYYMMDDhhmmsscc xorriso takes, seconds are optional
+fn modification_date(date: &str) -> Result<String, String> {
+	let invalid = || format!("Modification date must look like 2024-05-01 12:30:00, not {date}");
+
+	let (day, time) = date.trim().split_once(' ').unwrap_or((date.trim(), "00:00:00"));
+	let day: Vec<&str> = day.split('-').collect();
+	let mut time: Vec<&str> = time.trim().split(':').collect();
+	if time.len() == 2 {
+		time.push("00");
+	}
+
+	let parts: Vec<u32> = day.iter().chain(&time)
+		.map(|part| part.parse().map_err(|_| invalid()))
+		.collect::<Result<_, _>>()?;
+
+	let [year, month, day, hour, minute, second] = parts[..] else {
+		return Err(invalid());
+	};
+
+	let valid = (1970..=9999).contains(&year)
+		&& (1..=12).contains(&month)
+		&& (1..=31).contains(&day)
+		&& hour < 24 && minute < 60 && second < 60;
+
+	if !valid {
+		return Err(invalid());
+	}
+
+	Ok(format!("{year:04}{month:02}{day:02}{hour:02}{minute:02}{second:02}00"))
+}
+
+/// Writes an ISO image of `folder` to `output`, reporting progress in percent.
+pub async fn create(
+	folder: String,
+	output: String,
+	metadata: Metadata,
+	options: Options,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<u64>,
+) -> Result<(), String>
+{
+	tokio::task::spawn_blocking(move || {
+		if metadata.volume_id.trim().is_empty() {
+			return Err("The volume ID can't be empty".into());
+		}
+
+		let program = options.program();
+		let mut args = match program {
+			"xorriso" => vec!["-as".to_string(), "mkisofs".into()],
+			_ => Vec::new(),
+		};
+
+		args.extend(["-o".into(), output.clone()]);
+		args.extend(metadata.args(false)?);
+		args.extend(options.args().into_iter().map(String::from));
+		args.push(folder);
+
+		run(program, &args, &output, &mut cancel_rx, &progress_tx)
+	})
+	.await
+	.map_err(|e| format!("ISO error: {e}"))?
+}
+
+/// Writes a copy of the ISO at `iso` to `output` with `additions` in it, keeping its boot records.
+///
+/// `additions` are local files or folders and the path to put each at in the image. Fields
+/// left empty in `metadata` keep the original image's values.
+pub async fn remaster(
+	iso: String,
+	output: String,
+	additions: Vec<(String, String)>,
+	metadata: Metadata,
+	joliet: bool,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<u64>,
+) -> Result<(), String>
+{
+	tokio::task::spawn_blocking(move || {
+		if Path::new(&iso) == Path::new(&output) {
+			return Err("Save the remastered image as a new file, not over the original".into());
+		}
+
+		let mut args: Vec<String> = vec![
+			"-indev".into(), iso,
+			"-outdev".into(), output.clone(),
+			// El Torito and the hybrid MBR/GPT get rebuilt the way the original had them
+			"-boot_image".into(), "any".into(), "replay".into(),
+			"-joliet".into(), if joliet { "on" } else { "off" }.into(),
+		];
+
+		args.extend(metadata.args(true)?);
+
+		// Anything already at a path gets replaced, which is how a kickstart or driver gets updated
+		for (from, to) in additions {
+			args.extend(["-map".into(), from, format!("/{}", to.trim_start_matches('/'))]);
+		}
+
+		args.push("-commit".into());
+
+		run("xorriso", &args, &output, &mut cancel_rx, &progress_tx)
+	})
+	.await
+	.map_err(|e| format!("ISO error: {e}"))?
+}
+
+// Runs an image writing tool, passing on its progress and removing `output` if cancelled
+fn run(
+	program: &str,
+	args: &[String],
+	output: &str,
+	cancel_rx: &mut mpsc::Receiver<()>,
+	progress_tx: &mpsc::Sender<u64>,
+) -> Result<(), String>
+{
+	let mut child = Command::new(program)
+		.args(args)
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|e| tools::not_found(program, e))?;
+
+	let stderr = child.stderr.take().ok_or_else(|| format!("ISO error: no output from {program}"))?;
+	let mut messages = Vec::new();
+
+	// Progress lines look like "xorriso : UPDATE :  45.12% done, estimate finish ...", genisoimage leaves out the prefix
+	for line in BufReader::new(stderr).lines() {
+		let line = line.map_err(|e| format!("ISO error: {e}"))?;
+
+		if cancel_rx.try_recv().is_ok() {
+			let _ = child.kill();
+			let _ = child.wait();
+			let _ = std::fs::remove_file(output);
+			return Err("Cancelled".into());
+		}
+
+		match percent(&line) {
+			Some(percent) => {
+				let _ = progress_tx.blocking_send(percent);
+			},
+			None => messages.push(line),
+		}
+	}
+
+	let status = child.wait().map_err(|e| format!("ISO error: {e}"))?;
+
+	if status.success() {
+		let _ = progress_tx.blocking_send(100);
+		Ok(())
+	} else {
+		// xorriso explains itself in the FAILURE lines, the rest is chatter
+		let failures: Vec<&str> = messages.iter()
+			.filter(|line| line.contains("FAILURE") || line.contains("SORRY"))
+			.map(String::as_str)
+			.collect();
+
+		Err(format!("{program} error: {}", if failures.is_empty() { messages.join("\n") } else { failures.join("\n") }))
+	}
+}
+
+fn percent(line: &str) -> Option<u64> {
+	let end = line.find("% done")?;
+	let start = line[..end].rfind(' ').map_or(0, |i| i + 1);
+
+	line[start..end].parse::<f64>().ok().map(|percent| percent as u64)
+}
+
+// First file in `dir` bigger than `size`
+fn find_larger(dir: &Path, size: u64) -> Result<Option<std::path::PathBuf>, String> {
+	for entry in std::fs::read_dir(dir).map_err(|e| format!("ISO error: {}: {e}", dir.display()))? {
+		let entry = entry.map_err(|e| format!("ISO error: {e}"))?;
+		let metadata = entry.metadata().map_err(|e| format!("ISO error: {e}"))?;
+
+		if metadata.is_dir() {
+			if let Some(path) = find_larger(&entry.path(), size)? {
+				return Ok(Some(path));
+			}
+		} else if metadata.len() > size {
+			return Ok(Some(entry.path()));
+		}
+	}
+
+	Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn volume_fields_and_dates_go_to_the_tools_as_they_take_them() {
+		let metadata = Metadata {
+			volume_id: "FIELD_KIT".into(),
+			publisher: "Ops".into(),
+			modified: "2024-05-01 12:30".into(),
+			..Metadata::default()
+		};
+		assert!(metadata.check().is_ok());
+		assert_eq!(metadata.args(false).unwrap(), ["-V", "FIELD_KIT", "-publisher", "Ops", "--modification-date=2024050112300000"]);
+		assert_eq!(metadata.args(true).unwrap()[..2], ["-volid", "FIELD_KIT"]);
+
+		// What doesn't fit the volume descriptor, or isn't a date
+		assert!(Metadata { volume_id: "x".repeat(33), ..Metadata::default() }.check().is_err());
+		assert!(Metadata { preparer: "Zoë".into(), ..Metadata::default() }.check().is_err());
+		for date in ["2024-05-01 24:00", "2024-13-01", "2024-05", "yesterday"] {
+			assert!(modification_date(date).is_err(), "{date}");
+		}
+
+		let relaxed = Options { naming: Naming::Relaxed, udf: true, ..Options::default() };
+		assert_eq!(relaxed.program(), "genisoimage");
+		assert!(relaxed.args().contains(&"-joliet-long") && relaxed.args().contains(&"-udf"));
+		assert!(relaxed.check("/nonexistent", &metadata).is_err());
+
+		assert_eq!(percent("xorriso : UPDATE :  45.12% done, estimate finish Wed May  1 12:31:02 2024"), Some(45));
+		assert_eq!(percent(" 9.87% done, estimate finish Wed May  1 12:31:02 2024"), Some(9));
+		assert_eq!(percent("xorriso : NOTE : Writing to 'stdio:out.iso'"), None);
+	}
+}
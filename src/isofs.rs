@@ -0,0 +1,390 @@
+// Reading the ISO 9660 filesystem inside an image, without mounting it.
+//
+// Names come from Rock Ridge when the image has it, otherwise from the Joliet tree, and
+// only as a last resort from the upper case ISO 9660 names themselves.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const SECTOR: u64 = 2048;
+
+// Anything deeper is a corrupt or hostile image rather than a real directory tree
+const MAX_DEPTH: usize = 64;
+const MAX_DIRECTORY: u64 = 64 * 1024 * 1024;
+// Rock Ridge continuation areas fit in a sector
+const MAX_CONTINUATION: usize = SECTOR as usize;
+
+/// A file or directory in an ISO image.
+#[derive(Debug, Clone)]
+pub struct Entry {
+	pub name: String,
+	/// Size in bytes, the total of the children for directories
+	pub size: u64,
+	/// `YYYY-MM-DD HH:MM` as recorded, in the image's own time zone
+	pub modified: String,
+	/// `None` for files
+	pub children: Option<Vec<Entry>>,
+	/// Start sector and length of each piece of a file, big files take several
+	pub extents: Vec<(u64, u64)>,
+}
+
+impl Entry {
+	pub fn is_dir(&self) -> bool {
+		self.children.is_some()
+	}
+
+	/// The entry at `path` below this one, with `/` between the names.
+	pub fn find(&self, path: &str) -> Option<&Entry> {
+		path.split('/').filter(|name| !name.is_empty()).try_fold(self, |dir, name| {
+			dir.children.as_ref()?.iter().find(|child| child.name == name)
+		})
+	}
+}
+
+/// Reads the file `entry` out of the ISO at `iso` into memory.
+pub fn read_file(iso: &str, entry: &Entry) -> Result<Vec<u8>, String> {
+	let mut file = File::open(iso).map_err(|e| format!("Source error: {e}"))?;
+	let mut data = Vec::with_capacity(entry.size as usize);
+
+	for &(extent, length) in &entry.extents {
+		file.seek(SeekFrom::Start(extent * SECTOR)).map_err(|e| format!("Read error: {e}"))?;
+		(&mut file).take(length).read_to_end(&mut data).map_err(|e| format!("Read error: {e}"))?;
+	}
+
+	Ok(data)
+}
+
+/// Copies `entry` out of the ISO at `iso` into the folder `to`, a directory with everything below it.
+///
+/// `progress` gets the bytes copied so far and returns false to cancel.
+pub fn extract(iso: &str, entry: &Entry, to: &Path, progress: &mut impl FnMut(u64) -> bool) -> Result<(), String> {
+	let mut file = File::open(iso).map_err(|e| format!("Source error: {e}"))?;
+	let mut copied = 0;
+
+	// The root has no name of its own, its contents go straight into `to`
+	let target = if entry.name.is_empty() { to.to_path_buf() } else { to.join(safe_name(&entry.name)?) };
+	extract_entry(&mut file, entry, &target, &mut copied, progress)
+}
+
+fn extract_entry(
+	file: &mut File,
+	entry: &Entry,
+	to: &Path,
+	copied: &mut u64,
+	progress: &mut impl FnMut(u64) -> bool,
+) -> Result<(), String>
+{
+	if let Some(children) = &entry.children {
+		std::fs::create_dir_all(to).map_err(|e| format!("Extract error: {}: {e}", to.display()))?;
+
+		for child in children {
+			extract_entry(file, child, &to.join(safe_name(&child.name)?), copied, progress)?;
+		}
+
+		return Ok(());
+	}
+
+	let mut target = File::create(to).map_err(|e| format!("Extract error: {}: {e}", to.display()))?;
+	let mut buffer = vec![0; 4096 * 1024]; // 4MB buffer
+
+	for &(extent, length) in &entry.extents {
+		file.seek(SeekFrom::Start(extent * SECTOR)).map_err(|e| format!("Read error: {e}"))?;
+		let mut left = length;
+
+		while left > 0 {
+			let n = left.min(buffer.len() as u64) as usize;
+			file.read_exact(&mut buffer[..n]).map_err(|e| format!("Read error: {e}"))?;
+			target.write_all(&buffer[..n]).map_err(|e| format!("Write error: {e}"))?;
+
+			left -= n as u64;
+			*copied += n as u64;
+
+			if !progress(*copied) {
+				return Err("Cancelled".into());
+			}
+		}
+	}
+
+	Ok(())
+}
+
+// Names come from the image, which mustn't get to write outside the folder
+fn safe_name(name: &str) -> Result<&str, String> {
+	if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+		return Err(format!("Extract error: the image has a file named {name:?}, which isn't safe to extract"));
+	}
+
+	Ok(name)
+}
+
+/// Reads the whole directory tree of the ISO at `path`, returning its root.
+pub fn read_tree(path: &str) -> Result<Entry, String> {
+	let mut file = File::open(path).map_err(|e| format!("Source error: {e}"))?;
+	let (primary, joliet) = volume_roots(&mut file)?;
+
+	let mut tree = Tree { file, joliet: false, rock_ridge: false };
+	let root = tree.entry(&primary, String::new(), 0)?;
+
+	// Without Rock Ridge the Joliet tree has the better names
+	if !tree.rock_ridge && let Some(joliet) = joliet {
+		tree.joliet = true;
+		return tree.entry(&joliet, String::new(), 0);
+	}
+
+	Ok(root)
+}
+
+// Root directory records of the primary and the Joliet volume descriptors
+fn volume_roots(file: &mut File) -> Result<(Vec<u8>, Option<Vec<u8>>), String> {
+	let mut primary = None;
+	let mut joliet = None;
+	let mut descriptor = [0; SECTOR as usize];
+
+	for sector in 16..64 {
+		file.seek(SeekFrom::Start(sector * SECTOR)).map_err(|e| format!("ISO error: {e}"))?;
+		if file.read_exact(&mut descriptor).is_err() || &descriptor[1..6] != b"CD001" {
+			break;
+		}
+
+		match descriptor[0] {
+			1 => primary = Some(descriptor[156..190].to_vec()),
+			// A supplementary descriptor with a UCS-2 escape sequence is Joliet
+			2 if matches!(&descriptor[88..91], b"%/@" | b"%/C" | b"%/E") => joliet = Some(descriptor[156..190].to_vec()),
+			255 => break,
+			_ => {},
+		}
+	}
+
+	let primary = primary.ok_or("Not an ISO 9660 image, no primary volume descriptor found")?;
+	Ok((primary, joliet))
+}
+
+struct Tree {
+	file: File,
+	joliet: bool,
+	/// Whether any Rock Ridge names turned up
+	rock_ridge: bool,
+}
+
+impl Tree {
+	fn entry(&mut self, record: &[u8], name: String, depth: usize) -> Result<Entry, String> {
+		let (extent, length) = location(record);
+		let mut entry = Entry {
+			name,
+			size: 0,
+			modified: date(&record[18..25]),
+			children: None,
+			extents: vec![(extent, length)],
+		};
+
+		if record[25] & 2 == 0 {
+			entry.size = length;
+			return Ok(entry);
+		}
+
+		if depth > MAX_DEPTH {
+			return Err("ISO error: the directory tree is too deep".into());
+		}
+
+		let mut children: Vec<Entry> = Vec::new();
+		let mut continued = false;
+
+		for record in self.directory(extent, length)? {
+			let name = self.name(&record)?;
+
+			// Files over 4 GB come as several records of the same name, all but the last flagged as having more
+			if continued && let Some(last) = children.last_mut() && last.name == name {
+				let (extent, length) = location(&record);
+				last.extents.push((extent, length));
+				last.size += length;
+			} else {
+				children.push(self.entry(&record, name, depth + 1)?);
+			}
+
+			continued = record[25] & 0x80 != 0;
+		}
+
+		entry.size = children.iter().map(|child| child.size).sum();
+		entry.children = Some(children);
+		Ok(entry)
+	}
+
+	// Directory records in a directory's extent, without `.` and `..`
+	fn directory(&mut self, extent: u64, length: u64) -> Result<Vec<Vec<u8>>, String> {
+		if length > MAX_DIRECTORY {
+			return Err("ISO error: corrupt directory record".into());
+		}
+
+		let mut data = vec![0; length as usize];
+		self.file.seek(SeekFrom::Start(extent * SECTOR)).map_err(|e| format!("ISO error: {e}"))?;
+		self.file.read_exact(&mut data).map_err(|e| format!("ISO error: {e}"))?;
+
+		let mut records = Vec::new();
+		let mut offset = 0;
+
+		while offset < data.len() {
+			let len = data[offset] as usize;
+
+			// Records don't cross sectors, the rest of one is padded with zeros
+			if len == 0 {
+				offset = (offset / SECTOR as usize + 1) * SECTOR as usize;
+				continue;
+			}
+
+			if len < 34 || offset + len > data.len() || 33 + data[offset + 32] as usize > len {
+				return Err("ISO error: corrupt directory record".into());
+			}
+
+			let record = &data[offset..offset + len];
+			let name = &record[33..33 + (record[32] as usize).min(len - 33)];
+
+			if name != [0] && name != [1] {
+				records.push(record.to_vec());
+			}
+
+			offset += len;
+		}
+
+		Ok(records)
+	}
+
+	fn name(&mut self, record: &[u8]) -> Result<String, String> {
+		let name_len = record[32] as usize;
+		let name = &record[33..33 + name_len];
+
+		if self.joliet {
+			let units: Vec<u16> = name.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+			return Ok(strip_version(&String::from_utf16_lossy(&units)));
+		}
+
+		// The system use area follows the name, after a padding byte if the name's length is even
+		let system_use = &record[(33 + name_len + (1 - name_len % 2)).min(record.len())..];
+		if let Some(name) = self.rock_ridge_name(system_use)? {
+			self.rock_ridge = true;
+			return Ok(name);
+		}
+
+		Ok(strip_version(&String::from_utf8_lossy(name)))
+	}
+
+	// The NM entries of a record's system use area, following a continuation area if there is one
+	fn rock_ridge_name(&mut self, system_use: &[u8]) -> Result<Option<String>, String> {
+		let mut name: Option<Vec<u8>> = None;
+		let mut area = system_use.to_vec();
+
+		for _ in 0..8 {
+			let mut continuation = None;
+			let mut offset = 0;
+
+			while offset + 4 <= area.len() {
+				let len = area[offset + 2] as usize;
+				if len < 4 || offset + len > area.len() {
+					break;
+				}
+
+				let data = &area[offset + 4..offset + len];
+
+				match &area[offset..offset + 2] {
+					// Flags 2 and 4 are `.` and `..`, which aren't listed anyway
+					b"NM" if !data.is_empty() && data[0] & 6 == 0 => {
+						name.get_or_insert_with(Vec::new).extend_from_slice(&data[1..]);
+					},
+					b"CE" if data.len() >= 24 => {
+						let block = u32::from_le_bytes(data[0..4].try_into().unwrap()) as u64;
+						let offset = u32::from_le_bytes(data[8..12].try_into().unwrap()) as u64;
+						let length = u32::from_le_bytes(data[16..20].try_into().unwrap()) as usize;
+						continuation = Some((block * SECTOR + offset, length));
+					},
+					b"ST" => break,
+					_ => {},
+				}
+
+				offset += len;
+			}
+
+			let Some((position, length)) = continuation else { break };
+
+			if length > MAX_CONTINUATION {
+				return Err("ISO error: corrupt Rock Ridge continuation area".into());
+			}
+
+			area = vec![0; length];
+			self.file.seek(SeekFrom::Start(position)).map_err(|e| format!("ISO error: {e}"))?;
+			self.file.read_exact(&mut area).map_err(|e| format!("ISO error: {e}"))?;
+		}
+
+		Ok(name.map(|name| String::from_utf8_lossy(&name).to_string()))
+	}
+}
+
+// Little endian halves of the both-endian extent location and data length
+fn location(record: &[u8]) -> (u64, u64) {
+	let extent = u32::from_le_bytes(record[2..6].try_into().unwrap()) as u64;
+	let length = u32::from_le_bytes(record[10..14].try_into().unwrap()) as u64;
+
+	(extent, length)
+}
+
+// "FILE.TXT;1" to "FILE.TXT", and "README.;1" to "README"
+fn strip_version(name: &str) -> String {
+	let name = name.split_once(';').map_or(name, |(name, _)| name);
+	name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+// Years since 1900, month, day, hour, minute, second and a time zone offset
+fn date(recorded: &[u8]) -> String {
+	if recorded[1] == 0 {
+		return String::new();
+	}
+
+	format!(
+		"{:04}-{:02}-{:02} {:02}:{:02}",
+		1900 + recorded[0] as u32, recorded[1], recorded[2], recorded[3], recorded[4],
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	#[test]
+	fn files_come_out_of_the_image_under_their_long_names() {
+		let dir = TempDir::new("isofs");
+		let path = dir.file("image.iso");
+		let readme = crate::testing::image(5000);
+		// As long as a CE entry, for one to stand in for its NM entry
+		let long = "continuation-standin.md";
+		std::fs::write(&path, crate::testing::iso(&[("readme.txt", &readme), (long, b"long")])).unwrap();
+
+		let root = read_tree(&path).unwrap();
+		assert_eq!(root.children.as_ref().unwrap().iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>(), ["readme.txt", long]);
+		assert_eq!(root.size, 5004);
+
+		let entry = root.find("/readme.txt").unwrap();
+		assert_eq!(entry.modified, "2024-05-01 12:30");
+		assert_eq!(read_file(&path, entry).unwrap(), readme);
+
+		extract(&path, &root, Path::new(&dir.file("out")), &mut |_| true).unwrap();
+		assert_eq!(std::fs::read(dir.file("out/readme.txt")).unwrap(), readme);
+
+		// A continuation area claiming 4 GB, the image cut off in its root directory, or not an ISO
+		let mut image = std::fs::read(&path).unwrap();
+		let mut ce = vec![b'C', b'E', 28, 1];
+		ce.extend([0; 16]);
+		ce.extend(u32::MAX.to_le_bytes());
+		ce.extend([0; 4]);
+		let nm = image.windows(long.len()).rposition(|window| window == long.as_bytes()).unwrap() - 5;
+		image[nm..nm + 28].copy_from_slice(&ce);
+		std::fs::write(&path, &image).unwrap();
+		assert!(read_tree(&path).unwrap_err().contains("continuation"));
+
+		std::fs::write(&path, &image[..18 * 2048 + 100]).unwrap();
+		assert!(read_tree(&path).is_err());
+
+		image[16 * 2048 + 1] = b'X';
+		std::fs::write(&path, &image).unwrap();
+		assert!(read_tree(&path).is_err());
+	}
+}
@@ -0,0 +1,38 @@
+// Writing JSON by hand, for the few small documents sent to other programs.
+
+/// `value` as a quoted JSON string.
+pub fn string(value: &str) -> String {
+	let mut escaped = String::with_capacity(value.len() + 2);
+	escaped.push('"');
+
+	for c in value.chars() {
+		match c {
+			'"' => escaped.push_str("\\\""),
+			'\\' => escaped.push_str("\\\\"),
+			'\n' => escaped.push_str("\\n"),
+			'\r' => escaped.push_str("\\r"),
+			'\t' => escaped.push_str("\\t"),
+			c if u32::from(c) < 0x20 => escaped.push_str(&format!("\\u{:04x}", u32::from(c))),
+			c => escaped.push(c),
+		}
+	}
+
+	escaped.push('"');
+	escaped
+}
+
+/// `value` as a JSON string, or `null` without one.
+pub fn optional(value: Option<&str>) -> String {
+	value.map_or_else(|| "null".into(), string)
+}
+
+/// An object of `fields`, whose values are already JSON.
+pub fn object(fields: &[(&str, String)]) -> String {
+	let fields: Vec<String> = fields.iter().map(|(name, value)| format!("{}:{value}", string(name))).collect();
+	format!("{{{}}}", fields.join(","))
+}
+
+/// An array of `items`, already JSON.
+pub fn array(items: &[String]) -> String {
+	format!("[{}]", items.join(","))
+}
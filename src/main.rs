@@ -1,9 +1,110 @@
+mod api;
 mod app;
+mod audit;
+mod bmap;
+mod bootcfg;
+mod busy;
+mod cache;
+mod catalog;
+mod cli;
+mod compare;
+mod config;
+mod customize;
+mod devices;
+mod elevate;
+mod eta;
+mod filecopy;
+mod firstboot;
+mod hash;
+mod hooks;
+mod hybrid;
+mod instance;
+mod iso;
+mod isofs;
+mod json;
+mod mime;
+mod mount;
+mod notify;
+mod partition;
+mod pi;
+mod profile;
+mod provider;
+mod provision;
+mod qemu;
+mod recovery;
+mod remote;
+mod s3;
+mod secureboot;
+mod sftp;
+mod share;
+mod sound;
+mod source;
+mod target;
+mod taskbar;
+mod template;
+#[cfg(test)]
+mod testing;
+mod tools;
+mod udev;
+mod uefi;
+mod units;
+mod wim;
+mod writer;
 
-use crate::app::{theme, update, view};
+use std::process::ExitCode;
 
-fn main() -> iced::Result {
-    iced::application("ISO Maker", update, view)
+use crate::app::{scale_factor, start, subscription, theme, title, update, view};
+use crate::instance::Request;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // Sizes follow the setting everywhere, on the command line too, and the image sources
+    // set up work everywhere
+    let config = config::Config::load();
+    units::prefer(config.units);
+    provider::register_configured(&config);
+
+    if let Some(code) = cli::run(&args) {
+        // Notifications of the job it ran may still be on their way, and its hook running
+        notify::flush();
+        hooks::flush();
+        return code;
+    }
+
+    // `kiosk <image>` is the GUI too, given over to writing the one image
+    let kiosk = args.first().is_some_and(|command| command == "kiosk");
+
+    // An image to open, as a full path for the job to show
+    let image = args.get(usize::from(kiosk)).map(|image| {
+        if source::is_url(image) {
+            return image.clone();
+        }
+
+        std::path::absolute(image).map_or_else(|_| image.clone(), |path| path.to_string_lossy().into_owned())
+    });
+
+    // One window at a time, starting it again hands the image over to the one that's open.
+    // A kiosk keeps to itself, nothing else gets to open in it
+    if !kiosk {
+        let request = image.clone().map_or(Request::Focus, Request::Open);
+        if instance::forward(&request) {
+            return ExitCode::SUCCESS;
+        }
+        instance::listen();
+    }
+
+    let result = iced::application(title, update, view)
         .theme(theme)
-        .run()
+        .subscription(subscription)
+        .scale_factor(scale_factor)
+        .run_with(move || start(image, kiosk));
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        },
+    }
 }
@@ -0,0 +1,164 @@
+// Registering iso_maker as an app that opens disk images, so a double click or "Open with"
+// on an ISO starts it with the image picked.
+//
+// On Linux that's a desktop entry in the user's applications folder listing the image MIME
+// types, made the default for them with xdg-mime. On Windows it's a ProgID under the user's
+// classes that .iso and .img files can be opened with. Neither needs root or admin.
+
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+
+#[cfg(any(target_os = "linux", windows))]
+use crate::tools;
+
+/// What disk images are sent as, the compressed raw images included.
+pub const MIME_TYPES: [&str; 7] = [
+	"application/x-iso9660-image",
+	"application/x-cd-image",
+	"application/vnd.efi.iso",
+	"application/vnd.efi.img",
+	"application/x-raw-disk-image",
+	"application/x-raw-disk-image-xz-compressed",
+	"application/x-raw-disk-image-zstd-compressed",
+];
+
+/// Whether the platform has a way to register, the settings only offer it where it does.
+pub const SUPPORTED: bool = cfg!(any(target_os = "linux", windows));
+
+// Matches the one the taskbar progress is sent for
+#[cfg(target_os = "linux")]
+const DESKTOP_FILE: &str = "iso_maker.desktop";
+
+/// The desktop entry that opens images with `exe`.
+#[cfg(target_os = "linux")]
+pub fn desktop_entry(exe: &str) -> String {
+	format!(
+		"\
+[Desktop Entry]
+Type=Application
+Name=ISO Maker
+Comment=Write disk images to USB sticks and SD cards
+Exec=\"{exe}\" %f
+Terminal=false
+Categories=Utility;System;
+MimeType={};
+",
+		MIME_TYPES.join(";"),
+	)
+}
+
+#[cfg(any(target_os = "linux", windows))]
+fn exe() -> Result<String, String> {
+	std::env::current_exe()
+		.map(|path| path.to_string_lossy().into_owned())
+		.map_err(|e| format!("File type error: {e}"))
+}
+
+#[cfg(target_os = "linux")]
+fn applications() -> Option<PathBuf> {
+	dirs::data_dir().map(|dir| dir.join("applications"))
+}
+
+/// Whether iso_maker is registered for disk images.
+#[cfg(target_os = "linux")]
+pub fn registered() -> bool {
+	applications().is_some_and(|dir| dir.join(DESKTOP_FILE).is_file())
+}
+
+/// Registers iso_maker for disk images and makes it their default app.
+#[cfg(target_os = "linux")]
+pub fn register() -> Result<(), String> {
+	let dir = applications().ok_or("Could not find the applications folder")?;
+	std::fs::create_dir_all(&dir).map_err(|e| format!("File type error: {e}"))?;
+	std::fs::write(dir.join(DESKTOP_FILE), desktop_entry(&exe()?)).map_err(|e| format!("File type error: {e}"))?;
+
+	let mut args = vec!["default", DESKTOP_FILE];
+	args.extend(MIME_TYPES);
+	tools::run("xdg-mime", &args)?;
+
+	refresh(&dir);
+	Ok(())
+}
+
+/// Takes the registration out again.
+#[cfg(target_os = "linux")]
+pub fn unregister() -> Result<(), String> {
+	let dir = applications().ok_or("Could not find the applications folder")?;
+
+	match std::fs::remove_file(dir.join(DESKTOP_FILE)) {
+		Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(format!("File type error: {e}")),
+		_ => {},
+	}
+
+	refresh(&dir);
+	Ok(())
+}
+
+// Not every desktop has the cache, and the ones without look at the folder themselves
+#[cfg(target_os = "linux")]
+fn refresh(dir: &std::path::Path) {
+	let _ = tools::run("update-desktop-database", &[&dir.to_string_lossy()]);
+}
+
+#[cfg(windows)]
+const PROG_ID: &str = r"HKCU\Software\Classes\iso_maker.image";
+
+#[cfg(windows)]
+const EXTENSIONS: [&str; 2] = [".iso", ".img"];
+
+#[cfg(windows)]
+pub fn registered() -> bool {
+	tools::run("reg", &["query", PROG_ID]).is_ok()
+}
+
+#[cfg(windows)]
+pub fn register() -> Result<(), String> {
+	let command = format!("\"{}\" \"%1\"", exe()?);
+	tools::run("reg", &["add", &format!(r"{PROG_ID}\shell\open\command"), "/ve", "/d", &command, "/f"])?;
+
+	for extension in EXTENSIONS {
+		let key = format!(r"HKCU\Software\Classes\{extension}\OpenWithProgids");
+		tools::run("reg", &["add", &key, "/v", "iso_maker.image", "/d", "", "/f"])?;
+	}
+
+	Ok(())
+}
+
+#[cfg(windows)]
+pub fn unregister() -> Result<(), String> {
+	for extension in EXTENSIONS {
+		let key = format!(r"HKCU\Software\Classes\{extension}\OpenWithProgids");
+		let _ = tools::run("reg", &["delete", &key, "/v", "iso_maker.image", "/f"]);
+	}
+
+	tools::run("reg", &["delete", PROG_ID, "/f"]).map(|_| ())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn registered() -> bool {
+	false
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn register() -> Result<(), String> {
+	Err("Registering for disk images isn't supported on this platform".into())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn unregister() -> Result<(), String> {
+	Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn the_entry_opens_every_image_type() {
+		let entry = desktop_entry("/opt/iso maker/iso_maker");
+
+		assert!(entry.contains("Exec=\"/opt/iso maker/iso_maker\" %f\n"));
+		let types = entry.lines().find_map(|line| line.strip_prefix("MimeType=")).unwrap();
+		assert_eq!(types.trim_end_matches(';').split(';').collect::<Vec<_>>(), MIME_TYPES);
+	}
+}
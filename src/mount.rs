@@ -0,0 +1,81 @@
+// Temporary mounts, for putting files onto media after it has been written.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::tools;
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A filesystem mounted on a private directory until this is dropped.
+pub struct Mount {
+	dir: PathBuf,
+}
+
+impl Mount {
+	/// Mounts `device`, letting `mount` work out the filesystem type.
+	pub fn new(device: &str) -> Result<Self, String> {
+		Self::with_options(device, &[])
+	}
+
+	/// Mounts `device` read-only, for looking at what's on it without changing it.
+	pub fn read_only(device: &str) -> Result<Self, String> {
+		Self::with_options(device, &["-o", "ro"])
+	}
+
+	/// Mounts an image file read-only through a loop device.
+	pub fn image(path: &str) -> Result<Self, String> {
+		Self::with_options(path, &["-o", "loop,ro"])
+	}
+
+	fn with_options(device: &str, options: &[&str]) -> Result<Self, String> {
+		let dir = std::env::temp_dir().join(format!(
+			"iso_maker-{}-{}",
+			std::process::id(),
+			NEXT.fetch_add(1, Ordering::Relaxed),
+		));
+
+		std::fs::create_dir_all(&dir).map_err(|e| format!("Mount error: {e}"))?;
+
+		let dir_arg = dir.to_string_lossy();
+		let args: Vec<&str> = options.iter().copied().chain([device, &*dir_arg]).collect();
+
+		if let Err(e) = tools::run("mount", &args) {
+			let _ = std::fs::remove_dir(&dir);
+			return Err(e);
+		}
+
+		Ok(Self { dir })
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.dir
+	}
+}
+
+impl Drop for Mount {
+	fn drop(&mut self) {
+		// umount flushes everything out to the device before it returns
+		if tools::run("umount", &[&self.dir.to_string_lossy()]).is_ok() {
+			let _ = std::fs::remove_dir(&self.dir);
+		}
+	}
+}
+
+/// Copies the directory tree at `from` into `to`, creating `to` as needed.
+pub fn copy_dir(from: &Path, to: &Path) -> Result<(), String> {
+	std::fs::create_dir_all(to).map_err(|e| format!("Copy error: {e}"))?;
+
+	for entry in std::fs::read_dir(from).map_err(|e| format!("Copy error: {}: {e}", from.display()))? {
+		let entry = entry.map_err(|e| format!("Copy error: {e}"))?;
+		let target = to.join(entry.file_name());
+
+		if entry.file_type().map_err(|e| format!("Copy error: {e}"))?.is_dir() {
+			copy_dir(&entry.path(), &target)?;
+		} else {
+			std::fs::copy(entry.path(), &target).map_err(|e| format!("Copy error: {}: {e}", target.display()))?;
+		}
+	}
+
+	Ok(())
+}
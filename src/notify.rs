@@ -0,0 +1,203 @@
+// Notifications when a job that erased a device ends, for provisioning stations that a
+// central dashboard keeps track of.
+//
+// A webhook gets the job as a JSON POST, and an email goes out through the system's
+// `sendmail`, which whatever mail setup the machine has (postfix, msmtp, ...) provides. They're
+// sent on threads of their own so a slow server doesn't hold up the app; the command line
+// waits for them with [`flush`] before it exits.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::json;
+use crate::tools;
+
+// Long enough for a server on the other side of the world, short enough not to hang an exit
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static SENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Where notifications go, from the config.
+#[derive(Debug, Clone, Default)]
+pub struct Targets {
+	pub webhook: Option<String>,
+	pub email: Option<String>,
+}
+
+impl Targets {
+	pub fn new(config: &Config) -> Self {
+		let set = |value: &Option<String>| value.clone().filter(|value| !value.trim().is_empty());
+		Self { webhook: set(&config.webhook), email: set(&config.notify_email) }
+	}
+
+	fn is_empty(&self) -> bool {
+		self.webhook.is_none() && self.email.is_none()
+	}
+}
+
+/// A job as it's sent, the same as its line in the audit log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Job {
+	pub time: String,
+	pub host: String,
+	pub user: String,
+	/// The job's label, a ticket number or who ran it
+	pub label: Option<String>,
+	pub operation: String,
+	pub device: String,
+	pub serial: Option<String>,
+	pub image: String,
+	pub image_hash: Option<String>,
+	pub written_hash: Option<String>,
+	pub device_hash: Option<String>,
+	/// `done`, `cancelled` or `failed`
+	pub result: String,
+	pub error: Option<String>,
+}
+
+impl Job {
+	fn fields(&self) -> [(&'static str, Option<&str>); 13] {
+		[
+			("time", Some(&self.time)),
+			("host", Some(&self.host)),
+			("user", Some(&self.user)),
+			("label", self.label.as_deref()),
+			("operation", Some(&self.operation)),
+			("device", Some(&self.device)),
+			("serial", self.serial.as_deref()),
+			("image", Some(&self.image)),
+			("image_hash", self.image_hash.as_deref()),
+			("written_hash", self.written_hash.as_deref()),
+			("device_hash", self.device_hash.as_deref()),
+			("result", Some(&self.result)),
+			("error", self.error.as_deref()),
+		]
+	}
+
+	/// The job as a JSON object, with `null` for what isn't known.
+	pub fn json(&self) -> String {
+		let fields: Vec<(&str, String)> = self.fields()
+			.iter()
+			.map(|&(name, value)| (name, json::optional(value)))
+			.collect();
+
+		json::object(&fields)
+	}
+
+	/// The job as an email to `to`, headers and all.
+	pub fn email(&self, to: &str) -> String {
+		let body: String = self.fields()
+			.iter()
+			.map(|(name, value)| format!("{name}: {}\n", value.unwrap_or("-")))
+			.collect();
+
+		// A newline in a header would start another
+		let subject = format!("iso_maker: {} of {} {} on {}", self.operation, self.device, self.result, self.host).replace(['\r', '\n'], " ");
+		let to = to.replace(['\r', '\n'], " ");
+
+		format!("To: {to}\nSubject: {subject}\nContent-Type: text/plain; charset=utf-8\n\n{body}")
+	}
+}
+
+/// Sends `job` to `targets` in the background, failures go to stderr.
+pub fn send(targets: &Targets, job: Job) {
+	if targets.is_empty() {
+		return;
+	}
+
+	let targets = targets.clone();
+	let sending = std::thread::spawn(move || {
+		if let Some(url) = &targets.webhook && let Err(e) = post(url, &job.json()) {
+			eprintln!("{e}");
+		}
+
+		if let Some(to) = &targets.email && let Err(e) = mail(&job.email(to)) {
+			eprintln!("{e}");
+		}
+	});
+
+	if let Ok(mut pending) = SENDING.lock() {
+		pending.retain(|handle| !handle.is_finished());
+		pending.push(sending);
+	}
+}
+
+/// Waits for notifications still being sent, before the process exits.
+pub fn flush() {
+	let pending = SENDING.lock().map(|mut pending| std::mem::take(&mut *pending)).unwrap_or_default();
+
+	for handle in pending {
+		let _ = handle.join();
+	}
+}
+
+fn post(url: &str, json: &str) -> Result<(), String> {
+	ureq::post(url)
+		.config()
+		.timeout_global(Some(TIMEOUT))
+		.build()
+		.header("Content-Type", "application/json")
+		.send(json)
+		.map(|_| ())
+		.map_err(|e| format!("Webhook error: {e}"))
+}
+
+fn mail(message: &str) -> Result<(), String> {
+	// -t takes the recipient from the To header
+	let mut child = Command::new("sendmail")
+		.arg("-t")
+		.stdin(Stdio::piped())
+		.stdout(Stdio::null())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|e| tools::not_found("sendmail", e))?;
+
+	if let Some(mut stdin) = child.stdin.take() {
+		stdin.write_all(message.as_bytes()).map_err(|e| format!("sendmail error: {e}"))?;
+	}
+
+	let output = child.wait_with_output().map_err(|e| format!("sendmail error: {e}"))?;
+	if !output.status.success() {
+		return Err(format!("sendmail error: {}", String::from_utf8_lossy(&output.stderr).trim()));
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn a_job_goes_out_as_json_and_as_an_email() {
+		let job = Job {
+			time: "2026-10-14T09:41:07Z".into(),
+			host: "bench-3".into(),
+			user: "lab".into(),
+			label: Some("INC-42".into()),
+			operation: "write".into(),
+			device: "/dev/sdb".into(),
+			serial: Some("A1".into()),
+			image: "C:\\images\\\"golden\".iso".into(),
+			result: "failed".into(),
+			error: Some("Write error: it\nbroke".into()),
+			..Job::default()
+		};
+
+		let json = job.json();
+		assert!(json.starts_with("{\"time\":\"2026-10-14T09:41:07Z\",\"host\":\"bench-3\""), "{json}");
+		assert!(json.contains(r#""image":"C:\\images\\\"golden\".iso""#), "{json}");
+		assert!(json.contains(r#""image_hash":null"#), "{json}");
+		assert!(json.ends_with(r#""result":"failed","error":"Write error: it\nbroke"}"#), "{json}");
+
+		let email = job.email("lab@example.com\nBcc: everyone@example.com");
+		let (headers, body) = email.split_once("\n\n").unwrap();
+		assert_eq!(headers.lines().count(), 3, "{headers}");
+		assert!(headers.contains("Subject: iso_maker: write of /dev/sdb failed on bench-3"));
+		assert!(body.contains("label: INC-42\n") && body.contains("serial: A1\n") && body.contains("device_hash: -\n"), "{body}");
+	}
+}
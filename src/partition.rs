@@ -0,0 +1,802 @@
+// Partitioning and formatting devices with sfdisk and the mkfs tools.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::busy;
+use crate::devices;
+use crate::tools;
+use crate::units;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filesystem {
+	Fat32,
+	Exfat,
+	Ntfs,
+	Ext4,
+	Swap,
+}
+
+impl Filesystem {
+	/// Cluster sizes in bytes that are worth offering, mkfs picks one by the size otherwise.
+	pub fn cluster_sizes(self) -> &'static [u32] {
+		match self {
+			Filesystem::Fat32 | Filesystem::Ntfs => &[4096, 8192, 16384, 32768, 65536],
+			Filesystem::Exfat => &[4096, 16384, 32768, 131072, 1048576],
+			// Blocks bigger than a memory page can't be mounted
+			Filesystem::Ext4 => &[1024, 2048, 4096],
+			Filesystem::Swap => &[],
+		}
+	}
+}
+
+impl std::fmt::Display for Filesystem {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Filesystem::Fat32 => "FAT32",
+			Filesystem::Exfat => "exFAT",
+			Filesystem::Ntfs => "NTFS",
+			Filesystem::Ext4 => "ext4",
+			Filesystem::Swap => "Linux swap",
+		})
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Table {
+	Gpt,
+	/// Old style MBR, which boots on the widest range of firmware
+	Mbr,
+}
+
+/// What a partition is for, which decides its GPT type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+	Efi,
+	/// Windows basic data, also what exFAT and FAT data partitions use
+	Data,
+	/// Linux filesystem, for the images of one
+	Linux,
+	Swap,
+}
+
+impl Kind {
+	fn gpt_type(self) -> &'static str {
+		match self {
+			Kind::Efi => "C12A7328-F81F-11D2-BA4B-00A0C93EC93B",
+			Kind::Data => "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7",
+			Kind::Linux => "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+			Kind::Swap => "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F",
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Spec {
+	/// Size in bytes, `None` takes the rest of the device
+	pub size: Option<u64>,
+	pub kind: Kind,
+	/// `None` leaves it unformatted, for an image to be written to
+	pub filesystem: Option<Filesystem>,
+	pub label: String,
+}
+
+impl Spec {
+	// MBR types only go by the filesystem, apart from the EFI system and Linux partitions
+	fn mbr_type(&self) -> &'static str {
+		match (self.kind, self.filesystem) {
+			(Kind::Efi, _) => "ef",
+			(Kind::Linux, _) => "83",
+			(Kind::Swap, _) | (_, Some(Filesystem::Swap)) => "82",
+			(_, Some(Filesystem::Ext4)) => "83",
+			(_, Some(Filesystem::Fat32)) => "c",
+			(_, Some(Filesystem::Exfat | Filesystem::Ntfs) | None) => "7",
+		}
+	}
+}
+
+/// Device node of partition `number` (from 1), `/dev/sdb1` but `/dev/mmcblk0p1`.
+pub fn partition_path(device: &str, number: usize) -> String {
+	if device.ends_with(|c: char| c.is_ascii_digit()) {
+		format!("{device}p{number}")
+	} else {
+		format!("{device}{number}")
+	}
+}
+
+/// Whether `path` is one of `device`'s numbered partitions, not /dev/sdab when looking at /dev/sda.
+pub fn is_partition_of(path: &str, device: &str) -> bool {
+	path.strip_prefix(device)
+		.is_some_and(|rest| !rest.is_empty() && rest.trim_start_matches('p').chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Unmounts everything mounted from `device` or its partitions, the desktop likes to automount sticks.
+///
+/// One that won't come off fails with what's keeping it busy, see [`busy::error`].
+pub fn unmount_all(device: &str) -> Result<(), String> {
+	for (source, mount) in mounted(device) {
+		if let Err(e) = unmount(&source) {
+			return Err(busy::error(&source, &mount, e.trim_start_matches("umount error: ")));
+		}
+	}
+
+	Ok(())
+}
+
+// The desktop's automounts belong to udisks, which lets the user at the seat unmount them without root
+fn unmount(partition: &str) -> Result<(), String> {
+	tools::run("umount", &[partition]).or_else(|e| {
+		tools::run("udisksctl", &["unmount", "--no-user-interaction", "--block-device", partition]).map_err(|_| e)
+	})?;
+
+	Ok(())
+}
+
+/// Erases the partition table and filesystem signatures on `device`, so nothing half written
+/// on it is taken for something that works.
+pub fn wipe(device: &str) -> Result<(), String> {
+	unmount_all(device)?;
+	tools::run("wipefs", &["--all", "--force", device])?;
+
+	reread(device)
+}
+
+/// What's mounted from `device` or its partitions, and where.
+pub fn mounted(device: &str) -> Vec<(String, String)> {
+	#[cfg(target_os = "linux")]
+	let unescape = devices::unescape;
+	#[cfg(not(target_os = "linux"))]
+	let unescape = str::to_string;
+
+	let mounts = std::fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+
+	mounts.lines()
+		.filter_map(|line| {
+			let mut fields = line.split_whitespace();
+			Some((fields.next()?, fields.next()?))
+		})
+		.filter(|(source, _)| *source == device || is_partition_of(source, device))
+		.map(|(source, target)| (source.to_string(), unescape(target)))
+		.collect()
+}
+
+/// Replaces the partition table of `device` with a fresh one holding `partitions`, then formats
+/// the ones with a filesystem.
+///
+/// Returns the device nodes of the new partitions, in order.
+pub fn create(device: &str, table: Table, partitions: &[Spec]) -> Result<Vec<String>, String> {
+	let paths = lay_out(device, table, partitions)?;
+
+	for (path, spec) in paths.iter().zip(partitions) {
+		if let Some(filesystem) = spec.filesystem {
+			format(path, filesystem, &spec.label)?;
+		}
+	}
+
+	Ok(paths)
+}
+
+/// Replaces the partition table of `device` like [`create`], leaving the partitions unformatted.
+pub fn lay_out(device: &str, table: Table, partitions: &[Spec]) -> Result<Vec<String>, String> {
+	unmount_all(device)?;
+
+	let mut script = String::from(match table {
+		Table::Gpt => "label: gpt\n",
+		Table::Mbr => "label: dos\n",
+	});
+
+	for (i, spec) in partitions.iter().enumerate() {
+		if let Some(size) = spec.size {
+			script += &format!("size={}KiB, ", size.div_ceil(1024));
+		}
+
+		script += &match table {
+			Table::Gpt => format!("type={}, name=\"{}\"\n", spec.kind.gpt_type(), spec.label.replace('"', "")),
+			// Some BIOSes won't boot a stick without an active partition
+			Table::Mbr if i == 0 => format!("type={}, bootable\n", spec.mbr_type()),
+			Table::Mbr => format!("type={}\n", spec.mbr_type()),
+		};
+	}
+
+	// Wiping gets rid of old signatures that would confuse blkid later
+	tools::run_with_input(
+		"sfdisk",
+		&["--wipe", "always", "--wipe-partitions", "always", device],
+		Some(&script),
+	)?;
+
+	let paths: Vec<String> = (1..=partitions.len()).map(|n| partition_path(device, n)).collect();
+	wait_for(&paths)?;
+
+	Ok(paths)
+}
+
+/// Puts a fresh `filesystem` on `partition` of `device`, or with `None` on the whole device in a
+/// new MBR partition table of its own, which the most systems read. `cluster` is in bytes, `None`
+/// leaves it to mkfs. Returns the partition it's on.
+pub fn make_filesystem(device: &str, partition: Option<&str>, filesystem: Filesystem, label: &str, cluster: Option<u32>) -> Result<String, String> {
+	let path = match partition {
+		Some(partition) => {
+			unmount_all(partition)?;
+			partition.to_string()
+		},
+		None => {
+			let kind = match filesystem {
+				Filesystem::Ext4 => Kind::Linux,
+				Filesystem::Swap => Kind::Swap,
+				_ => Kind::Data,
+			};
+
+			let spec = Spec { size: None, kind, filesystem: Some(filesystem), label: label.to_string() };
+			lay_out(device, Table::Mbr, &[spec])?.remove(0)
+		},
+	};
+
+	format_with(&path, filesystem, label, cluster)?;
+	Ok(path)
+}
+
+/// Adds a partition in the free space after the last one and formats it like [`create`], returning
+/// its device node.
+///
+/// For hybrid ISOs, which only take up the start of the stick.
+pub fn append(device: &str, spec: &Spec) -> Result<String, String> {
+	unmount_all(device)?;
+
+	let gpt = relocate_gpt(device)?;
+
+	let mut script = String::new();
+	if let Some(size) = spec.size {
+		script += &format!("size={}KiB, ", size.div_ceil(1024));
+	}
+
+	script += &if gpt {
+		format!("type={}, name=\"{}\"\n", spec.kind.gpt_type(), spec.label.replace('"', ""))
+	} else {
+		format!("type={}\n", spec.mbr_type())
+	};
+
+	tools::run_with_input("sfdisk", &["--append", device], Some(&script))?;
+
+	// The new partition is the one starting furthest into the device
+	let path = last_partition(device)?.ok_or("Partition error: the new partition didn't show up")?;
+
+	wait_for(std::slice::from_ref(&path))?;
+	if let Some(filesystem) = spec.filesystem {
+		format(&path, filesystem, &spec.label)?;
+	}
+
+	Ok(path)
+}
+
+// The partition starting furthest into `device`
+fn last_partition(device: &str) -> Result<Option<String>, String> {
+	let last = tools::run("sfdisk", &["--dump", device])?
+		.lines()
+		.filter_map(|line| {
+			let (path, fields) = line.split_once(" : ")?;
+			let start = fields.split(',').find_map(|field| field.trim().strip_prefix("start="))?;
+
+			Some((start.trim().parse::<u64>().ok()?, path.trim().to_string()))
+		})
+		.max()
+		.map(|(_, path)| path);
+
+	Ok(last)
+}
+
+/// Grows the last partition on `device` and its filesystem to the end of the device, for images
+/// made for a smaller card. Returns the partition's device node.
+pub fn expand_last(device: &str) -> Result<String, String> {
+	unmount_all(device)?;
+	relocate_gpt(device)?;
+
+	let path = last_partition(device)?.ok_or_else(|| format!("Expand error: {device} has no partitions to grow"))?;
+	let filesystem = tools::run("blkid", &["--match-tag", "TYPE", "--output", "value", &path]).unwrap_or_default();
+	let filesystem = filesystem.trim();
+
+	// Checked first, growing only the partition would hide the space rather than use it
+	if !can_resize(Some(filesystem).filter(|filesystem| !filesystem.is_empty())) {
+		return Err(format!("Expand error: {path} has {filesystem}, which can't be grown"));
+	}
+
+	// No size takes all the space after it
+	tools::run_with_input("sfdisk", &["-N", &number(device, &path)?.to_string(), device], Some(",+\n"))?;
+	wait_for(std::slice::from_ref(&path))?;
+
+	if !filesystem.is_empty() {
+		resize_filesystem(&path, filesystem, None)?;
+	}
+
+	Ok(path)
+}
+
+// A GPT written as part of an image has its backup header where the image ended, which
+// keeps partitions from going past it. Whether the device has a GPT
+fn relocate_gpt(device: &str) -> Result<bool, String> {
+	let table = tools::run("sfdisk", &["--dump", device])?;
+	let gpt = table.lines().any(|line| line.trim() == "label: gpt");
+
+	if gpt {
+		tools::run("sfdisk", &["--relocate", "gpt-bak-std", device])?;
+	}
+
+	Ok(gpt)
+}
+
+/// A change to a device's partition table made after it was written, see [`edit`].
+#[derive(Debug, Clone)]
+pub enum Edit {
+	/// The partition at `path` goes, and everything on it
+	Delete(String),
+	/// The partition at `path` and its filesystem, as blkid calls it, go from `from` to `to` bytes
+	Resize { path: String, filesystem: Option<String>, from: u64, to: u64 },
+	/// A new partition in the free space after the last one, see [`append`]
+	Add(Spec),
+}
+
+impl std::fmt::Display for Edit {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Edit::Delete(path) => write!(f, "Delete {path}"),
+			Edit::Resize { path, from, to, .. } => write!(f, "Resize {path} from {} to {}", units::size(*from), units::size(*to)),
+			Edit::Add(spec) => {
+				let size = spec.size.map_or("the rest of the device".to_string(), units::size);
+				match spec.filesystem {
+					Some(filesystem) => write!(f, "Add a {filesystem} partition \"{}\" taking {size}", spec.label),
+					None => write!(f, "Add an empty partition \"{}\" taking {size}", spec.label),
+				}
+			},
+		}
+	}
+}
+
+/// Makes the change `edit` to `device`'s partition table.
+pub fn edit(device: &str, edit: &Edit) -> Result<(), String> {
+	match edit {
+		Edit::Delete(path) => {
+			unmount_all(device)?;
+			tools::run("sfdisk", &["--delete", device, &number(device, path)?.to_string()])?;
+		},
+		Edit::Resize { path, filesystem, from, to } => {
+			let number = number(device, path)?.to_string();
+			let filesystem = filesystem.as_deref();
+
+			if !can_resize(filesystem) {
+				return Err(format!("Resize error: {} filesystems can't be resized", filesystem.unwrap_or_default()));
+			}
+
+			unmount_all(device)?;
+			relocate_gpt(device)?;
+			let script = format!("size={}KiB\n", to.div_ceil(1024));
+
+			// The filesystem can't end past its partition, so it shrinks first and grows last. Swap
+			// is made again instead, once the partition has its new size either way.
+			let swap = filesystem == Some("swap");
+			if to < from && !swap && let Some(filesystem) = filesystem {
+				resize_filesystem(path, filesystem, Some(*to))?;
+			}
+
+			tools::run_with_input("sfdisk", &["-N", &number, device], Some(&script))?;
+			wait_for(std::slice::from_ref(path))?;
+
+			if (to > from || swap) && let Some(filesystem) = filesystem {
+				resize_filesystem(path, filesystem, Some(*to))?;
+			}
+		},
+		Edit::Add(spec) => {
+			append(device, spec)?;
+		},
+	}
+
+	let _ = tools::run("udevadm", &["settle"]);
+	Ok(())
+}
+
+// Number of partition `path` on `device`, `/dev/mmcblk0p2` is 2
+fn number(device: &str, path: &str) -> Result<usize, String> {
+	path.strip_prefix(device)
+		.filter(|_| is_partition_of(path, device))
+		.and_then(|rest| rest.trim_start_matches('p').parse().ok())
+		.ok_or_else(|| format!("Partition error: {path} isn't a partition of {device}"))
+}
+
+/// Whether a filesystem, as blkid calls it, can be resized along with its partition. `None` is
+/// an empty partition, which can always.
+pub fn can_resize(filesystem: Option<&str>) -> bool {
+	matches!(filesystem, None | Some("ext2" | "ext3" | "ext4" | "vfat" | "ntfs" | "swap"))
+}
+
+/// Grows or shrinks the filesystem on `path`, as blkid calls it, to `size` bytes, `None` fills
+/// the partition.
+pub fn resize_filesystem(path: &str, filesystem: &str, size: Option<u64>) -> Result<(), String> {
+	let kib = size.map(|size| format!("{}k", size / 1024));
+
+	match filesystem {
+		"ext2" | "ext3" | "ext4" => {
+			// Fixing anything counts as failing, resize2fs refuses it when it's really broken
+			let _ = tools::run("e2fsck", &["-f", "-p", path]);
+			let size = kib.map(|kib| kib.to_uppercase());
+			tools::run("resize2fs", &[path].into_iter().chain(size.as_deref()).collect::<Vec<_>>())?;
+		},
+		"vfat" => {
+			tools::run("fatresize", &["--size", kib.as_deref().unwrap_or("max"), path])?;
+		},
+		// It asks whether to go ahead even when forced
+		"ntfs" => {
+			let mut args = vec!["--force", path];
+			if let Some(kib) = &kib {
+				args.extend(["--size", kib]);
+			}
+			tools::run_with_input("ntfsresize", &args, Some("y\n"))?;
+		},
+		// Nothing to keep, it's just made again
+		"swap" => {
+			let label = tools::run("blkid", &["--match-tag", "LABEL", "--output", "value", path]).unwrap_or_default();
+			let blocks = size.map(|size| (size / 1024).to_string());
+			let mut args = vec![path];
+			if !label.trim().is_empty() {
+				args.extend(["-L", label.trim()]);
+			}
+			// In KiB blocks, after the device
+			args.extend(blocks.as_deref());
+			tools::run("mkswap", &args)?;
+		},
+		other => return Err(format!("Resize error: {other} filesystems can't be resized")),
+	}
+
+	Ok(())
+}
+
+/// Has the kernel pick up the partition table an image brought along.
+pub fn reread(device: &str) -> Result<(), String> {
+	tools::run("blockdev", &["--rereadpt", device])?;
+	let _ = tools::run("udevadm", &["settle"]);
+
+	Ok(())
+}
+
+/// A partition in a device's table, as written.
+#[derive(Debug, Clone)]
+pub struct Entry {
+	pub path: String,
+	/// Size in bytes
+	pub size: u64,
+	/// What the partition type is called, or its code when it isn't a common one
+	pub kind: String,
+	/// Active on MBR, legacy BIOS bootable on GPT
+	pub bootable: bool,
+	/// Filesystem label, or the GPT name without one
+	pub label: Option<String>,
+	pub filesystem: Option<String>,
+}
+
+/// Reads `device`'s partition table, with what udev found on each partition.
+pub fn read_table(device: &str) -> Result<Vec<Entry>, String> {
+	let dump = tools::run("sfdisk", &["--dump", device])?;
+	let sector_size = dump.lines()
+		.find_map(|line| line.strip_prefix("sector-size:"))
+		.and_then(|size| size.trim().parse().ok())
+		.unwrap_or(512u64);
+
+	let found = devices::list().into_iter()
+		.find(|current| current.path == device)
+		.map(|current| current.partitions)
+		.unwrap_or_default();
+
+	let entries = dump.lines()
+		.filter_map(|line| {
+			let (path, fields) = line.split_once(" : ")?;
+			let path = path.trim().to_string();
+			let fields: Vec<&str> = fields.split(',').map(str::trim).collect();
+			let field = |name: &str| fields.iter().find_map(|field| field.strip_prefix(name)?.strip_prefix('='));
+
+			let size = field("size")?.trim().parse::<u64>().ok()? * sector_size;
+			let partition = found.iter().find(|partition| partition.path == path);
+			let name = field("name").map(|name| name.trim_matches('"').to_string()).filter(|name| !name.is_empty());
+
+			Some(Entry {
+				size,
+				kind: type_name(field("type").unwrap_or_default()),
+				bootable: fields.contains(&"bootable") || field("attrs").is_some_and(|attrs| attrs.contains("LegacyBIOSBootable")),
+				label: partition.and_then(|partition| partition.label.clone()).or(name),
+				filesystem: partition.and_then(|partition| partition.filesystem.clone()),
+				path,
+			})
+		})
+		.collect();
+
+	Ok(entries)
+}
+
+// fdisk's names for the types images usually bring along
+fn type_name(code: &str) -> String {
+	let name = match code.to_ascii_uppercase().as_str() {
+		"0" => "Empty",
+		"B" | "C" => "FAT32",
+		"E" => "FAT16",
+		"7" => "NTFS/exFAT",
+		"17" => "Hidden NTFS",
+		"82" => "Linux swap",
+		"83" => "Linux",
+		"EE" => "GPT protective",
+		"EF" | "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => "EFI System",
+		"EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => "Microsoft basic data",
+		"E3C9E316-0B5C-4DB8-817D-F92DF00215AE" => "Microsoft reserved",
+		"DE94BBA4-06D1-4D40-A16A-BFD50179D6AC" => "Windows recovery",
+		"21686148-6449-6E6F-744E-656564454649" => "BIOS boot",
+		"0FC63DAF-8483-4772-8E79-3D69D8477DE4" => "Linux filesystem",
+		"4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709" => "Linux root (x86-64)",
+		"B921B045-1DF0-41C3-AF44-4C6F280D3FAE" => "Linux root (ARM64)",
+		"0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => "Linux swap",
+		_ => return format!("Type {code}"),
+	};
+
+	name.to_string()
+}
+
+/// Makes a new `filesystem` on `path`.
+pub fn format(path: &str, filesystem: Filesystem, label: &str) -> Result<(), String> {
+	format_with(path, filesystem, label, None)
+}
+
+/// Makes a new `filesystem` on `path` with clusters of `cluster` bytes, `None` lets mkfs pick.
+pub fn format_with(path: &str, filesystem: Filesystem, label: &str, cluster: Option<u32>) -> Result<(), String> {
+	let fat = fat_label(label);
+	let (program, mut args, flag) = match filesystem {
+		Filesystem::Fat32 => ("mkfs.fat", vec!["-F", "32", "-n", fat.as_str()], "-s"),
+		Filesystem::Exfat => ("mkfs.exfat", vec!["-L", label], "-c"),
+		Filesystem::Ntfs => ("mkfs.ntfs", vec!["--quick", "--label", label], "-c"),
+		Filesystem::Ext4 => ("mkfs.ext4", vec!["-F", "-L", label], "-b"),
+		Filesystem::Swap => ("mkswap", vec!["-L", label], ""),
+	};
+
+	// mkfs.fat counts clusters in sectors
+	let cluster = cluster.map(|bytes| match filesystem {
+		Filesystem::Fat32 => (bytes / 512).to_string(),
+		_ => bytes.to_string(),
+	});
+
+	if let Some(cluster) = &cluster && !flag.is_empty() {
+		args.extend([flag, cluster]);
+	}
+
+	args.push(path);
+	tools::run(program, &args).map(|_| ())
+}
+
+/// Parses a partition size like "256M" or "4 GiB", always in binary units, a plain number is bytes.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+	let size = size.trim();
+	let split = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+	let (number, unit) = size.split_at(split);
+
+	let shift = match unit.trim().to_ascii_lowercase().as_str() {
+		"" | "b" => 0,
+		"k" | "kib" => 10,
+		"m" | "mib" => 20,
+		"g" | "gib" => 30,
+		"t" | "tib" => 40,
+		_ => return Err(format!("{size} isn't a size like 256M or 4G")),
+	};
+
+	number.parse::<u64>()
+		.ok()
+		.and_then(|number| number.checked_mul(1 << shift))
+		.filter(|&bytes| bytes > 0)
+		.ok_or_else(|| format!("{size} isn't a size like 256M or 4G"))
+}
+
+/// Checks that `label` fits what `filesystem` allows, so mkfs doesn't quietly mangle or reject it.
+pub fn check_label(filesystem: Filesystem, label: &str) -> Result<(), String> {
+	// Lengths are in UTF-16 units for exFAT and NTFS, FAT only takes bytes of an OEM code page
+	let (name, max, forbidden) = match filesystem {
+		Filesystem::Fat32 => ("FAT32", 11, "\"*+,./:;<=>?[\\]|"),
+		Filesystem::Exfat => ("exFAT", 11, "\"*/:<>?\\|"),
+		Filesystem::Ntfs => ("NTFS", 32, ""),
+		Filesystem::Ext4 => ("ext4", 16, ""),
+		Filesystem::Swap => ("Swap", 16, ""),
+	};
+
+	if label.trim().is_empty() {
+		return Err("Labels can't be empty".into());
+	}
+
+	if filesystem == Filesystem::Fat32 && !label.is_ascii() {
+		return Err(format!("{name} labels can only use plain ASCII characters"));
+	}
+
+	if label.encode_utf16().count() > max {
+		return Err(format!("{name} labels can be at most {max} characters"));
+	}
+
+	if let Some(c) = label.chars().find(|c| c.is_control() || forbidden.contains(*c)) {
+		return Err(format!("{name} labels can't contain {c:?}"));
+	}
+
+	Ok(())
+}
+
+/// The label a FAT filesystem really gets, at most 11 characters and conventionally upper case.
+pub fn fat_label(label: &str) -> String {
+	label.to_uppercase().chars().take(11).collect()
+}
+
+// The kernel and udev take a moment to create the nodes after the table changes
+fn wait_for(paths: &[String]) -> Result<(), String> {
+	let _ = tools::run("udevadm", &["settle"]);
+
+	for _ in 0..50 {
+		if paths.iter().all(|path| std::path::Path::new(path).exists()) {
+			return Ok(());
+		}
+
+		std::thread::sleep(Duration::from_millis(100));
+	}
+
+	Err(format!("Partition error: {} did not show up", paths.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::tools::FakeTools;
+	use crate::testing::TempDir;
+
+	// What sfdisk --dump says about a GPT stick, its partitions not in the order they're on it
+	fn dump(device: &str) -> String {
+		format!("label: gpt\nlabel-id: 6E2A5C1B-3F0D-4C8E-9A61-0B7D2E4F8C13\ndevice: {device}\nunit: sectors\n\
+			first-lba: 34\nlast-lba: 31266782\nsector-size: 512\n\n\
+			{device}1 : start=        2048, size=     1048576, type=C12A7328-F81F-11D2-BA4B-00A0C93EC93B, uuid=1D2C3B4A-0000-4000-8000-000000000001, name=\"EFI System\", attrs=\"LegacyBIOSBootable\"\n\
+			{device}3 : start=     5244928, size=     4194304, type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, uuid=1D2C3B4A-0000-4000-8000-000000000003\n\
+			{device}2 : start=     1050624, size=     4194304, type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, uuid=1D2C3B4A-0000-4000-8000-000000000002, name=\"root\"\n")
+	}
+
+	// A device that's a directory of files, so its partitions show up when they're waited for
+	fn device(dir: &TempDir) -> String {
+		let device = dir.file("sdb");
+		for number in 1..=3 {
+			std::fs::write(partition_path(&device, number), "").unwrap();
+		}
+
+		device
+	}
+
+	#[test]
+	fn sizes_and_partitions_are_read_as_theyre_written() {
+		assert_eq!(parse_size("256M"), Ok(256 << 20));
+		assert_eq!(parse_size(" 4 GiB "), Ok(4 << 30));
+		assert_eq!(parse_size("512"), Ok(512));
+		assert!(parse_size("0").is_err());
+		assert!(parse_size("2X").is_err());
+		assert!(parse_size("G").is_err());
+		assert!(parse_size("99999999T").is_err());
+
+		assert_eq!(number("/dev/sdb", "/dev/sdb2"), Ok(2));
+		assert_eq!(number("/dev/mmcblk0", "/dev/mmcblk0p12"), Ok(12));
+		assert!(number("/dev/sda", "/dev/sdab1").is_err());
+		assert!(number("/dev/sda", "/dev/sda").is_err());
+	}
+
+	#[test]
+	fn filesystems_are_made_with_the_label_and_clusters_mkfs_takes() {
+		let tools = FakeTools::new();
+
+		make_filesystem("/dev/sdb", Some("/dev/sdb2"), Filesystem::Fat32, "Backups 2024", Some(32 << 10)).unwrap();
+		make_filesystem("/dev/sdb", Some("/dev/sdb2"), Filesystem::Exfat, "Backups 2024", Some(128 << 10)).unwrap();
+		make_filesystem("/dev/sdb", Some("/dev/sdb2"), Filesystem::Swap, "swap", Some(4096)).unwrap();
+
+		// mkfs.fat counts clusters in sectors, and swap has none
+		assert_eq!(tools.calls(), [
+			"mkfs.fat -F 32 -n BACKUPS 202 -s 64 /dev/sdb2",
+			"mkfs.exfat -L Backups 2024 -c 131072 /dev/sdb2",
+			"mkswap -L swap /dev/sdb2",
+		]);
+	}
+
+	#[test]
+	fn the_table_comes_from_sfdisk() {
+		let dir = TempDir::new("partition");
+		let device = dir.file("sdb");
+		let tools = FakeTools::new();
+		tools.answer("sfdisk", &dump(&device));
+
+		let table = read_table(&device).unwrap();
+		assert_eq!(table.iter().map(|entry| entry.path.as_str()).collect::<Vec<_>>(), [format!("{device}1"), format!("{device}3"), format!("{device}2")]);
+		assert_eq!(table.iter().map(|entry| entry.size).collect::<Vec<_>>(), [512 << 20, 2 << 30, 2 << 30]);
+		assert_eq!(table[0].kind, "EFI System");
+		assert!(table[0].bootable && !table[1].bootable);
+		assert_eq!(table.iter().map(|entry| entry.label.as_deref()).collect::<Vec<_>>(), [Some("EFI System"), None, Some("root")]);
+
+		// Furthest into the device, not last in the list
+		assert_eq!(last_partition(&device), Ok(Some(format!("{device}3"))));
+
+		tools.answer("sfdisk", "label: dos\n");
+		assert!(read_table(&device).unwrap().is_empty());
+		assert_eq!(last_partition(&device), Ok(None));
+	}
+
+	#[test]
+	fn a_filesystem_never_ends_past_its_partition() {
+		let dir = TempDir::new("partition");
+		let device = device(&dir);
+		let tools = FakeTools::new();
+		tools.answer("sfdisk", &dump(&device));
+
+		let resize = |from, to| Edit::Resize { path: format!("{device}2"), filesystem: Some("ext4".into()), from, to };
+		// Which of the partition and the filesystem went first, by their place among the calls
+		let order = |calls: Vec<String>| -> (usize, usize) {
+			let table = calls.iter().position(|call| call.starts_with("sfdisk -N 2")).unwrap();
+			let filesystem = calls.iter().position(|call| call.starts_with("resize2fs")).unwrap();
+			(table, filesystem)
+		};
+
+		edit(&device, &resize(2 << 30, 1 << 30)).unwrap();
+		let calls = tools.calls();
+		assert!(calls.contains(&format!("sfdisk -N 2 {device} < size=1048576KiB")));
+		assert!(calls.contains(&format!("resize2fs {device}2 1048576K")));
+		let (table, filesystem) = order(calls);
+		assert!(filesystem < table);
+
+		let grown = tools.calls().len();
+		edit(&device, &resize(2 << 30, 3 << 30)).unwrap();
+		let (table, filesystem) = order(tools.calls().split_off(grown));
+		assert!(table < filesystem);
+
+		// Swap is made again after the partition shrinks, at the size it shrinks to
+		let before = tools.calls().len();
+		let swap = Edit::Resize { path: format!("{device}2"), filesystem: Some("swap".into()), from: 2 << 30, to: 1 << 30 };
+		edit(&device, &swap).unwrap();
+		let calls = tools.calls().split_off(before);
+		let table = calls.iter().position(|call| call.starts_with("sfdisk -N 2")).unwrap();
+		let mkswap = calls.iter().position(|call| *call == format!("mkswap {device}2 1048576")).unwrap();
+		assert!(table < mkswap);
+
+		// Nothing is touched for a filesystem that can't follow
+		let before = tools.calls().len();
+		let exfat = Edit::Resize { path: format!("{device}2"), filesystem: Some("exfat".into()), from: 2 << 30, to: 1 << 30 };
+		assert!(edit(&device, &exfat).is_err());
+		assert_eq!(tools.calls().len(), before);
+
+		// Or when the files don't fit in less
+		tools.fail("resize2fs", "resize2fs error: No space left on device");
+		let before = tools.calls().len();
+		assert!(edit(&device, &resize(2 << 30, 1 << 30)).is_err());
+		assert!(!tools.calls()[before..].iter().any(|call| call.starts_with("sfdisk -N")));
+	}
+
+	#[test]
+	fn the_last_partition_grows_with_its_filesystem() {
+		let dir = TempDir::new("partition");
+		let device = device(&dir);
+		let tools = FakeTools::new();
+		tools.answer("sfdisk", &dump(&device));
+		tools.answer("blkid", "ext4\n");
+
+		assert_eq!(expand_last(&device), Ok(format!("{device}3")));
+		let calls = tools.calls();
+		assert!(calls.contains(&format!("sfdisk --relocate gpt-bak-std {device}")));
+		let table = calls.iter().position(|call| *call == format!("sfdisk -N 3 {device} < ,+")).unwrap();
+		let filesystem = calls.iter().position(|call| *call == format!("resize2fs {device}3")).unwrap();
+		assert!(table < filesystem);
+
+		// Growing the partition alone would only hide the space
+		tools.answer("blkid", "exfat\n");
+		let before = tools.calls().len();
+		assert!(expand_last(&device).is_err_and(|e| e.contains("can't be grown")));
+		assert!(!tools.calls()[before..].iter().any(|call| call.starts_with("sfdisk -N")));
+
+		// An empty one just grows
+		tools.answer("blkid", "");
+		let before = tools.calls().len();
+		expand_last(&device).unwrap();
+		assert!(!tools.calls()[before..].iter().any(|call| call.starts_with("resize2fs")));
+
+		tools.answer("sfdisk", "label: dos\n");
+		assert!(expand_last(&device).is_err_and(|e| e.contains("no partitions")));
+	}
+}
@@ -0,0 +1,311 @@
+// Raspberry Pi OS customization, applied on the boot partition after the image is written.
+//
+// Works like Raspberry Pi Imager's OS customisation: an empty `ssh` file turns on SSH,
+// `userconf.txt` sets up the user, and a `firstrun.sh` that cmdline.txt runs once on the
+// first boot sets the hostname and Wi-Fi, then removes itself.
+
+use std::fs;
+use std::path::Path;
+
+use sha2::{Digest, Sha512};
+
+// cmdline.txt gets this, the script takes it out again
+const RUN_FIRSTRUN: &str = " systemd.run=/boot/firstrun.sh systemd.run_success_action=reboot systemd.unit=kernel-command-line.target";
+
+/// What to set up on the first boot, empty fields are left as the image has them.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+	pub hostname: String,
+	pub ssh: bool,
+	pub user: String,
+	pub password: String,
+	pub ssid: String,
+	/// Empty for an open network
+	pub wifi_password: String,
+	/// Two letter country code, the Wi-Fi stays blocked without one
+	pub country: String,
+}
+
+impl Settings {
+	pub fn is_empty(&self) -> bool {
+		self.hostname.is_empty() && !self.ssh && self.user.is_empty() && self.ssid.is_empty()
+	}
+
+	/// Checks the settings before anything gets written.
+	pub fn check(&self) -> Result<(), String> {
+		let hostname = &self.hostname;
+		if !hostname.is_empty()
+			&& (hostname.len() > 63
+				|| hostname.starts_with('-')
+				|| hostname.ends_with('-')
+				|| !hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+		{
+			return Err("The hostname can only have letters, digits and hyphens, up to 63, and can't start or end with a hyphen".into());
+		}
+
+		let user = &self.user;
+		if !user.is_empty() {
+			if user.len() > 32
+				|| !user.starts_with(|c: char| c.is_ascii_lowercase())
+				|| !user.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+			{
+				return Err("The user name has to start with a lower case letter and can only have lower case letters, digits, - and _".into());
+			}
+
+			if user == "root" {
+				return Err("The user can't be root".into());
+			}
+
+			if self.password.is_empty() {
+				return Err("The user needs a password".into());
+			}
+		}
+
+		if !self.ssid.is_empty() {
+			if self.ssid.len() > 32 {
+				return Err("Wi-Fi network names are at most 32 bytes".into());
+			}
+
+			// wpa_supplicant.conf can't hold either inside its quotes, and a line could close the heredoc
+			let unquotable = |value: &str| value.chars().any(|c| c == '"' || c.is_control());
+			if unquotable(&self.ssid) || unquotable(&self.wifi_password) {
+				return Err("Wi-Fi network names and passwords can't have double quotes or line breaks in them".into());
+			}
+
+			let length = self.wifi_password.len();
+			if length != 0 && !(8..=63).contains(&length) {
+				return Err("Wi-Fi passwords are 8 to 63 characters".into());
+			}
+
+			if self.country.len() != 2 || !self.country.chars().all(|c| c.is_ascii_alphabetic()) {
+				return Err("Pick the Wi-Fi country as a two letter code, e.g. GB or US".into());
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Applies the settings to the boot partition mounted at `root`.
+	pub fn apply(&self, root: &Path) -> Result<(), String> {
+		let cmdline = root.join("cmdline.txt");
+		let kernel_line = fs::read_to_string(&cmdline)
+			.map_err(|_| "The image has no Raspberry Pi boot partition (with a cmdline.txt) to customize".to_string())?;
+
+		if self.ssh {
+			write(&root.join("ssh"), "")?;
+		}
+
+		if !self.user.is_empty() {
+			write(&root.join("userconf.txt"), &format!("{}:{}\n", self.user, crypt(&self.password, &salt())))?;
+		}
+
+		let script = self.firstrun();
+		if script.is_empty() {
+			return Ok(());
+		}
+
+		write(&root.join("firstrun.sh"), &script)?;
+
+		// One line, the script only runs if it's on the same one
+		if !kernel_line.contains("systemd.run=") {
+			write(&cmdline, &format!("{}{RUN_FIRSTRUN}\n", kernel_line.trim_end()))?;
+		}
+
+		Ok(())
+	}
+
+	// Newer images have imager_custom to do it properly, older ones get the files edited directly
+	fn firstrun(&self) -> String {
+		let mut steps = Vec::new();
+
+		if !self.hostname.is_empty() {
+			steps.push(format!(
+				"\
+CURRENT_HOSTNAME=$(cat /etc/hostname | tr -d \" \\t\\n\\r\")
+if [ -f /usr/lib/raspberrypi-sys-mods/imager_custom ]; then
+   /usr/lib/raspberrypi-sys-mods/imager_custom set_hostname {hostname}
+else
+   echo {hostname} >/etc/hostname
+   sed -i \"s/127.0.1.1.*$CURRENT_HOSTNAME/127.0.1.1\\t{hostname}/g\" /etc/hosts
+fi
+",
+				hostname = self.hostname,
+			));
+		}
+
+		if !self.ssid.is_empty() {
+			let country = self.country.to_uppercase();
+			let psk = if self.wifi_password.is_empty() {
+				"\tkey_mgmt=NONE".to_string()
+			} else {
+				format!("\tpsk=\"{}\"", self.wifi_password)
+			};
+
+			steps.push(format!(
+				"\
+if [ -f /usr/lib/raspberrypi-sys-mods/imager_custom ]; then
+   /usr/lib/raspberrypi-sys-mods/imager_custom set_wlan {ssid} {password} {country}
+else
+cat >/etc/wpa_supplicant/wpa_supplicant.conf <<'WPAEOF'
+country={country}
+ctrl_interface=DIR=/var/run/wpa_supplicant GROUP=netdev
+ap_scan=1
+
+update_config=1
+network={{
+\tssid=\"{ssid_conf}\"
+{psk}
+}}
+WPAEOF
+   chmod 600 /etc/wpa_supplicant/wpa_supplicant.conf
+   rfkill unblock wifi
+   for filename in /var/lib/systemd/rfkill/*:wlan ; do
+       echo 0 > $filename
+   done
+fi
+",
+				ssid = quote(&self.ssid),
+				password = quote(&self.wifi_password),
+				ssid_conf = self.ssid,
+			));
+		}
+
+		if steps.is_empty() {
+			return String::new();
+		}
+
+		format!(
+			"\
+#!/bin/bash
+
+set +e
+
+{}
+rm -f /boot/firstrun.sh /boot/firmware/firstrun.sh
+sed -i 's| systemd.run.*||g' /boot/cmdline.txt /boot/firmware/cmdline.txt 2>/dev/null
+exit 0
+",
+			steps.join("\n"),
+		)
+	}
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), String> {
+	fs::write(path, contents).map_err(|e| format!("Customize error: {}: {e}", path.display()))
+}
+
+// Single quoted for the shell, with any single quotes in it closed and escaped
+fn quote(value: &str) -> String {
+	format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+fn salt() -> String {
+	let mut random = [0u8; 16];
+
+	// Only chpasswd ever sees it, the clock is a good enough fallback
+	if fs::File::open("/dev/urandom").and_then(|mut file| std::io::Read::read_exact(&mut file, &mut random)).is_err() {
+		let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+		random = nanos.to_le_bytes();
+	}
+
+	random.iter().map(|byte| ITOA64[(byte & 0x3f) as usize] as char).collect()
+}
+
+/// The `$6$` SHA-512 crypt of `password` that /etc/shadow has, with the default 5000 rounds.
+fn crypt(password: &str, salt: &str) -> String {
+	let password = password.as_bytes();
+	let salt = &salt.as_bytes()[..salt.len().min(16)];
+
+	let repeated = |digest: &[u8], len: usize| -> Vec<u8> { digest.iter().copied().cycle().take(len).collect() };
+
+	let b = Sha512::new().chain_update(password).chain_update(salt).chain_update(password).finalize();
+
+	let mut a = Sha512::new().chain_update(password).chain_update(salt).chain_update(repeated(&b, password.len()));
+	let mut length = password.len();
+	while length > 0 {
+		if length & 1 == 1 {
+			a.update(b);
+		} else {
+			a.update(password);
+		}
+		length >>= 1;
+	}
+	let a = a.finalize();
+
+	let mut dp = Sha512::new();
+	for _ in 0..password.len() {
+		dp.update(password);
+	}
+	let p = repeated(&dp.finalize(), password.len());
+
+	let mut ds = Sha512::new();
+	for _ in 0..16 + a[0] as usize {
+		ds.update(salt);
+	}
+	let s = repeated(&ds.finalize(), salt.len());
+
+	let mut c = a;
+	for round in 0..5000 {
+		let mut hasher = Sha512::new();
+
+		if round % 2 == 1 { hasher.update(&p) } else { hasher.update(c) }
+		if round % 3 != 0 { hasher.update(&s) }
+		if round % 7 != 0 { hasher.update(&p) }
+		if round % 2 == 1 { hasher.update(c) } else { hasher.update(&p) }
+
+		c = hasher.finalize();
+	}
+
+	// Three bytes to four characters at a time, in crypt's own shuffled order
+	let mut encoded = String::new();
+	let mut push = |bytes: u32, count: usize| {
+		let mut bytes = bytes;
+		for _ in 0..count {
+			encoded.push(ITOA64[(bytes & 0x3f) as usize] as char);
+			bytes >>= 6;
+		}
+	};
+
+	for i in 0..21 {
+		let first = i * 22 % 63;
+		push((c[first] as u32) << 16 | (c[(first + 21) % 63] as u32) << 8 | c[(first + 42) % 63] as u32, 4);
+	}
+	push(c[63] as u32, 2);
+
+	format!("$6${}${encoded}", String::from_utf8_lossy(salt))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn passwords_hash_like_openssl_passwd() {
+		// openssl passwd -6 -salt saltsaltsaltsalt raspberry
+		assert_eq!(
+			crypt("raspberry", "saltsaltsaltsalt"),
+			"$6$saltsaltsaltsalt$00of8Y8ZpAGaxosd4pu0Ff19hH8rucpwfCxof/ELn604.90zo6K8TACeDQP6Ttrc5F/ngjq/a4JzEcD.BmaGf1",
+		);
+		assert_eq!(
+			crypt("hunter22 pw", "abc"),
+			"$6$abc$J.Nx0Y70D8rWJ6YSH3h9y6FVmRgA7FjO/3NGhGfTVn7LQdjm9el/Fc3uwHhJd1l5dCZmbGDVb3KTsIrKZEvvr/",
+		);
+	}
+
+	#[test]
+	fn wifi_settings_that_would_break_the_config_are_refused() {
+		let wifi = |ssid: &str, password: &str| Settings {
+			ssid: ssid.into(),
+			wifi_password: password.into(),
+			country: "GB".into(),
+			..Settings::default()
+		};
+
+		assert!(wifi("Field Kit", "it's a secret").check().is_ok());
+		assert!(wifi("Field \"Kit\"", "password").check().is_err());
+		assert!(wifi("Field Kit", "pass\"word").check().is_err());
+		assert!(wifi("Field Kit", "password\nWPAEOF\n").check().is_err());
+	}
+}
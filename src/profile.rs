@@ -0,0 +1,48 @@
+// Saved job setups, re-run in one click from the GUI or with `--profile` on the command line.
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::Mode;
+use crate::customize::{Automation, Customizations};
+use crate::hash::Algorithm;
+
+/// Everything about a job but the device, which is picked when it runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+	pub name: String,
+	/// Path or URL of the image
+	pub source: String,
+	pub mode: Mode,
+	/// Read the device back and compare with this, `None` skips it
+	pub verify: Option<Algorithm>,
+	/// Published checksum of the image, empty for none
+	pub checksum: String,
+	pub algorithm: Algorithm,
+	pub hybridize: bool,
+	/// File-copy mode's partition label, empty keeps the ISO's
+	pub label: String,
+	pub answer_file: Option<String>,
+	pub automation: Option<(Automation, String)>,
+	/// Files and folders copied onto the stick after the write
+	pub payload: Vec<String>,
+	/// Label of the exFAT data partition to add
+	pub data_partition: Option<String>,
+	/// Grow the last partition to the end of the device
+	pub expand: bool,
+	/// Script run once on the first boot
+	pub first_boot: Option<String>,
+}
+
+impl Profile {
+	/// What's done to the device after an image mode write.
+	pub fn customizations(&self) -> Customizations {
+		Customizations {
+			payload: self.payload.iter().map(Into::into).collect(),
+			data_partition: self.data_partition.clone(),
+			expand: self.expand,
+			first_boot: self.first_boot.as_ref().map(Into::into),
+			..Customizations::default()
+		}
+	}
+}
@@ -0,0 +1,303 @@
+// Image sources beyond files and http(s) URLs, so an organization's own image server can be
+// written from without changing iso_maker.
+//
+// A provider handles the URLs of one scheme, `<scheme>://<name>`: it lists the images it has,
+// opens one to be streamed onto the device, and can say what an image's checksum should be,
+// which the write then checks like one given with the job. Providers are registered when the
+// app starts. smb:// and nfs:// come built in (see share.rs), and so do s3:// (s3.rs) and
+// sftp:// (sftp.rs); others come from code or from the config, whose `[[providers]]` each
+// name a `scheme` and the `command` that does the work:
+//
+//     <command> list           an image a line, `<name>\t<size>\t<algorithm>:<digest>`, with
+//                              `-` for a size or checksum it doesn't know
+//     <command> fetch <name>   the image on stdout, exiting with 0 once it's all there
+
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::hash::Algorithm;
+use crate::s3;
+use crate::sftp;
+use crate::share;
+use crate::tools;
+
+static PROVIDERS: RwLock<Vec<Arc<dyn Provider>>> = RwLock::new(Vec::new());
+
+/// An image a provider has.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+	pub name: String,
+	pub size: Option<u64>,
+	pub checksum: Option<(Algorithm, String)>,
+}
+
+/// A source of images for the URLs of one scheme.
+pub trait Provider: Send + Sync {
+	/// The scheme it handles, `corp` for `corp://...`
+	fn scheme(&self) -> &str;
+
+	/// The images it has. This may go to the network, keep it off the UI thread.
+	fn list(&self) -> Result<Vec<Image>, String>;
+
+	/// Opens `name` for reading, with its size in bytes, 0 when it isn't known.
+	fn open(&self, name: &str) -> Result<(Box<dyn Read + Send>, u64), String>;
+
+	/// What `name`'s checksum should be, when the provider publishes one.
+	fn checksum(&self, name: &str) -> Result<Option<(Algorithm, String)>, String> {
+		Ok(self.list()?.into_iter().find(|image| image.name == name).and_then(|image| image.checksum))
+	}
+
+	/// Whether its checksums are signed, so they're checked even when the job gives its own,
+	/// which then has to agree.
+	fn signed(&self) -> bool {
+		false
+	}
+}
+
+/// A provider that runs a command, set up in the config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct External {
+	pub scheme: String,
+	pub command: String,
+}
+
+/// Adds `provider`, in place of any that already has its scheme.
+pub fn register(provider: Arc<dyn Provider>) {
+	if let Ok(mut providers) = PROVIDERS.write() {
+		providers.retain(|existing| existing.scheme() != provider.scheme());
+		providers.push(provider);
+	}
+}
+
+/// Registers the providers that come with iso_maker, then the ones in `config`, which can
+/// take their schemes over.
+pub fn register_configured(config: &Config) {
+	register(Arc::new(share::Smb));
+	register(Arc::new(share::Nfs));
+	register(Arc::new(s3::S3));
+	register(Arc::new(sftp::Sftp));
+
+	for catalog in &config.catalogs {
+		register(Arc::new(catalog.clone()));
+	}
+
+	for external in &config.providers {
+		register(Arc::new(external.clone()));
+	}
+}
+
+/// The provider for `url` and the name of the image in it, `None` for URLs no provider has.
+pub fn find(url: &str) -> Option<(Arc<dyn Provider>, String)> {
+	// Windows opens \\server\share paths itself, elsewhere they're smb:// URLs. host:path is scp's,
+	// unless there's a local file by that name
+	let scp = || sftp::from_scp(url).filter(|_| !std::path::Path::new(url).exists());
+	let url = share::from_unc(url).or_else(scp).unwrap_or_else(|| url.to_string());
+	let (scheme, name) = url.split_once("://")?;
+	let providers = PROVIDERS.read().ok()?;
+
+	providers.iter()
+		.find(|provider| provider.scheme() == scheme)
+		.map(|provider| (provider.clone(), name.to_string()))
+}
+
+/// Whether a provider handles `url`.
+pub fn handles(url: &str) -> bool {
+	find(url).is_some()
+}
+
+/// Every provider's images as their URLs, and the errors of the providers that couldn't list theirs.
+pub fn catalog() -> (Vec<(String, Image)>, Vec<String>) {
+	let providers: Vec<Arc<dyn Provider>> = PROVIDERS.read().map(|providers| providers.clone()).unwrap_or_default();
+	let (mut images, mut errors) = (Vec::new(), Vec::new());
+
+	for provider in providers {
+		match provider.list() {
+			Ok(list) => images.extend(list.into_iter().map(|image| (format!("{}://{}", provider.scheme(), image.name), image))),
+			Err(e) => errors.push(e),
+		}
+	}
+
+	(images, errors)
+}
+
+/// Whether the provider of `url` signs its checksums, see [`Provider::signed`].
+pub fn signed(url: &str) -> bool {
+	find(url).is_some_and(|(provider, _)| provider.signed())
+}
+
+/// The checksum the provider of `url` publishes for it, `None` for anything else.
+pub fn checksum(url: &str) -> Result<Option<(Algorithm, String)>, String> {
+	match find(url) {
+		Some((provider, name)) => provider.checksum(&name),
+		None => Ok(None),
+	}
+}
+
+impl Provider for External {
+	fn scheme(&self) -> &str {
+		&self.scheme
+	}
+
+	fn list(&self) -> Result<Vec<Image>, String> {
+		let listing = tools::run(&self.command, &["list"])?;
+		listing.lines().filter(|line| !line.trim().is_empty()).map(parse_line).collect()
+	}
+
+	fn open(&self, name: &str) -> Result<(Box<dyn Read + Send>, u64), String> {
+		let size = self.list()?.into_iter().find(|image| image.name == name).and_then(|image| image.size);
+
+		let mut command = Command::new(&self.command);
+		command.args(["fetch", name]);
+
+		Ok((stream(command, &self.command)?, size.unwrap_or(0)))
+	}
+}
+
+/// What `command` writes to stdout, as a reader that fails unless it exits with 0. Its stderr
+/// goes to ours.
+pub fn stream(mut command: Command, program: &str) -> Result<Box<dyn Read + Send>, String> {
+	let mut child = command
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.spawn()
+		.map_err(|e| tools::not_found(program, e))?;
+
+	let stdout = child.stdout.take().ok_or_else(|| format!("{program} error: no output"))?;
+	Ok(Box::new(Streamed { child, stdout, command: program.to_string() }))
+}
+
+// `<name>\t<size>\t<algorithm>:<digest>`, the last two can be `-`
+fn parse_line(line: &str) -> Result<Image, String> {
+	let mut fields = line.split('\t');
+	let name = fields.next().unwrap_or_default().trim().to_string();
+
+	let size = known(fields.next())
+		.map(|size| size.parse::<u64>().map_err(|_| format!("Provider error: {name} has a size of {size}")))
+		.transpose()?;
+
+	let checksum = match known(fields.next()).map(|checksum| checksum.split_once(':')) {
+		None => None,
+		Some(Some((algorithm, digest))) => Some((algorithm.parse().map_err(|e| format!("Provider error: {name}: {e}"))?, digest.to_string())),
+		Some(None) => return Err(format!("Provider error: {name}'s checksum needs an algorithm, e.g. sha256:9f86d0...")),
+	};
+
+	Ok(Image { name, size, checksum })
+}
+
+fn known(field: Option<&str>) -> Option<&str> {
+	field.map(str::trim).filter(|field| !field.is_empty() && *field != "-")
+}
+
+// A command's output, which is only all there if it exits with 0
+struct Streamed {
+	child: Child,
+	stdout: ChildStdout,
+	command: String,
+}
+
+impl Read for Streamed {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = self.stdout.read(buf)?;
+
+		if read == 0 && !buf.is_empty() {
+			let status = self.child.wait()?;
+			if !status.success() {
+				return Err(io::Error::other(format!("{} {status}", self.command)));
+			}
+		}
+
+		Ok(read)
+	}
+}
+
+// A job cancelled part way doesn't leave the command streaming into nothing
+impl Drop for Streamed {
+	fn drop(&mut self) {
+		if let Ok(None) = self.child.try_wait() {
+			let _ = self.child.kill();
+			let _ = self.child.wait();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn a_provider_lists_its_images_with_their_checksums() {
+		struct Lab;
+
+		impl Provider for Lab {
+			fn scheme(&self) -> &str {
+				"lab"
+			}
+
+			fn list(&self) -> Result<Vec<Image>, String> {
+				Ok(vec![
+					parse_line("golden.img\t5\tsha256:2cf24d")?,
+					parse_line("nightly.img\t-\t-")?,
+				])
+			}
+
+			fn open(&self, _name: &str) -> Result<(Box<dyn Read + Send>, u64), String> {
+				Ok((Box::new(&b"hello"[..]), 5))
+			}
+		}
+
+		register(Arc::new(Lab));
+		assert!(handles("lab://golden.img") && !handles("corp://golden.img"));
+		assert_eq!(checksum("lab://golden.img"), Ok(Some((Algorithm::Sha256, "2cf24d".into()))));
+		assert_eq!(checksum("lab://nightly.img"), Ok(None));
+
+		// Other tests register theirs too
+		let (images, _) = catalog();
+		assert!(images.iter().any(|(url, image)| url == "lab://nightly.img" && image.size.is_none()));
+
+		assert!(parse_line("broken.img\tbig\t-").is_err());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn a_command_provider_streams_what_it_fetches() {
+		use std::os::unix::fs::PermissionsExt;
+		use crate::source;
+		use crate::testing::TempDir;
+
+		let dir = TempDir::new("provider");
+		let command = dir.file("images");
+		std::fs::write(&command, "#!/bin/sh\ncase \"$1 $2\" in\n\
+			'list ') printf 'a.img\\t5\\t-\\nbad.img\\t-\\t-\\n' ;;\n\
+			'fetch a.img') printf hello ;;\n\
+			*) printf half; exit 1 ;;\nesac\n").unwrap();
+		std::fs::set_permissions(&command, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+		register(Arc::new(External { scheme: "script".into(), command }));
+
+		let mut opened = source::open("script://a.img", None).unwrap();
+		let mut image = Vec::new();
+		opened.reader.read_to_end(&mut image).unwrap();
+		assert_eq!((image.as_slice(), opened.len), (&b"hello"[..], 5));
+
+		// Cut off, it's an error rather than a short image
+		let mut opened = source::open("script://bad.img", None).unwrap();
+		assert!(opened.reader.read_to_end(&mut Vec::new()).is_err());
+	}
+
+	#[test]
+	fn a_local_file_with_a_colon_isnt_taken_for_a_host() {
+		register(Arc::new(sftp::Sftp));
+		assert!(handles("rack1:golden.iso"));
+
+		// Relative, like it'd be typed, so it's made where the tests run
+		let name = format!("provider-{}:golden.iso", std::process::id());
+		std::fs::write(&name, "").unwrap();
+		let found = handles(&name);
+		std::fs::remove_file(&name).unwrap();
+		assert!(!found);
+	}
+}
@@ -0,0 +1,229 @@
+// Provisioning layouts for embedded devices: a partition table laid out from a file, with an
+// image of its own written to each partition, e.g. a boot partition, two root filesystems to
+// switch between on updates, and a data partition.
+//
+// Layouts are TOML, run with `iso_maker provision <layout> <device>`:
+//
+//     table = "gpt"
+//
+//     [[partition]]
+//     label = "boot"
+//     size = "256M"
+//     kind = "efi"
+//     image = "boot.vfat"
+//
+//     [[partition]]
+//     label = "data"
+//     filesystem = "exfat"
+//
+// A partition without an image is formatted with its filesystem, or left empty with neither.
+// Images are found next to the layout file, and only the last partition can leave out its
+// size to take the rest of the device.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::partition::{self, Filesystem, Kind, Spec, Table};
+
+/// A layout file, checked and with its sizes and image paths worked out.
+#[derive(Debug, Clone)]
+pub struct Layout {
+	pub table: Table,
+	pub partitions: Vec<Part>,
+}
+
+/// One partition of a layout.
+#[derive(Debug, Clone)]
+pub struct Part {
+	pub spec: Spec,
+	/// Path or URL of what's written to it
+	pub image: Option<String>,
+}
+
+// As it's written in the file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+	table: Table,
+	#[serde(default)]
+	partition: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+	label: String,
+	size: Option<String>,
+	kind: Option<Kind>,
+	image: Option<String>,
+	filesystem: Option<Filesystem>,
+}
+
+// Most partitions there can be in an MBR without an extended one
+const MBR_PARTITIONS: usize = 4;
+
+// GPT names are 36 UTF-16 units
+const GPT_NAME: usize = 36;
+
+impl Layout {
+	/// Reads the layout at `path`.
+	pub fn load(path: &str) -> Result<Self, String> {
+		let contents = std::fs::read_to_string(path).map_err(|e| format!("Layout error: {e}"))?;
+		let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+
+		Self::parse(&contents, dir)
+	}
+
+	/// Parses a layout whose images are relative to `dir`.
+	pub fn parse(contents: &str, dir: &Path) -> Result<Self, String> {
+		let file: File = toml::from_str(contents).map_err(|e| format!("Layout error: {e}"))?;
+
+		if file.partition.is_empty() {
+			return Err("Layout error: there are no partitions".into());
+		}
+
+		if file.table == Table::Mbr && file.partition.len() > MBR_PARTITIONS {
+			return Err(format!("Layout error: an MBR can only hold {MBR_PARTITIONS} partitions, use a GPT for more"));
+		}
+
+		let last = file.partition.len() - 1;
+		let partitions = file.partition
+			.into_iter()
+			.enumerate()
+			.map(|(i, entry)| {
+				let label = entry.label;
+
+				if entry.image.is_some() && entry.filesystem.is_some() {
+					return Err(format!("Layout error: {label} has both an image and a filesystem, the image brings its own"));
+				}
+
+				let size = match entry.size {
+					Some(size) => Some(partition::parse_size(&size).map_err(|e| format!("Layout error: {label}: {e}"))?),
+					None if i == last => None,
+					None => return Err(format!("Layout error: {label} needs a size, only the last partition can take the rest of the device")),
+				};
+
+				match entry.filesystem {
+					Some(filesystem) => partition::check_label(filesystem, &label).map_err(|e| format!("Layout error: {label}: {e}"))?,
+					None if file.table == Table::Gpt && label.encode_utf16().count() > GPT_NAME => {
+						return Err(format!("Layout error: {label} is too long for a GPT name, which can be {GPT_NAME} characters"));
+					},
+					None => {},
+				}
+
+				let image = entry.image.map(|image| resolve(&image, dir)).transpose().map_err(|e| format!("Layout error: {label}: {e}"))?;
+				let kind = entry.kind.unwrap_or(match entry.filesystem {
+					Some(Filesystem::Swap) => Kind::Swap,
+					Some(Filesystem::Ext4) => Kind::Linux,
+					Some(_) => Kind::Data,
+					None => Kind::Linux,
+				});
+
+				Ok(Part { spec: Spec { size, kind, filesystem: entry.filesystem, label }, image })
+			})
+			.collect::<Result<Vec<_>, String>>()?;
+
+		Ok(Self { table: file.table, partitions })
+	}
+
+	/// What gets written where once the table is laid out on `device`, by partition path.
+	pub fn writes(&self, device: &str) -> Vec<(String, &Part)> {
+		self.partitions
+			.iter()
+			.enumerate()
+			.filter(|(_, part)| part.image.is_some())
+			.map(|(i, part)| (partition::partition_path(device, i + 1), part))
+			.collect()
+	}
+
+	/// Lays out the partition table on `device` and formats the partitions with a filesystem,
+	/// leaving the others for their images.
+	pub fn apply(&self, device: &str) -> Result<(), String> {
+		let specs: Vec<Spec> = self.partitions.iter().map(|part| part.spec.clone()).collect();
+		partition::create(device, self.table, &specs).map(|_| ())
+	}
+}
+
+// URLs are downloaded as they are, files have to be there before the device is wiped
+fn resolve(image: &str, dir: &Path) -> Result<String, String> {
+	if image.contains("://") {
+		return Ok(image.to_string());
+	}
+
+	let path = dir.join(image);
+	if !path.is_file() {
+		return Err(format!("{} doesn't exist", path.display()));
+	}
+
+	Ok(path.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	#[test]
+	fn a_layout_gives_every_partition_its_image() {
+		let dir = TempDir::new("provision");
+		for image in ["boot.vfat", "rootfs.ext4"] {
+			std::fs::write(dir.file(image), b"image").unwrap();
+		}
+		let images = std::path::PathBuf::from(dir.file(""));
+
+		let layout = Layout::parse(
+			r#"
+			table = "gpt"
+
+			[[partition]]
+			label = "boot"
+			size = "256M"
+			kind = "efi"
+			image = "boot.vfat"
+
+			[[partition]]
+			label = "rootfs-A"
+			size = "4 GiB"
+			image = "rootfs.ext4"
+
+			[[partition]]
+			label = "rootfs-B"
+			size = "4G"
+			image = "rootfs.ext4"
+
+			[[partition]]
+			label = "data"
+			filesystem = "exfat"
+			"#,
+			&images,
+		)
+		.unwrap();
+
+		let sizes: Vec<Option<u64>> = layout.partitions.iter().map(|part| part.spec.size).collect();
+		assert_eq!(sizes, [Some(256 << 20), Some(4 << 30), Some(4 << 30), None]);
+		assert_eq!(layout.partitions[1].spec.kind, Kind::Linux);
+		assert_eq!(layout.partitions[3].spec.kind, Kind::Data);
+
+		let writes: Vec<(String, String)> = layout
+			.writes("/dev/mmcblk0")
+			.into_iter()
+			.map(|(path, part)| (path, part.spec.label.clone()))
+			.collect();
+		assert_eq!(writes, [
+			("/dev/mmcblk0p1".to_string(), "boot".to_string()),
+			("/dev/mmcblk0p2".to_string(), "rootfs-A".to_string()),
+			("/dev/mmcblk0p3".to_string(), "rootfs-B".to_string()),
+		]);
+
+		// Anything that would leave the device half provisioned is caught before it's touched
+		for broken in [
+			"table = \"gpt\"\n[[partition]]\nlabel = \"a\"\nimage = \"boot.vfat\"\n[[partition]]\nlabel = \"b\"\nsize = \"1G\"",
+			"table = \"gpt\"\n[[partition]]\nlabel = \"a\"\nimage = \"missing.img\"",
+			"table = \"gpt\"\n[[partition]]\nlabel = \"a\"\nimage = \"boot.vfat\"\nfilesystem = \"fat32\"",
+			"table = \"gpt\"\n[[partition]]\nlabel = \"a\"\nsize = \"lots\"",
+		] {
+			assert!(Layout::parse(broken, &images).is_err(), "{broken}");
+		}
+	}
+}
@@ -0,0 +1,137 @@
+// Test boots in QEMU, so a stick, or the image before it's written, can be seen booting
+// before the walk to the machine it's for.
+//
+// The disk is attached as a USB stick the way firmware finds one, with writes going to a
+// throwaway overlay so the VM can't change it. UEFI needs OVMF, QEMU's build of the EDK II
+// firmware, which distributions package separately; without it only BIOS booting is offered.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::tools;
+
+const QEMU: &str = "qemu-system-x86_64";
+
+// Where distributions put OVMF, the combined images first as they don't need a variable store
+const OVMF: [&str; 6] = [
+	"/usr/share/qemu/OVMF.fd",
+	"/usr/share/ovmf/OVMF.fd",
+	"/usr/share/OVMF/OVMF.fd",
+	"/usr/share/OVMF/OVMF_CODE.fd",
+	"/usr/share/edk2/ovmf/OVMF_CODE.fd",
+	"/usr/share/edk2/x64/OVMF_CODE.fd",
+];
+
+// Enough for live systems to get to their desktop
+const MEMORY: &str = "2048";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+	Uefi,
+	Bios,
+}
+
+impl std::fmt::Display for Firmware {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Firmware::Uefi => "UEFI",
+			Firmware::Bios => "BIOS",
+		})
+	}
+}
+
+/// Whether QEMU is installed, looked for once.
+pub fn available() -> bool {
+	static FOUND: OnceLock<bool> = OnceLock::new();
+
+	*FOUND.get_or_init(|| {
+		std::env::var_os("PATH")
+			.is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(QEMU).is_file()))
+	})
+}
+
+/// The firmware that can be booted with, UEFI only when OVMF is installed.
+pub fn firmwares() -> Vec<Firmware> {
+	match ovmf() {
+		Some(_) => vec![Firmware::Uefi, Firmware::Bios],
+		None => vec![Firmware::Bios],
+	}
+}
+
+fn ovmf() -> Option<&'static Path> {
+	static FOUND: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+	FOUND.get_or_init(|| OVMF.iter().map(PathBuf::from).find(|path| path.is_file())).as_deref()
+}
+
+/// QEMU's arguments for booting `disk` with `firmware` from `ovmf`, `raw` for devices and
+/// images that are nothing but their blocks.
+pub fn args(disk: &str, raw: bool, firmware: Firmware, ovmf: Option<&Path>) -> Vec<String> {
+	// Commas in the path would be read as the next option, QEMU escapes them by doubling
+	let mut drive = format!("file={},if=none,id=stick,snapshot=on", disk.replace(',', ",,"));
+	if raw {
+		drive.push_str(",format=raw");
+	}
+
+	let mut args: Vec<String> = [
+		"-name", "iso_maker test boot",
+		"-machine", "q35",
+		"-accel", "kvm",
+		"-accel", "tcg",
+		"-m", MEMORY,
+		"-drive", &drive,
+		"-device", "qemu-xhci",
+		"-device", "usb-storage,drive=stick,bootindex=0",
+	].map(String::from).to_vec();
+
+	if firmware == Firmware::Uefi && let Some(ovmf) = ovmf {
+		let ovmf = ovmf.to_string_lossy().replace(',', ",,");
+
+		// The split images have to be flash, and read-only as there's no variable store
+		if ovmf.ends_with("_CODE.fd") {
+			args.extend(["-drive".into(), format!("if=pflash,format=raw,readonly=on,file={ovmf}")]);
+		} else {
+			args.extend(["-bios".into(), ovmf]);
+		}
+	}
+
+	args
+}
+
+/// Boots `disk` in a QEMU window and waits for it to be closed.
+pub async fn boot(disk: String, firmware: Firmware) -> Result<(), String> {
+	tokio::task::spawn_blocking(move || {
+		// Devices, and images the write would copy as they are
+		let raw = disk.starts_with("/dev/") || [".iso", ".img", ".raw"].iter().any(|extension| disk.to_lowercase().ends_with(extension));
+		let args = args(&disk, raw, firmware, ovmf());
+		let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+		tools::run(QEMU, &args).map(|_| ())
+	})
+	.await
+	.map_err(|e| format!("QEMU error: {e}"))?
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn the_disk_is_a_usb_stick_the_vm_cant_change() {
+		let args = args("/dev/sdz", true, Firmware::Uefi, Some(Path::new("/usr/share/OVMF/OVMF_CODE.fd")));
+		let after = |flag: &str| -> Vec<&str> {
+			args.windows(2).filter(|pair| pair[0] == flag).map(|pair| pair[1].as_str()).collect()
+		};
+
+		assert_eq!(after("-drive"), [
+			"file=/dev/sdz,if=none,id=stick,snapshot=on,format=raw",
+			"if=pflash,format=raw,readonly=on,file=/usr/share/OVMF/OVMF_CODE.fd",
+		]);
+		assert!(after("-device").contains(&"usb-storage,drive=stick,bootindex=0"));
+
+		// BIOS is QEMU's own, and a comma can't sneak another option in
+		let bios = super::args("/tmp/a,b.qcow2", false, Firmware::Bios, Some(Path::new("/usr/share/qemu/OVMF.fd")));
+		assert!(bios.contains(&"file=/tmp/a,,b.qcow2,if=none,id=stick,snapshot=on".to_string()), "{bios:?}");
+		assert!(!bios.iter().any(|arg| arg == "-bios" || arg.contains("pflash")));
+	}
+}
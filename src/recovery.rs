@@ -0,0 +1,172 @@
+// What's kept on disk about a job while it erases a device, so one cut off by a crash or a
+// power cut isn't forgotten.
+//
+// Each running job has a file in `iso_maker/jobs` in the local data directory with what it
+// writes where, and how much of it has been synced to the device, updated at every
+// checkpoint. The file goes once the job ends, however it ends, so any that are there when
+// the app starts are jobs that never got to.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::Mode;
+
+/// A job as it's kept on disk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interrupted {
+	pub source: String,
+	/// The device's path when it was written, which may belong to another one after a reboot
+	pub dest: String,
+	pub serial: Option<String>,
+	pub mode: Mode,
+	/// Bytes of the image synced to the device, safe to resume from
+	pub synced: u64,
+}
+
+impl Interrupted {
+	/// Whether the write can carry on where it was cut off, see [`crate::writer::Options::resume`].
+	pub fn resumable(&self) -> bool {
+		self.mode == Mode::Image && self.synced > 0 && self.serial.is_some()
+	}
+}
+
+/// A job that was cut off, found in the store.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leftover {
+	pub path: PathBuf,
+	pub job: Interrupted,
+}
+
+/// Where running jobs are kept.
+#[derive(Debug, Clone)]
+pub struct Store(Option<PathBuf>);
+
+impl Default for Store {
+	fn default() -> Self {
+		Self(dirs::data_local_dir().map(|dir| dir.join("iso_maker").join("jobs")))
+	}
+}
+
+impl Store {
+	/// Keeps jobs in `dir`, `None` doesn't keep them at all.
+	pub fn at(dir: Option<PathBuf>) -> Self {
+		Self(dir)
+	}
+
+	/// The jobs that were running when the app last stopped, oldest first.
+	pub fn leftover(&self) -> Vec<Leftover> {
+		let Some(entries) = self.0.as_ref().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+			return Vec::new();
+		};
+
+		let mut leftover: Vec<Leftover> = entries
+			.filter_map(|entry| {
+				let path = entry.ok()?.path();
+				if path.extension()? != "toml" {
+					return None;
+				}
+
+				let job = toml::from_str(&std::fs::read_to_string(&path).ok()?).ok()?;
+				Some(Leftover { path, job })
+			})
+			.collect();
+
+		// Named by when they started
+		leftover.sort_by(|a, b| a.path.cmp(&b.path));
+		leftover
+	}
+
+	/// Starts keeping `job` while it runs, `None` when there's nowhere to.
+	pub fn start(&self, job: Interrupted) -> Option<Record> {
+		static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+		let dir = self.0.as_ref()?;
+		std::fs::create_dir_all(dir).ok()?;
+
+		let started = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+		let name = format!("{:020}-{}-{}.toml", started.as_millis(), std::process::id(), COUNT.fetch_add(1, Ordering::Relaxed));
+
+		let record = Record { path: dir.join(name), job };
+		record.save();
+		Some(record)
+	}
+}
+
+/// Takes a job that was cut off out of the store, once it's been dealt with.
+pub fn forget(leftover: &Leftover) {
+	let _ = std::fs::remove_file(&leftover.path);
+}
+
+/// A running job's file, removed by [`Record::finish`].
+#[derive(Debug)]
+pub struct Record {
+	path: PathBuf,
+	job: Interrupted,
+}
+
+impl Record {
+	/// Notes how much has been synced, when that's moved on.
+	pub fn synced(&mut self, synced: u64) {
+		if synced != self.job.synced {
+			self.job.synced = synced;
+			self.save();
+		}
+	}
+
+	/// The job ended, whether it worked or not.
+	pub fn finish(self) {
+		let _ = std::fs::remove_file(&self.path);
+	}
+
+	// Not being able to keep it only costs the recovery, the job still goes ahead
+	fn save(&self) {
+		if let Ok(contents) = toml::to_string(&self.job) {
+			let _ = write_atomically(&self.path, &contents);
+		}
+	}
+}
+
+// Cut off half way through, the old file is still whole
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+	let temp = path.with_extension("tmp");
+	std::fs::write(&temp, contents)?;
+	std::fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	fn job(dest: &str) -> Interrupted {
+		Interrupted {
+			source: "/tmp/image.iso".into(),
+			dest: dest.into(),
+			serial: Some("A1".into()),
+			mode: Mode::Image,
+			synced: 0,
+		}
+	}
+
+	#[test]
+	fn only_jobs_that_never_ended_are_left_over() {
+		let dir = TempDir::new("recovery");
+		let store = Store::at(Some(dir.file("jobs").into()));
+
+		let mut cut_off = store.start(job("/dev/sdb")).unwrap();
+		let finished = store.start(job("/dev/sdc")).unwrap();
+		cut_off.synced(256 * 1024 * 1024);
+		finished.finish();
+
+		let leftover = store.leftover();
+		assert_eq!(leftover.len(), 1);
+		assert_eq!(leftover[0].job, Interrupted { synced: 256 * 1024 * 1024, ..job("/dev/sdb") });
+		assert!(leftover[0].job.resumable());
+
+		forget(&leftover[0]);
+		assert!(store.leftover().is_empty());
+		assert!(Store::at(None).start(job("/dev/sdb")).is_none());
+	}
+}
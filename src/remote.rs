@@ -0,0 +1,307 @@
+// Writing a device on another machine over SSH, so a laptop can drive a headless flashing box
+// in the rack: `iso_maker write golden.img root@rack1:/dev/sdb`, or `ssh://root@rack1:2222/dev/sdb`.
+//
+// The image is read here, unpacked and checked against its checksum, and streamed through
+// ssh, which logs in with the keys it always uses. When iso_maker is installed over there it
+// does the write, with its own unmounting, syncing and read-back verification, reporting its
+// progress the way it does to the GUI running it as root. Otherwise it's `dd`, and the
+// device is verified by comparing a `sha256sum` of what's on it with what was sent.
+// Whoever logs in has to be allowed to write the device, root or someone in the disk group.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
+
+use tokio::sync::mpsc;
+
+use crate::elevate;
+use crate::hash::{self, Algorithm};
+use crate::sftp;
+use crate::source::{self, STDIN};
+use crate::tools;
+use crate::writer::{Options, Phase, Readback};
+
+// What's sent at a time, and checked for cancelling between
+const CHUNK: usize = 1024 * 1024;
+
+/// A device on another machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+	/// `user@host`, for ssh
+	destination: String,
+	port: Option<u16>,
+	device: String,
+}
+
+/// The remote device `dest` is, `None` for a local one.
+pub fn parse(dest: &str) -> Option<Remote> {
+	let url = match dest.strip_prefix("ssh://") {
+		Some(rest) => rest.to_string(),
+		None => sftp::from_scp(dest)?.strip_prefix("sftp://")?.to_string(),
+	};
+
+	let (authority, device) = url.split_once('/')?;
+	let (destination, port) = match authority.rsplit_once(':') {
+		Some((destination, port)) => (destination, Some(port.parse().ok()?)),
+		None => (authority, None),
+	};
+
+	// Devices are whole paths, one in the home directory isn't
+	if destination.is_empty() || destination.starts_with('-') || device.is_empty() || device.starts_with("~/") {
+		return None;
+	}
+
+	Some(Remote { destination: destination.to_string(), port, device: format!("/{device}") })
+}
+
+impl Remote {
+	// ssh running `command` there, failing rather than asking for a password
+	fn ssh(&self, command: &str) -> Command {
+		let mut ssh = Command::new("ssh");
+		ssh.args(["-o", "BatchMode=yes"]);
+		if let Some(port) = self.port {
+			ssh.args(["-p", &port.to_string()]);
+		}
+
+		ssh.args(["--", &self.destination, command]);
+		ssh
+	}
+
+	// Whether iso_maker is there to do the write
+	fn has_helper(&self) -> Result<bool, String> {
+		let output = self.ssh("command -v iso_maker")
+			.stdin(Stdio::null())
+			.output()
+			.map_err(|e| tools::not_found("ssh", e))?;
+
+		match output.status.code() {
+			Some(0) => Ok(true),
+			// ssh's own, it couldn't get in
+			Some(255) => Err(format!("SSH error: {}", String::from_utf8_lossy(&output.stderr).trim())),
+			_ => Ok(false),
+		}
+	}
+}
+
+/// Writes `source` to the `remote` device, or with `verify_only` compares it with the image,
+/// like [`crate::writer::copy_with_progress`] does locally.
+pub fn copy(
+	source: &str,
+	remote: &Remote,
+	options: &Options,
+	verify_only: bool,
+	cancel_rx: &mut mpsc::Receiver<()>,
+	progress_tx: &mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	// What only works with the device at hand
+	if options.mbr.is_some() {
+		return Err("An ISO can't be hybridized on a remote device".into());
+	}
+	if options.resume > 0 {
+		return Err("A write to a remote device can't be resumed, start it over".into());
+	}
+
+	let helper = remote.has_helper()?;
+	let algorithm = options.expected.as_ref().map_or(Algorithm::Sha256, |(algorithm, _)| *algorithm);
+	let mut opened = source::open(source, Some(algorithm))?;
+
+	// Neither end can tell how far along the other is, a verification goes by the image too
+	let feeding = if verify_only { Phase::Verifying } else { Phase::Writing };
+
+	// The whole image goes over, unpacked, so the rest is all for the write over there to do
+	let (mut sent, mut reports) = if helper {
+		let helper_options = Options { expected: None, mbr: None, bmap: None, skip_unchanged: false, delta: false, ..options.clone() };
+		let mut args = elevate::args(&helper_options, verify_only);
+		args.extend([STDIN.into(), quote(&remote.device)]);
+
+		let child = spawn(remote.ssh(&format!("iso_maker {}", args.join(" "))))?;
+		(None, Reports::new(child, Some(options.clone()), feeding, progress_tx.clone()))
+	} else if verify_only {
+		(Some(Algorithm::Sha256.hasher()), None)
+	} else {
+		let child = spawn(remote.ssh(&format!("dd of={} bs=4M conv=fsync status=none", quote(&remote.device))))?;
+		(Some(Algorithm::Sha256.hasher()), Reports::new(child, None, feeding, progress_tx.clone()))
+	};
+
+	let mut buffer = vec![0; CHUNK];
+	let mut len = 0;
+
+	loop {
+		if matches!(cancel_rx.try_recv(), Ok(()) | Err(mpsc::error::TryRecvError::Disconnected)) {
+			// What's been sent so far isn't a whole image, it mustn't be finished with
+			if let Some(reports) = reports.take() {
+				reports.kill();
+			}
+			return Err("Cancelled".into());
+		}
+
+		let read = opened.reader.read(&mut buffer).map_err(|e| format!("Source error: {e}"))?;
+		if read == 0 {
+			break;
+		}
+
+		if let Some(reports) = &mut reports {
+			reports.send(&buffer[..read])?;
+		}
+		if let Some(hasher) = &mut sent {
+			hasher.update(&buffer[..read]);
+		}
+		len += read as u64;
+
+		// Over there it's read from a pipe, only this end knows how big the image is
+		let _ = progress_tx.blocking_send((feeding, opened.consumed.load(Ordering::Relaxed), opened.len));
+	}
+
+	if let Some(reports) = reports {
+		reports.finish()?;
+	}
+
+	// In place of the checksum the helper took of the image unpacked
+	let digest = opened.digest.and_then(|digest| digest.finish());
+	if let (Some(digest), Ok(mut image_hash)) = (&digest, options.image_hash.lock()) {
+		*image_hash = Some(format!("{}:{digest}", algorithm.id()));
+	}
+
+	if let (Some((algorithm, expected)), Some(digest)) = (&options.expected, digest)
+		&& !hash::matches(&digest, expected)
+	{
+		return Err(format!("The image does not match the expected {algorithm} checksum, got {digest}"));
+	}
+
+	// dd writes it and that's all, what's on the device gets hashed there to check it
+	match sent {
+		Some(sent) if verify_only || options.verify.is_some() => {
+			let _ = progress_tx.blocking_send((Phase::Verifying, 0, len));
+			let written = sent.finalize();
+			let output = remote.ssh(&format!("head -c {len} {} | sha256sum", quote(&remote.device)))
+				.stdin(Stdio::null())
+				.output()
+				.map_err(|e| tools::not_found("ssh", e))?;
+			if !output.status.success() {
+				return Err(format!("Verification error: {}", String::from_utf8_lossy(&output.stderr).trim()));
+			}
+
+			let device = String::from_utf8_lossy(&output.stdout).split_whitespace().next().unwrap_or_default().to_string();
+
+			if let Ok(mut readback) = options.readback.lock() {
+				*readback = Some(Readback { algorithm: Algorithm::Sha256, written: written.clone(), device: device.clone() });
+			}
+
+			if device != written {
+				return Err("Verification failed: what's on the device doesn't match the image".into());
+			}
+
+			let _ = progress_tx.blocking_send((Phase::Verifying, len, len));
+			Ok(())
+		},
+		_ => Ok(()),
+	}
+}
+
+fn spawn(mut command: Command) -> Result<Child, String> {
+	command.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|e| tools::not_found("ssh", e))
+}
+
+// For the remote shell, in single quotes
+fn quote(value: &str) -> String {
+	format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+// The remote end of a write: the image goes in, and what it says comes out on threads of its
+// own, so neither waits on the other
+struct Reports {
+	child: Child,
+	stdout: Option<JoinHandle<()>>,
+	stderr: Option<JoinHandle<String>>,
+}
+
+impl Reports {
+	// `options` to take in the helper's machine-readable output, dd has nothing to say. Its
+	// progress in the `feeding` phase goes by the pipe, that's reported from this end instead
+	fn new(mut child: Child, options: Option<Options>, feeding: Phase, progress_tx: mpsc::Sender<(Phase, u64, u64)>) -> Option<Self> {
+		let stdout = child.stdout.take().map(|stdout| std::thread::spawn(move || {
+			for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+				if let Some(options) = &options
+					&& let Some(progress) = elevate::machine_line(&line, options)
+					&& progress.0 != feeding
+				{
+					let _ = progress_tx.blocking_send(progress);
+				}
+			}
+		}));
+
+		let stderr = child.stderr.take().map(|mut stderr| std::thread::spawn(move || {
+			let mut said = String::new();
+			let _ = stderr.read_to_string(&mut said);
+			said
+		}));
+
+		Some(Self { child, stdout, stderr })
+	}
+
+	fn send(&mut self, data: &[u8]) -> Result<(), String> {
+		let Some(stdin) = &mut self.child.stdin else {
+			return Err("SSH error: the connection is closed".into());
+		};
+
+		// The other end giving up closes the pipe, what it said is the reason
+		if stdin.write_all(data).is_err() {
+			self.child.stdin = None;
+			return Err(self.wait().err().unwrap_or_else(|| "SSH error: the remote write stopped".into()));
+		}
+
+		Ok(())
+	}
+
+	// Waits for the write over there to finish with what it has
+	fn finish(mut self) -> Result<(), String> {
+		self.child.stdin = None;
+		self.wait()
+	}
+
+	fn wait(&mut self) -> Result<(), String> {
+		let status = self.child.wait().map_err(|e| format!("SSH error: {e}"))?;
+		if let Some(stdout) = self.stdout.take() {
+			let _ = stdout.join();
+		}
+		let said = self.stderr.take().and_then(|stderr| stderr.join().ok()).unwrap_or_default();
+
+		match status.success() {
+			true => Ok(()),
+			// The write's error is the last thing it prints
+			false => Err(said.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("The remote write failed").trim().to_string()),
+		}
+	}
+
+	fn kill(mut self) {
+		let _ = self.child.kill();
+		let _ = self.wait();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn remote_devices_are_told_from_local_ones() {
+		assert_eq!(parse("root@rack1:/dev/sdb"), Some(Remote { destination: "root@rack1".into(), port: None, device: "/dev/sdb".into() }));
+		assert_eq!(parse("ssh://flash@rack1:2222/dev/disk/by-id/usb-1"), Some(Remote {
+			destination: "flash@rack1".into(),
+			port: Some(2222),
+			device: "/dev/disk/by-id/usb-1".into(),
+		}));
+
+		assert_eq!(parse("/dev/sdb"), None);
+		assert_eq!(parse(r"\\.\PhysicalDrive1"), None);
+		assert_eq!(parse("rack1:sdb"), None);
+
+		assert_eq!(quote("/dev/it's"), r"'/dev/it'\''s'");
+	}
+}
@@ -0,0 +1,161 @@
+// Checking whether an ISO's UEFI boot loader will get past Secure Boot.
+//
+// Firmware with Secure Boot on only runs loaders signed with a key in its database, which
+// out of the box means Microsoft's. Linux distributions get there through shim, which
+// Microsoft signs and which in turn checks the distribution's own signature on GRUB.
+
+use std::fmt;
+
+use crate::isofs::{self, Entry};
+
+// Largest loader worth reading, shim and GRUB are a few MB at most
+pub const MAX_LOADER: u64 = 64 * 1024 * 1024;
+
+// Subjects of the Microsoft certificates that machines trust out of the box, for third party loaders and Windows
+const MICROSOFT_CAS: [&[u8]; 4] = [
+	b"Microsoft Corporation UEFI CA 2011",
+	b"Microsoft UEFI CA 2023",
+	b"Microsoft Windows Production PCA 2011",
+	b"Windows UEFI CA 2023",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signature {
+	/// Signed by Microsoft, directly or as shim
+	Microsoft,
+	/// Signed with someone's own key, which has to be enrolled in the firmware first
+	Other,
+	Unsigned,
+}
+
+/// What was found about the image's default UEFI loader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecureBoot {
+	/// Where it is in the image, e.g. EFI/BOOT/BOOTX64.EFI
+	pub loader: String,
+	pub signature: Signature,
+	pub shim: bool,
+}
+
+impl SecureBoot {
+	/// Why the stick won't boot with Secure Boot on, `None` when it will.
+	pub fn warning(&self) -> Option<String> {
+		match self.signature {
+			Signature::Microsoft => None,
+			Signature::Other => Some(format!(
+				"{} isn't signed by Microsoft, so with Secure Boot on it only boots where its key has been enrolled. Otherwise turn Secure Boot off in the firmware settings",
+				self.loader,
+			)),
+			Signature::Unsigned => Some(format!(
+				"{} isn't signed, so this stick won't boot with Secure Boot on. Turn it off in the firmware settings first",
+				self.loader,
+			)),
+		}
+	}
+}
+
+impl fmt::Display for SecureBoot {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let signature = match (self.signature, self.shim) {
+			(Signature::Microsoft, true) => "shim, signed by Microsoft",
+			(Signature::Microsoft, false) => "signed by Microsoft",
+			(Signature::Other, _) => "signed, not by Microsoft",
+			(Signature::Unsigned, _) => "unsigned",
+		};
+
+		write!(f, "{}: {signature}", self.loader)
+	}
+}
+
+/// Looks at the default UEFI loader of the ISO at `path`, `None` when it isn't an ISO or has no UEFI loader.
+pub fn check(path: &str) -> Option<SecureBoot> {
+	let root = isofs::read_tree(path).ok()?;
+	let (loader, entry) = default_loader(&root)?;
+
+	if entry.size > MAX_LOADER {
+		return None;
+	}
+
+	let data = isofs::read_file(path, entry).ok()?;
+
+	Some(SecureBoot {
+		loader,
+		signature: signature(&data),
+		shim: is_shim(&data),
+	})
+}
+
+/// Whether the loader `pe` is shim, which goes on to start GRUB.
+pub fn is_shim(pe: &[u8]) -> bool {
+	contains(pe, b"UEFI shim")
+}
+
+// EFI/BOOT/BOOTX64.EFI where firmware looks on removable media, or one for another architecture
+fn default_loader(root: &Entry) -> Option<(String, &Entry)> {
+	fn find<'a>(dir: &'a Entry, name: &str) -> Option<&'a Entry> {
+		dir.children.as_ref()?.iter().find(|child| child.name.eq_ignore_ascii_case(name))
+	}
+
+	let boot = find(root, "EFI").and_then(|efi| find(efi, "BOOT"))?;
+	let loaders: Vec<&Entry> = boot.children.as_ref()?.iter()
+		.filter(|child| {
+			let name = child.name.to_uppercase();
+			!child.is_dir() && name.starts_with("BOOT") && name.ends_with(".EFI")
+		})
+		.collect();
+
+	let loader = loaders.iter()
+		.find(|loader| loader.name.eq_ignore_ascii_case("BOOTX64.EFI"))
+		.or(loaders.first())?;
+
+	Some((format!("EFI/BOOT/{}", loader.name), loader))
+}
+
+// From the PE certificate table, where Authenticode signatures go
+fn signature(pe: &[u8]) -> Signature {
+	let Some(certificates) = certificate_table(pe) else {
+		return Signature::Unsigned;
+	};
+
+	if MICROSOFT_CAS.iter().any(|ca| contains(certificates, ca)) {
+		Signature::Microsoft
+	} else {
+		Signature::Other
+	}
+}
+
+fn certificate_table(pe: &[u8]) -> Option<&[u8]> {
+	let u16_at = |offset: usize| pe.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+	let u32_at = |offset: usize| pe.get(offset..offset + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+
+	if pe.get(0..2)? != b"MZ" {
+		return None;
+	}
+
+	let header = u32_at(0x3C)? as usize;
+	if pe.get(header..header + 4)? != b"PE\0\0" {
+		return None;
+	}
+
+	// The data directories follow the optional header's fixed fields, which are longer for PE32+
+	let optional = header + 24;
+	let (directories, count) = match u16_at(optional)? {
+		0x10B => (optional + 96, u32_at(optional + 92)?),
+		0x20B => (optional + 112, u32_at(optional + 108)?),
+		_ => return None,
+	};
+
+	// The security directory is the fifth, and unlike the others its address is a file offset
+	if count < 5 {
+		return None;
+	}
+
+	let offset = u32_at(directories + 4 * 8)? as usize;
+	let size = u32_at(directories + 4 * 8 + 4)? as usize;
+
+	(size > 0).then(|| pe.get(offset..offset.checked_add(size)?)).flatten()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+	haystack.windows(needle.len()).any(|window| window == needle)
+}
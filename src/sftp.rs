@@ -0,0 +1,473 @@
+// Images on a build server, `user@host:/path/to/image.iso` the way scp takes it or
+// `sftp://user@host[:port]/path/to/image.iso`, streamed onto the device over SFTP.
+//
+// ssh does the connecting and logging in, with the keys, agent and ~/.ssh/config it always
+// uses, and runs the server's sftp subsystem, which this speaks version 3 of: the file is
+// opened, its size asked for the progress, and read with a few dozen requests in flight so
+// the round trips don't hold it up. It works with accounts that are only allowed SFTP too.
+// There's nothing to type a password into, keys it is; ssh runs in batch mode so it fails
+// instead of waiting for one.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, Stdio};
+
+use crate::provider::{Image, Provider};
+use crate::source;
+use crate::tools;
+
+pub struct Sftp;
+
+// What's asked for at a time, and how many of those can be on their way
+const CHUNK: u32 = 64 * 1024;
+const IN_FLIGHT: usize = 32;
+
+// Packet types and status codes, from draft-ietf-secsh-filexfer-02
+const INIT: u8 = 1;
+const VERSION: u8 = 2;
+const OPEN: u8 = 3;
+const CLOSE: u8 = 4;
+const READ: u8 = 5;
+const FSTAT: u8 = 8;
+const STATUS: u8 = 101;
+const HANDLE: u8 = 102;
+const DATA: u8 = 103;
+const ATTRS: u8 = 105;
+
+const EOF: u32 = 1;
+const OPEN_READ: u32 = 1;
+const SIZE: u32 = 1;
+
+// Where the image is
+#[derive(Debug, PartialEq, Eq)]
+struct Location {
+	/// `user@host`, for ssh
+	destination: String,
+	port: Option<u16>,
+	/// Relative ones are in the user's home
+	path: String,
+}
+
+/// The `sftp://` URL for a source written the way scp takes it, `[user@]host:path`, `None`
+/// for anything else.
+pub fn from_scp(path: &str) -> Option<String> {
+	if path.contains("://") {
+		return None;
+	}
+
+	// A single letter is a Windows drive, and a slash makes it a local path with a colon in it
+	let (host, file) = path.split_once(':')?;
+	if host.len() < 2 || host.contains(['/', '\\']) || host.starts_with('-') || file.is_empty() {
+		return None;
+	}
+
+	let file = file.replace('%', "%25");
+	match file.strip_prefix('/') {
+		Some(absolute) => Some(format!("sftp://{host}/{absolute}")),
+		None => Some(format!("sftp://{host}/~/{file}")),
+	}
+}
+
+impl Provider for Sftp {
+	fn scheme(&self) -> &str {
+		"sftp"
+	}
+
+	// A server can't be listed without knowing where to look
+	fn list(&self) -> Result<Vec<Image>, String> {
+		Ok(Vec::new())
+	}
+
+	fn open(&self, name: &str) -> Result<(Box<dyn Read + Send>, u64), String> {
+		let location = Location::parse(name)?;
+
+		let mut ssh = Command::new("ssh");
+		ssh.args(["-o", "BatchMode=yes"]);
+		if let Some(port) = location.port {
+			ssh.args(["-p", &port.to_string()]);
+		}
+
+		let mut child = ssh.args(["-s", "--", &location.destination, "sftp"])
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+			.map_err(|e| tools::not_found("ssh", e))?;
+
+		let (Some(to), Some(from)) = (child.stdin.take(), child.stdout.take()) else {
+			return Err("SFTP error: ssh has no input or output".into());
+		};
+
+		match Download::start(Session { to, from, next_id: 0 }, &location.path) {
+			Ok(mut download) => {
+				let size = download.size.unwrap_or(0);
+				download.child = Some(child);
+				Ok((Box::new(download), size))
+			},
+			// Not getting in at all is ssh's to explain
+			Err(e) => Err(failed(child).unwrap_or(e)),
+		}
+	}
+}
+
+// What ssh said went wrong, once it's given up
+fn failed(mut child: Child) -> Option<String> {
+	let _ = child.kill();
+	let mut stderr = String::new();
+	child.stderr.take()?.read_to_string(&mut stderr).ok()?;
+	let _ = child.wait();
+
+	let stderr = stderr.trim();
+	(!stderr.is_empty()).then(|| format!("SFTP error: {stderr}"))
+}
+
+impl Location {
+	// `[user@]host[:port]/path`, as it comes after sftp://, with `/~/` for the home directory
+	fn parse(name: &str) -> Result<Self, String> {
+		let (authority, path) = name.split_once('/').filter(|(authority, path)| !authority.is_empty() && !path.is_empty())
+			.ok_or_else(|| format!("SFTP error: sftp://{name} needs a host and file, e.g. build@ci:/srv/images/golden.iso"))?;
+
+		let (destination, port) = match authority.rsplit_once(':') {
+			Some((destination, port)) => (destination, Some(port.parse().map_err(|_| format!("SFTP error: {port} isn't a port"))?)),
+			None => (authority, None),
+		};
+
+		if destination.starts_with('-') {
+			return Err(format!("SFTP error: {destination} isn't a host"));
+		}
+
+		let path = source::percent_decode(path);
+		let path = match path.strip_prefix("~/") {
+			Some(home) => home.to_string(),
+			None => format!("/{path}"),
+		};
+
+		Ok(Self { destination: destination.to_string(), port, path })
+	}
+}
+
+// One end of the sftp subsystem
+struct Session<W: Write, R: Read> {
+	to: W,
+	from: R,
+	next_id: u32,
+}
+
+impl<W: Write, R: Read> Session<W, R> {
+	// Sends a request, with the ID its answer comes back under
+	fn request(&mut self, kind: u8, fields: &[u8]) -> io::Result<u32> {
+		let id = self.next_id;
+		self.next_id = self.next_id.wrapping_add(1);
+
+		let mut payload = id.to_be_bytes().to_vec();
+		payload.extend_from_slice(fields);
+		self.send(kind, &payload)?;
+
+		Ok(id)
+	}
+
+	fn send(&mut self, kind: u8, payload: &[u8]) -> io::Result<()> {
+		let len = u32::try_from(payload.len() + 1).map_err(io::Error::other)?;
+
+		self.to.write_all(&len.to_be_bytes())?;
+		self.to.write_all(&[kind])?;
+		self.to.write_all(payload)?;
+		self.to.flush()
+	}
+
+	// The next packet, its type and what follows it
+	fn receive(&mut self) -> io::Result<(u8, Vec<u8>)> {
+		let mut len = [0; 4];
+		self.from.read_exact(&mut len)?;
+
+		let len = u32::from_be_bytes(len) as usize;
+		if len == 0 || len > 1024 * 1024 {
+			return Err(io::Error::other(format!("the server sent a {len} byte packet")));
+		}
+
+		let mut packet = vec![0; len];
+		self.from.read_exact(&mut packet)?;
+		let body = packet.split_off(1);
+
+		Ok((packet[0], body))
+	}
+
+	// The answer to request `id`, which has to be next as nothing else is asked meanwhile
+	fn answer(&mut self, id: u32) -> io::Result<(u8, Fields)> {
+		let (kind, body) = self.receive()?;
+		let mut fields = Fields(body);
+
+		if fields.u32()? != id {
+			return Err(io::Error::other("the server answered another request"));
+		}
+
+		Ok((kind, fields))
+	}
+}
+
+// What's in a packet, read front to back
+struct Fields(Vec<u8>);
+
+impl Fields {
+	fn take(&mut self, len: usize) -> io::Result<Vec<u8>> {
+		if self.0.len() < len {
+			return Err(io::Error::other("the server sent a packet that's cut short"));
+		}
+
+		let rest = self.0.split_off(len);
+		Ok(std::mem::replace(&mut self.0, rest))
+	}
+
+	fn u32(&mut self) -> io::Result<u32> {
+		Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap_or_default()))
+	}
+
+	fn u64(&mut self) -> io::Result<u64> {
+		Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap_or_default()))
+	}
+
+	fn string(&mut self) -> io::Result<Vec<u8>> {
+		let len = self.u32()? as usize;
+		self.take(len)
+	}
+
+	// A status packet's code, without reading past it
+	fn code(&self) -> Option<u32> {
+		Some(u32::from_be_bytes(self.0.get(..4)?.try_into().ok()?))
+	}
+
+	// Why a request failed, from a status packet
+	fn status(mut self) -> io::Error {
+		let code = self.u32().unwrap_or_default();
+		let message = self.string().map(|message| String::from_utf8_lossy(&message).into_owned()).unwrap_or_default();
+
+		match message.is_empty() {
+			true => io::Error::other(format!("the server failed with status {code}")),
+			false => io::Error::other(message),
+		}
+	}
+}
+
+fn string(value: &[u8]) -> Vec<u8> {
+	let mut field = (value.len() as u32).to_be_bytes().to_vec();
+	field.extend_from_slice(value);
+	field
+}
+
+// The file being read, ahead of what's been handed on
+struct Download<W: Write, R: Read> {
+	session: Session<W, R>,
+	handle: Vec<u8>,
+	size: Option<u64>,
+	/// Where the next read asked for starts
+	next: u64,
+	/// Reads on their way, by ID, offset and length, in the order their data goes out
+	pending: VecDeque<(u32, u64, u32)>,
+	/// Answers that came in ahead of their turn
+	early: HashMap<u32, (u8, Fields)>,
+	chunk: Vec<u8>,
+	pos: usize,
+	/// The server has said there's no more
+	ended: bool,
+	/// ssh, for as long as the download needs it
+	child: Option<Child>,
+}
+
+impl<W: Write, R: Read> Download<W, R> {
+	fn start(mut session: Session<W, R>, path: &str) -> Result<Self, String> {
+		let error = |e: io::Error| format!("SFTP error: {path}: {e}");
+
+		session.send(INIT, &3u32.to_be_bytes()).map_err(error)?;
+		match session.receive().map_err(error)? {
+			(VERSION, _) => {},
+			(kind, _) => return Err(format!("SFTP error: the server started with a packet of type {kind}")),
+		}
+
+		let mut open = string(path.as_bytes());
+		open.extend_from_slice(&OPEN_READ.to_be_bytes());
+		open.extend_from_slice(&0u32.to_be_bytes());
+
+		let id = session.request(OPEN, &open).map_err(error)?;
+		let handle = match session.answer(id).map_err(error)? {
+			(HANDLE, mut fields) => fields.string().map_err(error)?,
+			(_, fields) => return Err(error(fields.status())),
+		};
+
+		// Without a size the progress can't say how far along it is, the download still works
+		let id = session.request(FSTAT, &string(&handle)).map_err(error)?;
+		let size = match session.answer(id).map_err(error)? {
+			(ATTRS, mut fields) => match fields.u32().map_err(error)? & SIZE {
+				0 => None,
+				_ => Some(fields.u64().map_err(error)?),
+			},
+			_ => None,
+		};
+
+		Ok(Self {
+			session,
+			handle,
+			size,
+			next: 0,
+			pending: VecDeque::new(),
+			early: HashMap::new(),
+			chunk: Vec::new(),
+			pos: 0,
+			ended: false,
+			child: None,
+		})
+	}
+
+	fn ask(&mut self, offset: u64, len: u32) -> io::Result<u32> {
+		let mut read = string(&self.handle);
+		read.extend_from_slice(&offset.to_be_bytes());
+		read.extend_from_slice(&len.to_be_bytes());
+
+		self.session.request(READ, &read)
+	}
+
+	// Asks for more, up to the end when the size is known
+	fn fill(&mut self) -> io::Result<()> {
+		while self.pending.len() < IN_FLIGHT && !self.ended && self.size.is_none_or(|size| self.next < size) {
+			let len = self.size.map_or(CHUNK, |size| (size - self.next).min(u64::from(CHUNK)) as u32);
+			let id = self.ask(self.next, len)?;
+
+			self.pending.push_back((id, self.next, len));
+			self.next += u64::from(len);
+		}
+
+		Ok(())
+	}
+
+	// The answer to `id`, keeping the ones for reads further on
+	fn answer(&mut self, id: u32) -> io::Result<(u8, Fields)> {
+		loop {
+			if let Some(answer) = self.early.remove(&id) {
+				return Ok(answer);
+			}
+
+			let (kind, body) = self.session.receive()?;
+			let mut fields = Fields(body);
+			let answered = fields.u32()?;
+			self.early.insert(answered, (kind, fields));
+		}
+	}
+}
+
+impl<W: Write, R: Read> Read for Download<W, R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		while self.pos == self.chunk.len() {
+			self.fill()?;
+
+			let Some((id, offset, len)) = self.pending.pop_front() else {
+				return Ok(0);
+			};
+
+			match self.answer(id)? {
+				(DATA, mut fields) => {
+					let data = fields.string()?;
+
+					// The server can send less than was asked for, the rest is asked for again
+					if (data.len() as u32) < len && !data.is_empty() {
+						let rest = offset + data.len() as u64;
+						let id = self.ask(rest, len - data.len() as u32)?;
+						self.pending.push_front((id, rest, len - data.len() as u32));
+					}
+
+					(self.chunk, self.pos) = (data, 0);
+				},
+				(STATUS, fields) if fields.code() == Some(EOF) => {
+					if self.size.is_some_and(|size| offset < size) {
+						return Err(io::Error::other("the file got shorter while it was read"));
+					}
+
+					self.ended = true;
+				},
+				(_, fields) => return Err(fields.status()),
+			}
+		}
+
+		let len = buf.len().min(self.chunk.len() - self.pos);
+		buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+		self.pos += len;
+
+		Ok(len)
+	}
+}
+
+impl<W: Write, R: Read> Drop for Download<W, R> {
+	fn drop(&mut self) {
+		let _ = self.session.request(CLOSE, &string(&self.handle));
+
+		if let Some(mut child) = self.child.take() {
+			let _ = child.kill();
+			let _ = child.wait();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::image;
+
+	#[test]
+	fn an_image_is_read_over_sftp() {
+		assert_eq!(from_scp("build@ci:/srv/images/golden.iso").as_deref(), Some("sftp://build@ci/srv/images/golden.iso"));
+		assert_eq!(from_scp("ci:images/golden.iso").as_deref(), Some("sftp://ci/~/images/golden.iso"));
+		assert_eq!(from_scp(r"C:\images\golden.iso"), None);
+		assert_eq!(from_scp("./odd:name.iso"), None);
+
+		assert_eq!(Location::parse("build@ci:2222/~/golden.iso"), Ok(Location {
+			destination: "build@ci".into(),
+			port: Some(2222),
+			path: "golden.iso".into(),
+		}));
+
+		// A server that sends at most 1000 bytes a read
+		let served = image(150_000);
+		let (client_rx, mut server_tx) = io::pipe().unwrap();
+		let (mut server_rx, client_tx) = io::pipe().unwrap();
+
+		let file = served.clone();
+		let server = std::thread::spawn(move || {
+			let mut session = Session { to: &mut server_tx, from: &mut server_rx, next_id: 0 };
+			while let Ok((kind, body)) = session.receive() {
+				let mut fields = Fields(body);
+				if kind == INIT {
+					session.send(VERSION, &3u32.to_be_bytes()).unwrap();
+					continue;
+				}
+
+				let mut reply = fields.u32().unwrap().to_be_bytes().to_vec();
+				let kind = match kind {
+					OPEN => {
+						reply.extend(string(b"h"));
+						HANDLE
+					},
+					FSTAT => {
+						reply.extend(SIZE.to_be_bytes());
+						reply.extend((file.len() as u64).to_be_bytes());
+						ATTRS
+					},
+					READ => {
+						let (_, offset, len) = (fields.string().unwrap(), fields.u64().unwrap() as usize, fields.u32().unwrap() as usize);
+						reply.extend(string(&file[offset..(offset + len.min(1000)).min(file.len())]));
+						DATA
+					},
+					_ => break,
+				};
+				session.send(kind, &reply).unwrap();
+			}
+		});
+
+		let mut download = Download::start(Session { to: client_tx, from: client_rx, next_id: 0 }, "/srv/golden.img").unwrap();
+		assert_eq!(download.size, Some(150_000));
+
+		let mut read = Vec::new();
+		download.read_to_end(&mut read).unwrap();
+		assert!(read == served);
+
+		drop(download);
+		server.join().unwrap();
+	}
+}
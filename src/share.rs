@@ -0,0 +1,177 @@
+// Images on a LAN file server, streamed straight from the share instead of copied over first.
+//
+// `smb://[user@]server/share/path/to/image.iso` goes through Samba's `smbclient`, with the
+// password from `PASSWD` like smbclient takes it, or as a guest without a user. UNC paths,
+// `\\server\share\path\to\image.iso`, are the same, Windows opens them itself.
+// `nfs://server/export/path/to/image.iso` goes through libnfs's `nfs-cat`, no mount needed.
+// Neither lists images, a share has too many files that aren't any.
+
+use std::io::Read;
+use std::process::Command;
+
+use crate::provider::{self, Image, Provider};
+use crate::source;
+use crate::tools;
+
+pub struct Smb;
+
+pub struct Nfs;
+
+// Where a file is on an SMB share
+#[derive(Debug, PartialEq, Eq)]
+struct SmbPath {
+	user: Option<String>,
+	/// `//server/share`, as smbclient takes it
+	service: String,
+	/// The path in the share, with backslashes
+	path: String,
+}
+
+/// The `smb://` URL for a `\\server\share\...` path, `None` for anything else. `None` on
+/// Windows, where the path opens as it is.
+pub fn from_unc(path: &str) -> Option<String> {
+	if cfg!(windows) {
+		return None;
+	}
+
+	let rest = path.strip_prefix(r"\\")?;
+	Some(format!("smb://{}", rest.replace('\\', "/")))
+}
+
+impl Provider for Smb {
+	fn scheme(&self) -> &str {
+		"smb"
+	}
+
+	fn list(&self) -> Result<Vec<Image>, String> {
+		Ok(Vec::new())
+	}
+
+	fn open(&self, name: &str) -> Result<(Box<dyn Read + Send>, u64), String> {
+		let file = SmbPath::parse(name)?;
+
+		// Which also finds out the file is there before the write starts
+		let args = file.args(&format!("allinfo \"{}\"", file.path));
+		let info = tools::run("smbclient", &args.iter().map(String::as_str).collect::<Vec<_>>())?;
+		let size = stream_size(&info).unwrap_or(0);
+
+		let mut command = Command::new("smbclient");
+		command.args(file.args(&format!("get \"{}\" -", file.path)));
+
+		Ok((provider::stream(command, "smbclient")?, size))
+	}
+}
+
+impl SmbPath {
+	// `[user@]server/share/path`, as it comes after smb://
+	fn parse(name: &str) -> Result<Self, String> {
+		let name = source::percent_decode(name);
+		let (user, rest) = match name.split_once('@') {
+			Some((user, rest)) => (Some(user.to_string()), rest),
+			None => (None, name.as_str()),
+		};
+
+		let mut parts = rest.splitn(3, '/');
+		let (Some(server), Some(share), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+			return Err(format!("Share error: smb://{name} needs a server, share and file, e.g. smb://nas/images/golden.iso"));
+		};
+
+		if server.is_empty() || share.is_empty() || path.is_empty() || path.ends_with('/') {
+			return Err(format!("Share error: smb://{name} needs a server, share and file, e.g. smb://nas/images/golden.iso"));
+		}
+
+		// There's no escaping a quote inside smbclient's quotes, and -c splits commands at ; even there
+		if let Some(c) = path.chars().find(|&c| c == '"' || c == ';') {
+			return Err(format!("Share error: smbclient can't fetch a file with {c} in its name, {path}"));
+		}
+
+		Ok(Self { user, service: format!("//{server}/{share}"), path: path.replace('/', "\\") })
+	}
+
+	// smbclient running `command` in the share, with its messages on stderr so only the file
+	// comes out on stdout
+	fn args(&self, command: &str) -> Vec<String> {
+		let mut args = vec![self.service.clone(), "-E".into(), "-q".into()];
+
+		match &self.user {
+			Some(user) => args.extend(["-U".into(), user.clone()]),
+			None => args.push("-N".into()),
+		}
+
+		args.extend(["-c".into(), command.into()]);
+		args
+	}
+}
+
+// The size in smbclient's `allinfo`, `stream: [::$DATA], 4294967296 bytes`
+fn stream_size(info: &str) -> Option<u64> {
+	info.lines()
+		.find_map(|line| line.trim().strip_prefix("stream: [::$DATA],"))
+		.and_then(|size| size.trim().strip_suffix("bytes"))
+		.and_then(|size| size.trim().parse().ok())
+}
+
+impl Provider for Nfs {
+	fn scheme(&self) -> &str {
+		"nfs"
+	}
+
+	fn list(&self) -> Result<Vec<Image>, String> {
+		Ok(Vec::new())
+	}
+
+	fn open(&self, name: &str) -> Result<(Box<dyn Read + Send>, u64), String> {
+		let url = format!("nfs://{name}");
+		let Some((dir, file)) = name.rsplit_once('/').filter(|(dir, file)| dir.contains('/') && !file.is_empty()) else {
+			return Err(format!("Share error: {url} needs a server, export and file, e.g. nfs://nas/srv/images/golden.iso"));
+		};
+
+		// Only for the progress, nfs-cat says when the file isn't there
+		let size = tools::run("nfs-ls", &[&format!("nfs://{dir}")])
+			.ok()
+			.and_then(|listing| listed_size(&listing, &source::percent_decode(file)))
+			.unwrap_or(0);
+
+		let mut command = Command::new("nfs-cat");
+		command.arg(&url);
+
+		Ok((provider::stream(command, "nfs-cat")?, size))
+	}
+}
+
+// The size of `file` in nfs-ls's listing, `-rw-r--r--  1  1000  1000   4294967296 golden.iso`
+fn listed_size(listing: &str, file: &str) -> Option<u64> {
+	listing.lines().find_map(|line| {
+		let mut fields = line.split_whitespace();
+		let size = fields.nth(4)?;
+		let name = fields.collect::<Vec<_>>().join(" ");
+
+		(name == file || name.ends_with(&format!("/{file}"))).then(|| size.parse().ok()).flatten()
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn share_paths_are_found_on_their_server() {
+		assert_eq!(SmbPath::parse("ops@nas/images/Field%20Kit/golden.iso"), Ok(SmbPath {
+			user: Some("ops".into()),
+			service: "//nas/images".into(),
+			path: r"Field Kit\golden.iso".into(),
+		}));
+		assert!(SmbPath::parse("nas/images").is_err());
+		assert!(SmbPath::parse("nas/images/folder/").is_err());
+		assert!(SmbPath::parse("nas/images/a\"b.iso").is_err());
+		assert!(SmbPath::parse("nas/images/golden.iso;!rm -rf ~").is_err());
+
+		if !cfg!(windows) {
+			assert_eq!(from_unc(r"\\nas\images\golden.iso").as_deref(), Some("smb://nas/images/golden.iso"));
+		}
+		assert_eq!(from_unc("/srv/golden.iso"), None);
+
+		assert_eq!(stream_size("altname: golden.iso\nstream: [::$DATA], 4294967296 bytes\n"), Some(4_294_967_296));
+		assert_eq!(listed_size("-rw-r--r--  1  1000  1000       1024 other.iso\n-rw-r--r--  1  1000  1000       2048 golden.iso\n", "golden.iso"), Some(2048));
+	}
+}
@@ -0,0 +1,68 @@
+// A short sound when a job ends, for when the machine is across the room from the sticks.
+//
+// iced has no audio, so this goes to whatever the platform plays event sounds with: the
+// freedesktop sound theme through canberra-gtk-play (or paplay) on Linux, afplay on macOS,
+// and the system sounds through PowerShell on Windows.
+
+/// How a job ended, each with its own sound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sound {
+	Complete,
+	Failed,
+}
+
+/// Plays `sound` without waiting for it.
+pub fn play(sound: Sound) {
+	// Fire and forget, no player just means no sound
+	std::thread::spawn(move || run(sound));
+}
+
+#[cfg(target_os = "linux")]
+fn run(sound: Sound) {
+	let (id, file) = match sound {
+		Sound::Complete => ("complete", "/usr/share/sounds/freedesktop/stereo/complete.oga"),
+		Sound::Failed => ("dialog-error", "/usr/share/sounds/freedesktop/stereo/dialog-error.oga"),
+	};
+
+	// The sound theme's own sound if there's one, the stock freedesktop one otherwise
+	if !quietly(std::process::Command::new("canberra-gtk-play").args(["--id", id])) {
+		quietly(std::process::Command::new("paplay").arg(file));
+	}
+}
+
+#[cfg(target_os = "macos")]
+fn run(sound: Sound) {
+	let file = match sound {
+		Sound::Complete => "/System/Library/Sounds/Glass.aiff",
+		Sound::Failed => "/System/Library/Sounds/Basso.aiff",
+	};
+
+	quietly(std::process::Command::new("afplay").arg(file));
+}
+
+#[cfg(windows)]
+fn run(sound: Sound) {
+	let name = match sound {
+		Sound::Complete => "Asterisk",
+		Sound::Failed => "Hand",
+	};
+
+	quietly(std::process::Command::new("powershell").args([
+		"-NoProfile",
+		"-Command",
+		&format!("[System.Media.SystemSounds]::{name}.Play(); Start-Sleep -Milliseconds 500"),
+	]));
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn run(_sound: Sound) {}
+
+// Whether it played
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn quietly(command: &mut std::process::Command) -> bool {
+	command
+		.stdout(std::process::Stdio::null())
+		.stderr(std::process::Stdio::null())
+		.status()
+		.is_ok_and(|status| status.success())
+}
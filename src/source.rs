@@ -0,0 +1,426 @@
+// Opening images for writing, decompressing them on the way when needed.
+
+mod blocks;
+mod dmg;
+mod qcow2;
+mod vhd;
+mod vhdx;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+use memmap2::Mmap;
+
+use crate::cache;
+use crate::hash::{self, Algorithm, Hasher};
+use crate::provider;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+	None,
+	Xz,
+	Zstd,
+}
+
+/// Disk image formats that wrap the disk rather than just compressing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+	Dmg,
+	Vhd,
+	Vhdx,
+	Qcow2,
+}
+
+/// An image opened for reading, yielding the raw bytes to put on the device.
+pub struct Source {
+	pub reader: Box<dyn Read + Send>,
+	/// Size of the file itself, so the compressed size for compressed images.
+	/// Zero when a server didn't say how big the download is
+	pub len: u64,
+	/// Bytes of the file consumed so far, which is what progress is measured against
+	pub consumed: Arc<AtomicU64>,
+	/// Checksum of the file as it streams past, when one was asked for
+	pub digest: Option<RunningHash>,
+}
+
+/// Hash of the raw file bytes, updated as the source is read.
+#[derive(Clone)]
+pub struct RunningHash(Arc<Mutex<Pending>>);
+
+enum Pending {
+	Streaming(Hasher),
+	/// Containers are read out of order, so their file is hashed by a second reader
+	Separate(JoinHandle<Option<String>>),
+	Finished,
+}
+
+impl RunningHash {
+	fn streaming(algorithm: Algorithm) -> Self {
+		Self(Arc::new(Mutex::new(Pending::Streaming(algorithm.hasher()))))
+	}
+
+	fn separate(path: &str, algorithm: Algorithm) -> Self {
+		let hash = Self(Arc::new(Mutex::new(Pending::Finished)));
+		let path = path.to_string();
+
+		// Gives up once the source is dropped, e.g. the job was cancelled
+		let alive = Arc::downgrade(&hash.0);
+		let handle = std::thread::spawn(move || {
+			let file = File::open(path).ok()?;
+			hash::hash_reader(file, algorithm, |_| alive.strong_count() > 0).ok()
+		});
+
+		if let Ok(mut pending) = hash.0.lock() {
+			*pending = Pending::Separate(handle);
+		}
+
+		hash
+	}
+
+	pub fn update(&self, data: &[u8]) {
+		if let Ok(mut pending) = self.0.lock() && let Pending::Streaming(hasher) = &mut *pending {
+			hasher.update(data);
+		}
+	}
+
+	/// Hex digest of everything read so far, can only be taken once.
+	pub fn finish(&self) -> Option<String> {
+		let pending = std::mem::replace(&mut *self.0.lock().ok()?, Pending::Finished);
+
+		match pending {
+			Pending::Streaming(hasher) => Some(hasher.finalize()),
+			Pending::Separate(handle) => handle.join().ok().flatten(),
+			Pending::Finished => None,
+		}
+	}
+}
+
+/// `-` reads the image from stdin, like dd does.
+pub const STDIN: &str = "-";
+
+/// Whether `path` is streamed from somewhere rather than a local file, over http(s) or from a provider.
+pub fn is_url(path: &str) -> bool {
+	path.starts_with("http://") || path.starts_with("https://") || provider::handles(path)
+}
+
+/// Turns a typed or pasted source into a URL or the path of an image file that exists.
+///
+/// Takes what file managers put on the clipboard too: quoted paths and `file://` URIs.
+pub fn resolve(input: &str) -> Result<String, String> {
+	let input = input.trim();
+	let input = ["\"", "'"].iter()
+		.find_map(|quote| input.strip_prefix(quote)?.strip_suffix(quote))
+		.unwrap_or(input);
+
+	if input.is_empty() {
+		return Err("Type or paste the path or URL of an image".into());
+	}
+
+	if is_url(input) {
+		return Ok(input.to_string());
+	}
+
+	if input.contains("://") && !input.starts_with("file://") {
+		return Err("Only http:// and https:// URLs, and those of the providers set up, can be streamed".into());
+	}
+
+	let path = match input.strip_prefix("file://") {
+		// The host part is empty for local files, file:///home/...
+		Some(uri) => percent_decode(uri.strip_prefix("localhost").unwrap_or(uri)),
+		None => input.to_string(),
+	};
+
+	let path = match (path.strip_prefix("~/"), dirs::home_dir()) {
+		(Some(rest), Some(home)) => home.join(rest).to_string_lossy().to_string(),
+		_ => path,
+	};
+
+	let metadata = std::fs::metadata(&path).map_err(|e| format!("Source error: {path}: {e}"))?;
+	if metadata.is_dir() {
+		return Err(format!("{path} is a folder, pick an image file"));
+	}
+
+	Ok(path)
+}
+
+/// `%20` and friends back to the bytes they stand for.
+pub fn percent_decode(uri: &str) -> String {
+	let bytes = uri.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+
+	while i < bytes.len() {
+		let hex = bytes.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+		match (bytes[i], hex) {
+			(b'%', Some(byte)) => {
+				decoded.push(byte);
+				i += 3;
+			},
+			(byte, _) => {
+				decoded.push(byte);
+				i += 1;
+			},
+		}
+	}
+
+	String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Opens a local image, stdin or starts streaming a URL, optionally hashing the raw bytes on the way.
+///
+/// This blocks on the network for URLs, so keep it off the UI thread.
+pub fn open(path: &str, hash: Option<Algorithm>) -> Result<Source, String> {
+	let (inner, len): (Box<dyn Read + Send>, u64) = if path == STDIN {
+		// Piped in, so there's no telling how big it is
+		(Box::new(io::stdin()), 0)
+	} else if let Some((provider, name)) = provider::find(path) {
+		provider.open(&name)?
+	} else if is_url(path) {
+		// Streamed straight into the writer, nothing is kept on disk
+		let response = ureq::get(path)
+			.call()
+			.map_err(|e| format!("Download error: {e}"))?;
+
+		let body = response.into_body();
+		let len = body.content_length().unwrap_or(0);
+
+		(Box::new(body.into_reader()), len)
+	} else {
+		let mut file = File::open(path).map_err(|e| format!("Source error: {e}"))?;
+		let len = file.metadata().map_err(|e| format!("Metadata error: {e}"))?.len();
+
+		match detect_container(&mut file).map_err(|e| format!("Source error: {e}"))? {
+			Some(Container::Dmg) => return open_container(path, file, len, hash, dmg::Dmg::new),
+			Some(Container::Vhd) => return open_container(path, file, len, hash, vhd::open),
+			Some(Container::Vhdx) => return open_container(path, file, len, hash, vhdx::open),
+			Some(Container::Qcow2) => return open_container(path, file, len, hash, qcow2::open),
+			None => {},
+		}
+
+		// Read once, it isn't worth keeping in the page cache
+		(Box::new(cache::DropBehind::new(file)), len)
+	};
+
+	let consumed = Arc::new(AtomicU64::new(0));
+	let digest = hash.map(RunningHash::streaming);
+	let counted = Counting { inner, consumed: consumed.clone(), digest: digest.clone() };
+
+	// Sniff the magic bytes rather than trusting the extension
+	let mut buffered = BufReader::with_capacity(1024 * 1024, counted);
+	let compression = detect(buffered.fill_buf().map_err(|e| format!("Source error: {e}"))?);
+
+	let reader: Box<dyn Read + Send> = match compression {
+		Compression::None => Box::new(buffered),
+		Compression::Xz => Box::new(xz_decoder(buffered)?),
+		Compression::Zstd => Box::new(prefetch(zstd_decoder(buffered)?)),
+	};
+
+	Ok(Source { reader, len, consumed, digest })
+}
+
+/// A plain local image mapped into memory, so it's written without copying each chunk into a buffer first.
+pub struct Mapped {
+	pub map: Arc<Mmap>,
+	/// The mapped file, to drop its cached pages once they're written
+	pub file: File,
+	/// Checksum of the file, when one was asked for, for the writer to update as it goes
+	pub digest: Option<RunningHash>,
+}
+
+/// Maps an uncompressed local image, `None` for anything else, which is read through [`open`] instead.
+pub fn map(path: &str, hash: Option<Algorithm>) -> Option<Mapped> {
+	if path == STDIN || is_url(path) {
+		return None;
+	}
+
+	let mut file = File::open(path).ok()?;
+	if !file.metadata().ok()?.is_file() || detect_container(&mut file).ok()?.is_some() {
+		return None;
+	}
+
+	// SAFETY: the image changing while it's written would garble the write whichever way it's
+	// read; the map only adds that it being truncated meanwhile kills the process
+	let map = unsafe { Mmap::map(&file) }.ok()?;
+	if map.is_empty() || detect(&map) != Compression::None {
+		return None;
+	}
+
+	// It's read front to back once, the kernel can read ahead and drop pages behind
+	#[cfg(unix)]
+	let _ = map.advise(memmap2::Advice::Sequential);
+
+	Some(Mapped { map: Arc::new(map), file, digest: hash.map(RunningHash::streaming) })
+}
+
+fn detect_container(file: &mut File) -> io::Result<Option<Container>> {
+	if qcow2::detect(file)? {
+		Ok(Some(Container::Qcow2))
+	} else if vhdx::detect(file)? {
+		Ok(Some(Container::Vhdx))
+	} else if vhd::detect(file)? {
+		Ok(Some(Container::Vhd))
+	} else if dmg::detect(file)? {
+		Ok(Some(Container::Dmg))
+	} else {
+		Ok(None)
+	}
+}
+
+// Disk image containers need to seek around the file, so they only work for local files
+fn open_container<R: Read + Send + 'static>(
+	path: &str,
+	file: File,
+	len: u64,
+	hash: Option<Algorithm>,
+	open: impl FnOnce(Counting<File>) -> Result<R, String>,
+) -> Result<Source, String>
+{
+	let consumed = Arc::new(AtomicU64::new(0));
+	let counted = Counting { inner: file, consumed: consumed.clone(), digest: None };
+	let reader = Box::new(open(counted)?);
+	let digest = hash.map(|algorithm| RunningHash::separate(path, algorithm));
+
+	Ok(Source { reader, len, consumed, digest })
+}
+
+fn detect(magic: &[u8]) -> Compression {
+	match magic {
+		[0xFD, b'7', b'z', b'X', b'Z', 0x00, ..] => Compression::Xz,
+		[0x28, 0xB5, 0x2F, 0xFD, ..] => Compression::Zstd,
+		_ => Compression::None,
+	}
+}
+
+// Multi-threaded decoding only kicks in for files with several blocks (`xz -T`),
+// single block files still decode fine, just on one thread
+fn xz_decoder(reader: impl BufRead + Send) -> Result<impl Read + Send, String> {
+	use liblzma::stream::MtStreamBuilder;
+
+	let threads = std::thread::available_parallelism().map_or(1, |n| n.get() as u32);
+
+	// Let the decoder use up to a quarter of RAM for threads before falling back to one
+	let mut system = sysinfo::System::new();
+	system.refresh_memory();
+	let memlimit = (system.total_memory() / 4).max(256 * 1024 * 1024);
+
+	let stream = MtStreamBuilder::new()
+		.threads(threads)
+		.memlimit_threading(memlimit)
+		.memlimit_stop(u64::MAX)
+		.decoder()
+		.map_err(|e| format!("xz error: {e}"))?;
+
+	Ok(liblzma::bufread::XzDecoder::new_stream(reader, stream))
+}
+
+// Images compressed with `zstd --long` need a bigger window than the decoder allows by default
+fn zstd_decoder(reader: impl BufRead + Send + 'static) -> Result<impl Read + Send + 'static, String> {
+	let mut decoder = zstd::stream::read::Decoder::with_buffer(reader).map_err(|e| format!("zstd error: {e}"))?;
+
+	decoder.window_log_max(31).map_err(|e| format!("zstd error: {e}"))?;
+
+	Ok(decoder)
+}
+
+// zstd decompression itself is single threaded, so the next few chunks are decoded on a
+// thread of their own while the writer is busy with the current one
+fn prefetch(mut reader: impl Read + Send + 'static) -> Prefetch {
+	let (tx, rx) = mpsc::sync_channel(4);
+
+	std::thread::spawn(move || loop {
+		let mut chunk = vec![0; 1024 * 1024];
+
+		match read_full(&mut reader, &mut chunk) {
+			Ok(0) => break,
+			Ok(n) => {
+				chunk.truncate(n);
+
+				// The reading side went away, e.g. the job was cancelled
+				if tx.send(Ok(chunk)).is_err() {
+					break;
+				}
+			},
+			Err(e) => {
+				let _ = tx.send(Err(e));
+				break;
+			},
+		}
+	});
+
+	Prefetch { rx, chunk: Vec::new(), pos: 0 }
+}
+
+struct Prefetch {
+	rx: mpsc::Receiver<io::Result<Vec<u8>>>,
+	chunk: Vec<u8>,
+	pos: usize,
+}
+
+impl Read for Prefetch {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.pos == self.chunk.len() {
+			match self.rx.recv() {
+				Ok(Ok(chunk)) => {
+					self.chunk = chunk;
+					self.pos = 0;
+				},
+				Ok(Err(e)) => return Err(e),
+				// Decoder thread finished
+				Err(_) => return Ok(0),
+			}
+		}
+
+		let n = buf.len().min(self.chunk.len() - self.pos);
+		buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+		self.pos += n;
+
+		Ok(n)
+	}
+}
+
+/// Fills as much of `buffer` as possible, only returning short at the end of the stream.
+///
+/// Decoders tend to hand out small reads, this keeps writes to the device in big chunks.
+pub fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+	let mut filled = 0;
+
+	while filled < buffer.len() {
+		match reader.read(&mut buffer[filled..]) {
+			Ok(0) => break,
+			Ok(n) => filled += n,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		}
+	}
+
+	Ok(filled)
+}
+
+struct Counting<R> {
+	inner: R,
+	consumed: Arc<AtomicU64>,
+	digest: Option<RunningHash>,
+}
+
+impl<R: Read> Read for Counting<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.inner.read(buf)?;
+		self.consumed.fetch_add(n as u64, Ordering::Relaxed);
+
+		if let Some(digest) = &self.digest {
+			digest.update(&buf[..n]);
+		}
+
+		Ok(n)
+	}
+}
+
+impl<R: Seek> Seek for Counting<R> {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		self.inner.seek(pos)
+	}
+}
@@ -0,0 +1,55 @@
+// Shared reader for images made of fixed size blocks, each stored somewhere in the file or left out.
+
+use std::io::{self, Read};
+
+pub trait Blocks {
+	/// Size of the disk inside the image
+	fn size(&self) -> u64;
+
+	fn block_size(&self) -> u64;
+
+	/// Fills `buffer`, one block long, with the contents of block `index`.
+	fn read_block(&mut self, index: u64, buffer: &mut [u8]) -> io::Result<()>;
+}
+
+/// Reads the disk inside a block mapped image from start to end.
+pub struct Flatten<B> {
+	blocks: B,
+	/// Bytes of the disk produced so far
+	position: u64,
+	buffer: Vec<u8>,
+	pos: usize,
+}
+
+impl<B: Blocks> Flatten<B> {
+	pub fn new(blocks: B) -> Self {
+		Self { blocks, position: 0, buffer: Vec::new(), pos: 0 }
+	}
+}
+
+impl<B: Blocks> Read for Flatten<B> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.pos == self.buffer.len() {
+			let size = self.blocks.size();
+			let block_size = self.blocks.block_size();
+
+			if self.position >= size {
+				return Ok(0);
+			}
+
+			self.buffer.resize(block_size as usize, 0);
+			self.blocks.read_block(self.position / block_size, &mut self.buffer)?;
+
+			// The last block can hang over the end of the disk
+			self.buffer.truncate((size - self.position).min(block_size) as usize);
+			self.pos = 0;
+		}
+
+		let n = buf.len().min(self.buffer.len() - self.pos);
+		buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+		self.pos += n;
+		self.position += n as u64;
+
+		Ok(n)
+	}
+}
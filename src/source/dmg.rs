@@ -0,0 +1,313 @@
+// Apple disk images (UDIF .dmg), flattened to the raw disk they describe.
+//
+// The file ends in a "koly" trailer pointing at an XML plist, whose blkx entries are
+// tables of chunks mapping runs of sectors to raw, zeroed or compressed data.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use base64::Engine;
+use flate2::read::ZlibDecoder;
+
+const SECTOR: u64 = 512;
+const TRAILER: usize = 512;
+// hdiutil's chunks are a MiB at most, anything bigger is a broken table
+const MAX_CHUNK: u64 = 64 * 1024 * 1024;
+
+// Chunk types
+const ZERO: u32 = 0x0000_0000;
+const RAW: u32 = 0x0000_0001;
+const IGNORE: u32 = 0x0000_0002;
+const ADC: u32 = 0x8000_0004;
+const ZLIB: u32 = 0x8000_0005;
+const BZIP2: u32 = 0x8000_0006;
+const LZFSE: u32 = 0x8000_0007;
+const LZMA: u32 = 0x8000_0008;
+const COMMENT: u32 = 0x7FFF_FFFE;
+const END: u32 = 0xFFFF_FFFF;
+
+/// Whether `file` ends in a UDIF trailer, there's nothing to go on at the start of the file.
+pub fn detect(file: &mut (impl Read + Seek)) -> io::Result<bool> {
+	let found = trailer(file).is_ok_and(|trailer| &trailer[..4] == b"koly");
+	file.rewind()?;
+
+	Ok(found)
+}
+
+fn trailer(file: &mut (impl Read + Seek)) -> io::Result<[u8; TRAILER]> {
+	let mut trailer = [0; TRAILER];
+	file.seek(SeekFrom::End(-(TRAILER as i64)))?;
+	file.read_exact(&mut trailer)?;
+
+	Ok(trailer)
+}
+
+struct Chunk {
+	kind: u32,
+	/// Where the chunk goes on the disk
+	offset: u64,
+	len: u64,
+	/// Where its data is in the file
+	data_offset: u64,
+	data_len: u64,
+}
+
+/// Reads the disk inside a DMG from start to end.
+pub struct Dmg<R> {
+	inner: R,
+	chunks: Vec<Chunk>,
+	next: usize,
+	/// Size of the disk according to the trailer
+	size: u64,
+	/// Bytes of the disk produced so far
+	position: u64,
+	buffer: Vec<u8>,
+	pos: usize,
+	zeros: u64,
+}
+
+impl<R: Read + Seek> Dmg<R> {
+	pub fn new(mut inner: R) -> Result<Self, String> {
+		let trailer = trailer(&mut inner).map_err(|e| format!("DMG error: {e}"))?;
+		if &trailer[..4] != b"koly" {
+			return Err("DMG error: the image has no UDIF trailer".into());
+		}
+
+		let data_fork = be64(&trailer, 0x18);
+		let xml_offset = be64(&trailer, 0xD8);
+		let xml_len = be64(&trailer, 0xE0);
+		let sectors = be64(&trailer, 0x1EC);
+
+		// Very old images keep the tables in a resource fork instead
+		if xml_len == 0 {
+			return Err("DMG error: the image has no partition map, convert it with `hdiutil convert -format UDZO`".into());
+		}
+
+		// Only what the file has room for, before anything's allocated for it
+		let file_len = inner.seek(SeekFrom::End(0)).map_err(|e| format!("DMG error: {e}"))?;
+		if xml_offset.checked_add(xml_len).is_none_or(|end| end > file_len) {
+			return Err("DMG error: the partition map is past the end of the file".into());
+		}
+
+		let mut xml = vec![0; xml_len as usize];
+		inner.seek(SeekFrom::Start(xml_offset))
+			.and_then(|_| inner.read_exact(&mut xml))
+			.map_err(|e| format!("DMG error: {e}"))?;
+
+		let mut chunks = Vec::new();
+
+		for table in blkx_tables(&String::from_utf8_lossy(&xml))? {
+			parse_table(&table, data_fork, &mut chunks)?;
+		}
+
+		chunks.sort_by_key(|chunk| chunk.offset);
+
+		Ok(Self {
+			inner,
+			chunks,
+			next: 0,
+			size: sectors * SECTOR,
+			position: 0,
+			buffer: Vec::new(),
+			pos: 0,
+			zeros: 0,
+		})
+	}
+
+	// Loads the next run of output, returns false at the end of the disk
+	fn advance(&mut self) -> io::Result<bool> {
+		let Some(chunk) = self.chunks.get(self.next) else {
+			// Pad out to the size the trailer claims
+			self.zeros = self.size.saturating_sub(self.position);
+			return Ok(self.zeros > 0);
+		};
+
+		// Sectors no chunk covers read as zeros
+		if chunk.offset > self.position {
+			self.zeros = chunk.offset - self.position;
+			return Ok(true);
+		}
+
+		self.next += 1;
+		self.pos = 0;
+		self.buffer.clear();
+
+		match chunk.kind {
+			ZERO | IGNORE => self.zeros = chunk.len,
+			RAW => {
+				self.inner.seek(SeekFrom::Start(chunk.data_offset))?;
+				(&mut self.inner).take(chunk.data_len).read_to_end(&mut self.buffer)?;
+			},
+			ZLIB => {
+				self.inner.seek(SeekFrom::Start(chunk.data_offset))?;
+				ZlibDecoder::new((&mut self.inner).take(chunk.data_len)).read_to_end(&mut self.buffer)?;
+			},
+			ADC | BZIP2 | LZFSE | LZMA => {
+				return Err(io::Error::other(
+					"DMG images compressed with ADC, bzip2, LZFSE or LZMA aren't supported, convert with `hdiutil convert -format UDZO`",
+				));
+			},
+			kind => return Err(io::Error::other(format!("Unknown DMG chunk type {kind:#x}"))),
+		}
+
+		// Keep everything after this chunk in the right place even if its data came up short
+		if chunk.kind != ZERO && chunk.kind != IGNORE {
+			self.buffer.resize(chunk.len as usize, 0);
+		}
+
+		Ok(true)
+	}
+}
+
+impl<R: Read + Seek> Read for Dmg<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		while self.zeros == 0 && self.pos == self.buffer.len() {
+			if !self.advance()? {
+				return Ok(0);
+			}
+		}
+
+		let n = if self.zeros > 0 {
+			let n = self.zeros.min(buf.len() as u64) as usize;
+			buf[..n].fill(0);
+			self.zeros -= n as u64;
+			n
+		} else {
+			let n = buf.len().min(self.buffer.len() - self.pos);
+			buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+			self.pos += n;
+			n
+		};
+
+		self.position += n as u64;
+
+		Ok(n)
+	}
+}
+
+// The base64 "mish" tables under resource-fork/blkx, one per partition
+fn blkx_tables(xml: &str) -> Result<Vec<Vec<u8>>, String> {
+	let start = xml.find("<key>blkx</key>").ok_or("DMG error: no block tables in the image")?;
+	let blkx = &xml[start..];
+	let mut rest = &blkx[..blkx.find("</array>").unwrap_or(blkx.len())];
+	let mut tables = Vec::new();
+
+	while let Some(open) = rest.find("<data>") {
+		let body = &rest[open + "<data>".len()..];
+		let close = body.find("</data>").ok_or("DMG error: malformed partition map")?;
+
+		// Wrapped over several lines in the plist
+		let encoded: String = body[..close].split_whitespace().collect();
+		let table = base64::engine::general_purpose::STANDARD
+			.decode(encoded)
+			.map_err(|e| format!("DMG error: {e}"))?;
+
+		tables.push(table);
+		rest = &body[close..];
+	}
+
+	Ok(tables)
+}
+
+fn parse_table(table: &[u8], data_fork: u64, chunks: &mut Vec<Chunk>) -> Result<(), String> {
+	if table.len() < 0xCC || &table[..4] != b"mish" {
+		return Err("DMG error: bad block table".into());
+	}
+
+	let first_sector = be64(table, 0x08);
+	let data_offset = be64(table, 0x18);
+	let count = be32(table, 0xC8) as usize;
+
+	for i in 0..count {
+		let entry = table.get(0xCC + i * 40..0xCC + (i + 1) * 40).ok_or("DMG error: truncated block table")?;
+		let kind = be32(entry, 0);
+
+		if kind == COMMENT || kind == END {
+			continue;
+		}
+
+		let sectors = be64(entry, 16);
+		if kind != ZERO && kind != IGNORE && sectors > MAX_CHUNK / SECTOR {
+			return Err("DMG error: bad block table".into());
+		}
+
+		chunks.push(Chunk {
+			kind,
+			offset: first_sector.saturating_add(be64(entry, 8)).saturating_mul(SECTOR),
+			len: sectors.saturating_mul(SECTOR),
+			data_offset: data_fork.saturating_add(data_offset).saturating_add(be64(entry, 24)),
+			data_len: be64(entry, 32),
+		});
+	}
+
+	Ok(())
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+	u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn be64(data: &[u8], offset: usize) -> u64 {
+	u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Cursor, Write};
+
+	use flate2::write::ZlibEncoder;
+
+	use super::*;
+
+	#[test]
+	fn dmg_chunks_are_put_back_in_place() {
+		let raw = vec![0xAA; SECTOR as usize];
+		let mut zlib = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+		zlib.write_all(&[0xBB; SECTOR as usize]).unwrap();
+		let compressed = zlib.finish().unwrap();
+
+		// A raw sector, one of zeros, a compressed one, and the last padded out by the trailer
+		let mut table = vec![0; 0xCC];
+		table[..4].copy_from_slice(b"mish");
+		table[0xC8..0xCC].copy_from_slice(&4u32.to_be_bytes());
+		for (kind, sector, data_offset, data_len) in [
+			(RAW, 0, 0, SECTOR),
+			(ZERO, 1, 0, 0),
+			(ZLIB, 2, SECTOR, compressed.len() as u64),
+			(END, 3, 0, 0),
+		] {
+			let mut entry = [0; 40];
+			entry[..4].copy_from_slice(&kind.to_be_bytes());
+			entry[8..16].copy_from_slice(&(sector as u64).to_be_bytes());
+			entry[16..24].copy_from_slice(&1u64.to_be_bytes());
+			entry[24..32].copy_from_slice(&data_offset.to_be_bytes());
+			entry[32..40].copy_from_slice(&data_len.to_be_bytes());
+			table.extend(entry);
+		}
+
+		let encoded = base64::engine::general_purpose::STANDARD.encode(&table);
+		let xml = format!("<plist><dict><key>resource-fork</key><dict><key>blkx</key><array><dict><data>\n{encoded}\n</data></dict></array></dict></dict></plist>");
+
+		let mut file = [raw.clone(), compressed].concat();
+		let mut trailer = [0; TRAILER];
+		trailer[..4].copy_from_slice(b"koly");
+		trailer[0xD8..0xE0].copy_from_slice(&(file.len() as u64).to_be_bytes());
+		trailer[0xE0..0xE8].copy_from_slice(&(xml.len() as u64).to_be_bytes());
+		trailer[0x1EC..0x1F4].copy_from_slice(&4u64.to_be_bytes());
+		file.extend(xml.as_bytes());
+		file.extend(trailer);
+
+		assert!(detect(&mut Cursor::new(&file)).unwrap());
+		let mut disk = Vec::new();
+		Dmg::new(Cursor::new(&file)).unwrap().read_to_end(&mut disk).unwrap();
+		assert_eq!(disk, [raw, vec![0; 512], vec![0xBB; 512], vec![0; 512]].concat());
+
+		// Cut short, or claiming a partition map the file doesn't have room for
+		assert!(!detect(&mut Cursor::new(&file[..100])).unwrap());
+		assert!(Dmg::new(Cursor::new(&file[..file.len() - 1])).is_err());
+
+		let mut corrupt = file.clone();
+		let at = corrupt.len() - TRAILER + 0xE0;
+		corrupt[at..at + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+		assert!(Dmg::new(Cursor::new(&corrupt)).is_err());
+	}
+}
@@ -0,0 +1,279 @@
+// QEMU disk images (.qcow2), as shipped by most distros for cloud and VM use.
+//
+// A two level table maps each cluster of the disk to a cluster in the file, which can be
+// compressed, all zeros or not there at all. Backing files and encryption aren't supported.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use flate2::read::DeflateDecoder;
+
+use super::blocks::{Blocks, Flatten};
+
+const OFFSET_MASK: u64 = 0x00FF_FFFF_FFFF_FE00;
+const COMPRESSED: u64 = 1 << 62;
+const ZERO: u64 = 1;
+
+// Incompatible feature bits
+const CORRUPT: u64 = 1 << 1;
+const EXTERNAL_DATA: u64 = 1 << 2;
+const EXTENDED_L2: u64 = 1 << 4;
+
+const SUBCLUSTERS: u64 = 32;
+
+/// Whether `file` starts with the qcow magic.
+pub fn detect(file: &mut (impl Read + Seek)) -> io::Result<bool> {
+	let mut magic = [0; 4];
+	let found = file.read_exact(&mut magic).is_ok() && &magic == b"QFI\xFB";
+	file.rewind()?;
+
+	Ok(found)
+}
+
+/// Opens the disk inside a qcow2 image for reading from start to end.
+pub fn open<R: Read + Seek + Send + 'static>(inner: R) -> Result<Flatten<Qcow2<R>>, String> {
+	Qcow2::new(inner).map(Flatten::new)
+}
+
+pub struct Qcow2<R> {
+	inner: R,
+	size: u64,
+	cluster_bits: u32,
+	extended: bool,
+	zstd: bool,
+	l1: Vec<u64>,
+	/// The last L2 table read and where it was, the disk is read in order so it's usually the next one
+	l2: Option<(u64, Vec<u64>)>,
+	compressed: Vec<u8>,
+}
+
+impl<R: Read + Seek> Qcow2<R> {
+	fn new(mut inner: R) -> Result<Self, String> {
+		let mut header = [0; 112];
+		inner.read_exact(&mut header).map_err(|e| format!("qcow2 error: {e}"))?;
+
+		if &header[..4] != b"QFI\xFB" {
+			return Err("qcow2 error: not a qcow2 image".into());
+		}
+
+		let version = be32(&header, 4);
+		let cluster_bits = be32(&header, 20);
+
+		if !(2..=3).contains(&version) {
+			return Err(format!("qcow2 error: version {version} isn't supported"));
+		}
+
+		if !(9..=21).contains(&cluster_bits) {
+			return Err(format!("qcow2 error: bad cluster size 2^{cluster_bits}"));
+		}
+
+		if be64(&header, 8) != 0 {
+			return Err("qcow2 error: the image has a backing file, flatten it with `qemu-img convert` first".into());
+		}
+
+		if be32(&header, 32) != 0 {
+			return Err("qcow2 error: encrypted images aren't supported".into());
+		}
+
+		// Version 2 headers stop before the feature bits
+		let (features, zstd) = if version >= 3 {
+			(be64(&header, 72), be32(&header, 100) > 104 && header[104] == 1)
+		} else {
+			(0, false)
+		};
+
+		if features & CORRUPT != 0 {
+			return Err("qcow2 error: the image is marked as corrupt".into());
+		}
+
+		if features & EXTERNAL_DATA != 0 {
+			return Err("qcow2 error: images with an external data file aren't supported".into());
+		}
+
+		// The table has to be in the file before anything's allocated for it
+		let (l1_offset, l1_len) = (be64(&header, 40), be32(&header, 36) as u64 * 8);
+		let file_len = inner.seek(SeekFrom::End(0)).map_err(|e| format!("qcow2 error: {e}"))?;
+		if l1_offset.checked_add(l1_len).is_none_or(|end| end > file_len) {
+			return Err("qcow2 error: the L1 table is past the end of the file".into());
+		}
+
+		let mut l1 = vec![0; l1_len as usize];
+		inner.seek(SeekFrom::Start(l1_offset))
+			.and_then(|_| inner.read_exact(&mut l1))
+			.map_err(|e| format!("qcow2 error: {e}"))?;
+
+		Ok(Self {
+			inner,
+			size: be64(&header, 24),
+			cluster_bits,
+			extended: features & EXTENDED_L2 != 0,
+			zstd,
+			l1: l1.chunks_exact(8).map(|entry| be64(entry, 0)).collect(),
+			l2: None,
+			compressed: Vec::new(),
+		})
+	}
+
+	fn cluster_size(&self) -> u64 {
+		1 << self.cluster_bits
+	}
+
+	// The L2 entry for a cluster, and its subcluster bitmap with extended L2 entries
+	fn l2_entry(&mut self, index: u64) -> io::Result<(u64, u64)> {
+		let words = if self.extended { 2 } else { 1 };
+		let per_table = self.cluster_size() / (8 * words);
+
+		let table = self.l1.get((index / per_table) as usize).copied().unwrap_or(0) & OFFSET_MASK;
+
+		if table == 0 {
+			return Ok((0, 0));
+		}
+
+		if self.l2.as_ref().is_none_or(|(offset, _)| *offset != table) {
+			let mut data = vec![0; self.cluster_size() as usize];
+			self.inner.seek(SeekFrom::Start(table))?;
+			self.inner.read_exact(&mut data)?;
+
+			self.l2 = Some((table, data.chunks_exact(8).map(|entry| be64(entry, 0)).collect()));
+		}
+
+		let entries = self.l2.as_ref().map(|(_, entries)| entries.as_slice()).unwrap_or_default();
+		let i = ((index % per_table) * words) as usize;
+
+		Ok((entries[i], if self.extended { entries[i + 1] } else { 0 }))
+	}
+
+	fn read_compressed(&mut self, entry: u64, buffer: &mut [u8]) -> io::Result<()> {
+		// The offset and sector count share the entry, split depending on the cluster size
+		let offset_bits = 62 - (self.cluster_bits - 8);
+		let offset = entry & ((1 << offset_bits) - 1);
+		let sectors = (entry >> offset_bits) & ((1 << (self.cluster_bits - 8)) - 1);
+		let len = (sectors + 1) * 512 - (offset & 511);
+
+		// Compressed clusters can end right at the end of the file, short of the sector count
+		self.compressed.clear();
+		self.inner.seek(SeekFrom::Start(offset))?;
+		(&mut self.inner).take(len).read_to_end(&mut self.compressed)?;
+
+		if self.zstd {
+			zstd::stream::read::Decoder::new(self.compressed.as_slice())?.read_exact(buffer)
+		} else {
+			DeflateDecoder::new(self.compressed.as_slice()).read_exact(buffer)
+		}
+	}
+}
+
+impl<R: Read + Seek> Blocks for Qcow2<R> {
+	fn size(&self) -> u64 {
+		self.size
+	}
+
+	fn block_size(&self) -> u64 {
+		self.cluster_size()
+	}
+
+	fn read_block(&mut self, index: u64, buffer: &mut [u8]) -> io::Result<()> {
+		let (entry, bitmap) = self.l2_entry(index)?;
+
+		if entry & COMPRESSED != 0 {
+			return self.read_compressed(entry & !(3 << 62), buffer);
+		}
+
+		let offset = entry & OFFSET_MASK;
+
+		if !self.extended {
+			if offset == 0 || entry & ZERO != 0 {
+				buffer.fill(0);
+				return Ok(());
+			}
+
+			self.inner.seek(SeekFrom::Start(offset))?;
+			return self.inner.read_exact(buffer);
+		}
+
+		// Subclusters are allocated one by one, the low half of the bitmap says which are in the file
+		let subcluster = (self.cluster_size() / SUBCLUSTERS) as usize;
+
+		for (i, part) in buffer.chunks_mut(subcluster).enumerate() {
+			if offset != 0 && bitmap & (1 << i) != 0 {
+				self.inner.seek(SeekFrom::Start(offset + (i * subcluster) as u64))?;
+				self.inner.read_exact(part)?;
+			} else {
+				part.fill(0);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+	u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn be64(data: &[u8], offset: usize) -> u64 {
+	u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Cursor, Write};
+
+	use flate2::write::DeflateEncoder;
+
+	use super::*;
+
+	const CLUSTER: usize = 4096;
+
+	fn put(file: &mut [u8], offset: usize, data: &[u8]) {
+		file[offset..offset + data.len()].copy_from_slice(data);
+	}
+
+	fn read(file: &[u8]) -> Result<Vec<u8>, String> {
+		let mut disk = Vec::new();
+		open(Cursor::new(file.to_vec()))?.read_to_end(&mut disk).map_err(|e| e.to_string())?;
+		Ok(disk)
+	}
+
+	#[test]
+	fn qcow2_clusters_are_put_back_in_place() {
+		let data = crate::testing::image(2 * CLUSTER);
+		let mut deflate = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+		deflate.write_all(&data[CLUSTER..]).unwrap();
+		let compressed = deflate.finish().unwrap();
+
+		// Header, L1 table, L2 table, then the clusters, 4 KiB each
+		let mut file = vec![0; 4 * CLUSTER];
+		put(&mut file, 0, b"QFI\xFB");
+		put(&mut file, 4, &3u32.to_be_bytes());
+		put(&mut file, 20, &12u32.to_be_bytes());
+		put(&mut file, 24, &(4 * CLUSTER as u64).to_be_bytes());
+		put(&mut file, 36, &1u32.to_be_bytes());
+		put(&mut file, 40, &(CLUSTER as u64).to_be_bytes());
+		put(&mut file, 100, &112u32.to_be_bytes());
+		put(&mut file, CLUSTER, &(2 * CLUSTER as u64).to_be_bytes());
+
+		// Stored, all zeros, not there at all, and compressed into the sectors that follow
+		let sectors = (compressed.len() as u64).div_ceil(512) - 1;
+		let entries = [3 * CLUSTER as u64, ZERO, 0, COMPRESSED | sectors << 58 | (4 * CLUSTER as u64)];
+		for (i, entry) in entries.into_iter().enumerate() {
+			put(&mut file, 2 * CLUSTER + i * 8, &entry.to_be_bytes());
+		}
+		put(&mut file, 3 * CLUSTER, &data[..CLUSTER]);
+		file.extend(&compressed);
+
+		let disk = read(&file).unwrap();
+		assert_eq!(&disk[..CLUSTER], &data[..CLUSTER]);
+		assert!(disk[CLUSTER..3 * CLUSTER].iter().all(|&byte| byte == 0));
+		assert_eq!(&disk[3 * CLUSTER..], &data[CLUSTER..]);
+
+		// Cut short, not qcow2, or with an L1 table bigger than the file
+		assert!(read(&file[..64]).is_err());
+
+		let mut corrupt = file.clone();
+		put(&mut corrupt, 0, b"QFI\0");
+		assert!(read(&corrupt).is_err());
+
+		put(&mut file, 36, &u32::MAX.to_be_bytes());
+		assert!(read(&file).is_err());
+	}
+}
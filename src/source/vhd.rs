@@ -0,0 +1,214 @@
+// Virtual PC / Hyper-V disk images (.vhd), fixed and dynamic.
+//
+// Every VHD ends in a 512 byte footer. Fixed images are the raw disk followed by the footer,
+// dynamic ones point at a block allocation table where unallocated blocks read as zeros.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use super::blocks::{Blocks, Flatten};
+
+const SECTOR: u64 = 512;
+const FOOTER: usize = 512;
+
+const FIXED: u32 = 2;
+const DYNAMIC: u32 = 3;
+const DIFFERENCING: u32 = 4;
+
+const UNALLOCATED: u32 = 0xFFFF_FFFF;
+
+// Virtual PC and Hyper-V use 2 MiB blocks, past this it's a broken header
+const MAX_BLOCK: u64 = 256 * 1024 * 1024;
+
+/// Whether `file` ends in a VHD footer.
+pub fn detect(file: &mut (impl Read + Seek)) -> io::Result<bool> {
+	let found = footer(file).is_ok_and(|footer| &footer[..8] == b"conectix");
+	file.rewind()?;
+
+	Ok(found)
+}
+
+fn footer(file: &mut (impl Read + Seek)) -> io::Result<[u8; FOOTER]> {
+	let mut footer = [0; FOOTER];
+	file.seek(SeekFrom::End(-(FOOTER as i64)))?;
+	file.read_exact(&mut footer)?;
+
+	Ok(footer)
+}
+
+/// Opens the disk inside a VHD for reading from start to end.
+pub fn open<R: Read + Seek + Send + 'static>(mut inner: R) -> Result<Box<dyn Read + Send>, String> {
+	let footer = footer(&mut inner).map_err(|e| format!("VHD error: {e}"))?;
+	if &footer[..8] != b"conectix" {
+		return Err("VHD error: the image has no VHD footer".into());
+	}
+
+	let size = be64(&footer, 0x30);
+
+	match be32(&footer, 0x3C) {
+		FIXED => {
+			inner.rewind().map_err(|e| format!("VHD error: {e}"))?;
+			Ok(Box::new(inner.take(size)))
+		},
+		DYNAMIC => Ok(Box::new(Flatten::new(Dynamic::new(inner, be64(&footer, 0x10), size)?))),
+		DIFFERENCING => Err("VHD error: differencing images need their parent, merge them first".into()),
+		kind => Err(format!("VHD error: unknown disk type {kind}")),
+	}
+}
+
+struct Dynamic<R> {
+	inner: R,
+	size: u64,
+	block_size: u64,
+	/// Sector each block starts at, where its sector bitmap is
+	table: Vec<u32>,
+	bitmap: Vec<u8>,
+}
+
+impl<R: Read + Seek> Dynamic<R> {
+	fn new(mut inner: R, header_offset: u64, size: u64) -> Result<Self, String> {
+		let mut header = [0; 1024];
+		inner.seek(SeekFrom::Start(header_offset))
+			.and_then(|_| inner.read_exact(&mut header))
+			.map_err(|e| format!("VHD error: {e}"))?;
+
+		if &header[..8] != b"cxsparse" {
+			return Err("VHD error: bad dynamic disk header".into());
+		}
+
+		let table_offset = be64(&header, 0x10);
+		let entries = be32(&header, 0x1C) as usize;
+		let block_size = be32(&header, 0x20) as u64;
+
+		if block_size == 0 || block_size > MAX_BLOCK || !block_size.is_multiple_of(SECTOR) {
+			return Err(format!("VHD error: bad block size {block_size}"));
+		}
+
+		// The table has to be in the file before anything's allocated for it
+		let file_len = inner.seek(SeekFrom::End(0)).map_err(|e| format!("VHD error: {e}"))?;
+		if table_offset.checked_add(entries as u64 * 4).is_none_or(|end| end > file_len) {
+			return Err("VHD error: the block allocation table is past the end of the file".into());
+		}
+
+		let mut table = vec![0; entries * 4];
+		inner.seek(SeekFrom::Start(table_offset))
+			.and_then(|_| inner.read_exact(&mut table))
+			.map_err(|e| format!("VHD error: {e}"))?;
+
+		// One bit per sector, padded out to whole sectors
+		let bitmap_len = (block_size / SECTOR).div_ceil(8).div_ceil(SECTOR) * SECTOR;
+
+		Ok(Self {
+			inner,
+			size,
+			block_size,
+			table: table.chunks_exact(4).map(|entry| be32(entry, 0)).collect(),
+			bitmap: vec![0; bitmap_len as usize],
+		})
+	}
+}
+
+impl<R: Read + Seek> Blocks for Dynamic<R> {
+	fn size(&self) -> u64 {
+		self.size
+	}
+
+	fn block_size(&self) -> u64 {
+		self.block_size
+	}
+
+	fn read_block(&mut self, index: u64, buffer: &mut [u8]) -> io::Result<()> {
+		let sector = match self.table.get(index as usize) {
+			Some(&sector) if sector != UNALLOCATED => sector as u64,
+			_ => {
+				buffer.fill(0);
+				return Ok(());
+			},
+		};
+
+		self.inner.seek(SeekFrom::Start(sector * SECTOR))?;
+		self.inner.read_exact(&mut self.bitmap)?;
+		self.inner.read_exact(buffer)?;
+
+		// Sectors that were never written aren't guaranteed to be zero in the file
+		for (i, sector) in buffer.chunks_mut(SECTOR as usize).enumerate() {
+			if self.bitmap[i / 8] & (0x80 >> (i % 8)) == 0 {
+				sector.fill(0);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+	u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn be64(data: &[u8], offset: usize) -> u64 {
+	u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use super::*;
+
+	fn footer(kind: u32, header_offset: u64, size: u64) -> Vec<u8> {
+		let mut footer = vec![0; FOOTER];
+		footer[..8].copy_from_slice(b"conectix");
+		footer[0x10..0x18].copy_from_slice(&header_offset.to_be_bytes());
+		footer[0x30..0x38].copy_from_slice(&size.to_be_bytes());
+		footer[0x3C..0x40].copy_from_slice(&kind.to_be_bytes());
+		footer
+	}
+
+	fn read(file: Vec<u8>) -> Result<Vec<u8>, String> {
+		let mut disk = Vec::new();
+		open(Cursor::new(file))?.read_to_end(&mut disk).map_err(|e| e.to_string())?;
+		Ok(disk)
+	}
+
+	#[test]
+	fn vhd_disks_come_out_as_they_went_in() {
+		let fixed = crate::testing::image(4096);
+		assert_eq!(read([fixed.clone(), footer(FIXED, u64::MAX, 4096)].concat()).unwrap(), fixed);
+
+		// Three 4 KiB blocks: one whole, one never allocated, one with only its first sector written
+		const BLOCK: usize = 4096;
+		let mut file = footer(DYNAMIC, 512, 3 * BLOCK as u64);
+		let mut header = vec![0; 1024];
+		header[..8].copy_from_slice(b"cxsparse");
+		header[0x10..0x18].copy_from_slice(&1536u64.to_be_bytes());
+		header[0x1C..0x20].copy_from_slice(&3u32.to_be_bytes());
+		header[0x20..0x24].copy_from_slice(&(BLOCK as u32).to_be_bytes());
+		file.extend(header);
+
+		let mut table = vec![0; 512];
+		for (i, sector) in [4u32, UNALLOCATED, 13].into_iter().enumerate() {
+			table[i * 4..i * 4 + 4].copy_from_slice(&sector.to_be_bytes());
+		}
+		file.extend(table);
+
+		let data = crate::testing::image(2 * BLOCK);
+		file.extend([0xFF; 512]);
+		file.extend(&data[..BLOCK]);
+		file.extend([0x80; 1].iter().chain(&[0; 511]));
+		file.extend(&data[BLOCK..]);
+		file.extend(footer(DYNAMIC, 512, 3 * BLOCK as u64));
+
+		let disk = read(file.clone()).unwrap();
+		assert_eq!(&disk[..BLOCK], &data[..BLOCK]);
+		assert!(disk[BLOCK..2 * BLOCK].iter().all(|&byte| byte == 0));
+		assert_eq!(&disk[2 * BLOCK..2 * BLOCK + 512], &data[BLOCK..BLOCK + 512]);
+		assert!(disk[2 * BLOCK + 512..].iter().all(|&byte| byte == 0));
+
+		// Cut short, without its footer, or with a table bigger than the file
+		assert!(read(file[..file.len() - 1].to_vec()).is_err());
+		assert!(read(file[..100].to_vec()).is_err());
+
+		let mut corrupt = file.clone();
+		corrupt[512 + 0x1C..512 + 0x20].copy_from_slice(&u32::MAX.to_be_bytes());
+		assert!(read(corrupt).is_err());
+	}
+}
@@ -0,0 +1,262 @@
+// Hyper-V disk images (.vhdx), fixed and dynamic.
+//
+// Region tables near the start of the file point at the metadata, which has the disk and
+// block sizes, and the block allocation table saying where in the file each block lives.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use super::blocks::{Blocks, Flatten};
+
+const KB: u64 = 1024;
+const MB: u64 = 1024 * 1024;
+
+// The largest block size the format allows
+const MAX_BLOCK: u64 = 256 * MB;
+
+const BAT: &str = "2DC27766-F623-4200-9D64-115E9BFD4A08";
+const METADATA: &str = "8B7CA206-4790-4B9A-B8FE-575F050F886E";
+const FILE_PARAMETERS: &str = "CAA16737-FA36-4D43-B3B6-33F0AA44E76B";
+const DISK_SIZE: &str = "2FA54224-CD1B-4876-B211-5DBED83BF4B8";
+const SECTOR_SIZE: &str = "8141BF1D-A96F-4709-BA47-F233A8FAAB5F";
+
+// Payload block states, everything else reads as zeros
+const FULLY_PRESENT: u64 = 6;
+const PARTIALLY_PRESENT: u64 = 7;
+
+/// Whether `file` starts with the VHDX signature.
+pub fn detect(file: &mut (impl Read + Seek)) -> io::Result<bool> {
+	let mut signature = [0; 8];
+	let found = file.read_exact(&mut signature).is_ok() && &signature == b"vhdxfile";
+	file.rewind()?;
+
+	Ok(found)
+}
+
+/// Opens the disk inside a VHDX for reading from start to end.
+pub fn open<R: Read + Seek + Send + 'static>(inner: R) -> Result<Flatten<Vhdx<R>>, String> {
+	Vhdx::new(inner).map(Flatten::new)
+}
+
+pub struct Vhdx<R> {
+	inner: R,
+	size: u64,
+	block_size: u64,
+	/// Payload blocks per sector bitmap block in the table
+	chunk_ratio: u64,
+	table: Vec<u64>,
+}
+
+impl<R: Read + Seek> Vhdx<R> {
+	fn new(mut inner: R) -> Result<Self, String> {
+		// Two copies of the header, the one with the higher sequence number is current
+		let header = [64 * KB, 128 * KB].into_iter()
+			.filter_map(|offset| read_at(&mut inner, offset, 4 * KB as usize).ok())
+			.filter(|header| &header[..4] == b"head")
+			.max_by_key(|header| le64(header, 8))
+			.ok_or("VHDX error: no valid header")?;
+
+		// Replaying the log is Hyper-V's job, the disk is inconsistent until it's done
+		if header[48..64].iter().any(|&byte| byte != 0) {
+			return Err("VHDX error: the image wasn't closed cleanly, attach it in Hyper-V once to repair it".into());
+		}
+
+		let regions = read_at(&mut inner, 192 * KB, 64 * KB as usize)
+			.map_err(|e| format!("VHDX error: {e}"))?;
+
+		if &regions[..4] != b"regi" {
+			return Err("VHDX error: bad region table".into());
+		}
+
+		let (mut table_region, mut metadata_region) = (None, None);
+
+		for i in 0..le32(&regions, 8) as usize {
+			let entry = regions.get(16 + i * 32..16 + (i + 1) * 32).ok_or("VHDX error: bad region table")?;
+			let region = (le64(entry, 16), le32(entry, 24) as usize);
+
+			if entry[..16] == guid(BAT) {
+				table_region = Some(region);
+			} else if entry[..16] == guid(METADATA) {
+				metadata_region = Some(region);
+			}
+		}
+
+		let (table_offset, table_len) = table_region.ok_or("VHDX error: no block allocation table")?;
+		let (metadata_offset, metadata_len) = metadata_region.ok_or("VHDX error: no metadata")?;
+
+		let metadata = read_at(&mut inner, metadata_offset, metadata_len).map_err(|e| format!("VHDX error: {e}"))?;
+
+		if metadata.len() < 32 || &metadata[..8] != b"metadata" {
+			return Err("VHDX error: bad metadata table".into());
+		}
+
+		let item = |id: &str, len: usize| -> Result<&[u8], String> {
+			(0..le16(&metadata, 10) as usize)
+				.filter_map(|i| metadata.get(32 + i * 32..32 + (i + 1) * 32))
+				.find(|entry| entry[..16] == guid(id))
+				.and_then(|entry| {
+					let offset = le32(entry, 16) as usize;
+					metadata.get(offset..offset + len)
+				})
+				.ok_or_else(|| "VHDX error: incomplete metadata".to_string())
+		};
+
+		let parameters = item(FILE_PARAMETERS, 8)?;
+		let block_size = le32(parameters, 0) as u64;
+		let size = le64(item(DISK_SIZE, 8)?, 0);
+		let sector_size = le32(item(SECTOR_SIZE, 4)?, 0) as u64;
+
+		if le32(parameters, 4) & 2 != 0 {
+			return Err("VHDX error: differencing images need their parent, merge them first".into());
+		}
+
+		if block_size == 0 || block_size > MAX_BLOCK || sector_size == 0 {
+			return Err("VHDX error: bad block size".into());
+		}
+
+		let table = read_at(&mut inner, table_offset, table_len).map_err(|e| format!("VHDX error: {e}"))?;
+
+		Ok(Self {
+			inner,
+			size,
+			block_size,
+			chunk_ratio: (8 * MB * sector_size / block_size).max(1),
+			table: table.chunks_exact(8).map(|entry| le64(entry, 0)).collect(),
+		})
+	}
+}
+
+impl<R: Read + Seek> Blocks for Vhdx<R> {
+	fn size(&self) -> u64 {
+		self.size
+	}
+
+	fn block_size(&self) -> u64 {
+		self.block_size
+	}
+
+	fn read_block(&mut self, index: u64, buffer: &mut [u8]) -> io::Result<()> {
+		// Every chunk_ratio payload entries are followed by a sector bitmap entry
+		let entry = self.table.get((index + index / self.chunk_ratio) as usize).copied().unwrap_or(0);
+
+		match entry & 7 {
+			FULLY_PRESENT => {
+				self.inner.seek(SeekFrom::Start((entry >> 20) * MB))?;
+				self.inner.read_exact(buffer)
+			},
+			PARTIALLY_PRESENT => Err(io::Error::other("VHDX error: the image depends on a parent")),
+			_ => {
+				buffer.fill(0);
+				Ok(())
+			},
+		}
+	}
+}
+
+fn read_at(inner: &mut (impl Read + Seek), offset: u64, len: usize) -> io::Result<Vec<u8>> {
+	// Lengths come from the file, it has to have that much before any of it is allocated
+	let file_len = inner.seek(SeekFrom::End(0))?;
+	if offset.checked_add(len as u64).is_none_or(|end| end > file_len) {
+		return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "past the end of the file"));
+	}
+
+	let mut data = vec![0; len];
+	inner.seek(SeekFrom::Start(offset))?;
+	inner.read_exact(&mut data)?;
+
+	Ok(data)
+}
+
+// On disk GUIDs have their first three fields little endian
+fn guid(text: &str) -> [u8; 16] {
+	let hex: String = text.chars().filter(|c| *c != '-').collect();
+	let mut bytes: [u8; 16] = hex::decode(hex).ok().and_then(|bytes| bytes.try_into().ok()).unwrap_or_default();
+
+	bytes[..4].reverse();
+	bytes[4..6].reverse();
+	bytes[6..8].reverse();
+
+	bytes
+}
+
+fn le16(data: &[u8], offset: usize) -> u16 {
+	u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn le32(data: &[u8], offset: usize) -> u32 {
+	u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le64(data: &[u8], offset: usize) -> u64 {
+	u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use super::*;
+
+	fn put(file: &mut [u8], offset: u64, data: &[u8]) {
+		file[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+	}
+
+	fn read(file: &[u8]) -> Result<Vec<u8>, String> {
+		let mut disk = Vec::new();
+		open(Cursor::new(file.to_vec()))?.read_to_end(&mut disk).map_err(|e| e.to_string())?;
+		Ok(disk)
+	}
+
+	#[test]
+	fn vhdx_disks_come_out_as_they_went_in() {
+		const BLOCK: u64 = 64 * KB;
+		let (metadata, table) = (256 * KB, 320 * KB);
+
+		let mut file = vec![0; (MB + BLOCK) as usize];
+		put(&mut file, 0, b"vhdxfile");
+		put(&mut file, 64 * KB, b"head");
+		put(&mut file, 64 * KB + 8, &1u64.to_le_bytes());
+
+		put(&mut file, 192 * KB, b"regi");
+		put(&mut file, 192 * KB + 8, &2u32.to_le_bytes());
+		for (i, (id, offset)) in [(BAT, table), (METADATA, metadata)].into_iter().enumerate() {
+			let entry = 192 * KB + 16 + i as u64 * 32;
+			put(&mut file, entry, &guid(id));
+			put(&mut file, entry + 16, &offset.to_le_bytes());
+			put(&mut file, entry + 24, &(64 * KB as u32).to_le_bytes());
+		}
+
+		put(&mut file, metadata, b"metadata");
+		put(&mut file, metadata + 10, &3u16.to_le_bytes());
+		let items: [(&str, &[u8]); 3] = [
+			(FILE_PARAMETERS, &(BLOCK as u32).to_le_bytes()),
+			(DISK_SIZE, &(2 * BLOCK).to_le_bytes()),
+			(SECTOR_SIZE, &512u32.to_le_bytes()),
+		];
+		for (i, (id, value)) in items.into_iter().enumerate() {
+			let (entry, item) = (metadata + 32 + i as u64 * 32, 1024 + i as u64 * 16);
+			put(&mut file, entry, &guid(id));
+			put(&mut file, entry + 16, &(item as u32).to_le_bytes());
+			put(&mut file, metadata + item, value);
+		}
+
+		// The first block a MiB in, the second never written
+		put(&mut file, table, &((1 << 20) | FULLY_PRESENT).to_le_bytes());
+		let data = crate::testing::image(BLOCK as usize);
+		put(&mut file, MB, &data);
+
+		let disk = read(&file).unwrap();
+		assert_eq!(&disk[..BLOCK as usize], &data);
+		assert_eq!(disk.len() as u64, 2 * BLOCK);
+		assert!(disk[BLOCK as usize..].iter().all(|&byte| byte == 0));
+
+		// Cut off before the table, or with a metadata region bigger than the file
+		assert!(read(&file[..(300 * KB) as usize]).is_err());
+
+		let mut corrupt = file.clone();
+		put(&mut corrupt, 192 * KB + 16 + 32 + 24, &u32::MAX.to_le_bytes());
+		assert!(read(&corrupt).is_err());
+
+		put(&mut file, 64 * KB, b"xxxx");
+		assert!(read(&file).is_err());
+	}
+}
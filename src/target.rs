@@ -0,0 +1,258 @@
+// Where images are written to: a disk, or a plain file standing in for one.
+//
+// The write engine only goes through [`Target`], so it doesn't care which it has. Files
+// are what the tests write to, along with loop devices, and they're handy for making an
+// image of what a stick would get.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::cache::{self, WriteBehind};
+
+/// Something an image can be written to.
+pub trait Target: Send {
+	/// How big it is, for a file how much has been written so far.
+	fn len(&mut self) -> io::Result<u64>;
+
+	/// Writes `data` at `offset`.
+	fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+
+	/// Fills `buffer` with what's at `offset`, for a delta write to compare against.
+	fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()>;
+
+	/// Makes a file `len` long, so blocks skipped at the end of an image count; a disk has
+	/// the size it has.
+	fn set_len(&mut self, len: u64) -> io::Result<()>;
+
+	/// Puts everything written so far on the medium, device caches included.
+	fn flush(&mut self) -> io::Result<()>;
+
+	/// Tells it `range` isn't needed any more, so it can be erased ahead of being written
+	/// again. Reads from it afterwards may give zeros or the old data.
+	fn discard(&mut self, range: Range<u64>) -> io::Result<()>;
+}
+
+/// Opens `path` to be written, `truncate` empties a file first.
+pub fn open(path: &str, truncate: bool) -> io::Result<Box<dyn Target>> {
+	let file = OpenOptions::new()
+		.read(true)
+		.write(true)
+		.create(true)
+		.truncate(truncate)
+		.open(path)?;
+
+	let writer = Positioned { file, position: 0, write_behind: WriteBehind::default() };
+
+	if is_disk(&writer.file) {
+		Ok(Box::new(Disk(writer)))
+	} else {
+		Ok(Box::new(ImageFile(writer)))
+	}
+}
+
+#[cfg(unix)]
+fn is_disk(file: &File) -> bool {
+	use std::os::unix::fs::FileTypeExt;
+
+	file.metadata().is_ok_and(|metadata| metadata.file_type().is_block_device())
+}
+
+// Windows opens disks as \\.\PhysicalDriveN, which aren't files either
+#[cfg(not(unix))]
+fn is_disk(file: &File) -> bool {
+	!file.metadata().is_ok_and(|metadata| metadata.is_file())
+}
+
+// Writes go one after the other, only a gap needs a seek
+struct Positioned {
+	file: File,
+	position: u64,
+	write_behind: WriteBehind,
+}
+
+impl Positioned {
+	fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+		if offset != self.position {
+			self.file.seek(SeekFrom::Start(offset))?;
+		}
+
+		self.file.write_all(data)?;
+		self.position = offset + data.len() as u64;
+		self.write_behind.written(&self.file, self.position);
+
+		Ok(())
+	}
+
+	// What's read isn't needed again once it's been compared, like the image's own pages
+	fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+		if offset != self.position {
+			self.file.seek(SeekFrom::Start(offset))?;
+		}
+
+		let read = self.file.read_exact(buffer);
+		self.position = self.file.stream_position()?;
+		cache::drop_read(&self.file, offset..offset + buffer.len() as u64);
+
+		read
+	}
+
+	fn len(&mut self) -> io::Result<u64> {
+		let len = self.file.seek(SeekFrom::End(0))?;
+		self.file.seek(SeekFrom::Start(self.position))?;
+
+		Ok(len)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.file.sync_all()?;
+		self.write_behind.finish(&self.file);
+
+		Ok(())
+	}
+}
+
+// A whole disk, a USB stick, SD card or loop device
+struct Disk(Positioned);
+
+impl Target for Disk {
+	fn len(&mut self) -> io::Result<u64> {
+		self.0.len()
+	}
+
+	fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+		self.0.write_at(offset, data)
+	}
+
+	fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+		self.0.read_at(offset, buffer)
+	}
+
+	fn set_len(&mut self, _len: u64) -> io::Result<()> {
+		Ok(())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.0.flush()?;
+		cache::flush_device(&self.0.file)
+	}
+
+	#[cfg(target_os = "linux")]
+	fn discard(&mut self, range: Range<u64>) -> io::Result<()> {
+		use std::os::fd::AsRawFd;
+
+		// Not in libc, _IO(0x12, 119) from linux/fs.h
+		const BLKDISCARD: libc::Ioctl = 0x1277;
+
+		let range = [range.start, range.end - range.start];
+		if unsafe { libc::ioctl(self.0.file.as_raw_fd(), BLKDISCARD, range.as_ptr()) } == 0 {
+			Ok(())
+		} else {
+			Err(io::Error::last_os_error())
+		}
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	fn discard(&mut self, _range: Range<u64>) -> io::Result<()> {
+		Err(io::ErrorKind::Unsupported.into())
+	}
+}
+
+// A file standing in for a disk
+struct ImageFile(Positioned);
+
+impl Target for ImageFile {
+	fn len(&mut self) -> io::Result<u64> {
+		self.0.len()
+	}
+
+	fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+		self.0.write_at(offset, data)
+	}
+
+	fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+		self.0.read_at(offset, buffer)
+	}
+
+	fn set_len(&mut self, len: u64) -> io::Result<()> {
+		self.0.file.set_len(len)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.0.flush()
+	}
+
+	// Punches a hole, so the range reads back as zeros and takes no space
+	#[cfg(target_os = "linux")]
+	fn discard(&mut self, range: Range<u64>) -> io::Result<()> {
+		use std::os::fd::AsRawFd;
+
+		let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+		let (start, len) = (range.start as libc::off_t, (range.end - range.start) as libc::off_t);
+
+		if unsafe { libc::fallocate(self.0.file.as_raw_fd(), mode, start, len) } == 0 {
+			Ok(())
+		} else {
+			Err(io::Error::last_os_error())
+		}
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	fn discard(&mut self, _range: Range<u64>) -> io::Result<()> {
+		Err(io::ErrorKind::Unsupported.into())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	#[test]
+	fn writes_at_offsets_with_gaps() {
+		let dir = TempDir::new("target-gaps");
+		let path = dir.file("out.img");
+
+		let mut target = open(&path, true).unwrap();
+		target.write_at(0, b"abcd").unwrap();
+		target.write_at(8, b"efgh").unwrap();
+		target.set_len(16).unwrap();
+		target.flush().unwrap();
+
+		assert_eq!(target.len().unwrap(), 16);
+		assert_eq!(std::fs::read(&path).unwrap(), b"abcd\0\0\0\0efgh\0\0\0\0");
+	}
+
+	#[test]
+	fn keeps_the_file_when_not_truncating() {
+		let dir = TempDir::new("target-keep");
+		let path = dir.file("out.img");
+		std::fs::write(&path, b"0123456789").unwrap();
+
+		let mut target = open(&path, false).unwrap();
+		target.write_at(4, b"ab").unwrap();
+		target.flush().unwrap();
+
+		assert_eq!(std::fs::read(&path).unwrap(), b"0123ab6789");
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn discarding_a_file_zeroes_the_range() {
+		let dir = TempDir::new("target-discard");
+		let path = dir.file("out.img");
+		std::fs::write(&path, vec![0xAA; 64 * 1024]).unwrap();
+
+		let mut target = open(&path, false).unwrap();
+
+		// Not every filesystem can punch holes
+		if target.discard(4096..8192).is_err() {
+			return;
+		}
+
+		let data = std::fs::read(&path).unwrap();
+		assert_eq!(data.len(), 64 * 1024);
+		assert!(data[4096..8192].iter().all(|&byte| byte == 0));
+		assert!(data[..4096].iter().chain(&data[8192..]).all(|&byte| byte == 0xAA));
+	}
+}
@@ -0,0 +1,55 @@
+// Progress reporting to the desktop's taskbar / dock.
+//
+// iced (and winit underneath it) has no API for taskbar progress, so this goes
+// straight to the platform. On Linux the Unity LauncherEntry D-Bus signal is
+// understood by the Ubuntu dock, KDE Plasma and most docks; other platforms are
+// a no-op for now.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+
+// Desktop entry the dock matches the signal against
+#[cfg(target_os = "linux")]
+const DESKTOP_ID: &str = "application://iso_maker.desktop";
+
+// Last whole percent that was sent, -1 when hidden
+static LAST_PERCENT: AtomicI32 = AtomicI32::new(-1);
+
+/// Shows `progress` (0.0 to 1.0) on the taskbar entry, or hides it with `None`.
+pub fn set_progress(progress: Option<f32>) {
+	let percent = progress.map_or(-1, |p| (p.clamp(0., 1.) * 100.) as i32);
+
+	// Only bother the desktop when the visible value actually changes
+	if LAST_PERCENT.swap(percent, Ordering::Relaxed) == percent {
+		return;
+	}
+
+	emit(percent);
+}
+
+#[cfg(target_os = "linux")]
+fn emit(percent: i32) {
+	let properties = if percent < 0 {
+		"{'progress-visible': <false>}".to_string()
+	} else {
+		format!("{{'progress': <{:.2}>, 'progress-visible': <true>}}", percent as f64 / 100.)
+	};
+
+	// Fire and forget, a missing gdbus or dock simply means no taskbar progress
+	std::thread::spawn(move || {
+		let _ = std::process::Command::new("gdbus")
+			.args([
+				"emit",
+				"--session",
+				"--object-path", "/com/canonical/unity/launcherentry/iso_maker",
+				"--signal", "com.canonical.Unity.LauncherEntry.Update",
+				DESKTOP_ID,
+				&properties,
+			])
+			.stdout(std::process::Stdio::null())
+			.stderr(std::process::Stdio::null())
+			.status();
+	});
+}
+
+#[cfg(not(target_os = "linux"))]
+fn emit(_percent: i32) {}
@@ -0,0 +1,224 @@
+// Job templates: a job written down in a TOML file, so a team can share one and flash sticks
+// the same way on every machine. Unlike a profile, which lives in one user's config, it also
+// says which devices the job is for:
+//
+//     name = "Field laptops"
+//     image = "golden.img"
+//     checksum = "sha256:9f86d0..."
+//     verify = "blake3"
+//     label = "Field rollout"
+//
+//     [target]
+//     model = "Extreme"
+//     min_size = "32G"
+//     max_size = "128G"
+//
+//     [after]
+//     expand = true
+//     payload = ["docs"]
+//     first_boot = "setup.sh"
+//
+// Files are found next to the template. `verify` can be `none`, and goes by the settings when
+// it's left out. `[after]` takes what a profile does to a stick once the image is written:
+// `payload`, `data_partition` (its label), `expand` and `first_boot`.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::app::Mode;
+use crate::devices::Device;
+use crate::hash::Algorithm;
+use crate::partition;
+use crate::profile::Profile;
+use crate::units;
+
+/// A template, checked and with its paths worked out.
+#[derive(Debug, Clone)]
+pub struct Template {
+	/// The job, as a profile would have it
+	pub profile: Profile,
+	/// Verification, `None` goes by the settings
+	pub verify: Option<Option<Algorithm>>,
+	/// The job's label, empty for none
+	pub label: String,
+	pub target: Target,
+}
+
+/// Which devices a template is for, anything goes without a rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Target {
+	/// Part of the model name, any case
+	pub model: Option<String>,
+	pub min_size: Option<u64>,
+	pub max_size: Option<u64>,
+}
+
+// As it's written in the file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+	name: Option<String>,
+	image: String,
+	checksum: Option<String>,
+	verify: Option<String>,
+	label: Option<String>,
+	#[serde(default)]
+	target: TargetEntry,
+	#[serde(default)]
+	after: After,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TargetEntry {
+	model: Option<String>,
+	min_size: Option<String>,
+	max_size: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct After {
+	#[serde(default)]
+	payload: Vec<String>,
+	data_partition: Option<String>,
+	#[serde(default)]
+	expand: bool,
+	first_boot: Option<String>,
+}
+
+impl Template {
+	/// Reads the template at `path`.
+	pub fn load(path: &str) -> Result<Self, String> {
+		let contents = std::fs::read_to_string(path).map_err(|e| format!("Template error: {e}"))?;
+		let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+
+		let mut template = Self::parse(&contents, dir)?;
+		if template.profile.name.is_empty() {
+			template.profile.name = Path::new(path).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+		}
+
+		Ok(template)
+	}
+
+	/// Parses a template whose files are relative to `dir`.
+	pub fn parse(contents: &str, dir: &Path) -> Result<Self, String> {
+		let file: File = toml::from_str(contents).map_err(|e| format!("Template error: {e}"))?;
+
+		let (algorithm, checksum) = match file.checksum.as_deref().map(|checksum| checksum.split_once(':')) {
+			None => (Algorithm::default(), String::new()),
+			Some(Some((algorithm, digest))) => (algorithm.parse().map_err(|e| format!("Template error: {e}"))?, digest.to_string()),
+			Some(None) => return Err("Template error: the checksum needs an algorithm and digest, e.g. sha256:9f86d0...".into()),
+		};
+
+		let verify = match file.verify.as_deref() {
+			None => None,
+			Some("none") => Some(None),
+			Some(name) => Some(Some(name.parse().map_err(|e| format!("Template error: {e}"))?)),
+		};
+
+		let size = |size: Option<String>| size.map(|size| partition::parse_size(&size).map_err(|e| format!("Template error: {e}"))).transpose();
+		let target = Target {
+			model: file.target.model.filter(|model| !model.trim().is_empty()),
+			min_size: size(file.target.min_size)?,
+			max_size: size(file.target.max_size)?,
+		};
+
+		if file.after.expand && file.after.data_partition.is_some() {
+			return Err("Template error: expand would take the space the data partition needs".into());
+		}
+
+		let profile = Profile {
+			name: file.name.unwrap_or_default(),
+			source: resolve(&file.image, dir),
+			mode: Mode::Image,
+			verify: verify.flatten(),
+			checksum,
+			algorithm,
+			payload: file.after.payload.iter().map(|path| resolve(path, dir)).collect(),
+			data_partition: file.after.data_partition,
+			expand: file.after.expand,
+			first_boot: file.after.first_boot.map(|path| resolve(&path, dir)),
+			..Profile::default()
+		};
+
+		Ok(Self { profile, verify, label: file.label.unwrap_or_default(), target })
+	}
+}
+
+impl Target {
+	/// Why `device` isn't one the template is for, `Ok` when it is.
+	pub fn check(&self, device: &Device) -> Result<(), String> {
+		if let Some(model) = &self.model && !device.model.to_lowercase().contains(&model.to_lowercase()) {
+			return Err(format!("{} is a {}, the template is for {model} devices", device.path, device.model));
+		}
+
+		if let Some(min) = self.min_size && device.size < min {
+			return Err(format!("{} is {}, the template needs at least {}", device.path, units::size(device.size), units::size(min)));
+		}
+
+		if let Some(max) = self.max_size && device.size > max {
+			return Err(format!("{} is {}, the template is for devices up to {}", device.path, units::size(device.size), units::size(max)));
+		}
+
+		Ok(())
+	}
+
+	/// The only device in `devices` the template is for, leaving out the running system's.
+	pub fn pick<'a>(&self, devices: &'a [Device]) -> Result<&'a Device, String> {
+		let matching: Vec<&Device> = devices.iter()
+			.filter(|device| !device.holds_system() && self.check(device).is_ok())
+			.collect();
+
+		match matching.as_slice() {
+			[device] => Ok(device),
+			[] => Err("No device the template is for is plugged in".into()),
+			_ => Err(format!("{} devices match the template, say which one", matching.len())),
+		}
+	}
+}
+
+// URLs are left as they are, files are next to the template
+fn resolve(path: &str, dir: &Path) -> String {
+	if path.contains("://") || Path::new(path).is_absolute() {
+		return path.to_string();
+	}
+
+	dir.join(path).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::stick;
+
+	#[test]
+	fn a_template_says_what_to_write_and_to_which_sticks() {
+		let template = Template::parse(r#"
+			image = "golden.img"
+			checksum = "sha256:9f86d0"
+			verify = "none"
+
+			[target]
+			model = "test"
+			min_size = "8G"
+
+			[after]
+			payload = ["docs", "/srv/extra"]
+		"#, Path::new("/srv/templates")).unwrap();
+
+		assert_eq!(template.profile.source, "/srv/templates/golden.img");
+		assert_eq!((template.profile.algorithm, template.profile.checksum.as_str()), (Algorithm::Sha256, "9f86d0"));
+		assert_eq!(template.verify, Some(None));
+		assert_eq!(template.profile.payload, ["/srv/templates/docs", "/srv/extra"]);
+
+		let mut small = stick("/dev/sdc", "B");
+		small.size = 4_000_000_000;
+		assert!(template.target.check(&small).is_err());
+		assert_eq!(template.target.pick(&[stick("/dev/sdb", "A"), small]).unwrap().path, "/dev/sdb");
+		assert!(template.target.pick(&[stick("/dev/sdb", "A"), stick("/dev/sdd", "C")]).is_err());
+
+		assert!(Template::parse("image = \"a.img\"\nimgae = \"typo\"", Path::new("")).is_err());
+	}
+}
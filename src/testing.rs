@@ -0,0 +1,188 @@
+// Helpers for the tests: scratch directories, test images, loop devices, made-up
+// device lists, faults and system tools.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::process::Command;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::devices::{Device, DeviceEnumerator};
+
+pub mod faults;
+pub mod tools;
+
+/// A directory of its own for a test, removed again when it's dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+	pub fn new(name: &str) -> Self {
+		static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+		let path = std::env::temp_dir().join(format!(
+			"iso_maker-{name}-{}-{}",
+			std::process::id(),
+			COUNT.fetch_add(1, Ordering::Relaxed),
+		));
+		std::fs::create_dir_all(&path).unwrap();
+
+		Self(path)
+	}
+
+	/// Path of `name` in the directory, as the writer takes it.
+	pub fn file(&self, name: &str) -> String {
+		self.0.join(name).to_string_lossy().into_owned()
+	}
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+/// `len` bytes that don't repeat every block, so misplaced writes show up.
+pub fn image(len: usize) -> Vec<u8> {
+	let mut rng = Rng::new(0x2545_F491_4F6C_DD1D);
+
+	(0..len).map(|_| rng.next_u64() as u8).collect()
+}
+
+/// Numbers that look random but are the same every run, for tests that try many cases.
+pub struct Rng(u64);
+
+impl Rng {
+	/// Any `seed` but 0.
+	pub fn new(seed: u64) -> Self {
+		Self(seed)
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		// xorshift, enough to look random
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	/// Somewhere in `range`.
+	pub fn within(&mut self, range: std::ops::Range<u64>) -> u64 {
+		range.start + self.next_u64() % (range.end - range.start)
+	}
+}
+
+/// A loop device over a file, detached when it's dropped.
+pub struct LoopDevice {
+	pub path: String,
+}
+
+impl LoopDevice {
+	/// Sets up a loop device over `backing`, `None` where that takes root the tests don't have.
+	pub fn attach(backing: &str) -> Option<Self> {
+		let output = Command::new("losetup").args(["--find", "--show", backing]).output().ok()?;
+		if !output.status.success() {
+			return None;
+		}
+
+		let path = String::from_utf8(output.stdout).ok()?.trim().to_string();
+		Some(Self { path })
+	}
+}
+
+impl Drop for LoopDevice {
+	fn drop(&mut self) {
+		let _ = Command::new("losetup").args(["--detach", &self.path]).status();
+	}
+}
+
+/// A device list a test changes as it goes, to plug sticks in and pull them out.
+#[derive(Clone, Default)]
+pub struct FakeDevices(Rc<RefCell<Vec<Device>>>);
+
+impl FakeDevices {
+	pub fn new(devices: Vec<Device>) -> Self {
+		Self(Rc::new(RefCell::new(devices)))
+	}
+
+	pub fn set(&self, devices: Vec<Device>) {
+		*self.0.borrow_mut() = devices;
+	}
+}
+
+impl DeviceEnumerator for FakeDevices {
+	fn list(&self) -> Vec<Device> {
+		self.0.borrow().clone()
+	}
+
+	// Typed in paths are looked up among the same devices
+	fn find(&self, path: &str) -> Option<Device> {
+		self.0.borrow().iter().find(|device| device.path == path).cloned()
+	}
+}
+
+/// An empty 16 GB USB stick at `path`.
+pub fn stick(path: &str, serial: &str) -> Device {
+	Device {
+		path: path.into(),
+		model: "Test Stick".into(),
+		serial: Some(serial.into()),
+		size: 16_000_000_000,
+		partition_table: None,
+		partitions: Vec::new(),
+		removable: true,
+	}
+}
+
+/// An ISO 9660 image with `files`, names and contents, in its root directory. The names are
+/// upper cased for ISO 9660 and kept as they are in Rock Ridge NM entries.
+pub fn iso(files: &[(&str, &[u8])]) -> Vec<u8> {
+	const SECTOR: usize = 2048;
+	const ROOT: usize = 18;
+
+	// Both-endian extent and length, recorded 2024-05-01 12:30
+	fn record(extent: usize, len: usize, flags: u8, name: &[u8], system_use: &[u8]) -> Vec<u8> {
+		let mut record = vec![0; 33];
+		record[2..6].copy_from_slice(&(extent as u32).to_le_bytes());
+		record[6..10].copy_from_slice(&(extent as u32).to_be_bytes());
+		record[10..14].copy_from_slice(&(len as u32).to_le_bytes());
+		record[14..18].copy_from_slice(&(len as u32).to_be_bytes());
+		record[18..25].copy_from_slice(&[124, 5, 1, 12, 30, 0, 0]);
+		record[25] = flags;
+		record[28] = 1;
+		record[32] = name.len() as u8;
+		record.extend(name);
+		if name.len().is_multiple_of(2) {
+			record.push(0);
+		}
+		record.extend(system_use);
+		record[0] = record.len() as u8;
+		record
+	}
+
+	let mut image = vec![0; (ROOT + 1) * SECTOR];
+	let mut directory = [record(ROOT, SECTOR, 2, &[0], &[]), record(ROOT, SECTOR, 2, &[1], &[])].concat();
+
+	for (name, contents) in files {
+		let extent = image.len() / SECTOR;
+		let mut nm = vec![b'N', b'M', 5 + name.len() as u8, 1, 0];
+		nm.extend(name.as_bytes());
+
+		directory.extend(record(extent, contents.len(), 0, format!("{};1", name.to_uppercase()).as_bytes(), &nm));
+		image.extend(*contents);
+		image.resize(image.len().div_ceil(SECTOR) * SECTOR, 0);
+	}
+
+	let root = record(ROOT, SECTOR, 2, &[0], &[]);
+	let pvd = 16 * SECTOR;
+	image[pvd] = 1;
+	image[pvd + 1..pvd + 6].copy_from_slice(b"CD001");
+	image[pvd + 6] = 1;
+	image[pvd + 156..pvd + 190].copy_from_slice(&root);
+
+	let terminator = 17 * SECTOR;
+	image[terminator] = 255;
+	image[terminator + 1..terminator + 6].copy_from_slice(b"CD001");
+
+	image[ROOT * SECTOR..ROOT * SECTOR + directory.len()].copy_from_slice(&directory);
+	image
+}
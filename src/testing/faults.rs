@@ -0,0 +1,163 @@
+// A source and a target that misbehave on purpose, for testing how the write engine copes.
+//
+// Reads can come back short or fail with EIO at given offsets, writes can fail with EIO a
+// few times or for good, and take their time. What the engine did with them is kept in a
+// [`Probe`] for the test to look at afterwards.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::target::Target;
+
+// What a stick that's failing gives
+const EIO: i32 = 5;
+
+/// What goes wrong, and where.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+	/// Reads give at most this many bytes, 0 doesn't limit them
+	pub short_reads: usize,
+	/// Offsets in the source that can't be read
+	pub read_errors: Vec<u64>,
+	/// Offsets on the target that fail to be written
+	pub write_errors: Vec<u64>,
+	/// How many times each of those fails before it goes through, `usize::MAX` for never
+	pub failures: usize,
+	/// How long each write takes
+	pub slow_writes: Duration,
+}
+
+/// What the engine did, for the test to check once it's done.
+#[derive(Debug, Default)]
+pub struct Probe {
+	pub reader_dropped: AtomicBool,
+	pub target_dropped: AtomicBool,
+	/// Writes tried, failed ones included
+	pub writes: AtomicUsize,
+	/// Where the furthest write before the last flush ended, all that's safely on the target
+	pub flushed: AtomicU64,
+}
+
+/// A source that hands out `data`, as badly as the faults say.
+pub struct FaultyReader {
+	data: Vec<u8>,
+	position: usize,
+	faults: Faults,
+	probe: Arc<Probe>,
+	/// How much was read, for the engine's progress
+	pub consumed: Arc<AtomicU64>,
+}
+
+impl FaultyReader {
+	pub fn new(data: Vec<u8>, faults: &Faults, probe: &Arc<Probe>) -> Self {
+		Self { data, position: 0, faults: faults.clone(), probe: probe.clone(), consumed: Arc::default() }
+	}
+}
+
+impl Read for FaultyReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let mut end = (self.position + buf.len()).min(self.data.len());
+		if self.faults.short_reads > 0 {
+			end = end.min(self.position + self.faults.short_reads);
+		}
+
+		// What comes before a bad spot still reads fine
+		if let Some(bad) = first_in(&self.faults.read_errors, self.position as u64..end as u64) {
+			if bad == self.position as u64 {
+				return Err(io::Error::from_raw_os_error(EIO));
+			}
+			end = bad as usize;
+		}
+
+		let n = end - self.position;
+		buf[..n].copy_from_slice(&self.data[self.position..end]);
+		self.position = end;
+		self.consumed.store(end as u64, Ordering::Relaxed);
+
+		Ok(n)
+	}
+}
+
+impl Drop for FaultyReader {
+	fn drop(&mut self) {
+		self.probe.reader_dropped.store(true, Ordering::Relaxed);
+	}
+}
+
+/// A target that passes writes on to `inner`, as badly as the faults say.
+pub struct FaultyTarget {
+	inner: Box<dyn Target>,
+	faults: Faults,
+	/// Times each write error has happened so far
+	failed: HashMap<u64, usize>,
+	/// Where the furthest write ended
+	written: u64,
+	probe: Arc<Probe>,
+}
+
+impl FaultyTarget {
+	pub fn new(inner: Box<dyn Target>, faults: &Faults, probe: &Arc<Probe>) -> Self {
+		Self { inner, faults: faults.clone(), failed: HashMap::new(), written: 0, probe: probe.clone() }
+	}
+}
+
+impl Target for FaultyTarget {
+	fn len(&mut self) -> io::Result<u64> {
+		self.inner.len()
+	}
+
+	fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+		self.probe.writes.fetch_add(1, Ordering::Relaxed);
+		std::thread::sleep(self.faults.slow_writes);
+
+		let end = offset + data.len() as u64;
+		let bad = first_in(&self.faults.write_errors, offset..end)
+			.filter(|bad| self.failed.get(bad).copied().unwrap_or(0) < self.faults.failures);
+
+		// Like a real device, whatever comes before the bad spot is written before it fails
+		let Some(bad) = bad else {
+			self.written = self.written.max(end);
+			return self.inner.write_at(offset, data);
+		};
+
+		*self.failed.entry(bad).or_default() += 1;
+		self.inner.write_at(offset, &data[..(bad - offset) as usize])?;
+		self.written = self.written.max(bad);
+
+		Err(io::Error::from_raw_os_error(EIO))
+	}
+
+	fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+		self.inner.read_at(offset, buffer)
+	}
+
+	fn set_len(&mut self, len: u64) -> io::Result<()> {
+		self.inner.set_len(len)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()?;
+		self.probe.flushed.store(self.written, Ordering::Relaxed);
+
+		Ok(())
+	}
+
+	fn discard(&mut self, range: Range<u64>) -> io::Result<()> {
+		self.inner.discard(range)
+	}
+}
+
+impl Drop for FaultyTarget {
+	fn drop(&mut self) {
+		self.probe.target_dropped.store(true, Ordering::Relaxed);
+	}
+}
+
+// The first of `offsets` in `range`
+fn first_in(offsets: &[u64], range: Range<u64>) -> Option<u64> {
+	offsets.iter().copied().filter(|offset| range.contains(offset)).min()
+}
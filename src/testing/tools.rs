@@ -0,0 +1,66 @@
+// Stand-ins for the system tools partitioning and formatting are left to, so what would be run
+// can be checked without root or a stick.
+//
+// While a [`FakeTools`] is around, [`crate::tools::run`] on the same thread doesn't start
+// anything: it keeps the call, and gives back what the test said the program prints.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct State {
+	outputs: HashMap<String, Result<String, String>>,
+	calls: Vec<String>,
+}
+
+thread_local! {
+	static FAKE: RefCell<Option<Rc<RefCell<State>>>> = const { RefCell::new(None) };
+}
+
+/// Tools that only pretend to run, for as long as it's kept.
+pub struct FakeTools(Rc<RefCell<State>>);
+
+impl FakeTools {
+	pub fn new() -> Self {
+		let state = Rc::new(RefCell::new(State::default()));
+		FAKE.with(|fake| *fake.borrow_mut() = Some(state.clone()));
+
+		Self(state)
+	}
+
+	/// `program` prints `output`, the ones not answered for print nothing.
+	pub fn answer(&self, program: &str, output: &str) {
+		self.0.borrow_mut().outputs.insert(program.into(), Ok(output.into()));
+	}
+
+	/// `program` fails with `error`.
+	pub fn fail(&self, program: &str, error: &str) {
+		self.0.borrow_mut().outputs.insert(program.into(), Err(error.into()));
+	}
+
+	/// What was run so far, the program and its arguments, with what went to its stdin after `<`.
+	pub fn calls(&self) -> Vec<String> {
+		self.0.borrow().calls.clone()
+	}
+}
+
+impl Drop for FakeTools {
+	fn drop(&mut self) {
+		FAKE.with(|fake| *fake.borrow_mut() = None);
+	}
+}
+
+/// The answer for `program`, `None` when the tools aren't faked and it should really be run.
+pub fn run(program: &str, args: &[&str], input: Option<&str>) -> Option<Result<String, String>> {
+	let state = FAKE.with(|fake| fake.borrow().clone())?;
+	let mut state = state.borrow_mut();
+
+	let mut call = [program].iter().chain(args).copied().collect::<Vec<_>>().join(" ");
+	if let Some(input) = input {
+		call += &format!(" < {}", input.trim_end());
+	}
+	state.calls.push(call);
+
+	Some(state.outputs.get(program).cloned().unwrap_or_else(|| Ok(String::new())))
+}
@@ -0,0 +1,47 @@
+// Running the system tools that partitioning, formatting and friends are left to.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs `program` to completion, returning its stdout, or its stderr as the error.
+pub fn run(program: &str, args: &[&str]) -> Result<String, String> {
+	run_with_input(program, args, None)
+}
+
+/// Like [`run`], with `input` written to the program's stdin.
+pub fn run_with_input(program: &str, args: &[&str], input: Option<&str>) -> Result<String, String> {
+	#[cfg(test)]
+	if let Some(faked) = crate::testing::tools::run(program, args, input) {
+		return faked;
+	}
+
+	let mut child = Command::new(program)
+		.args(args)
+		.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|e| not_found(program, e))?;
+
+	if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+		stdin.write_all(input.as_bytes()).map_err(|e| format!("{program} error: {e}"))?;
+	}
+
+	let output = child.wait_with_output().map_err(|e| format!("{program} error: {e}"))?;
+
+	if output.status.success() {
+		Ok(String::from_utf8_lossy(&output.stdout).to_string())
+	} else {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		Err(format!("{program} error: {}", stderr.trim()))
+	}
+}
+
+/// Error for a tool that couldn't be started, pointing at the likely fix.
+pub fn not_found(program: &str, e: std::io::Error) -> String {
+	if e.kind() == std::io::ErrorKind::NotFound {
+		format!("{program} was not found, install it to use this feature")
+	} else {
+		format!("{program} error: {e}")
+	}
+}
@@ -0,0 +1,93 @@
+// A udev rule that gives a group of iso_maker's own write access to removable disks, so
+// writing doesn't need root every time. Only the users added to it get access, not everyone
+// sharing a primary group like `users`, which on many distros is every account there is.
+//
+// Installing and removing the rule still needs root once, it's done through pkexec. The
+// group only takes effect once the user logs in again.
+
+use std::path::Path;
+
+use crate::tools;
+
+const RULE_PATH: &str = "/etc/udev/rules.d/70-iso_maker.rules";
+
+// Applies the rule to the disks already plugged in too
+const RELOAD: &str = "udevadm control --reload-rules && udevadm trigger --subsystem-match=block";
+
+/// Whether the rule is installed.
+pub fn installed() -> bool {
+	Path::new(RULE_PATH).is_file()
+}
+
+/// The group the rule hands the disks to.
+pub const GROUP: &str = "iso_maker";
+
+/// The user to add to the group.
+fn user() -> Result<String, String> {
+	let user = tools::run("id", &["-un"])?.trim().to_string();
+
+	if user == "root" {
+		return Err("You're root, writing works without the rule already".into());
+	}
+
+	// It goes into a root shell as an argument, but make sure it's a name
+	if user.is_empty() || !user.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) || user.starts_with('-') {
+		return Err(format!("Device access error: {user:?} can't be added to a group"));
+	}
+
+	Ok(user)
+}
+
+/// What goes in the rules file.
+pub fn rule() -> String {
+	format!(
+		"\
+# Added by iso_maker: lets the {GROUP} group write to removable and USB disks, and their partitions, without root.
+# Remove this file (or use iso_maker's settings) to undo it.
+SUBSYSTEM==\"block\", ATTRS{{removable}}==\"1\", GROUP=\"{GROUP}\", MODE=\"0660\"
+SUBSYSTEM==\"block\", ENV{{ID_BUS}}==\"usb\", GROUP=\"{GROUP}\", MODE=\"0660\"
+",
+	)
+}
+
+/// Installs the rule and adds the user to its group, asking for the root password.
+pub fn install() -> Result<(), String> {
+	let user = user()?;
+	let script = format!(
+		"(getent group {GROUP} >/dev/null || groupadd --system {GROUP}) && usermod -aG {GROUP} \"$1\" \
+			&& cat > {RULE_PATH} && chmod 644 {RULE_PATH} && {RELOAD}",
+	);
+
+	elevated(&script, &[&user], Some(&rule()))
+}
+
+/// Removes the rule again. The group stays, with nothing to give its members.
+pub fn uninstall() -> Result<(), String> {
+	elevated(&format!("rm -f {RULE_PATH} && {RELOAD}"), &[], None)
+}
+
+// `args` are the script's $1 and on
+fn elevated(script: &str, args: &[&str], input: Option<&str>) -> Result<(), String> {
+	let mut command = vec!["sh", "-c", script, "sh"];
+	command.extend(args);
+
+	tools::run_with_input("pkexec", &command, input).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn the_rule_only_gives_disks_to_its_own_group() {
+		let rule = rule();
+		let lines: Vec<&str> = rule.lines().filter(|line| !line.starts_with('#')).collect();
+
+		assert_eq!(lines.len(), 2);
+		for line in lines {
+			assert!(line.starts_with("SUBSYSTEM==\"block\", "), "{line}");
+			assert!(line.ends_with("GROUP=\"iso_maker\", MODE=\"0660\""), "{line}");
+		}
+		assert!(!rule.contains("users") && !rule.contains("GROUP=\"root\""));
+	}
+}
@@ -0,0 +1,166 @@
+// Checking a written stick will boot on UEFI machines.
+//
+// Firmware boots removable media from the loader at EFI/BOOT/BOOT<arch>.EFI on a FAT
+// partition, the EFI system partition when there is one. Once a write is done the partition
+// it would pick is mounted read-only and looked through, so a stick that won't boot shows
+// itself before the walk to the machine rather than at it.
+
+use std::path::{Path, PathBuf};
+
+use crate::mount::Mount;
+use crate::partition::Entry;
+use crate::secureboot;
+
+// The removable media loader for each architecture, x86-64 first as most machines are
+const LOADERS: [(&str, &str); 4] = [
+	("BOOTX64.EFI", "x86-64"),
+	("BOOTIA32.EFI", "32-bit x86"),
+	("BOOTAA64.EFI", "ARM64"),
+	("BOOTRISCV64.EFI", "RISC-V"),
+];
+
+/// What's on the partition UEFI firmware would boot the stick from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Boot {
+	/// The partition, `None` when the device has none firmware could boot from
+	pub partition: Option<String>,
+	pub filesystem: Option<String>,
+	pub files: Files,
+}
+
+/// The boot files found on a partition.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Files {
+	/// File names in EFI/BOOT, as they're spelled there
+	pub loaders: Vec<String>,
+	/// Whether a loader is shim, which starts GRUB from next to itself
+	pub shim: bool,
+	/// Whether there's a GRUB loader in EFI/BOOT
+	pub grub: bool,
+}
+
+impl Boot {
+	/// Why the stick likely won't boot on UEFI machines, `None` when it looks like it will.
+	pub fn warning(&self) -> Option<String> {
+		let Some(partition) = &self.partition else {
+			return Some("The device has no partition UEFI firmware can boot from".into());
+		};
+
+		let filesystem = self.filesystem.as_deref().unwrap_or("no filesystem");
+		if filesystem != "vfat" {
+			return Some(format!("{partition} has {filesystem}, which most UEFI firmware can't read, so the stick likely won't boot on UEFI machines"));
+		}
+
+		let found = |name: &str| self.files.loaders.iter().any(|loader| loader.eq_ignore_ascii_case(name));
+		if !found(LOADERS[0].0) {
+			let others: Vec<&str> = LOADERS[1..].iter().filter(|(name, _)| found(name)).map(|(_, arch)| *arch).collect();
+
+			return Some(match others.as_slice() {
+				[] => format!("{partition} has no EFI/BOOT/{}, so the stick likely won't boot on UEFI machines", LOADERS[0].0),
+				others => format!("{partition} only has loaders for {} UEFI machines, most PCs won't boot it", others.join(" and ")),
+			});
+		}
+
+		if self.files.shim && !self.files.grub {
+			return Some(format!("{partition} boots shim, but there's no GRUB next to it in EFI/BOOT for it to start"));
+		}
+
+		None
+	}
+}
+
+/// The partition firmware boots from: the EFI system partition, or else the first FAT one.
+pub fn boot_partition(partitions: &[Entry]) -> Option<&Entry> {
+	partitions.iter()
+		.find(|entry| entry.kind == "EFI System")
+		.or_else(|| partitions.iter().find(|entry| entry.filesystem.as_deref() == Some("vfat")))
+}
+
+/// Mounts the partition in `partitions` that firmware boots from and looks at its boot files.
+pub fn check(partitions: &[Entry]) -> Result<Boot, String> {
+	let Some(entry) = boot_partition(partitions) else {
+		return Ok(Boot { partition: None, filesystem: None, files: Files::default() });
+	};
+
+	let mount = Mount::read_only(&entry.path)?;
+
+	Ok(Boot {
+		partition: Some(entry.path.clone()),
+		filesystem: entry.filesystem.clone(),
+		files: files(mount.path()),
+	})
+}
+
+/// The removable media boot files under `root`, FAT being case-insensitive.
+pub fn files(root: &Path) -> Files {
+	let find = |dir: &Path, name: &str| -> Option<PathBuf> {
+		std::fs::read_dir(dir).ok()?
+			.filter_map(Result::ok)
+			.find(|entry| entry.file_name().to_string_lossy().eq_ignore_ascii_case(name))
+			.map(|entry| entry.path())
+	};
+
+	let Some(boot) = find(root, "EFI").and_then(|efi| find(&efi, "BOOT")) else {
+		return Files::default();
+	};
+
+	let names: Vec<(String, PathBuf)> = std::fs::read_dir(&boot)
+		.into_iter()
+		.flatten()
+		.filter_map(Result::ok)
+		.filter(|entry| entry.path().is_file())
+		.map(|entry| (entry.file_name().to_string_lossy().into_owned(), entry.path()))
+		.collect();
+
+	let is_efi = |name: &str, prefix: &str| {
+		let name = name.to_uppercase();
+		name.starts_with(prefix) && name.ends_with(".EFI")
+	};
+
+	let loaders: Vec<(String, PathBuf)> = names.iter()
+		.filter(|(name, _)| is_efi(name, "BOOT"))
+		.cloned()
+		.collect();
+
+	let shim = loaders.iter().any(|(_, path)| {
+		let small = std::fs::metadata(path).is_ok_and(|metadata| metadata.len() <= secureboot::MAX_LOADER);
+		small && std::fs::read(path).is_ok_and(|data| secureboot::is_shim(&data))
+	});
+
+	Files {
+		loaders: loaders.into_iter().map(|(name, _)| name).collect(),
+		shim,
+		grub: names.iter().any(|(name, _)| is_efi(name, "GRUB")),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+
+	#[test]
+	fn a_stick_without_an_x64_loader_gets_a_warning() {
+		let dir = TempDir::new("uefi");
+		let root = PathBuf::from(dir.file(""));
+		std::fs::create_dir_all(root.join("efi/boot")).unwrap();
+		std::fs::write(root.join("efi/boot/bootaa64.efi"), b"MZ").unwrap();
+
+		let mut boot = Boot { partition: Some("/dev/sdz2".into()), filesystem: Some("vfat".into()), files: files(&root) };
+		assert_eq!(boot.files.loaders, ["bootaa64.efi"]);
+		assert!(boot.warning().unwrap().contains("ARM64"), "{boot:?}");
+
+		// shim without the GRUB it goes on to start is no better
+		std::fs::write(root.join("efi/boot/bootx64.efi"), b"MZ... UEFI shim ...").unwrap();
+		boot.files = files(&root);
+		assert!(boot.files.shim);
+		assert!(boot.warning().unwrap().contains("GRUB"), "{boot:?}");
+
+		std::fs::write(root.join("efi/boot/grubx64.efi"), b"MZ").unwrap();
+		boot.files = files(&root);
+		assert_eq!(boot.warning(), None);
+
+		boot.filesystem = Some("iso9660".into());
+		assert!(boot.warning().is_some());
+	}
+}
@@ -0,0 +1,291 @@
+// Sizes, speeds, times left and percentages, written the way the user's locale writes them.
+//
+// The locale is taken from the environment the way the C library takes it: LC_ALL, then
+// LC_NUMERIC for numbers or LC_MESSAGES for words, then LANG. Numbers get the locale's
+// decimal separator, and the languages below get their own unit names and wording. Anything
+// else, Windows included where these aren't set, is written as in English.
+//
+// Sizes go up in thousands (GB, as vendors mark their sticks) or in 1024s (GiB, as most
+// operating systems count), whichever the settings ask for.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// How sizes are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Units {
+	/// GB, a thousand MB
+	#[default]
+	Decimal,
+	/// GiB, 1024 MiB
+	Binary,
+}
+
+impl Units {
+	pub const ALL: [Units; 2] = [Units::Decimal, Units::Binary];
+}
+
+impl fmt::Display for Units {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Units::Decimal => "GB, as marked on the device",
+			Units::Binary => "GiB",
+		})
+	}
+}
+
+// The setting, for everything that formats a size without the config at hand
+static BINARY: AtomicBool = AtomicBool::new(false);
+
+/// Makes [`size`] and [`speed`] count in `units` from now on.
+pub fn prefer(units: Units) {
+	BINARY.store(units == Units::Binary, Ordering::Relaxed);
+}
+
+fn preferred() -> Units {
+	if BINARY.load(Ordering::Relaxed) { Units::Binary } else { Units::Decimal }
+}
+
+/// How numbers and units are written.
+#[derive(Debug, Clone, Copy)]
+pub struct Locale {
+	decimal: char,
+	/// Between a number and its percent sign
+	percent_space: bool,
+	words: &'static Words,
+}
+
+// What a language calls things
+#[derive(Debug)]
+struct Words {
+	language: &'static str,
+	/// Bytes, kilobytes and so on up to terabytes
+	units: [&'static str; 5],
+	/// The same, in 1024s
+	binary_units: [&'static str; 5],
+	seconds: &'static str,
+	minutes: &'static str,
+	hours: &'static str,
+	/// Wrapped round a time left, `{}` being the time
+	left: &'static str,
+}
+
+const ENGLISH: Words = Words {
+	language: "en",
+	units: ["B", "KB", "MB", "GB", "TB"],
+	binary_units: ["B", "KiB", "MiB", "GiB", "TiB"],
+	seconds: "s",
+	minutes: "min",
+	hours: "h",
+	left: "about {} left",
+};
+
+const LANGUAGES: [Words; 8] = [
+	ENGLISH,
+	Words { language: "de", units: ["B", "kB", "MB", "GB", "TB"], left: "noch etwa {}", ..ENGLISH },
+	Words { language: "es", left: "quedan unos {}", ..ENGLISH },
+	Words { language: "fr", units: ["o", "ko", "Mo", "Go", "To"], binary_units: ["o", "Kio", "Mio", "Gio", "Tio"], left: "encore environ {}", ..ENGLISH },
+	Words { language: "it", left: "circa {} rimanenti", ..ENGLISH },
+	Words { language: "nl", left: "nog ongeveer {}", ..ENGLISH },
+	Words { language: "pt", left: "cerca de {} restantes", ..ENGLISH },
+	Words {
+		language: "ru",
+		units: ["Б", "КБ", "МБ", "ГБ", "ТБ"],
+		binary_units: ["Б", "КиБ", "МиБ", "ГиБ", "ТиБ"],
+		seconds: "с",
+		minutes: "мин",
+		hours: "ч",
+		left: "осталось около {}",
+	},
+];
+
+// Languages that write 1,5 rather than 1.5
+const DECIMAL_COMMA: [&str; 22] = [
+	"bg", "ca", "cs", "da", "de", "el", "es", "fi", "fr", "hr", "hu", "id",
+	"it", "nb", "nl", "pl", "pt", "ro", "ru", "sk", "sv", "tr",
+];
+
+// Languages that put a space before the percent sign
+const PERCENT_SPACE: [&str; 9] = ["cs", "de", "es", "fi", "fr", "nb", "ru", "sk", "sv"];
+
+impl Default for Locale {
+	fn default() -> Self {
+		Self::new(None, None)
+	}
+}
+
+impl Locale {
+	/// The locale the environment asks for.
+	pub fn current() -> &'static Self {
+		static CURRENT: OnceLock<Locale> = OnceLock::new();
+
+		CURRENT.get_or_init(|| {
+			let var = |name| std::env::var(name).ok().filter(|value: &String| !value.is_empty());
+			let numeric = var("LC_ALL").or_else(|| var("LC_NUMERIC")).or_else(|| var("LANG"));
+			let messages = var("LC_ALL").or_else(|| var("LC_MESSAGES")).or_else(|| var("LANG"));
+
+			Self::new(numeric.as_deref(), messages.as_deref())
+		})
+	}
+
+	/// From locale names like `de_DE.UTF-8`, one for numbers and one for words.
+	pub fn new(numeric: Option<&str>, messages: Option<&str>) -> Self {
+		let numeric = numeric.map(language).unwrap_or("en");
+		let messages = messages.map(language).unwrap_or("en");
+
+		Self {
+			decimal: if DECIMAL_COMMA.contains(&numeric) { ',' } else { '.' },
+			percent_space: PERCENT_SPACE.contains(&numeric),
+			words: LANGUAGES.iter().find(|words| words.language == messages).unwrap_or(&LANGUAGES[0]),
+		}
+	}
+
+	/// E.g. 4.7 GB or 4.4 GiB, bytes as they are below a kilobyte.
+	pub fn size(&self, bytes: u64, units: Units) -> String {
+		let (step, names) = match units {
+			Units::Decimal => (1000., &self.words.units),
+			Units::Binary => (1024., &self.words.binary_units),
+		};
+
+		let mut size = bytes as f64;
+		let mut unit = 0;
+
+		while size >= step && unit < names.len() - 1 {
+			size /= step;
+			unit += 1;
+		}
+
+		if unit == 0 {
+			format!("{bytes} {}", names[0])
+		} else {
+			format!("{} {}", self.decimal(size, 1), names[unit])
+		}
+	}
+
+	/// E.g. 38.2 MB/s.
+	pub fn speed(&self, bytes_per_second: f64, units: Units) -> String {
+		format!("{}/s", self.size(bytes_per_second as u64, units))
+	}
+
+	/// E.g. 12.5% for 0.125, with `decimals` places.
+	pub fn percent(&self, fraction: impl Into<f64>, decimals: usize) -> String {
+		let space = if self.percent_space { "\u{a0}" } else { "" };
+		format!("{}{space}%", self.decimal(fraction.into() * 100., decimals))
+	}
+
+	/// E.g. 3.4:1.
+	pub fn ratio(&self, ratio: f64) -> String {
+		format!("{}:1", self.decimal(ratio, 1))
+	}
+
+	/// E.g. "about 2 min left", rounded so it doesn't tick down every second.
+	pub fn time_left(&self, remaining: Duration) -> String {
+		let Words { seconds, minutes, hours, .. } = self.words;
+		let secs = remaining.as_secs();
+
+		let time = match secs {
+			0..60 => format!("{} {seconds}", (secs.div_ceil(5) * 5).max(5)),
+			60..3600 => format!("{} {minutes}", (secs + 30) / 60),
+			_ => format!("{} {hours} {} {minutes}", secs / 3600, secs % 3600 / 60),
+		};
+
+		self.words.left.replace("{}", &time)
+	}
+
+	fn decimal(&self, number: f64, decimals: usize) -> String {
+		let number = format!("{number:.decimals$}");
+
+		if self.decimal == '.' {
+			number
+		} else {
+			number.replace('.', &self.decimal.to_string())
+		}
+	}
+}
+
+// de_DE.UTF-8 and de-DE to de, C and POSIX to en
+fn language(name: &str) -> &str {
+	let language = name.split(['_', '-', '.', '@']).next().unwrap_or(name);
+
+	match language {
+		"C" | "POSIX" | "" => "en",
+		_ => language,
+	}
+}
+
+/// A size in the user's locale and units, see [`Locale::size`].
+pub fn size(bytes: u64) -> String {
+	Locale::current().size(bytes, preferred())
+}
+
+/// A speed in the user's locale and units, see [`Locale::speed`].
+pub fn speed(bytes_per_second: f64) -> String {
+	Locale::current().speed(bytes_per_second, preferred())
+}
+
+/// A percentage in the user's locale, see [`Locale::percent`].
+pub fn percent(fraction: impl Into<f64>, decimals: usize) -> String {
+	Locale::current().percent(fraction, decimals)
+}
+
+/// A ratio in the user's locale, see [`Locale::ratio`].
+pub fn ratio(ratio: f64) -> String {
+	Locale::current().ratio(ratio)
+}
+
+/// The time a job has left in the user's locale, see [`Locale::time_left`].
+pub fn time_left(remaining: Duration) -> String {
+	Locale::current().time_left(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn english_is_the_default() {
+		for locale in [Locale::default(), Locale::new(Some("C"), Some("POSIX")), Locale::new(Some("xx_XX"), Some("xx_XX"))] {
+			assert_eq!(locale.size(999, Units::Decimal), "999 B");
+			assert_eq!(locale.size(4_700_000_000, Units::Decimal), "4.7 GB");
+			assert_eq!(locale.speed(38_240_000., Units::Decimal), "38.2 MB/s");
+			assert_eq!(locale.percent(0.125, 1), "12.5%");
+			assert_eq!(locale.time_left(Duration::from_secs(3)), "about 5 s left");
+			assert_eq!(locale.time_left(Duration::from_secs(3725)), "about 1 h 2 min left");
+		}
+	}
+
+	#[test]
+	fn numbers_and_words_follow_their_own_locales() {
+		let german = Locale::new(Some("de_DE.UTF-8"), Some("de_DE.UTF-8"));
+		assert_eq!(german.size(1_500, Units::Decimal), "1,5 kB");
+		assert_eq!(german.percent(0.5, 0), "50\u{a0}%");
+		assert_eq!(german.time_left(Duration::from_secs(110)), "noch etwa 2 min");
+		assert_eq!(german.ratio(3.42), "3,4:1");
+
+		let french = Locale::new(Some("fr_FR"), Some("fr_FR"));
+		assert_eq!(french.speed(2_000_000., Units::Decimal), "2,0 Mo/s");
+
+		// English words with German numbers, or the other way round
+		assert_eq!(Locale::new(Some("de_DE"), Some("en_US")).size(1_500, Units::Decimal), "1,5 KB");
+		assert_eq!(Locale::new(Some("en_GB"), Some("de-AT")).size(1_500, Units::Decimal), "1.5 kB");
+
+		// A comma but no words of its own
+		assert_eq!(Locale::new(Some("pl_PL"), Some("pl_PL")).size(2_500_000, Units::Decimal), "2,5 MB");
+	}
+
+	#[test]
+	fn binary_units_go_up_in_1024s() {
+		let english = Locale::default();
+		assert_eq!(english.size(1023, Units::Binary), "1023 B");
+		assert_eq!(english.size(1536, Units::Binary), "1.5 KiB");
+		assert_eq!(english.size(16_000_000_000, Units::Binary), "14.9 GiB");
+		assert_eq!(english.speed(10_485_760., Units::Binary), "10.0 MiB/s");
+
+		let french = Locale::new(Some("fr_FR"), Some("fr_FR"));
+		assert_eq!(french.size(16_000_000_000, Units::Binary), "14,9 Gio");
+	}
+}
@@ -0,0 +1,206 @@
+// Windows To Go: applying a Windows image (install.wim / install.esd) to a stick and making it boot.
+//
+// wimlib does the heavy lifting, writing the image straight onto a fresh NTFS partition.
+// bcdboot only exists on Windows, so the UEFI boot manager and the image's template boot
+// store are copied onto an EFI system partition by hand.
+
+use std::fmt;
+use std::io::Read;
+use std::process::{Command, Stdio};
+
+use tokio::sync::mpsc;
+
+use crate::mount::{self, Mount};
+use crate::partition::{self, Filesystem, Kind, Spec, Table};
+use crate::tools;
+use crate::writer::Phase;
+
+const ESP_SIZE: u64 = 260 * 1024 * 1024;
+
+/// One of the editions in a WIM, e.g. Windows 11 Pro.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WimImage {
+	pub index: u32,
+	pub name: String,
+}
+
+impl fmt::Display for WimImage {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.index, self.name)
+	}
+}
+
+/// Whether `path` looks like a Windows image rather than something to write raw.
+pub fn is_wim(path: &str) -> bool {
+	let path = path.to_lowercase();
+	path.ends_with(".wim") || path.ends_with(".esd")
+}
+
+/// Lists the editions in a WIM.
+pub async fn images(path: String) -> Result<Vec<WimImage>, String> {
+	tokio::task::spawn_blocking(move || {
+		parse_info(&tools::run("wimlib-imagex", &["info", &path])?)
+	})
+	.await
+	.map_err(|e| format!("WIM error: {e}"))?
+}
+
+// The editions in what `wimlib-imagex info` prints, a block of "Key: value" lines per
+// image, Index coming first
+fn parse_info(info: &str) -> Result<Vec<WimImage>, String> {
+	let mut images = Vec::new();
+	let mut index = None;
+
+	for line in info.lines() {
+		let Some((key, value)) = line.split_once(':') else { continue };
+
+		match key.trim() {
+			"Index" => index = value.trim().parse().ok(),
+			"Name" => if let Some(index) = index.take() {
+				images.push(WimImage { index, name: value.trim().to_string() });
+			},
+			_ => {},
+		}
+	}
+
+	if images.is_empty() {
+		return Err("WIM error: no images found".into());
+	}
+
+	Ok(images)
+}
+
+/// Partitions `device`, applies edition `index` of `wim` to it and installs the UEFI boot files.
+pub async fn windows_to_go(
+	wim: String,
+	index: u32,
+	device: String,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	tokio::task::spawn_blocking(move || {
+		let _ = progress_tx.blocking_send((Phase::Partitioning, 0, 1));
+
+		let partitions = partition::create(&device, Table::Gpt, &[
+			Spec { size: Some(ESP_SIZE), kind: Kind::Efi, filesystem: Some(Filesystem::Fat32), label: "SYSTEM".into() },
+			Spec { size: None, kind: Kind::Data, filesystem: Some(Filesystem::Ntfs), label: "Windows".into() },
+		])?;
+
+		apply(&wim, index, &partitions[1], &mut cancel_rx, &progress_tx)?;
+
+		let _ = progress_tx.blocking_send((Phase::Finishing, 0, 1));
+		install_boot_files(&partitions[0], &partitions[1])
+	})
+	.await
+	.map_err(|e| format!("WIM error: {e}"))?
+}
+
+// Given a block device wimlib writes the NTFS volume itself, no mounting needed
+fn apply(
+	wim: &str,
+	index: u32,
+	partition: &str,
+	cancel_rx: &mut mpsc::Receiver<()>,
+	progress_tx: &mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	let mut child = Command::new("wimlib-imagex")
+		.args(["apply", wim, &index.to_string(), partition])
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|e| tools::not_found("wimlib-imagex", e))?;
+
+	let mut stdout = child.stdout.take().ok_or("WIM error: no output from wimlib")?;
+	let mut buffer = [0; 4096];
+	let mut line = String::new();
+
+	// Progress lines end in \r and look like "... 1848 MiB of 4163 MiB (44%) done"
+	loop {
+		if cancel_rx.try_recv().is_ok() {
+			let _ = child.kill();
+			let _ = child.wait();
+			return Err("Cancelled".into());
+		}
+
+		let n = stdout.read(&mut buffer).map_err(|e| format!("WIM error: {e}"))?;
+		if n == 0 {
+			break;
+		}
+
+		for c in String::from_utf8_lossy(&buffer[..n]).chars() {
+			if c != '\r' && c != '\n' {
+				line.push(c);
+				continue;
+			}
+
+			if let Some(percent) = percent(&line) {
+				let _ = progress_tx.blocking_send((Phase::Applying, percent, 100));
+			}
+
+			line.clear();
+		}
+	}
+
+	let output = child.wait_with_output().map_err(|e| format!("WIM error: {e}"))?;
+
+	if output.status.success() {
+		Ok(())
+	} else {
+		Err(format!("wimlib-imagex error: {}", String::from_utf8_lossy(&output.stderr).trim()))
+	}
+}
+
+fn percent(line: &str) -> Option<u64> {
+	let start = line.rfind('(')? + 1;
+	let end = start + line[start..].find("%)")?;
+
+	line[start..end].trim().parse().ok()
+}
+
+fn install_boot_files(esp: &str, windows: &str) -> Result<(), String> {
+	let esp = Mount::new(esp)?;
+	let windows = Mount::new(windows)?;
+
+	let boot = windows.path().join("Windows/Boot/EFI");
+	let microsoft = esp.path().join("EFI/Microsoft/Boot");
+	mount::copy_dir(&boot, &microsoft)?;
+
+	// The fallback path is what firmware boots from removable media
+	let fallback = esp.path().join("EFI/Boot");
+	std::fs::create_dir_all(&fallback).map_err(|e| format!("Boot files error: {e}"))?;
+	std::fs::copy(boot.join("bootmgfw.efi"), fallback.join("bootx64.efi"))
+		.map_err(|e| format!("Boot files error: bootmgfw.efi: {e}"))?;
+
+	std::fs::copy(
+		windows.path().join("Windows/System32/config/BCD-Template"),
+		microsoft.join("BCD"),
+	).map_err(|e| format!("Boot files error: BCD-Template: {e}"))?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn editions_and_progress_are_read_from_wimlib() {
+		let info = "WIM Information:\n----------------\nPath:           install.wim\nImage Count:    2\n\n\
+			Available Images:\n-----------------\nIndex:                  1\nName:                   Windows 11 Home\n\
+			Directory Count:        21456\n\nIndex:                  6\nName:                   Windows 11 Pro\n";
+		assert_eq!(parse_info(info).unwrap(), [
+			WimImage { index: 1, name: "Windows 11 Home".into() },
+			WimImage { index: 6, name: "Windows 11 Pro".into() },
+		]);
+
+		// Cut off before the first image's name, or not wimlib's at all
+		assert!(parse_info(&info[..info.find("Name:").unwrap()]).is_err());
+		assert!(parse_info("Index: x\nName: Windows 11 Pro\n").is_err());
+
+		assert_eq!(percent("Extracting file data: 1848 MiB of 4163 MiB (44%) done"), Some(44));
+		assert_eq!(percent("Extracting file data: 1848 MiB of 4163 MiB"), None);
+	}
+}
@@ -0,0 +1,1522 @@
+// The write engine: streams a source onto a device, then optionally reads it back to verify.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self as std_mpsc, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use memmap2::Mmap;
+use tokio::sync::mpsc::{self, error::TryRecvError};
+use tokio::sync::oneshot;
+
+use crate::bmap::Bmap;
+use crate::cache;
+use crate::compare;
+use crate::hash::{self, Algorithm};
+use crate::hybrid::WithMbr;
+use crate::partition;
+use crate::provider;
+use crate::remote;
+use crate::source::{self, RunningHash};
+use crate::target::{self, Target};
+use crate::units;
+
+/// Which pass of a write job is running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+	Writing,
+	/// Reading the image through for a dry run
+	Reading,
+	/// Comparing the device with the image to see whether it needs writing at all
+	Checking,
+	/// Waiting for the kernel to put what was written on the device
+	Syncing,
+	Verifying,
+	Partitioning,
+	/// Extracting a Windows image onto the device
+	Applying,
+	/// Boot files and other last touches after the main write
+	Finishing,
+}
+
+impl std::fmt::Display for Phase {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Phase::Writing => "Copying",
+			Phase::Reading => "Reading image",
+			Phase::Checking => "Checking device",
+			Phase::Syncing => "Syncing",
+			Phase::Verifying => "Verifying",
+			Phase::Partitioning => "Partitioning",
+			Phase::Applying => "Applying image",
+			Phase::Finishing => "Finishing",
+		})
+	}
+}
+
+// Size of each read when verifying
+const CHUNK: usize = 4096 * 1024; // 4MB
+
+// Buffers going round between reading and writing, one is written while the others fill
+const IN_FLIGHT: usize = 3;
+
+// Blocks a delta write compares and rewrites, small enough that a changed file in an
+// image only costs a little more than its own size
+const DELTA_BLOCK: usize = 64 * 1024;
+
+// Times a write that fails is tried before the write job fails with it
+const WRITE_ATTEMPTS: usize = 3;
+
+/// Start of the error for a device the user isn't allowed to open, see [`is_permission_denied`].
+const PERMISSION_DENIED: &str = "Permission denied";
+
+/// What a compressed image has unpacked to for `read` bytes of the file, e.g. "4.1 GB
+/// unpacked (3.4:1)", for the progress to say alongside. `None` for an image that isn't
+/// compressed, and for phases that don't go by the file.
+pub fn unpacked(phase: Phase, read: u64, unpacked: u64) -> Option<String> {
+	// The reader runs ahead of the write, an image that isn't compressed never gets past it
+	let compressed = read > 0 && unpacked > read + read / 100;
+
+	(matches!(phase, Phase::Writing | Phase::Reading) && compressed)
+		.then(|| format!("{} unpacked ({})", units::size(unpacked), units::ratio(unpacked as f64 / read as f64)))
+}
+
+/// Whether a write failed because only root can open the device, which can be got around.
+pub fn is_permission_denied(error: &str) -> bool {
+	error.starts_with(PERMISSION_DENIED)
+}
+
+// EACCES and EPERM both, the usual way for a write as a normal user to fail
+fn open_error(dest: &str, e: std::io::Error) -> String {
+	if e.kind() == std::io::ErrorKind::PermissionDenied {
+		format!("{PERMISSION_DENIED}: only root can open {dest}")
+	} else {
+		format!("Dest error: {e}")
+	}
+}
+
+/// Start of the error for a device that went away during the job, see [`is_disconnected`].
+const DISCONNECTED: &str = "Disconnected";
+
+/// Whether a job failed because its device was unplugged, rather than the write going wrong.
+pub fn is_disconnected(error: &str) -> bool {
+	error.starts_with(DISCONNECTED)
+}
+
+// An error reading or writing the device, telling a stick that was pulled out from one that failed
+fn device_error(what: &str, dest: &str, e: std::io::Error) -> String {
+	if unplugged(dest, &e) {
+		format!("{DISCONNECTED}: {dest} went away during the {}, it was unplugged or lost power", what.to_lowercase())
+	} else {
+		format!("{what} error: {e}")
+	}
+}
+
+// ENXIO and ENODEV, or their Windows counterparts, are what a device that's gone fails with.
+// EIO is as often a bad stick, it only counts once the device node is gone as well,
+// which udev takes a moment to get to.
+fn unplugged(dest: &str, e: &std::io::Error) -> bool {
+	#[cfg(unix)]
+	const GONE: &[i32] = &[6, 19];
+	// ERROR_NOT_READY, ERROR_NO_SUCH_DEVICE, ERROR_DEVICE_NOT_CONNECTED
+	#[cfg(not(unix))]
+	const GONE: &[i32] = &[21, 433, 1167];
+
+	match e.raw_os_error() {
+		Some(code) if GONE.contains(&code) => true,
+		Some(5) if cfg!(unix) => {
+			// A file written to for testing can't be pulled out, only the disk it's on
+			if std::fs::metadata(dest).is_ok_and(|metadata| metadata.is_file()) {
+				return false;
+			}
+
+			std::thread::sleep(Duration::from_millis(500));
+			!std::path::Path::new(dest).exists()
+		},
+		// Opening it again found nothing where it was
+		_ => e.kind() == std::io::ErrorKind::NotFound,
+	}
+}
+
+/// How to write an image.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+	/// Read the device back and compare with this
+	pub verify: Option<Algorithm>,
+	/// Checksum published for the image, checked as it's written
+	pub expected: Option<(Algorithm, String)>,
+	/// Swapped in for the image's first sector
+	pub mbr: Option<[u8; 512]>,
+	/// Only write the blocks it maps
+	pub bmap: Option<Bmap>,
+	/// Write uncompressed local images straight out of a memory map
+	pub mmap: bool,
+	/// Bytes written between syncs with the device, 0 only syncs at the end
+	pub checkpoint: u64,
+	/// How much of the device is known to be written, updated at each checkpoint
+	pub synced: Arc<AtomicU64>,
+	/// Bytes at the start already on the device from a write that was cut off, only written
+	/// from here on (they're still verified)
+	pub resume: u64,
+	/// Checksum of the image file as `<algorithm>:<digest>`, once it's all been read
+	pub image_hash: Arc<Mutex<Option<String>>>,
+	/// What the verification hashed, once the device has been read back
+	pub readback: Arc<Mutex<Option<Readback>>>,
+	/// Compare the device with the image first, and leave it as it is if it already has it
+	pub skip_unchanged: bool,
+	/// Set when the device already had the image and nothing was written
+	pub unchanged: Arc<AtomicBool>,
+	/// Read the device ahead of the write and only write the blocks that differ
+	pub delta: bool,
+	/// How much a delta write found different and wrote
+	pub rewritten: Arc<AtomicU64>,
+	/// What the image has unpacked to so far, more than has been read of the file when it's
+	/// compressed. Progress goes by the file, this is the size on the device
+	pub unpacked: Arc<AtomicU64>,
+}
+
+/// The digests a verified write compared, kept as evidence the device got the image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Readback {
+	pub algorithm: Algorithm,
+	/// What was written, the image as it went onto the device
+	pub written: String,
+	/// What was read back from the device
+	pub device: String,
+}
+
+/// Writes `source` to `dest` as `options` say.
+///
+/// The work happens on threads of its own rather than tokio's blocking pool: this one
+/// writes, a second reads (and decompresses) the source ahead of it, and a third hashes
+/// the read-back.
+pub async fn copy_with_progress(
+	source: String,
+	dest: String,
+	options: Options,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	if let Some(remote) = remote::parse(&dest) {
+		return on_thread("iso_maker remote writer", move || {
+			remote::copy(&source, &remote, &published(&source, options)?, false, &mut cancel_rx, &progress_tx)
+		}).await;
+	}
+
+	on_thread("iso_maker writer", move || copy(source, dest, options, cancel_rx, progress_tx)).await
+}
+
+/// Compares the device with `source` without writing anything, to re-check a stick made earlier.
+///
+/// The image is read the same way a write reads it, so compressed images, the MBR added when
+/// hybridizing and the blocks a bmap leaves out all compare the way they'd have been written.
+pub async fn verify_only(
+	source: String,
+	dest: String,
+	options: Options,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	on_thread("iso_maker verifier", move || {
+		let options = published(&source, options)?;
+		match remote::parse(&dest) {
+			Some(remote) => remote::copy(&source, &remote, &options, true, &mut cancel_rx, &progress_tx),
+			None => compare(&source, &dest, &options, Phase::Verifying, &mut cancel_rx, &progress_tx),
+		}
+	}).await
+}
+
+/// Goes through writing `source` to `dest` without writing anything, to try out profiles
+/// and scripts safely.
+///
+/// The device is opened the way the write opens it and checked for size, and the whole image
+/// is read, decompressed and hashed. Returns what the write would do, a line each, or the
+/// error it would stop with.
+pub async fn dry_run(
+	source: String,
+	dest: String,
+	options: Options,
+	cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<Vec<String>, String>
+{
+	if remote::parse(&dest).is_some() {
+		return Err("A dry run opens the device, it can't be on another machine".into());
+	}
+
+	on_thread("iso_maker dry run", move || simulate(source, dest, options, cancel_rx, progress_tx)).await
+}
+
+/// Overwrites all of `dest` with zeros, for a full format, and with `verify` reads it back to
+/// check every block kept them, which finds the bad blocks a quick format never touches.
+pub async fn zero(
+	dest: String,
+	verify: bool,
+	cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	on_thread("iso_maker zeroing", move || zero_fill(&dest, verify, cancel_rx, progress_tx)).await
+}
+
+// Runs `work` on a new thread, for the UI's runtime to wait on
+async fn on_thread<T: Send + 'static>(name: &str, work: impl FnOnce() -> Result<T, String> + Send + 'static) -> Result<T, String> {
+	let (done_tx, done_rx) = oneshot::channel();
+
+	std::thread::Builder::new()
+		.name(name.into())
+		.spawn(move || {
+			let _ = done_tx.send(work());
+		})
+		.map_err(|e| format!("Write error: {e}"))?;
+
+	done_rx.await.map_err(|_| "Write error: the write stopped unexpectedly".to_string())?
+}
+
+// Checked against the checksum the image's provider publishes, when the job didn't give one. A
+// signed one always counts, the job's can't be used to write an image the provider didn't sign
+fn published(source: &str, mut options: Options) -> Result<Options, String> {
+	if options.expected.is_none() {
+		options.expected = provider::checksum(source)?;
+	} else if provider::signed(source) {
+		let signed = provider::checksum(source)?;
+
+		let agrees = match (&options.expected, &signed) {
+			(Some((algorithm, digest)), Some((published, expected))) => algorithm == published && hash::matches(expected, digest),
+			_ => false,
+		};
+		if !agrees {
+			let signed = signed.map_or("no checksum".to_string(), |(algorithm, digest)| format!("{algorithm} {digest}"));
+			return Err(format!("Checksum error: the checksum given doesn't match the one {source} is signed with, {signed}"));
+		}
+	}
+
+	Ok(options)
+}
+
+// The UI dropping its end counts as cancelling too
+fn cancelled(cancel_rx: &mut mpsc::Receiver<()>) -> bool {
+	matches!(cancel_rx.try_recv(), Ok(()) | Err(TryRecvError::Disconnected))
+}
+
+fn copy(
+	source: String,
+	dest: String,
+	options: Options,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	let options = published(&source, options)?;
+
+	// Written to while mounted, the filesystem would write its own idea of the device back over it
+	partition::unmount_all(&dest)?;
+
+	// Reading is much quicker than writing, and stops at the first byte that differs. Only a
+	// local file can be read twice, a download or stdin would have to come again
+	if options.skip_unchanged && options.resume == 0 && std::path::Path::new(&source).is_file() {
+		match compare(&source, &dest, &options, Phase::Checking, &mut cancel_rx, &progress_tx) {
+			Ok(()) => {
+				options.unchanged.store(true, Ordering::Relaxed);
+				return Ok(());
+			},
+			Err(e) if e == "Cancelled" => return Err(e),
+			// Different, too small or not readable, the write finds out which
+			Err(_) => {},
+		}
+	}
+
+	let algorithm = image_algorithm(&options.expected);
+	let opened = open_source(&source, Some(algorithm), options.mbr, options.mmap, ChunkSize::default().get())?;
+
+	// Truncating a file written to for testing would lose what a resumed or delta write skips
+	let target = target::open(&dest, options.resume == 0 && !options.delta).map_err(|e| open_error(&dest, e))?;
+
+	write(opened, target, &dest, options, cancel_rx, progress_tx)
+}
+
+// The write itself, once the source and `target` are open. The tests hand it ones that misbehave
+fn write(
+	opened: Opened,
+	mut target: Box<dyn Target>,
+	dest: &str,
+	options: Options,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	let Options { verify, expected, bmap, checkpoint, synced, resume, image_hash, readback, delta, rewritten, unpacked, .. } = options;
+	synced.store(resume, Ordering::Relaxed);
+	rewritten.store(0, Ordering::Relaxed);
+
+	let mut chunk_size = ChunkSize::default();
+	let (mut chunks, total, digest, consumed) = opened;
+
+	// A full write replaces everything, the device can erase it all ahead of time. Raspberry
+	// Pi Imager does the same; a bmap leaves the blocks it skips as they are, and a delta
+	// write the ones that are the same
+	if resume == 0 && bmap.is_none() && !delta && let Ok(len) = target.len() {
+		let _ = target.discard(0..len);
+	}
+
+	let mut copied = 0;
+
+	// Hash what goes out so the read-back has something to compare against
+	let mut written_hash = verify.map(Algorithm::hasher);
+	let mut filter = bmap.as_ref().map(Bmap::filter);
+	let mut on_device = Vec::new();
+
+	while let Some(buffer) = chunks.next(chunk_size.get(), &mut cancel_rx)? {
+		let n = buffer.len();
+		let mut writing = Duration::ZERO;
+
+		// Unmapped blocks are skipped over, whatever the device had there stays
+		let spans = match filter.as_mut() {
+			Some(filter) => filter.spans(copied, &buffer)?,
+			None => vec![(copied, 0..n)],
+		};
+
+		for (offset, span) in spans {
+			if let Some(hasher) = written_hash.as_mut() {
+				hasher.update(&buffer[span.clone()]);
+			}
+
+			// What a resumed write already has on the device is left as it is
+			let skip = resume.saturating_sub(offset).min(span.len() as u64);
+			let (offset, span) = (offset + skip, span.start + skip as usize..span.end);
+			if span.is_empty() {
+				continue;
+			}
+
+			let started = Instant::now();
+			if delta {
+				on_device.resize(span.len(), 0);
+				let written = write_changed(target.as_mut(), offset, &buffer[span], &mut on_device, dest)?;
+				rewritten.fetch_add(written, Ordering::Relaxed);
+			} else {
+				write_at(target.as_mut(), offset, &buffer[span], dest)?;
+			}
+			writing += started.elapsed();
+		}
+
+		copied += n as u64;
+		unpacked.store(copied, Ordering::Relaxed);
+		let _ = progress_tx.blocking_send((Phase::Writing, consumed.load(Ordering::Relaxed), total));
+
+		// Chunks skipped over took no time at all, nor do a delta write's blocks that are the same
+		if copied > resume && !delta {
+			chunk_size.record(n, writing);
+		}
+
+		// Everything before this is on the device, should the write stop from here on
+		if checkpoint > 0 && copied.saturating_sub(synced.load(Ordering::Relaxed)) >= checkpoint {
+			target.flush().map_err(|e| device_error("Sync", dest, e))?;
+			synced.store(copied, Ordering::Relaxed);
+		}
+
+		// Back to the reader for the chunk after the ones already in flight
+		chunks.recycle(buffer, chunk_size.get());
+	}
+
+	if filter.is_some_and(|filter| !filter.finished()) {
+		return Err("The image is shorter than its bmap says".into());
+	}
+
+	if copied < resume {
+		return Err("The image is shorter than what was already written, it isn't the one the write was resumed from".into());
+	}
+
+	// Writing to a file rather than a device, the skipped blocks at the end still count
+	if bmap.is_some() {
+		target.set_len(copied).map_err(|e| device_error("Write", dest, e))?;
+	}
+
+	sync(dest, target.as_mut(), copied - synced.load(Ordering::Relaxed), &progress_tx)?;
+	synced.store(copied, Ordering::Relaxed);
+	drop(target);
+
+	let digest = digest.and_then(|digest| digest.finish());
+	if let (Some(digest), Ok(mut image_hash)) = (&digest, image_hash.lock()) {
+		*image_hash = Some(format!("{}:{digest}", image_algorithm(&expected).id()));
+	}
+
+	if let (Some((algorithm, expected)), Some(digest)) = (expected, digest)
+		&& !hash::matches(&digest, &expected)
+	{
+		return Err(format!("The image does not match the expected {algorithm} checksum, got {digest}"));
+	}
+
+	let (Some(algorithm), Some(written_hash)) = (verify, written_hash) else {
+		return Ok(());
+	};
+
+	// Read back exactly what was written, the device is usually bigger than the image
+	let ranges: Vec<Range<u64>> = match &bmap {
+		Some(bmap) => bmap.ranges().collect(),
+		None => std::iter::once(0..copied).collect(),
+	};
+	let expected_len: u64 = ranges.iter().map(|range| range.end - range.start).sum();
+
+	let (verified, digest) = read_back(dest, ranges, algorithm, &mut cancel_rx, &progress_tx)?;
+	let written = written_hash.finalize();
+
+	// Kept whether they match or not, a failed verification is worth having on record too
+	if let Ok(mut readback) = readback.lock() {
+		*readback = Some(Readback { algorithm, written: written.clone(), device: digest.clone() });
+	}
+
+	if verified != expected_len || digest != written {
+		return Err("Verification failed: the device contents don't match the image".into());
+	}
+
+	Ok(())
+}
+
+// Writes `data` at `offset`, trying again when the device fails it but is still there;
+// a flaky hub or cable can fail a write now and then without anything being wrong with the stick
+fn write_at(target: &mut dyn Target, offset: u64, data: &[u8], dest: &str) -> Result<(), String> {
+	let mut attempt = 1;
+
+	loop {
+		match target.write_at(offset, data) {
+			Ok(()) => return Ok(()),
+			Err(e) if attempt < WRITE_ATTEMPTS && !unplugged(dest, &e) => {
+				attempt += 1;
+				std::thread::sleep(Duration::from_millis(50));
+			},
+			Err(e) => return Err(device_error("Write", dest, e)),
+		}
+	}
+}
+
+// Writes the blocks of `data` that differ from what the device has at `offset`, in runs, and
+// returns how much that was. Anything that can't be read, e.g. past the end of a file, is
+// written the same as a block that differs
+fn write_changed(target: &mut dyn Target, offset: u64, data: &[u8], on_device: &mut [u8], dest: &str) -> Result<u64, String> {
+	if target.read_at(offset, on_device).is_err() {
+		write_at(target, offset, data, dest)?;
+		return Ok(data.len() as u64);
+	}
+
+	let mut written = 0;
+	let mut run: Option<usize> = None;
+
+	for start in (0..data.len()).step_by(DELTA_BLOCK).chain([data.len()]) {
+		let end = (start + DELTA_BLOCK).min(data.len());
+		let differs = start < data.len() && data[start..end] != on_device[start..end];
+
+		match (run, differs) {
+			(None, true) => run = Some(start),
+			(Some(from), false) => {
+				write_at(target, offset + from as u64, &data[from..start], dest)?;
+				written += (start - from) as u64;
+				run = None;
+			},
+			_ => {},
+		}
+	}
+
+	Ok(written)
+}
+
+// Syncs the whole write with the device, reporting how much of the `unsynced` bytes the
+// kernel still has to write out, so the minutes a slow stick can take don't look like a hang
+fn sync(dest: &str, target: &mut dyn Target, unsynced: u64, progress_tx: &mpsc::Sender<(Phase, u64, u64)>) -> Result<(), String> {
+	std::thread::scope(|scope| {
+		let (done_tx, done_rx) = std_mpsc::channel();
+		scope.spawn(move || {
+			let _ = done_tx.send(target.flush());
+		});
+
+		loop {
+			match done_rx.recv_timeout(Duration::from_millis(250)) {
+				Ok(result) => return result.map_err(|e| device_error("Sync", dest, e)),
+				Err(RecvTimeoutError::Timeout) => {
+					// Other writes count in what's pending, this one can't have more left than it wrote
+					if let Some(pending) = cache::pending() {
+						let remaining = pending.min(unsynced);
+						let _ = progress_tx.blocking_send((Phase::Syncing, unsynced - remaining, unsynced));
+					}
+				},
+				Err(RecvTimeoutError::Disconnected) => return Err("Sync error: the sync stopped unexpectedly".into()),
+			}
+		}
+	})
+}
+
+// Reads `ranges` of the device and hashes them, returning how much was read and the digest.
+//
+// The hashing runs on a thread of its own while the next chunk is read, so the device is
+// read at full speed instead of waiting on the hasher. Two buffers go back and forth
+// between the two.
+fn read_back(
+	dest: &str,
+	ranges: Vec<Range<u64>>,
+	algorithm: Algorithm,
+	cancel_rx: &mut mpsc::Receiver<()>,
+	progress_tx: &mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(u64, String), String>
+{
+	let expected_len: u64 = ranges.iter().map(|range| range.end - range.start).sum();
+
+	let mut readback = File::open(dest).map_err(|e| device_error("Verify", dest, e))?;
+
+	let (full_tx, full_rx) = std_mpsc::sync_channel::<(Vec<u8>, usize)>(1);
+	let (empty_tx, empty_rx) = std_mpsc::sync_channel(2);
+	let _ = empty_tx.send(vec![0; CHUNK]);
+	let _ = empty_tx.send(vec![0; CHUNK]);
+
+	let recycle = empty_tx.clone();
+	let hashing = std::thread::Builder::new()
+		.name("iso_maker hasher".into())
+		.spawn(move || {
+			let mut hasher = algorithm.hasher();
+
+			while let Ok((chunk, n)) = full_rx.recv() {
+				hasher.update(&chunk[..n]);
+				let _ = empty_tx.send(chunk);
+			}
+
+			hasher.finalize()
+		})
+		.map_err(|e| format!("Verify error: {e}"))?;
+
+	let mut verified = 0;
+
+	for range in ranges {
+		readback.seek(SeekFrom::Start(range.start)).map_err(|e| device_error("Verify", dest, e))?;
+		let mut mapped = (&mut readback).take(range.end - range.start);
+
+		loop {
+			if cancelled(cancel_rx) {
+				return Err("Cancelled".into());
+			}
+
+			let mut buffer = empty_rx.recv().map_err(|_| "Verify error: the hashing stopped")?;
+			let n = source::read_full(&mut mapped, &mut buffer).map_err(|e| device_error("Verify", dest, e))?;
+
+			if n == 0 {
+				let _ = recycle.send(buffer);
+				break;
+			}
+
+			full_tx.send((buffer, n)).map_err(|_| "Verify error: the hashing stopped")?;
+			verified += n as u64;
+			let _ = progress_tx.blocking_send((Phase::Verifying, verified, expected_len));
+		}
+
+		cache::drop_read(&readback, range);
+	}
+
+	// No more chunks, the hasher finishes what it has
+	drop(full_tx);
+	let digest = hashing.join().map_err(|_| "Verify error: the hashing stopped")?;
+
+	Ok((verified, digest))
+}
+
+// Compares the device with the image as it would be written, reporting it as `phase`
+fn compare(
+	source: &str,
+	dest: &str,
+	options: &Options,
+	phase: Phase,
+	cancel_rx: &mut mpsc::Receiver<()>,
+	progress_tx: &mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<(), String>
+{
+	let Options { expected, mbr, bmap, mmap, image_hash, .. } = options;
+
+	// Hashed either way, a device found to have the image already is logged with it
+	let (mut chunks, total, digest, consumed) = open_source(source, Some(image_algorithm(expected)), *mbr, *mmap, CHUNK)?;
+
+	let mut device = File::open(dest).map_err(|e| open_error(dest, e))?;
+
+	let mut on_device = vec![0; CHUNK];
+	let mut compared = 0;
+	let mut filter = bmap.as_ref().map(Bmap::filter);
+
+	while let Some(buffer) = chunks.next(CHUNK, cancel_rx)? {
+		let n = buffer.len();
+
+		let spans = match filter.as_mut() {
+			Some(filter) => filter.spans(compared, &buffer)?,
+			None => vec![(compared, 0..n)],
+		};
+
+		for (offset, span) in spans {
+			let on_device = &mut on_device[..span.len()];
+
+			device.seek(SeekFrom::Start(offset)).map_err(|e| device_error("Verify", dest, e))?;
+			device.read_exact(on_device).map_err(|e| match e.kind() {
+				std::io::ErrorKind::UnexpectedEof => "Verification failed: the device is smaller than the image".to_string(),
+				_ => device_error("Verify", dest, e),
+			})?;
+
+			if let Some(i) = buffer[span].iter().zip(on_device.iter()).position(|(a, b)| a != b) {
+				return Err(format!("Verification failed: the device differs from the image at byte {}", offset + i as u64));
+			}
+		}
+
+		compared += n as u64;
+		let _ = progress_tx.blocking_send((phase, consumed.load(Ordering::Relaxed), total));
+		chunks.recycle(buffer, CHUNK);
+	}
+
+	if filter.is_some_and(|filter| !filter.finished()) {
+		return Err("The image is shorter than its bmap says".into());
+	}
+
+	let digest = digest.and_then(|digest| digest.finish());
+	if let (Some(digest), Ok(mut image_hash)) = (&digest, image_hash.lock()) {
+		*image_hash = Some(format!("{}:{digest}", image_algorithm(expected).id()));
+	}
+
+	if let (Some((algorithm, expected)), Some(digest)) = (expected, digest)
+		&& !hash::matches(&digest, expected)
+	{
+		return Err(format!("The image does not match the expected {algorithm} checksum, got {digest}"));
+	}
+
+	Ok(())
+}
+
+fn zero_fill(dest: &str, verify: bool, mut cancel_rx: mpsc::Receiver<()>, progress_tx: mpsc::Sender<(Phase, u64, u64)>) -> Result<(), String> {
+	partition::unmount_all(dest)?;
+
+	let mut target = target::open(dest, false).map_err(|e| open_error(dest, e))?;
+	let len = target.len().map_err(|e| device_error("Write", dest, e))?;
+	let zeros = vec![0; CHUNK];
+
+	let mut offset = 0;
+	while offset < len {
+		if cancelled(&mut cancel_rx) {
+			return Err("Cancelled".into());
+		}
+
+		let n = (len - offset).min(CHUNK as u64) as usize;
+		write_at(&mut *target, offset, &zeros[..n], dest)?;
+		offset += n as u64;
+		let _ = progress_tx.blocking_send((Phase::Writing, offset, len));
+	}
+
+	sync(dest, &mut *target, len, &progress_tx)?;
+	drop(target);
+
+	if !verify {
+		return Ok(());
+	}
+
+	let mut readback = File::open(dest).map_err(|e| device_error("Verify", dest, e))?;
+	let mut buffer = vec![0; CHUNK];
+
+	let mut offset = 0;
+	while offset < len {
+		if cancelled(&mut cancel_rx) {
+			return Err("Cancelled".into());
+		}
+
+		// What was just written is still cached, the device itself has to answer
+		let n = (len - offset).min(CHUNK as u64) as usize;
+		cache::drop_read(&readback, offset..offset + n as u64);
+		readback.read_exact(&mut buffer[..n]).map_err(|e| device_error("Verify", dest, e))?;
+
+		if let Some(at) = buffer[..n].iter().position(|&byte| byte != 0) {
+			return Err(format!("Verify error: {dest} doesn't hold the zeros written at {}, the device may be failing", offset + at as u64));
+		}
+
+		offset += n as u64;
+		let _ = progress_tx.blocking_send((Phase::Verifying, offset, len));
+	}
+
+	Ok(())
+}
+
+fn simulate(
+	source: String,
+	dest: String,
+	options: Options,
+	mut cancel_rx: mpsc::Receiver<()>,
+	progress_tx: mpsc::Sender<(Phase, u64, u64)>,
+) -> Result<Vec<String>, String>
+{
+	let Options { verify, expected, mbr, bmap, mmap, checkpoint, resume, skip_unchanged, delta, unpacked, .. } = published(&source, options)?;
+	let mut notes = Vec::new();
+
+	// Opening it to write changes nothing yet, only writing does. A file gets made if it isn't there
+	let capacity = match std::fs::metadata(&dest) {
+		Ok(metadata) => {
+			if let Err(e) = std::fs::OpenOptions::new().write(true).open(&dest) {
+				let error = open_error(&dest, e);
+				if !is_permission_denied(&error) {
+					return Err(error);
+				}
+				notes.push(format!("{error}, the write would have to be run as root"));
+			}
+
+			(!metadata.is_file()).then(|| compare::size(&dest)).transpose().map_err(|e| format!("Dest error: {e}"))?
+		},
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+			notes.push(format!("{dest} doesn't exist, the image would be written to a new file there"));
+			None
+		},
+		Err(e) => return Err(format!("Dest error: {e}")),
+	};
+
+	for (partition, mount) in partition::mounted(&dest) {
+		notes.push(format!("{partition} is mounted at {mount}, it would be unmounted before writing"));
+	}
+
+	let (mut chunks, total, digest, consumed) = open_source(&source, expected.as_ref().map(|(algorithm, _)| *algorithm), mbr, mmap, CHUNK)?;
+
+	// What the write would hash for its read-back, or sha256 to say what the image is
+	let algorithm = verify.unwrap_or(Algorithm::Sha256);
+	let mut hasher = algorithm.hasher();
+	let mut filter = bmap.as_ref().map(Bmap::filter);
+	let (mut len, mut written) = (0, 0);
+
+	while let Some(buffer) = chunks.next(CHUNK, &mut cancel_rx)? {
+		let n = buffer.len();
+
+		let spans = match filter.as_mut() {
+			Some(filter) => filter.spans(len, &buffer)?,
+			None => vec![(len, 0..n)],
+		};
+
+		for (offset, span) in spans {
+			hasher.update(&buffer[span.clone()]);
+			written += (offset + span.len() as u64).saturating_sub(offset.max(resume));
+		}
+
+		len += n as u64;
+		unpacked.store(len, Ordering::Relaxed);
+		let _ = progress_tx.blocking_send((Phase::Reading, consumed.load(Ordering::Relaxed), total));
+		chunks.recycle(buffer, CHUNK);
+	}
+
+	if filter.is_some_and(|filter| !filter.finished()) {
+		return Err("The image is shorter than its bmap says".into());
+	}
+
+	if len < resume {
+		return Err("The image is shorter than what was already written, it isn't the one the write was resumed from".into());
+	}
+
+	if let Some(capacity) = capacity && len > capacity {
+		return Err(format!("The image is {}, it doesn't fit on {dest} ({})", units::size(len), units::size(capacity)));
+	}
+
+	if let (Some((algorithm, expected)), Some(digest)) = (&expected, digest.and_then(|digest| digest.finish()))
+		&& !hash::matches(&digest, expected)
+	{
+		return Err(format!("The image does not match the expected {algorithm} checksum, got {digest}"));
+	}
+
+	let mut report = vec![match capacity {
+		Some(capacity) => format!(
+			"{} of the {} image would be written to {dest} ({}, {} left over)",
+			units::size(written), units::size(len), units::size(capacity), units::size(capacity - len),
+		),
+		None => format!("{} of the {} image would be written to {dest}", units::size(written), units::size(len)),
+	}];
+
+	if skip_unchanged && resume == 0 && std::path::Path::new(&source).is_file() {
+		report.push("The device would be compared with the image first, and left as it is if it has it already".into());
+	}
+
+	if delta {
+		report.push("The device would be read ahead of the write, and only the blocks that differ from the image written".into());
+	}
+
+	if resume > 0 {
+		report.push(format!("The first {} are taken as written already, from the write that was cut off", units::size(resume)));
+	}
+
+	if mbr.is_some() {
+		report.push("A partition table pointing UEFI firmware at the boot image would replace the ISO's empty first sector".into());
+	}
+
+	if let Some(bmap) = &bmap {
+		report.push(format!("Only the {} the bmap maps would be written", units::size(bmap.mapped())));
+	}
+
+	if let Some((algorithm, _)) = &expected {
+		report.push(format!("The image matches the expected {algorithm} checksum"));
+	}
+
+	if checkpoint > 0 && checkpoint < len {
+		report.push(format!("It would be synced with the device every {}, so it can be resumed if it's cut off", units::size(checkpoint)));
+	}
+
+	let digest = hasher.finalize();
+	report.push(match verify {
+		Some(algorithm) => format!("It would be read back and verified with {algorithm}, which should give {digest}"),
+		None => format!("It wouldn't be verified, what would be written has the {algorithm} checksum {digest}"),
+	});
+
+	report.extend(notes);
+	Ok(report)
+}
+
+// What a write hashes the image with as it's read: the algorithm of the checksum it's checked
+// against, or SHA-256 for the audit log
+fn image_algorithm(expected: &Option<(Algorithm, String)>) -> Algorithm {
+	expected.as_ref().map_or(Algorithm::Sha256, |(algorithm, _)| *algorithm)
+}
+
+// The chunks of the source to write, with its size, checksum and how much of it was consumed.
+type Opened = (Chunks, u64, Option<RunningHash>, Arc<AtomicU64>);
+
+// Decompression happens in the source, progress follows how much of the file was read
+fn open_source(
+	source: &str,
+	algorithm: Option<Algorithm>,
+	mbr: Option<[u8; 512]>,
+	mmap: bool,
+	chunk_size: usize,
+) -> Result<Opened, String>
+{
+
+	if mmap && let Some(mapped) = source::map(source, algorithm) {
+		let consumed = Arc::new(AtomicU64::new(0));
+		let total = mapped.map.len() as u64;
+		let digest = mapped.digest.clone();
+
+		let chunks = Chunks::Mapped {
+			map: mapped.map,
+			file: mapped.file,
+			offset: 0,
+			mbr: mbr.map(Box::new),
+			digest: mapped.digest,
+			consumed: consumed.clone(),
+		};
+		return Ok((chunks, total, digest, consumed));
+	}
+
+	let mut source = source::open(source, algorithm)?;
+
+	if let Some(mbr) = mbr {
+		source.reader = Box::new(WithMbr::new(source.reader, mbr));
+	}
+
+	Ok((Chunks::Read(read_ahead(source.reader, chunk_size)), source.len, source.digest, source.consumed))
+}
+
+// How much to write at once, picked from how long the writes take.
+//
+// It starts modest and doubles while writes finish quickly, so fast devices aren't held
+// back by the per-write overhead, and halves when they take long, which keeps progress and
+// cancelling responsive on slow sticks.
+struct ChunkSize(usize);
+
+impl ChunkSize {
+	const MIN: usize = 256 * 1024;
+	const MAX: usize = 32 * 1024 * 1024;
+
+	// A write quicker than this can take a bigger chunk, one slower than SLOW a smaller one
+	const FAST: Duration = Duration::from_millis(50);
+	const SLOW: Duration = Duration::from_millis(400);
+
+	fn get(&self) -> usize {
+		self.0
+	}
+
+	fn record(&mut self, bytes: usize, took: Duration) {
+		// The short chunk at the end says nothing about the device
+		if bytes < self.0 {
+			return;
+		}
+
+		if took < Self::FAST {
+			self.0 = (self.0 * 2).min(Self::MAX);
+		} else if took > Self::SLOW {
+			self.0 = (self.0 / 2).max(Self::MIN);
+		}
+	}
+}
+
+impl Default for ChunkSize {
+	fn default() -> Self {
+		Self(1024 * 1024)
+	}
+}
+
+// A piece of the image, in a buffer it was read into or straight out of the memory map
+enum Chunk {
+	Read(Vec<u8>, usize),
+	Mapped(Arc<Mmap>, Range<usize>),
+}
+
+impl std::ops::Deref for Chunk {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		match self {
+			Chunk::Read(buffer, n) => &buffer[..*n],
+			Chunk::Mapped(map, range) => &map[range.clone()],
+		}
+	}
+}
+
+// Where the chunks come from: read, and decompressed, on a thread of their own, or taken
+// from an uncompressed image that's mapped into memory
+enum Chunks {
+	Read(ReadAhead),
+	Mapped {
+		map: Arc<Mmap>,
+		file: File,
+		offset: usize,
+		mbr: Option<Box<[u8; 512]>>,
+		digest: Option<RunningHash>,
+		consumed: Arc<AtomicU64>,
+	},
+}
+
+impl Chunks {
+	// The next chunk, up to `size` long for a mapped image, `None` at the end of the source
+	fn next(&mut self, size: usize, cancel_rx: &mut mpsc::Receiver<()>) -> Result<Option<Chunk>, String> {
+		match self {
+			Chunks::Read(read_ahead) => Ok(read_ahead.next(cancel_rx)?.map(|(buffer, n)| Chunk::Read(buffer, n))),
+			Chunks::Mapped { map, offset, mbr, digest, consumed, .. } => {
+				if cancelled(cancel_rx) {
+					return Err("Cancelled".into());
+				}
+
+				if *offset >= map.len() {
+					return Ok(None);
+				}
+
+				let range = *offset..(*offset + size).min(map.len());
+				*offset = range.end;
+				consumed.store(range.end as u64, Ordering::Relaxed);
+
+				if let Some(digest) = digest {
+					digest.update(&map[range.clone()]);
+				}
+
+				// The added MBR goes over the start, which can't be done in the map itself
+				if let Some(mbr) = mbr && range.start == 0 {
+					let mut buffer = map[range].to_vec();
+					let end = buffer.len().min(mbr.len());
+					buffer[..end].copy_from_slice(&mbr[..end]);
+
+					let n = buffer.len();
+					return Ok(Some(Chunk::Read(buffer, n)));
+				}
+
+				Ok(Some(Chunk::Mapped(map.clone(), range)))
+			},
+		}
+	}
+
+	// Hands a buffer back to be read into again, `size` long this time, or lets go of the
+	// pages of a mapped chunk that's been written
+	fn recycle(&self, chunk: Chunk, size: usize) {
+		match (self, chunk) {
+			(Chunks::Read(read_ahead), Chunk::Read(mut buffer, _)) => {
+				buffer.resize(size, 0);
+				read_ahead.recycle(buffer);
+			},
+			(Chunks::Mapped { map, file, .. }, Chunk::Mapped(_, range)) => {
+				// SAFETY: the map is read-only and backed by the file, the pages are read in
+				// again should they be touched after this
+				#[cfg(unix)]
+				let _ = unsafe { map.unchecked_advise_range(memmap2::UncheckedAdvice::DontNeed, range.start, range.len()) };
+				// From the start, the kernel keeps pages that straddle two chunks until all of them is dropped
+				cache::drop_read(file, 0..range.end as u64);
+			},
+			_ => {},
+		}
+	}
+}
+
+// Chunks of the source, read on a thread of its own ahead of where the writing is.
+//
+// The device is written while the next chunks are read and decompressed, instead of the two
+// taking turns. The reading stops at the end of the source, or once this is dropped.
+struct ReadAhead {
+	full_rx: std_mpsc::Receiver<Result<(Vec<u8>, usize), String>>,
+	empty_tx: std_mpsc::SyncSender<Vec<u8>>,
+}
+
+impl ReadAhead {
+	// The next chunk and how much of it is filled, `None` at the end of the source
+	fn next(&self, cancel_rx: &mut mpsc::Receiver<()>) -> Result<Option<(Vec<u8>, usize)>, String> {
+		loop {
+			// A stalled download still gets cancelled
+			if cancelled(cancel_rx) {
+				return Err("Cancelled".into());
+			}
+
+			match self.full_rx.recv_timeout(Duration::from_millis(100)) {
+				Ok(chunk) => return chunk.map(Some),
+				Err(RecvTimeoutError::Timeout) => continue,
+				Err(RecvTimeoutError::Disconnected) => return Ok(None),
+			}
+		}
+	}
+
+	fn recycle(&self, buffer: Vec<u8>) {
+		let _ = self.empty_tx.send(buffer);
+	}
+}
+
+fn read_ahead(mut reader: Box<dyn Read + Send>, chunk_size: usize) -> ReadAhead {
+	let (full_tx, full_rx) = std_mpsc::sync_channel(IN_FLIGHT);
+	let (empty_tx, empty_rx) = std_mpsc::sync_channel(IN_FLIGHT);
+
+	for _ in 0..IN_FLIGHT {
+		let _ = empty_tx.send(vec![0; chunk_size]);
+	}
+
+	// Nothing to do if the thread can't start, the receiver sees the image end straight away
+	let _ = std::thread::Builder::new()
+		.name("iso_maker reader".into())
+		.spawn(move || {
+			while let Ok(mut buffer) = empty_rx.recv() {
+				let chunk = match source::read_full(&mut reader, &mut buffer) {
+					Ok(0) => break,
+					Ok(n) => Ok((buffer, n)),
+					Err(e) => Err(format!("Read error: {e}")),
+				};
+
+				let failed = chunk.is_err();
+				if full_tx.send(chunk).is_err() || failed {
+					break;
+				}
+			}
+		});
+
+	ReadAhead { full_rx, empty_tx }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::faults::{FaultyReader, FaultyTarget, Faults, Probe};
+	use crate::testing::{self, LoopDevice, Rng, TempDir};
+
+	// An odd size, so the last chunk is a short one
+	const IMAGE_LEN: usize = 6 * 1024 * 1024 + 4321;
+
+	fn options() -> Options {
+		Options { verify: Some(Algorithm::Blake3), mmap: true, ..Options::default() }
+	}
+
+	// `copy` or `verify`
+	type Work = fn(String, String, Options, mpsc::Receiver<()>, mpsc::Sender<(Phase, u64, u64)>) -> Result<(), String>;
+
+	#[test]
+	fn a_signed_checksum_counts_whatever_the_job_gives() {
+		struct Distro;
+
+		impl provider::Provider for Distro {
+			fn scheme(&self) -> &str {
+				"signed-distro"
+			}
+
+			fn list(&self) -> Result<Vec<provider::Image>, String> {
+				Ok(vec![provider::Image { name: "golden.iso".into(), size: None, checksum: Some((Algorithm::Sha256, "9f86d0".into())) }])
+			}
+
+			fn open(&self, _name: &str) -> Result<(Box<dyn std::io::Read + Send>, u64), String> {
+				Err("not opened in this test".into())
+			}
+
+			fn checksum(&self, name: &str) -> Result<Option<(Algorithm, String)>, String> {
+				match name {
+					"golden.iso" => Ok(Some((Algorithm::Sha256, "9f86d0".into()))),
+					_ => Err(format!("Catalog error: {name} isn't signed")),
+				}
+			}
+
+			fn signed(&self) -> bool {
+				true
+			}
+		}
+
+		provider::register(std::sync::Arc::new(Distro));
+		let given = |expected: Option<(Algorithm, &str)>| {
+			published("signed-distro://golden.iso", Options { expected: expected.map(|(algorithm, digest)| (algorithm, digest.into())), ..Options::default() })
+				.map(|options| options.expected)
+		};
+
+		assert_eq!(given(None), Ok(Some((Algorithm::Sha256, "9f86d0".into()))));
+		assert_eq!(given(Some((Algorithm::Sha256, "9F86D0"))), Ok(Some((Algorithm::Sha256, "9F86D0".into()))));
+		assert!(given(Some((Algorithm::Sha256, "000000"))).is_err());
+		assert!(given(Some((Algorithm::Sha512, "9f86d0"))).is_err());
+
+		// Nor one that isn't signed at all
+		let unsigned = Options { expected: Some((Algorithm::Sha256, "9f86d0".into())), ..Options::default() };
+		assert!(published("signed-distro://other.iso", unsigned).is_err());
+	}
+
+	// Runs `work` like the GUI does, without anyone cancelling or watching the progress
+	fn run(work: Work, source: &str, dest: &str, options: Options) -> Result<(), String> {
+		let (_cancel_tx, cancel_rx) = mpsc::channel(1);
+		let (progress_tx, _) = mpsc::channel(1);
+
+		work(source.into(), dest.into(), options, cancel_rx, progress_tx)
+	}
+
+	// What `verify_only` runs
+	fn verify(source: String, dest: String, options: Options, mut cancel_rx: mpsc::Receiver<()>, progress_tx: mpsc::Sender<(Phase, u64, u64)>) -> Result<(), String> {
+		compare(&source, &dest, &options, Phase::Verifying, &mut cancel_rx, &progress_tx)
+	}
+
+	// What a write with faults did
+	struct Outcome {
+		result: Result<(), String>,
+		progress: Vec<(Phase, u64, u64)>,
+		probe: Arc<Probe>,
+	}
+
+	// Writes `image` to `dest` through a source and a target that misbehave as `faults` say,
+	// cancelling after `cancel_after` progress updates if given
+	fn write_faulty(image: &[u8], dest: &str, faults: &Faults, options: Options, cancel_after: Option<usize>) -> Outcome {
+		let probe = Arc::new(Probe::default());
+
+		let reader = FaultyReader::new(image.to_vec(), faults, &probe);
+		let consumed = reader.consumed.clone();
+		let chunks = Chunks::Read(read_ahead(Box::new(reader), ChunkSize::default().get()));
+		let opened = (chunks, image.len() as u64, None, consumed);
+
+		let target = target::open(dest, options.resume == 0).unwrap();
+		let target = Box::new(FaultyTarget::new(target, faults, &probe));
+
+		let (cancel_tx, cancel_rx) = mpsc::channel(1);
+		let (progress_tx, mut progress_rx) = mpsc::channel(100);
+		let dest = dest.to_string();
+		let writing = std::thread::spawn(move || write(opened, target, &dest, options, cancel_rx, progress_tx));
+
+		let mut progress = Vec::new();
+		while let Some(update) = progress_rx.blocking_recv() {
+			progress.push(update);
+			if cancel_after == Some(progress.len()) {
+				let _ = cancel_tx.try_send(());
+			}
+		}
+
+		Outcome { result: writing.join().unwrap(), progress, probe }
+	}
+
+	// Each phase's progress only goes forward, and never past its total
+	fn assert_reported(outcome: &Outcome) {
+		for phase in [Phase::Writing, Phase::Verifying] {
+			let updates: Vec<_> = outcome.progress.iter().filter(|(of, ..)| *of == phase).collect();
+
+			assert!(updates.windows(2).all(|pair| pair[0].1 <= pair[1].1), "{phase} went backwards");
+			assert!(updates.iter().all(|(_, done, total)| done <= total), "{phase} went past its total");
+
+			if outcome.result.is_ok() {
+				assert!(updates.last().is_some_and(|(_, done, total)| done == total), "{phase} didn't finish");
+			}
+		}
+	}
+
+	// Nothing's left open once the write has stopped. The reader's thread lets go of it in
+	// its own time
+	fn assert_cleaned_up(probe: &Probe) {
+		assert!(probe.target_dropped.load(Ordering::Relaxed), "the target is still open");
+
+		let deadline = Instant::now() + Duration::from_secs(5);
+		while !probe.reader_dropped.load(Ordering::Relaxed) {
+			assert!(Instant::now() < deadline, "the source is still open");
+			std::thread::sleep(Duration::from_millis(10));
+		}
+	}
+
+	// Random cases each property is tried with
+	const CASES: u64 = 16;
+
+	// Around 1 to 3 chunks, rarely a whole number of them
+	fn case_image(rng: &mut Rng) -> Vec<u8> {
+		testing::image(rng.within(1 << 20..3 << 20) as usize)
+	}
+
+	fn source(dir: &TempDir) -> (String, Vec<u8>) {
+		let path = dir.file("source.img");
+		let image = testing::image(IMAGE_LEN);
+		std::fs::write(&path, &image).unwrap();
+
+		(path, image)
+	}
+
+	#[test]
+	fn writes_and_verifies_a_file() {
+		let dir = TempDir::new("write-file");
+		let (source, image) = source(&dir);
+		let dest = dir.file("dest.img");
+
+		for mmap in [true, false] {
+			let readback = Arc::default();
+			run(copy, &source, &dest, Options { mmap, readback: Arc::clone(&readback), ..options() }).unwrap();
+			assert!(std::fs::read(&dest).unwrap() == image);
+			run(verify, &source, &dest, options()).unwrap();
+
+			// Both sides of the comparison are kept for the audit log
+			let digest = blake3::hash(&image).to_hex().to_string();
+			let readback = readback.lock().unwrap().clone().unwrap();
+			assert_eq!(readback, Readback { algorithm: Algorithm::Blake3, written: digest.clone(), device: digest });
+		}
+	}
+
+	#[test]
+	fn writes_and_verifies_a_loop_device() {
+		let dir = TempDir::new("write-loop");
+		let (source, image) = source(&dir);
+
+		// Bigger than the image, like a stick
+		let backing = dir.file("backing.img");
+		std::fs::write(&backing, vec![0xEE; 2 * IMAGE_LEN]).unwrap();
+
+		let Some(device) = LoopDevice::attach(&backing) else {
+			eprintln!("skipped, setting up a loop device needs root");
+			return;
+		};
+
+		let synced = Arc::new(AtomicU64::new(0));
+		let options = Options { checkpoint: 1024 * 1024, synced: synced.clone(), ..options() };
+		run(copy, &source, &device.path, options).unwrap();
+		run(verify, &source, &device.path, Options::default()).unwrap();
+		assert_eq!(synced.load(Ordering::Relaxed), IMAGE_LEN as u64);
+
+		drop(device);
+		assert!(std::fs::read(&backing).unwrap()[..IMAGE_LEN] == image[..]);
+	}
+
+	#[test]
+	fn verify_only_finds_the_first_difference() {
+		let dir = TempDir::new("verify-only");
+		let (source, _) = source(&dir);
+		let dest = dir.file("dest.img");
+		run(copy, &source, &dest, options()).unwrap();
+
+		let mut written = std::fs::read(&dest).unwrap();
+		written[5 * 1024 * 1024 + 7] ^= 1;
+		std::fs::write(&dest, &written).unwrap();
+
+		let error = run(verify, &source, &dest, options()).unwrap_err();
+		assert!(error.ends_with(&format!("at byte {}", 5 * 1024 * 1024 + 7)), "{error}");
+
+		written.truncate(1024);
+		std::fs::write(&dest, &written).unwrap();
+		let error = run(verify, &source, &dest, options()).unwrap_err();
+		assert!(error.contains("smaller than the image"), "{error}");
+	}
+
+	#[test]
+	fn a_device_that_has_the_image_is_left_alone() {
+		let dir = TempDir::new("unchanged");
+		let (source, image) = source(&dir);
+		let dest = dir.file("dest.img");
+
+		// Whatever's after the image stays, as it would on a stick
+		let mut on_device = image.clone();
+		on_device.extend(b"after the image");
+		std::fs::write(&dest, &on_device).unwrap();
+
+		let unchanged = Arc::new(AtomicBool::new(false));
+		let options = || Options { skip_unchanged: true, unchanged: unchanged.clone(), ..options() };
+		run(copy, &source, &dest, options()).unwrap();
+		assert!(unchanged.load(Ordering::Relaxed));
+		assert!(std::fs::read(&dest).unwrap() == on_device);
+
+		// One byte off and it's written after all
+		unchanged.store(false, Ordering::Relaxed);
+		on_device[IMAGE_LEN - 1] ^= 1;
+		std::fs::write(&dest, &on_device).unwrap();
+		run(copy, &source, &dest, options()).unwrap();
+		assert!(!unchanged.load(Ordering::Relaxed));
+		assert!(std::fs::read(&dest).unwrap() == image);
+	}
+
+	#[test]
+	fn a_delta_write_only_writes_what_differs() {
+		let dir = TempDir::new("delta");
+		let (source, image) = source(&dir);
+		let dest = dir.file("dest.img");
+
+		// Last night's build, a few blocks different and the end cut short
+		let mut on_device = image.clone();
+		on_device[10] ^= 1;
+		on_device[3 * DELTA_BLOCK + 5] ^= 1;
+		on_device[4 * DELTA_BLOCK] ^= 1;
+		on_device.truncate(IMAGE_LEN - 100);
+		std::fs::write(&dest, &on_device).unwrap();
+
+		let rewritten = Arc::new(AtomicU64::new(0));
+		run(copy, &source, &dest, Options { delta: true, rewritten: rewritten.clone(), ..options() }).unwrap();
+
+		assert!(std::fs::read(&dest).unwrap() == image);
+		// The short chunk at the end can't be read in full, so it's written whole
+		let last_chunk = (IMAGE_LEN % ChunkSize::default().get()) as u64;
+		assert_eq!(rewritten.load(Ordering::Relaxed), 3 * DELTA_BLOCK as u64 + last_chunk);
+	}
+
+	#[test]
+	fn zeroing_leaves_nothing_behind() {
+		let dir = TempDir::new("zero");
+		let dest = dir.file("dest.img");
+		std::fs::write(&dest, testing::image(IMAGE_LEN)).unwrap();
+
+		let (_cancel_tx, cancel_rx) = mpsc::channel(1);
+		let (progress_tx, _) = mpsc::channel(1);
+		zero_fill(&dest, true, cancel_rx, progress_tx).unwrap();
+
+		let zeroed = std::fs::read(&dest).unwrap();
+		assert_eq!(zeroed.len(), IMAGE_LEN);
+		assert!(zeroed.iter().all(|&byte| byte == 0));
+	}
+
+	#[test]
+	fn only_compressed_images_say_what_they_unpacked_to() {
+		// Three chunks read ahead of the write
+		assert_eq!(unpacked(Phase::Writing, 4 << 20, 1 << 20), None);
+		assert_eq!(unpacked(Phase::Writing, 1 << 20, 1 << 20), None);
+		assert!(unpacked(Phase::Writing, 1 << 20, 3 << 20).is_some());
+		assert!(unpacked(Phase::Reading, 1 << 20, 3 << 20).is_some());
+
+		// The read-back goes by what's on the device
+		assert_eq!(unpacked(Phase::Verifying, 1 << 20, 3 << 20), None);
+		assert_eq!(unpacked(Phase::Writing, 0, 1 << 20), None);
+	}
+
+	#[test]
+	fn resuming_writes_the_rest_and_verifies_it_all() {
+		let dir = TempDir::new("resume");
+		let (source, image) = source(&dir);
+		let dest = dir.file("dest.img");
+		let half = IMAGE_LEN / 2;
+
+		// A write cut off half way, with junk after what was synced
+		let mut partial = image[..half].to_vec();
+		partial.resize(IMAGE_LEN, 0);
+		std::fs::write(&dest, &partial).unwrap();
+
+		run(copy, &source, &dest, Options { resume: half as u64, ..options() }).unwrap();
+		assert!(std::fs::read(&dest).unwrap() == image);
+
+		// The part that's skipped is still read back
+		partial[10] ^= 1;
+		std::fs::write(&dest, &partial).unwrap();
+		let error = run(copy, &source, &dest, Options { resume: half as u64, ..options() }).unwrap_err();
+		assert!(error.starts_with("Verification failed"), "{error}");
+	}
+
+	#[test]
+	fn a_dry_run_writes_nothing() {
+		let dir = TempDir::new("dry-run");
+		let (source, image) = source(&dir);
+		let dest = dir.file("dest.img");
+		std::fs::write(&dest, b"untouched").unwrap();
+
+		let dry_run = |options| {
+			let (_cancel_tx, cancel_rx) = mpsc::channel(1);
+			let (progress_tx, _) = mpsc::channel(1);
+			simulate(source.clone(), dest.clone(), options, cancel_rx, progress_tx)
+		};
+
+		let report = dry_run(options()).unwrap();
+		assert_eq!(std::fs::read(&dest).unwrap(), b"untouched");
+
+		// It knows what the read-back would come to
+		let mut hasher = Algorithm::Blake3.hasher();
+		hasher.update(&image);
+		let digest = hasher.finalize();
+		assert!(report.iter().any(|line| line.ends_with(&digest)), "{report:?}");
+
+		let error = dry_run(Options { expected: Some((Algorithm::Sha256, "00".repeat(32))), ..options() }).unwrap_err();
+		assert!(error.starts_with("The image does not match"), "{error}");
+	}
+
+	#[test]
+	fn faults_that_clear_up_are_ridden_out() {
+		let dir = TempDir::new("faults-transient");
+		let mut rng = Rng::new(7);
+
+		for case in 0..CASES {
+			let image = case_image(&mut rng);
+			let dest = dir.file(&format!("dest-{case}.img"));
+			let faults = Faults {
+				short_reads: rng.within(1..64 * 1024) as usize,
+				write_errors: (0..rng.within(0..4)).map(|_| rng.within(0..image.len() as u64)).collect(),
+				// Fewer times than the engine tries
+				failures: rng.within(1..WRITE_ATTEMPTS as u64) as usize,
+				slow_writes: Duration::from_millis(rng.within(0..3)),
+				..Faults::default()
+			};
+
+			let synced = Arc::new(AtomicU64::new(0));
+			let checkpoint = rng.within(0..2) * 512 * 1024;
+			let outcome = write_faulty(&image, &dest, &faults, Options { checkpoint, synced: synced.clone(), ..options() }, None);
+
+			assert!(outcome.result.is_ok(), "case {case} with {faults:?}: {:?}", outcome.result);
+			assert!(std::fs::read(&dest).unwrap() == image, "case {case} with {faults:?}");
+			assert_eq!(synced.load(Ordering::Relaxed), image.len() as u64);
+			assert_reported(&outcome);
+			assert_cleaned_up(&outcome.probe);
+		}
+	}
+
+	#[test]
+	fn faults_that_stay_are_reported() {
+		let dir = TempDir::new("faults-persistent");
+		let mut rng = Rng::new(11);
+
+		for case in 0..CASES {
+			let image = case_image(&mut rng);
+			let dest = dir.file(&format!("dest-{case}.img"));
+			let bad = rng.within(0..image.len() as u64);
+
+			// Half of them can't read the source, the other half can't write the device
+			let reading = case % 2 == 0;
+			let faults = if reading {
+				Faults { read_errors: vec![bad], short_reads: rng.within(1..64 * 1024) as usize, ..Faults::default() }
+			} else {
+				Faults { write_errors: vec![bad], failures: usize::MAX, ..Faults::default() }
+			};
+
+			let synced = Arc::new(AtomicU64::new(0));
+			let outcome = write_faulty(&image, &dest, &faults, Options { checkpoint: 256 * 1024, synced: synced.clone(), ..options() }, None);
+
+			let error = outcome.result.clone().unwrap_err();
+			let expected = if reading { "Read error" } else { "Write error" };
+			assert!(error.starts_with(expected), "case {case} with {faults:?}: {error}");
+			assert_reported(&outcome);
+			assert_cleaned_up(&outcome.probe);
+
+			// Nothing from the bad spot on is written, and only what was flushed counts as synced
+			let written = std::fs::read(&dest).unwrap();
+			let synced = synced.load(Ordering::Relaxed);
+			assert!(written.len() as u64 <= bad, "case {case}: wrote {} bytes past byte {bad}", written.len());
+			assert!(written[..] == image[..written.len()], "case {case}");
+			assert!(synced <= outcome.probe.flushed.load(Ordering::Relaxed), "case {case}: {synced} synced but not flushed");
+
+			// Once the fault's gone, the write carries on from the last checkpoint
+			let outcome = write_faulty(&image, &dest, &Faults::default(), Options { resume: synced, ..options() }, None);
+			assert!(outcome.result.is_ok(), "case {case}: {:?}", outcome.result);
+			assert!(std::fs::read(&dest).unwrap() == image, "case {case}");
+		}
+	}
+
+	#[test]
+	fn slow_writes_can_be_cancelled() {
+		let dir = TempDir::new("faults-slow");
+		let image = testing::image(IMAGE_LEN);
+		let dest = dir.file("dest.img");
+		let faults = Faults { slow_writes: Duration::from_millis(100), short_reads: 4096, ..Faults::default() };
+
+		let outcome = write_faulty(&image, &dest, &faults, options(), Some(2));
+
+		assert_eq!(outcome.result, Err("Cancelled".into()));
+		assert!(outcome.probe.writes.load(Ordering::Relaxed) < IMAGE_LEN.div_ceil(ChunkSize::default().get()));
+		assert_reported(&outcome);
+		assert_cleaned_up(&outcome.probe);
+	}
+}